@@ -0,0 +1,356 @@
+//! Signature checks on the beacon.
+//!
+//! The beacon cannot read house contents, but it can refuse writes that were not
+//! signed by someone who holds the house signing key or who joined via an invite,
+//! and it can make WebSocket clients prove the identity key behind their user_id.
+
+use std::collections::{BTreeMap, HashSet};
+use std::fmt;
+
+use base64::engine::general_purpose::STANDARD as BASE64;
+use base64::Engine;
+use chrono::{DateTime, Utc};
+use ed25519_dalek::{Signature, Verifier, VerifyingKey};
+use hyper::{Body, Response, StatusCode};
+use sha2::{Digest, Sha256};
+
+use crate::{AckRequest, EncryptedServerHint};
+
+/// Reason a signed write was rejected.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum AuthError {
+    MissingSignature,
+    MalformedKey,
+    MalformedSignature,
+    BadSignature,
+    UnauthorizedSigner,
+    PubkeyMismatch,
+    UserIdMismatch,
+}
+
+impl AuthError {
+    /// Stable machine-readable code, returned to clients in the `error` field.
+    pub fn code(&self) -> &'static str {
+        match self {
+            AuthError::MissingSignature => "missing_signature",
+            AuthError::MalformedKey => "malformed_key",
+            AuthError::MalformedSignature => "malformed_signature",
+            AuthError::BadSignature => "bad_signature",
+            AuthError::UnauthorizedSigner => "unauthorized_signer",
+            AuthError::PubkeyMismatch => "pubkey_mismatch",
+            AuthError::UserIdMismatch => "user_id_mismatch",
+        }
+    }
+
+    pub fn status(&self) -> StatusCode {
+        match self {
+            AuthError::MissingSignature | AuthError::BadSignature => StatusCode::UNAUTHORIZED,
+            AuthError::UnauthorizedSigner => StatusCode::FORBIDDEN,
+            AuthError::MalformedKey
+            | AuthError::MalformedSignature
+            | AuthError::PubkeyMismatch
+            | AuthError::UserIdMismatch => StatusCode::BAD_REQUEST,
+        }
+    }
+
+    /// JSON error response: `{"error": "<code>", "message": "<text>"}`.
+    pub fn into_response(self) -> Response<Body> {
+        let body = serde_json::json!({
+            "error": self.code(),
+            "message": self.to_string(),
+        });
+        Response::builder()
+            .status(self.status())
+            .header("Content-Type", "application/json")
+            .body(Body::from(body.to_string()))
+            .unwrap()
+    }
+}
+
+impl fmt::Display for AuthError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let msg = match self {
+            AuthError::MissingSignature => "Signature is required",
+            AuthError::MalformedKey => "Public key is not a valid Ed25519 key",
+            AuthError::MalformedSignature => "Signature is not a valid base64 Ed25519 signature",
+            AuthError::BadSignature => "Signature does not match",
+            AuthError::UnauthorizedSigner => "Signer is not authorized for this house",
+            AuthError::PubkeyMismatch => "Signing pubkey does not match the request path",
+            AuthError::UserIdMismatch => "user_id is not derived from the given public key",
+        };
+        f.write_str(msg)
+    }
+}
+
+/// House signing keys are base64 (as used in URLs and hints).
+pub fn decode_house_key(signing_pubkey: &str) -> Result<VerifyingKey, AuthError> {
+    let bytes = BASE64.decode(signing_pubkey).map_err(|_| AuthError::MalformedKey)?;
+    let arr: [u8; 32] = bytes.try_into().map_err(|_| AuthError::MalformedKey)?;
+    VerifyingKey::from_bytes(&arr).map_err(|_| AuthError::MalformedKey)
+}
+
+/// Member identity keys are hex (as produced by the client IdentityManager).
+pub fn decode_member_key(member_pubkey: &str) -> Result<VerifyingKey, AuthError> {
+    let bytes = hex::decode(member_pubkey).map_err(|_| AuthError::MalformedKey)?;
+    let arr: [u8; 32] = bytes.try_into().map_err(|_| AuthError::MalformedKey)?;
+    VerifyingKey::from_bytes(&arr).map_err(|_| AuthError::MalformedKey)
+}
+
+/// Verify a base64 Ed25519 signature over `payload`.
+pub fn verify_signature(key: &VerifyingKey, payload: &[u8], signature_b64: &str) -> Result<(), AuthError> {
+    if signature_b64.is_empty() {
+        return Err(AuthError::MissingSignature);
+    }
+    let bytes = BASE64.decode(signature_b64).map_err(|_| AuthError::MalformedSignature)?;
+    let arr: [u8; 64] = bytes.try_into().map_err(|_| AuthError::MalformedSignature)?;
+    let signature = Signature::from_bytes(&arr);
+    key.verify(payload, &signature).map_err(|_| AuthError::BadSignature)
+}
+
+/// Bytes covered by a hint signature. Must match the client's `hint_signing_payload`.
+/// Covers `key_epoch` and `revision` too, so a relay can't replay an old state as a newer write.
+pub fn hint_signing_payload(
+    signing_pubkey: &str,
+    encrypted_state: &str,
+    key_epoch: u32,
+    revision: u64,
+    last_updated: &DateTime<Utc>,
+) -> Vec<u8> {
+    format!(
+        "cordia-hint-v2\n{}\n{}\n{}\n{}\n{}",
+        signing_pubkey,
+        key_epoch,
+        revision,
+        encrypted_state,
+        last_updated.timestamp_millis()
+    )
+    .into_bytes()
+}
+
+/// Bytes covered by the house signature on an invite create request.
+pub fn invite_create_payload(signing_pubkey: &str, code: &str, max_uses: u32, encrypted_payload: &str) -> Vec<u8> {
+    format!(
+        "cordia-invite-create-v1\n{}\n{}\n{}\n{}",
+        signing_pubkey, code, max_uses, encrypted_payload
+    )
+    .into_bytes()
+}
+
+/// Bytes covered by the house signature on an invite revoke request.
+pub fn invite_revoke_payload(signing_pubkey: &str, code: &str) -> Vec<u8> {
+    format!("cordia-invite-revoke-v1\n{}\n{}", signing_pubkey, code).into_bytes()
+}
+
+/// Bytes covered by the house signature when the owner authorizes a member key directly
+/// (sealed invites, which never go through invite redemption).
+pub fn member_authorize_payload(signing_pubkey: &str, member_pubkey: &str) -> Vec<u8> {
+    format!("cordia-member-add-v1\n{}\n{}", signing_pubkey, member_pubkey).into_bytes()
+}
+
+/// Check an owner's direct authorization of a member key (well-formed key, house signature).
+pub fn verify_member_authorization(signing_pubkey: &str, member_pubkey: &str, signature_b64: &str) -> Result<(), AuthError> {
+    decode_member_key(member_pubkey)?;
+    verify_house_signature(signing_pubkey, &member_authorize_payload(signing_pubkey, member_pubkey), signature_b64)
+}
+
+/// Bytes covered by the house signature on a key rotation.
+/// Sealed keys are listed in user_id order, one `user_id:blob` per line.
+pub fn key_rotation_payload(
+    signing_pubkey: &str,
+    key_epoch: u32,
+    sealed_keys: &BTreeMap<String, String>,
+    removed_user_ids: &[String],
+) -> Vec<u8> {
+    let sealed = sealed_keys
+        .iter()
+        .map(|(user_id, blob)| format!("{}:{}", user_id, blob))
+        .collect::<Vec<_>>()
+        .join("\n");
+    format!(
+        "cordia-rekey-v1\n{}\n{}\n{}\n{}",
+        signing_pubkey,
+        key_epoch,
+        removed_user_ids.join(","),
+        sealed
+    )
+    .into_bytes()
+}
+
+/// Verify a signature made with the house signing key itself (owner-only operations).
+pub fn verify_house_signature(signing_pubkey: &str, payload: &[u8], signature_b64: &str) -> Result<(), AuthError> {
+    let key = decode_house_key(signing_pubkey)?;
+    verify_signature(&key, payload, signature_b64)
+}
+
+/// Check a hint posted for `signing_pubkey`.
+/// Accepted signers are the house key itself, or a member key in `member_signers`.
+pub fn verify_server_hint(
+    signing_pubkey: &str,
+    hint: &EncryptedServerHint,
+    member_signers: &HashSet<String>,
+) -> Result<(), AuthError> {
+    if hint.signing_pubkey != signing_pubkey {
+        return Err(AuthError::PubkeyMismatch);
+    }
+
+    let key = match hint.signer_pubkey.as_deref() {
+        None => decode_house_key(signing_pubkey)?,
+        Some(member) => {
+            if !member_signers.contains(member) {
+                return Err(AuthError::UnauthorizedSigner);
+            }
+            decode_member_key(member)?
+        }
+    };
+
+    let payload = hint_signing_payload(
+        &hint.signing_pubkey,
+        &hint.encrypted_state,
+        hint.key_epoch,
+        hint.revision,
+        &hint.last_updated,
+    );
+    verify_signature(&key, &payload, &hint.signature)
+}
+
+/// Bytes a member signs to ack a house's events up to a cursor (a seq, or an event_id from
+/// older clients).
+pub fn event_ack_payload(signing_pubkey: &str, user_id: &str, last_seq: Option<u64>, last_event_id: &str) -> Vec<u8> {
+    format!(
+        "cordia-ack-v1\n{}\n{}\n{}\n{}",
+        signing_pubkey,
+        user_id,
+        last_seq.map(|seq| seq.to_string()).unwrap_or_default(),
+        last_event_id
+    )
+    .into_bytes()
+}
+
+/// Check an ack: it must be signed by a member key in `member_signers`, and made for the
+/// user_id of that key, so only current members move the house's ack point.
+pub fn verify_event_ack(signing_pubkey: &str, ack: &AckRequest, member_signers: &HashSet<String>) -> Result<(), AuthError> {
+    if !member_signers.contains(&ack.member_pubkey) {
+        return Err(AuthError::UnauthorizedSigner);
+    }
+    let key = decode_member_key(&ack.member_pubkey)?;
+    if user_id_for_key(&key) != ack.user_id {
+        return Err(AuthError::UserIdMismatch);
+    }
+    let payload = event_ack_payload(signing_pubkey, &ack.user_id, ack.last_seq, &ack.last_event_id);
+    verify_signature(&key, &payload, &ack.signature)
+}
+
+/// Bytes the client signs to answer a WebSocket auth challenge.
+pub fn auth_challenge_payload(nonce: &str) -> Vec<u8> {
+    format!("cordia-auth-v1\n{}", nonce).into_bytes()
+}
+
+/// Client user_ids are the hex of the first 16 bytes of sha256(identity pubkey).
+pub fn user_id_for_key(key: &VerifyingKey) -> String {
+    let hash = Sha256::digest(key.as_bytes());
+    hex::encode(&hash[..16])
+}
+
+/// Check an AuthResponse against the nonce issued to the connection.
+pub fn verify_auth_response(nonce: &str, user_id: &str, public_key: &str, signature: &str) -> Result<(), AuthError> {
+    let key = decode_member_key(public_key)?;
+    if user_id_for_key(&key) != user_id {
+        return Err(AuthError::UserIdMismatch);
+    }
+    verify_signature(&key, &auth_challenge_payload(nonce), signature)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use ed25519_dalek::{Signer, SigningKey};
+
+    fn identity(seed: u8) -> (SigningKey, String, String) {
+        let key = SigningKey::from_bytes(&[seed; 32]);
+        let public_key = hex::encode(key.verifying_key().to_bytes());
+        let user_id = user_id_for_key(&key.verifying_key());
+        (key, public_key, user_id)
+    }
+
+    fn sign(key: &SigningKey, payload: &[u8]) -> String {
+        BASE64.encode(key.sign(payload).to_bytes())
+    }
+
+    #[test]
+    fn auth_response_signed_by_the_identity_key_verifies() {
+        let (key, public_key, user_id) = identity(1);
+        let signature = sign(&key, &auth_challenge_payload("nonce"));
+        assert_eq!(verify_auth_response("nonce", &user_id, &public_key, &signature), Ok(()));
+    }
+
+    #[test]
+    fn auth_response_rejects_bad_signatures() {
+        let (key, public_key, user_id) = identity(1);
+        let (other, _, _) = identity(2);
+
+        // Another nonce, another key, and garbage all fail
+        let stale = sign(&key, &auth_challenge_payload("old-nonce"));
+        assert_eq!(verify_auth_response("nonce", &user_id, &public_key, &stale), Err(AuthError::BadSignature));
+        let forged = sign(&other, &auth_challenge_payload("nonce"));
+        assert_eq!(verify_auth_response("nonce", &user_id, &public_key, &forged), Err(AuthError::BadSignature));
+        assert_eq!(verify_auth_response("nonce", &user_id, &public_key, "not base64"), Err(AuthError::MalformedSignature));
+        assert_eq!(verify_auth_response("nonce", &user_id, "abcd", &forged), Err(AuthError::MalformedKey));
+    }
+
+    #[test]
+    fn hint_signature_covers_revision_and_key_epoch() {
+        let house = SigningKey::from_bytes(&[3; 32]);
+        let signing_pubkey = BASE64.encode(house.verifying_key().to_bytes());
+        let mut hint = EncryptedServerHint {
+            signing_pubkey: signing_pubkey.clone(),
+            encrypted_state: "state".to_string(),
+            signature: String::new(),
+            signer_pubkey: None,
+            key_epoch: 1,
+            revision: 4,
+            last_updated: Utc::now(),
+        };
+        hint.signature = sign(&house, &hint_signing_payload(&signing_pubkey, "state", 1, 4, &hint.last_updated));
+        assert_eq!(verify_server_hint(&signing_pubkey, &hint, &HashSet::new()), Ok(()));
+
+        let mut replayed = hint.clone();
+        replayed.revision = 9;
+        assert_eq!(verify_server_hint(&signing_pubkey, &replayed, &HashSet::new()), Err(AuthError::BadSignature));
+        let mut relabeled = hint;
+        relabeled.key_epoch = 2;
+        assert_eq!(verify_server_hint(&signing_pubkey, &relabeled, &HashSet::new()), Err(AuthError::BadSignature));
+    }
+
+    #[test]
+    fn acks_must_be_signed_by_a_member_for_their_own_user_id() {
+        let (key, member_pubkey, user_id) = identity(4);
+        let (_, _, other_user_id) = identity(5);
+        let signers: HashSet<String> = [member_pubkey.clone()].into();
+        let signed = |user_id: &str, last_seq: u64| AckRequest {
+            user_id: user_id.to_string(),
+            last_event_id: String::new(),
+            last_seq: Some(last_seq),
+            member_pubkey: member_pubkey.clone(),
+            signature: sign(&key, &event_ack_payload("house", user_id, Some(last_seq), "")),
+        };
+
+        assert_eq!(verify_event_ack("house", &signed(&user_id, 3), &signers), Ok(()));
+        assert_eq!(verify_event_ack("house", &signed(&user_id, 3), &HashSet::new()), Err(AuthError::UnauthorizedSigner));
+        assert_eq!(verify_event_ack("other-house", &signed(&user_id, 3), &signers), Err(AuthError::BadSignature));
+        assert_eq!(verify_event_ack("house", &signed(&other_user_id, 3), &signers), Err(AuthError::UserIdMismatch));
+        let mut moved = signed(&user_id, 3);
+        moved.last_seq = Some(9);
+        assert_eq!(verify_event_ack("house", &moved, &signers), Err(AuthError::BadSignature));
+    }
+
+    #[test]
+    fn auth_response_user_id_must_match_the_key() {
+        let (key, public_key, _) = identity(1);
+        let (_, _, other_user_id) = identity(2);
+        let signature = sign(&key, &auth_challenge_payload("nonce"));
+        assert_eq!(
+            verify_auth_response("nonce", &other_user_id, &public_key, &signature),
+            Err(AuthError::UserIdMismatch)
+        );
+    }
+}
@@ -0,0 +1,100 @@
+//! Cross-instance fan-out over Redis pub/sub.
+//!
+//! Every beacon delivers to its own sockets directly and hands the same message to the other
+//! instances:
+//! - house-wide and voice-room messages go to one broadcast channel that every instance reads;
+//!   each instance then delivers to its local subscribers / room members
+//! - peer-directed messages (offers, answers, ICE candidates) go to the channel of the instance
+//!   the peer is connected to, found via `route:peer:{peer_id}` or the voice room hash
+//! - a peer_id belongs to the user that claimed it (`{user_id}@{instance_id}`): another user's
+//!   Register for it is refused, and an instance only deletes routes and voice entries it holds
+//!
+//! Without the `redis-backend` feature (or without a Redis URL) delivery stays in-process.
+
+use serde::{Deserialize, Serialize};
+
+use crate::{PeerId, ServerId, SigningPubkey};
+
+/// Channel every instance subscribes to
+pub const BROADCAST_CHANNEL: &str = "beacon:broadcast";
+
+/// Channel for messages addressed to peers on one instance
+pub fn instance_channel(instance_id: &str) -> String {
+    format!("beacon:instance:{}", instance_id)
+}
+
+/// A message for sockets on other instances. `json` is the serialized SignalingMessage.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(tag = "kind", rename_all = "snake_case")]
+pub enum ClusterEnvelope {
+    /// Every peer subscribed to the house
+    House {
+        signing_pubkey: SigningPubkey,
+        /// Set for state updates where only the latest value per key matters
+        coalesce_key: Option<String>,
+        json: String,
+    },
+    /// Every member of a voice room, minus `exclude_peer`
+    VoiceRoom {
+        server_id: ServerId,
+        chat_id: String,
+        exclude_peer: Option<PeerId>,
+        json: String,
+    },
+    /// One peer
+    Peer {
+        peer_id: PeerId,
+        json: String,
+    },
+}
+
+/// What goes over the wire: the envelope plus the publishing instance, so it can skip its own
+#[derive(Debug, Serialize, Deserialize)]
+pub struct ClusterFrame {
+    pub origin: String,
+    #[serde(flatten)]
+    pub envelope: ClusterEnvelope,
+}
+
+/// Receive frames from the broadcast channel and this instance's channel and deliver them
+/// locally. Reconnects (with a short pause) whenever the pub/sub connection drops.
+#[cfg(feature = "redis-backend")]
+pub async fn run_subscriber(client: redis::Client, state: std::sync::Arc<crate::state::AppState>) {
+    use futures_util::StreamExt;
+    use log::{info, warn};
+
+    let own_channel = instance_channel(&state.instance_id);
+    loop {
+        match client.get_async_pubsub().await {
+            Ok(mut pubsub) => {
+                let subscribed = async {
+                    pubsub.subscribe(BROADCAST_CHANNEL).await?;
+                    pubsub.subscribe(&own_channel).await
+                }
+                .await;
+                match subscribed {
+                    Ok(()) => {
+                        info!("Cluster fan-out subscribed as instance {}", state.instance_id);
+                        let mut messages = pubsub.on_message();
+                        while let Some(msg) = messages.next().await {
+                            let Ok(payload) = msg.get_payload::<String>() else {
+                                continue;
+                            };
+                            match serde_json::from_str::<ClusterFrame>(&payload) {
+                                Ok(frame) if frame.origin != state.instance_id => {
+                                    state.deliver_cluster_envelope(frame.envelope).await;
+                                }
+                                Ok(_) => {}
+                                Err(e) => warn!("Ignoring malformed cluster frame: {}", e),
+                            }
+                        }
+                        warn!("Cluster pub/sub connection closed; reconnecting");
+                    }
+                    Err(e) => warn!("Cluster pub/sub subscribe failed: {}", e),
+                }
+            }
+            Err(e) => warn!("Cluster pub/sub connect failed: {}", e),
+        }
+        tokio::time::sleep(tokio::time::Duration::from_secs(2)).await;
+    }
+}
@@ -1,92 +1,17 @@
 #[cfg(feature = "postgres")]
+use std::collections::{HashMap, HashSet};
+#[cfg(feature = "postgres")]
 use chrono::{DateTime, Duration, Utc};
 #[cfg(feature = "postgres")]
 use sqlx::{PgPool, Row};
 #[cfg(feature = "postgres")]
-use crate::{ProfileRecord, ProfileSnapshotRecord, EncryptedServerHint, InviteTokenCreateRequest, InviteTokenRecord, ServerEvent};
-
+use crate::{auth, ProfileRecord, ProfileSnapshotRecord, EncryptedServerHint, InviteTokenCreateRequest, InviteTokenRecord, InviteError, HintError, KeyRotationRequest, KeyRotationError, SealedKeyRecord, ServerEvent, EventPage, EventQueryError, EventQueueStats};
 #[cfg(feature = "postgres")]
-pub async fn init_db(pool: &PgPool) -> Result<(), String> {
-    sqlx::query(
-        r#"
-        CREATE TABLE IF NOT EXISTS profiles (
-          user_id TEXT PRIMARY KEY,
-          display_name TEXT NOT NULL,
-          real_name TEXT,
-          show_real_name BOOLEAN NOT NULL,
-          rev BIGINT NOT NULL,
-          updated_at TIMESTAMPTZ NOT NULL
-        );
-        "#,
-    )
-    .execute(pool)
-    .await
-    .map_err(|e| format!("init_db profiles: {}", e))?;
-
-    sqlx::query(
-        r#"
-        CREATE TABLE IF NOT EXISTS server_hints (
-          signing_pubkey TEXT PRIMARY KEY,
-          encrypted_state TEXT NOT NULL,
-          signature TEXT NOT NULL,
-          last_updated TIMESTAMPTZ NOT NULL
-        );
-        "#,
-    )
-    .execute(pool)
-    .await
-    .map_err(|e| format!("init_db server_hints: {}", e))?;
+use crate::state::events::{event_cursor_expired, min_acked_seq};
 
-    sqlx::query(
-        r#"
-        CREATE TABLE IF NOT EXISTS invite_tokens (
-          code TEXT PRIMARY KEY,
-          signing_pubkey TEXT NOT NULL,
-          encrypted_payload TEXT NOT NULL,
-          signature TEXT NOT NULL,
-          created_at TIMESTAMPTZ NOT NULL,
-          expires_at TIMESTAMPTZ NOT NULL,
-          max_uses INTEGER NOT NULL,
-          remaining_uses INTEGER NOT NULL
-        );
-        "#,
-    )
-    .execute(pool)
-    .await
-    .map_err(|e| format!("init_db invite_tokens: {}", e))?;
-
-    sqlx::query(
-        r#"
-        CREATE TABLE IF NOT EXISTS server_events (
-          event_id TEXT PRIMARY KEY,
-          signing_pubkey TEXT NOT NULL,
-          event_type TEXT NOT NULL,
-          encrypted_payload TEXT NOT NULL,
-          signature TEXT NOT NULL,
-          timestamp TIMESTAMPTZ NOT NULL
-        );
-        "#,
-    )
-    .execute(pool)
-    .await
-    .map_err(|e| format!("init_db server_events: {}", e))?;
-
-    sqlx::query(
-        r#"
-        CREATE TABLE IF NOT EXISTS member_acks (
-          signing_pubkey TEXT NOT NULL,
-          user_id TEXT NOT NULL,
-          last_event_id TEXT NOT NULL,
-          updated_at TIMESTAMPTZ NOT NULL,
-          PRIMARY KEY (signing_pubkey, user_id)
-        );
-        "#,
-    )
-    .execute(pool)
-    .await
-    .map_err(|e| format!("init_db member_acks: {}", e))?;
-    Ok(())
-}
+/// Label for backend metrics
+#[cfg(feature = "postgres")]
+pub const BACKEND: &str = "postgres";
 
 #[cfg(feature = "postgres")]
 pub async fn upsert_profile_db(pool: &PgPool, user_id: &str, rec: &ProfileRecord) -> Result<(), String> {
@@ -149,24 +74,40 @@ pub async fn load_profiles_db(pool: &PgPool, user_ids: &[String]) -> Result<Vec<
 }
 
 #[cfg(feature = "postgres")]
-pub async fn upsert_server_hint_db(pool: &PgPool, hint: &EncryptedServerHint) -> Result<(), String> {
-    sqlx::query(
+pub async fn upsert_server_hint_db(pool: &PgPool, hint: &EncryptedServerHint) -> Result<(), HintError> {
+    // Compare-and-swap: only replace the stored hint if this write is based on it
+    let result = sqlx::query(
         r#"
-        INSERT INTO server_hints (signing_pubkey, encrypted_state, signature, last_updated)
-        VALUES ($1, $2, $3, $4)
+        INSERT INTO server_hints (signing_pubkey, encrypted_state, signature, signer_pubkey, key_epoch, revision, last_updated)
+        VALUES ($1, $2, $3, $4, $5, $6, $7)
         ON CONFLICT (signing_pubkey) DO UPDATE
         SET encrypted_state = EXCLUDED.encrypted_state,
             signature = EXCLUDED.signature,
-            last_updated = EXCLUDED.last_updated;
+            signer_pubkey = EXCLUDED.signer_pubkey,
+            key_epoch = EXCLUDED.key_epoch,
+            revision = EXCLUDED.revision,
+            last_updated = EXCLUDED.last_updated
+        WHERE server_hints.revision + 1 = EXCLUDED.revision;
         "#,
     )
     .bind(&hint.signing_pubkey)
     .bind(&hint.encrypted_state)
     .bind(&hint.signature)
+    .bind(&hint.signer_pubkey)
+    .bind(hint.key_epoch as i32)
+    .bind(hint.revision as i64)
     .bind(hint.last_updated)
     .execute(pool)
     .await
-    .map_err(|e| format!("upsert_server_hint_db: {}", e))?;
+    .map_err(|e| HintError::Storage(format!("upsert_server_hint_db: {}", e)))?;
+
+    if result.rows_affected() == 0 {
+        let current = get_server_hint_db(pool, &hint.signing_pubkey)
+            .await
+            .map_err(HintError::Storage)?
+            .ok_or_else(|| HintError::Storage("upsert_server_hint_db: hint vanished".to_string()))?;
+        return Err(HintError::Conflict(Box::new(current)));
+    }
     Ok(())
 }
 
@@ -174,7 +115,7 @@ pub async fn upsert_server_hint_db(pool: &PgPool, hint: &EncryptedServerHint) ->
 pub async fn get_server_hint_db(pool: &PgPool, signing_pubkey: &str) -> Result<Option<EncryptedServerHint>, String> {
     let row = sqlx::query(
         r#"
-        SELECT signing_pubkey, encrypted_state, signature, last_updated
+        SELECT signing_pubkey, encrypted_state, signature, signer_pubkey, key_epoch, revision, last_updated
         FROM server_hints
         WHERE signing_pubkey = $1
         "#,
@@ -188,10 +129,132 @@ pub async fn get_server_hint_db(pool: &PgPool, signing_pubkey: &str) -> Result<O
         signing_pubkey: r.try_get("signing_pubkey").unwrap_or_default(),
         encrypted_state: r.try_get("encrypted_state").unwrap_or_default(),
         signature: r.try_get("signature").unwrap_or_default(),
+        signer_pubkey: r.try_get::<Option<String>, _>("signer_pubkey").unwrap_or(None),
+        key_epoch: r.try_get::<i32, _>("key_epoch").unwrap_or(0) as u32,
+        revision: r.try_get::<i64, _>("revision").unwrap_or(0) as u64,
         last_updated: r.try_get("last_updated").unwrap_or_else(|_| Utc::now()),
     }))
 }
 
+#[cfg(feature = "postgres")]
+pub async fn authorize_member_signer_db(pool: &PgPool, signing_pubkey: &str, signer_pubkey: &str) -> Result<(), String> {
+    sqlx::query(
+        r#"
+        INSERT INTO house_signers (signing_pubkey, signer_pubkey, authorized_at)
+        VALUES ($1, $2, NOW())
+        ON CONFLICT (signing_pubkey, signer_pubkey) DO NOTHING;
+        "#,
+    )
+    .bind(signing_pubkey)
+    .bind(signer_pubkey)
+    .execute(pool)
+    .await
+    .map_err(|e| format!("authorize_member_signer_db: {}", e))?;
+    Ok(())
+}
+
+#[cfg(feature = "postgres")]
+pub async fn load_house_signers_db(pool: &PgPool, signing_pubkey: &str) -> Result<HashSet<String>, String> {
+    let rows = sqlx::query("SELECT signer_pubkey FROM house_signers WHERE signing_pubkey = $1")
+        .bind(signing_pubkey)
+        .fetch_all(pool)
+        .await
+        .map_err(|e| format!("load_house_signers_db: {}", e))?;
+
+    Ok(rows
+        .into_iter()
+        .filter_map(|r| r.try_get::<String, _>("signer_pubkey").ok())
+        .collect())
+}
+
+#[cfg(feature = "postgres")]
+pub async fn put_key_rotation_db(pool: &PgPool, signing_pubkey: &str, req: &KeyRotationRequest) -> Result<(), KeyRotationError> {
+    let payload = auth::key_rotation_payload(signing_pubkey, req.key_epoch, &req.sealed_keys, &req.removed_user_ids);
+    auth::verify_house_signature(signing_pubkey, &payload, &req.signature)?;
+
+    let sealed_json = serde_json::to_string(&req.sealed_keys)
+        .map_err(|e| KeyRotationError::Storage(format!("put_key_rotation_db: {}", e)))?;
+
+    // Only move forward: an older or replayed rotation must not roll members back to a leaked key
+    let result = sqlx::query(
+        r#"
+        INSERT INTO house_key_rotations (signing_pubkey, key_epoch, sealed_keys, created_at)
+        VALUES ($1, $2, $3, NOW())
+        ON CONFLICT (signing_pubkey) DO UPDATE
+        SET key_epoch = EXCLUDED.key_epoch,
+            sealed_keys = EXCLUDED.sealed_keys,
+            created_at = EXCLUDED.created_at
+        WHERE house_key_rotations.key_epoch < EXCLUDED.key_epoch;
+        "#,
+    )
+    .bind(signing_pubkey)
+    .bind(req.key_epoch as i32)
+    .bind(&sealed_json)
+    .execute(pool)
+    .await
+    .map_err(|e| KeyRotationError::Storage(format!("put_key_rotation_db: {}", e)))?;
+
+    if result.rows_affected() == 0 {
+        let current: i32 = sqlx::query_scalar("SELECT key_epoch FROM house_key_rotations WHERE signing_pubkey = $1")
+            .bind(signing_pubkey)
+            .fetch_one(pool)
+            .await
+            .map_err(|e| KeyRotationError::Storage(format!("put_key_rotation_db: {}", e)))?;
+        return Err(KeyRotationError::StaleEpoch { current: current as u32 });
+    }
+
+    if !req.removed_user_ids.is_empty() {
+        let signers = load_house_signers_db(pool, signing_pubkey)
+            .await
+            .map_err(KeyRotationError::Storage)?;
+        for signer in signers {
+            let removed = match auth::decode_member_key(&signer) {
+                Ok(key) => req.removed_user_ids.contains(&auth::user_id_for_key(&key)),
+                Err(_) => true,
+            };
+            if removed {
+                sqlx::query("DELETE FROM house_signers WHERE signing_pubkey = $1 AND signer_pubkey = $2")
+                    .bind(signing_pubkey)
+                    .bind(&signer)
+                    .execute(pool)
+                    .await
+                    .map_err(|e| KeyRotationError::Storage(format!("put_key_rotation_db: {}", e)))?;
+            }
+        }
+    }
+
+    Ok(())
+}
+
+#[cfg(feature = "postgres")]
+pub async fn get_sealed_key_db(pool: &PgPool, signing_pubkey: &str, user_id: &str) -> Result<Option<SealedKeyRecord>, String> {
+    let row = sqlx::query(
+        r#"
+        SELECT key_epoch, sealed_keys, created_at
+        FROM house_key_rotations
+        WHERE signing_pubkey = $1
+        "#,
+    )
+    .bind(signing_pubkey)
+    .fetch_optional(pool)
+    .await
+    .map_err(|e| format!("get_sealed_key_db: {}", e))?;
+
+    let Some(r) = row else {
+        return Ok(None);
+    };
+    let sealed_json: String = r.try_get("sealed_keys").unwrap_or_default();
+    let sealed_keys: std::collections::BTreeMap<String, String> = serde_json::from_str(&sealed_json)
+        .map_err(|e| format!("get_sealed_key_db: {}", e))?;
+
+    Ok(sealed_keys.get(user_id).map(|sealed_key| SealedKeyRecord {
+        signing_pubkey: signing_pubkey.to_string(),
+        key_epoch: r.try_get::<i32, _>("key_epoch").unwrap_or(0) as u32,
+        sealed_key: sealed_key.clone(),
+        created_at: r.try_get("created_at").unwrap_or_else(|_| Utc::now()),
+    }))
+}
+
 #[cfg(feature = "postgres")]
 pub async fn gc_expired_invites_db(pool: &PgPool) -> Result<(), String> {
     sqlx::query("DELETE FROM invite_tokens WHERE expires_at <= NOW()")
@@ -202,17 +265,19 @@ pub async fn gc_expired_invites_db(pool: &PgPool) -> Result<(), String> {
 }
 
 #[cfg(feature = "postgres")]
-pub async fn upsert_invite_db(pool: &PgPool, signing_pubkey: &str, req: InviteTokenCreateRequest) -> Result<InviteTokenRecord, String> {
+pub async fn upsert_invite_db(pool: &PgPool, signing_pubkey: &str, req: InviteTokenCreateRequest, ttl_days: i64) -> Result<InviteTokenRecord, InviteError> {
     let code = req.code.trim().to_string();
     if code.len() < 6 || code.len() > 64 {
-        return Err("Invalid invite code length".to_string());
+        return Err(InviteError::InvalidCode);
     }
+    let payload = auth::invite_create_payload(signing_pubkey, &code, req.max_uses, &req.encrypted_payload);
+    auth::verify_house_signature(signing_pubkey, &payload, &req.signature)?;
     let now = Utc::now();
-    let expires_at = now + Duration::days(30);
+    let expires_at = now + Duration::days(ttl_days);
     let max_uses = req.max_uses;
     let remaining_uses = req.max_uses;
 
-    sqlx::query(
+    let res = sqlx::query(
         r#"
         INSERT INTO invite_tokens (code, signing_pubkey, encrypted_payload, signature, created_at, expires_at, max_uses, remaining_uses)
         VALUES ($1, $2, $3, $4, $5, $6, $7, $8)
@@ -223,7 +288,8 @@ pub async fn upsert_invite_db(pool: &PgPool, signing_pubkey: &str, req: InviteTo
             created_at = EXCLUDED.created_at,
             expires_at = EXCLUDED.expires_at,
             max_uses = EXCLUDED.max_uses,
-            remaining_uses = EXCLUDED.remaining_uses;
+            remaining_uses = EXCLUDED.remaining_uses
+        WHERE invite_tokens.signing_pubkey = EXCLUDED.signing_pubkey OR invite_tokens.expires_at <= NOW();
         "#,
     )
     .bind(&code)
@@ -236,7 +302,12 @@ pub async fn upsert_invite_db(pool: &PgPool, signing_pubkey: &str, req: InviteTo
     .bind(remaining_uses as i32)
     .execute(pool)
     .await
-    .map_err(|e| format!("upsert_invite_db: {}", e))?;
+    .map_err(|e| InviteError::Storage(format!("upsert_invite_db: {}", e)))?;
+
+    // The conditional update touches nothing when another house holds the code (expired codes are free)
+    if res.rows_affected() == 0 {
+        return Err(InviteError::CodeTaken);
+    }
 
     Ok(InviteTokenRecord {
         code,
@@ -250,6 +321,30 @@ pub async fn upsert_invite_db(pool: &PgPool, signing_pubkey: &str, req: InviteTo
     })
 }
 
+/// Copy an invite created while the DB was unreachable; an existing row for the code wins.
+#[cfg(feature = "postgres")]
+pub async fn restore_invite_db(pool: &PgPool, rec: &InviteTokenRecord) -> Result<(), String> {
+    sqlx::query(
+        r#"
+        INSERT INTO invite_tokens (code, signing_pubkey, encrypted_payload, signature, created_at, expires_at, max_uses, remaining_uses)
+        VALUES ($1, $2, $3, $4, $5, $6, $7, $8)
+        ON CONFLICT (code) DO NOTHING;
+        "#,
+    )
+    .bind(&rec.code)
+    .bind(&rec.signing_pubkey)
+    .bind(&rec.encrypted_payload)
+    .bind(&rec.signature)
+    .bind(rec.created_at)
+    .bind(rec.expires_at)
+    .bind(rec.max_uses as i32)
+    .bind(rec.remaining_uses as i32)
+    .execute(pool)
+    .await
+    .map_err(|e| format!("restore_invite_db: {}", e))?;
+    Ok(())
+}
+
 #[cfg(feature = "postgres")]
 pub async fn get_invite_db(pool: &PgPool, code: &str) -> Result<Option<InviteTokenRecord>, String> {
     let row = sqlx::query(
@@ -306,21 +401,71 @@ pub async fn redeem_invite_db(pool: &PgPool, code: &str) -> Result<Option<Invite
 }
 
 #[cfg(feature = "postgres")]
-pub async fn revoke_invite_db(pool: &PgPool, code: &str) -> Result<bool, String> {
-    let res = sqlx::query("DELETE FROM invite_tokens WHERE code = $1")
+pub async fn revoke_invite_db(pool: &PgPool, code: &str, signature: &str) -> Result<(), InviteError> {
+    let row = sqlx::query("SELECT signing_pubkey FROM invite_tokens WHERE code = $1")
+        .bind(code)
+        .fetch_optional(pool)
+        .await
+        .map_err(|e| InviteError::Storage(format!("revoke_invite_db: {}", e)))?;
+    let Some(row) = row else {
+        return Err(InviteError::NotFound);
+    };
+    let signing_pubkey: String = row
+        .try_get("signing_pubkey")
+        .map_err(|e| InviteError::Storage(format!("revoke_invite_db signing_pubkey: {}", e)))?;
+
+    let payload = auth::invite_revoke_payload(&signing_pubkey, code);
+    auth::verify_house_signature(&signing_pubkey, &payload, signature)?;
+
+    let res = sqlx::query("DELETE FROM invite_tokens WHERE code = $1 AND signing_pubkey = $2")
         .bind(code)
+        .bind(&signing_pubkey)
         .execute(pool)
         .await
-        .map_err(|e| format!("revoke_invite_db: {}", e))?;
-    Ok(res.rows_affected() > 0)
+        .map_err(|e| InviteError::Storage(format!("revoke_invite_db: {}", e)))?;
+    if res.rows_affected() == 0 {
+        return Err(InviteError::NotFound);
+    }
+    Ok(())
 }
 
 #[cfg(feature = "postgres")]
-pub async fn insert_event_db(pool: &PgPool, event: &ServerEvent) -> Result<(), String> {
-    sqlx::query(
+pub async fn insert_event_db(pool: &PgPool, event: &ServerEvent) -> Result<ServerEvent, String> {
+    insert_event_at_db(pool, event, 0).await
+}
+
+/// Copy an event accepted in memory while the DB was unreachable. It keeps its seq unless
+/// another instance has used that seq in the meantime, in which case it gets the next free one.
+#[cfg(feature = "postgres")]
+pub async fn restore_event_db(pool: &PgPool, event: &ServerEvent) -> Result<ServerEvent, String> {
+    insert_event_at_db(pool, event, event.seq).await
+}
+
+/// Insert with seq `max(preferred_seq, last_seq + 1)`
+#[cfg(feature = "postgres")]
+async fn insert_event_at_db(pool: &PgPool, event: &ServerEvent, preferred_seq: u64) -> Result<ServerEvent, String> {
+    let mut tx = pool.begin().await.map_err(|e| format!("insert_event_db: {}", e))?;
+
+    // The row lock on house_event_seqs serializes concurrent posts to the same house
+    let seq: i64 = sqlx::query_scalar(
         r#"
-        INSERT INTO server_events (event_id, signing_pubkey, event_type, encrypted_payload, signature, timestamp)
-        VALUES ($1, $2, $3, $4, $5, $6)
+        INSERT INTO house_event_seqs (signing_pubkey, last_seq)
+        VALUES ($1, GREATEST($2, 1))
+        ON CONFLICT (signing_pubkey) DO UPDATE
+        SET last_seq = GREATEST(house_event_seqs.last_seq + 1, $2)
+        RETURNING last_seq;
+        "#,
+    )
+    .bind(&event.signing_pubkey)
+    .bind(preferred_seq as i64)
+    .fetch_one(&mut *tx)
+    .await
+    .map_err(|e| format!("insert_event_db seq: {}", e))?;
+
+    let inserted = sqlx::query(
+        r#"
+        INSERT INTO server_events (event_id, signing_pubkey, event_type, encrypted_payload, signature, timestamp, seq)
+        VALUES ($1, $2, $3, $4, $5, $6, $7)
         ON CONFLICT (event_id) DO NOTHING;
         "#,
     )
@@ -330,94 +475,136 @@ pub async fn insert_event_db(pool: &PgPool, event: &ServerEvent) -> Result<(), S
     .bind(&event.encrypted_payload)
     .bind(&event.signature)
     .bind(event.timestamp)
-    .execute(pool)
+    .bind(seq)
+    .execute(&mut *tx)
     .await
     .map_err(|e| format!("insert_event_db: {}", e))?;
-    Ok(())
+
+    if inserted.rows_affected() == 0 {
+        // A retried post: give the seq back and return the event as first stored
+        tx.rollback().await.map_err(|e| format!("insert_event_db: {}", e))?;
+        let row = sqlx::query("SELECT timestamp, seq FROM server_events WHERE signing_pubkey = $1 AND event_id = $2")
+            .bind(&event.signing_pubkey)
+            .bind(&event.event_id)
+            .fetch_optional(pool)
+            .await
+            .map_err(|e| format!("insert_event_db existing: {}", e))?
+            .ok_or_else(|| format!("insert_event_db: event_id {} belongs to another house", event.event_id))?;
+        let mut stored = event.clone();
+        stored.timestamp = row.try_get("timestamp").unwrap_or(event.timestamp);
+        stored.seq = row.try_get::<i64, _>("seq").unwrap_or(0) as u64;
+        return Ok(stored);
+    }
+
+    tx.commit().await.map_err(|e| format!("insert_event_db: {}", e))?;
+
+    let mut stored = event.clone();
+    stored.seq = seq as u64;
+    Ok(stored)
 }
 
+/// Latest seq of every house, to seed the in-memory counters used during an outage
 #[cfg(feature = "postgres")]
-async fn get_event_timestamp_db(pool: &PgPool, signing_pubkey: &str, event_id: &str) -> Result<Option<DateTime<Utc>>, String> {
-    let row = sqlx::query(
-        r#"
-        SELECT timestamp
-        FROM server_events
-        WHERE signing_pubkey = $1 AND event_id = $2
-        "#,
-    )
-    .bind(signing_pubkey)
-    .bind(event_id)
-    .fetch_optional(pool)
-    .await
-    .map_err(|e| format!("get_event_timestamp_db: {}", e))?;
+pub async fn load_event_seqs_db(pool: &PgPool) -> Result<Vec<(String, u64)>, String> {
+    let rows = sqlx::query("SELECT signing_pubkey, last_seq FROM house_event_seqs")
+        .fetch_all(pool)
+        .await
+        .map_err(|e| format!("load_event_seqs_db: {}", e))?;
 
-    Ok(row.and_then(|r| r.try_get("timestamp").ok()))
+    Ok(rows
+        .into_iter()
+        .filter_map(|r| {
+            let spk = r.try_get::<String, _>("signing_pubkey").ok()?;
+            let seq = r.try_get::<i64, _>("last_seq").ok()?;
+            Some((spk, seq as u64))
+        })
+        .collect())
 }
 
+/// Seq of a retained event (for clients that still send an event_id cursor)
 #[cfg(feature = "postgres")]
-pub async fn get_events_db(pool: &PgPool, signing_pubkey: &str, since: Option<&str>) -> Result<Vec<ServerEvent>, String> {
-    let rows = if let Some(since_id) = since {
-        let Some(since_ts) = get_event_timestamp_db(pool, signing_pubkey, since_id).await? else {
-            return Ok(Vec::new());
-        };
-        sqlx::query(
-            r#"
-            SELECT event_id, signing_pubkey, event_type, encrypted_payload, signature, timestamp
-            FROM server_events
-            WHERE signing_pubkey = $1
-              AND (timestamp > $2 OR (timestamp = $2 AND event_id > $3))
-            ORDER BY timestamp ASC, event_id ASC
-            "#,
-        )
+pub async fn get_event_seq_db(pool: &PgPool, signing_pubkey: &str, event_id: &str) -> Result<Option<u64>, String> {
+    let seq: Option<i64> = sqlx::query_scalar("SELECT seq FROM server_events WHERE signing_pubkey = $1 AND event_id = $2")
         .bind(signing_pubkey)
-        .bind(since_ts)
-        .bind(since_id)
-        .fetch_all(pool)
+        .bind(event_id)
+        .fetch_optional(pool)
         .await
-        .map_err(|e| format!("get_events_db since: {}", e))?
-    } else {
-        sqlx::query(
-            r#"
-            SELECT event_id, signing_pubkey, event_type, encrypted_payload, signature, timestamp
-            FROM server_events
-            WHERE signing_pubkey = $1
-            ORDER BY timestamp ASC, event_id ASC
-            "#,
-        )
+        .map_err(|e| format!("get_event_seq_db: {}", e))?;
+    Ok(seq.map(|s| s as u64))
+}
+
+/// Up to `limit` events with seq > `after_seq`, oldest first
+#[cfg(feature = "postgres")]
+pub async fn get_events_page_db(pool: &PgPool, signing_pubkey: &str, after_seq: u64, limit: usize) -> Result<EventPage, EventQueryError> {
+    let storage = |e: sqlx::Error| EventQueryError::Storage(format!("get_events_page_db: {}", e));
+
+    let latest_seq: Option<i64> = sqlx::query_scalar("SELECT last_seq FROM house_event_seqs WHERE signing_pubkey = $1")
         .bind(signing_pubkey)
-        .fetch_all(pool)
+        .fetch_optional(pool)
         .await
-        .map_err(|e| format!("get_events_db: {}", e))?
-    };
+        .map_err(storage)?;
+    let latest_seq = latest_seq.unwrap_or(0) as u64;
+    let oldest: Option<i64> = sqlx::query_scalar("SELECT MIN(seq) FROM server_events WHERE signing_pubkey = $1")
+        .bind(signing_pubkey)
+        .fetch_one(pool)
+        .await
+        .map_err(storage)?;
+    if event_cursor_expired(after_seq, oldest.map(|s| s as u64), latest_seq) {
+        return Err(EventQueryError::CursorExpired { latest_seq });
+    }
 
-    let mut out = Vec::with_capacity(rows.len());
-    for row in rows {
-        out.push(ServerEvent {
+    let rows = sqlx::query(
+        r#"
+        SELECT event_id, signing_pubkey, event_type, encrypted_payload, signature, timestamp, seq
+        FROM server_events
+        WHERE signing_pubkey = $1 AND seq > $2
+        ORDER BY seq ASC
+        LIMIT $3
+        "#,
+    )
+    .bind(signing_pubkey)
+    .bind(after_seq as i64)
+    .bind(limit as i64 + 1)
+    .fetch_all(pool)
+    .await
+    .map_err(storage)?;
+
+    let has_more = rows.len() > limit;
+    let events: Vec<ServerEvent> = rows
+        .into_iter()
+        .take(limit)
+        .map(|row| ServerEvent {
             event_id: row.try_get("event_id").unwrap_or_default(),
             signing_pubkey: row.try_get("signing_pubkey").unwrap_or_default(),
             event_type: row.try_get("event_type").unwrap_or_default(),
             encrypted_payload: row.try_get("encrypted_payload").unwrap_or_default(),
             signature: row.try_get("signature").unwrap_or_default(),
             timestamp: row.try_get("timestamp").unwrap_or_else(|_| Utc::now()),
-        });
-    }
-    Ok(out)
+            seq: row.try_get::<i64, _>("seq").unwrap_or(0) as u64,
+        })
+        .collect();
+
+    Ok(EventPage {
+        next_seq: events.last().map(|e| e.seq).unwrap_or(after_seq),
+        events,
+        has_more,
+    })
 }
 
 #[cfg(feature = "postgres")]
-pub async fn ack_events_db(pool: &PgPool, signing_pubkey: &str, user_id: &str, last_event_id: &str) -> Result<(), String> {
+pub async fn ack_events_db(pool: &PgPool, signing_pubkey: &str, user_id: &str, last_seq: u64) -> Result<(), String> {
     sqlx::query(
         r#"
-        INSERT INTO member_acks (signing_pubkey, user_id, last_event_id, updated_at)
-        VALUES ($1, $2, $3, NOW())
+        INSERT INTO member_acks (signing_pubkey, user_id, last_event_id, last_seq, updated_at)
+        VALUES ($1, $2, '', LEAST($3, COALESCE((SELECT last_seq FROM house_event_seqs WHERE signing_pubkey = $1), 0)), NOW())
         ON CONFLICT (signing_pubkey, user_id) DO UPDATE
-        SET last_event_id = EXCLUDED.last_event_id,
+        SET last_seq = GREATEST(member_acks.last_seq, EXCLUDED.last_seq),
             updated_at = NOW();
         "#,
     )
     .bind(signing_pubkey)
     .bind(user_id)
-    .bind(last_event_id)
+    .bind(last_seq as i64)
     .execute(pool)
     .await
     .map_err(|e| format!("ack_events_db: {}", e))?;
@@ -425,11 +612,137 @@ pub async fn ack_events_db(pool: &PgPool, signing_pubkey: &str, user_id: &str, l
 }
 
 #[cfg(feature = "postgres")]
-pub async fn gc_old_events_db(pool: &PgPool, cutoff: DateTime<Utc>) -> Result<(), String> {
+async fn load_member_acks_db(pool: &PgPool, signing_pubkey: &str) -> Result<HashMap<String, u64>, String> {
+    let rows = sqlx::query("SELECT user_id, last_seq FROM member_acks WHERE signing_pubkey = $1")
+        .bind(signing_pubkey)
+        .fetch_all(pool)
+        .await
+        .map_err(|e| format!("load_member_acks_db: {}", e))?;
+
+    Ok(rows
+        .into_iter()
+        .filter_map(|r| {
+            let user_id: String = r.try_get("user_id").ok()?;
+            let seq: i64 = r.try_get("last_seq").ok()?;
+            Some((user_id, seq as u64))
+        })
+        .collect())
+}
+
+/// Highest seq every known member of the house has acked (see `min_acked_seq`)
+#[cfg(feature = "postgres")]
+async fn acked_seq_db(pool: &PgPool, signing_pubkey: &str) -> Result<u64, String> {
+    let signers = load_house_signers_db(pool, signing_pubkey).await?;
+    let acks = load_member_acks_db(pool, signing_pubkey).await?;
+    Ok(min_acked_seq(Some(&signers), &acks))
+}
+
+#[cfg(feature = "postgres")]
+pub async fn event_queue_stats_db(pool: &PgPool, signing_pubkey: &str) -> Result<EventQueueStats, String> {
+    let acked_seq = acked_seq_db(pool, signing_pubkey).await?;
+
+    let latest_seq: Option<i64> = sqlx::query_scalar("SELECT last_seq FROM house_event_seqs WHERE signing_pubkey = $1")
+        .bind(signing_pubkey)
+        .fetch_optional(pool)
+        .await
+        .map_err(|e| format!("event_queue_stats_db: {}", e))?;
+
+    let counts = sqlx::query(
+        r#"
+        SELECT COUNT(*) AS depth, COUNT(*) FILTER (WHERE seq > $2) AS unacked
+        FROM server_events
+        WHERE signing_pubkey = $1
+        "#,
+    )
+    .bind(signing_pubkey)
+    .bind(acked_seq as i64)
+    .fetch_one(pool)
+    .await
+    .map_err(|e| format!("event_queue_stats_db: {}", e))?;
+
+    let oldest = sqlx::query(
+        r#"
+        SELECT seq, timestamp
+        FROM server_events
+        WHERE signing_pubkey = $1 AND seq > $2
+        ORDER BY seq ASC
+        LIMIT 1
+        "#,
+    )
+    .bind(signing_pubkey)
+    .bind(acked_seq as i64)
+    .fetch_optional(pool)
+    .await
+    .map_err(|e| format!("event_queue_stats_db: {}", e))?;
+    let oldest_timestamp: Option<DateTime<Utc>> = oldest.as_ref().and_then(|r| r.try_get("timestamp").ok());
+
+    Ok(EventQueueStats {
+        signing_pubkey: signing_pubkey.to_string(),
+        queue_depth: counts.try_get::<i64, _>("depth").unwrap_or(0) as usize,
+        latest_seq: latest_seq.unwrap_or(0) as u64,
+        acked_seq,
+        unacked: counts.try_get::<i64, _>("unacked").unwrap_or(0) as usize,
+        oldest_unacked_seq: oldest.as_ref().and_then(|r| r.try_get::<i64, _>("seq").ok()).map(|s| s as u64),
+        oldest_unacked_age_secs: oldest_timestamp.map(|ts| (Utc::now() - ts).num_seconds().max(0)),
+    })
+}
+
+/// Retained events and the number of houses they belong to (for metrics)
+#[cfg(feature = "postgres")]
+pub async fn event_queue_totals_db(pool: &PgPool) -> Result<(u64, u64), String> {
+    let row = sqlx::query("SELECT COUNT(*) AS events, COUNT(DISTINCT signing_pubkey) AS houses FROM server_events")
+        .fetch_one(pool)
+        .await
+        .map_err(|e| format!("event_queue_totals_db: {}", e))?;
+    Ok((
+        row.try_get::<i64, _>("events").unwrap_or(0) as u64,
+        row.try_get::<i64, _>("houses").unwrap_or(0) as u64,
+    ))
+}
+
+/// Drop events older than `cutoff` or acked by every known member, then keep at most
+/// `max_unacked` per house.
+#[cfg(feature = "postgres")]
+pub async fn gc_old_events_db(pool: &PgPool, cutoff: DateTime<Utc>, max_unacked: usize) -> Result<(), String> {
     sqlx::query("DELETE FROM server_events WHERE timestamp <= $1")
         .bind(cutoff)
         .execute(pool)
         .await
         .map_err(|e| format!("gc_old_events_db: {}", e))?;
+
+    let houses: Vec<String> = sqlx::query_scalar("SELECT DISTINCT signing_pubkey FROM server_events")
+        .fetch_all(pool)
+        .await
+        .map_err(|e| format!("gc_old_events_db houses: {}", e))?;
+
+    for signing_pubkey in houses {
+        let acked_seq = acked_seq_db(pool, &signing_pubkey).await?;
+        if acked_seq > 0 {
+            sqlx::query("DELETE FROM server_events WHERE signing_pubkey = $1 AND seq <= $2")
+                .bind(&signing_pubkey)
+                .bind(acked_seq as i64)
+                .execute(pool)
+                .await
+                .map_err(|e| format!("gc_old_events_db acked: {}", e))?;
+        }
+
+        sqlx::query(
+            r#"
+            DELETE FROM server_events
+            WHERE signing_pubkey = $1
+              AND seq <= (
+                SELECT seq FROM server_events
+                WHERE signing_pubkey = $1
+                ORDER BY seq DESC
+                OFFSET $2 LIMIT 1
+              );
+            "#,
+        )
+        .bind(&signing_pubkey)
+        .bind(max_unacked as i64)
+        .execute(pool)
+        .await
+        .map_err(|e| format!("gc_old_events_db cap: {}", e))?;
+    }
     Ok(())
 }
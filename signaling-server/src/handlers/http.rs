@@ -3,22 +3,20 @@ use hyper::{Body, Method, Request, Response, StatusCode};
 use log::{info, warn};
 use sysinfo::{System, get_current_pid};
 use crate::{
-    decode_path_segment, EncryptedServerHint, InviteTokenCreateRequest,
-    ServerEvent, AckRequest,
+    auth, decode_path_segment, EncryptedServerHint, HintError, InviteTokenCreateRequest, InviteRedeemRequest, InviteRevokeRequest,
+    KeyRotationRequest, MemberAuthorizeRequest, ServerEvent, AckRequest,
     state::AppState,
+    state::events::clamp_event_limit,
+    metrics::metrics,
 };
+use std::net::IpAddr;
 use std::sync::Arc;
 
 type SharedState = Arc<AppState>;
 
-#[cfg(feature = "postgres")]
-use crate::handlers::db::{
-    gc_expired_invites_db, upsert_invite_db, get_invite_db, redeem_invite_db, revoke_invite_db,
-    upsert_server_hint_db, get_server_hint_db, insert_event_db, get_events_db, ack_events_db,
-};
-
 pub async fn handle_api_request(
     req: Request<Body>,
+    ip: IpAddr,
     state: SharedState,
 ) -> Result<Response<Body>, hyper::Error> {
     let path = req.uri().path().to_string();
@@ -43,6 +41,21 @@ pub async fn handle_api_request(
                     let signaling = state.signaling.lock().await;
                     signaling.conn_peers.len()
                 };
+                let invite_lookups = {
+                    let events = state.events.lock().await;
+                    serde_json::json!({
+                        "failed": events.invite_lookup_failures_total,
+                        "refused": events.invite_lookups_refused_total,
+                    })
+                };
+                let rate_limits = {
+                    let limits = state.rate_limits.lock().await;
+                    serde_json::json!({
+                        "limited": limits.limited_total,
+                        "bans": limits.bans_total,
+                        "banned_ips": limits.banned_count(),
+                    })
+                };
                 let uptime_secs = state.started_at.elapsed().as_secs();
                 let started_at_utc = state.started_at_utc.clone();
                 let (memory_mb, cpu_percent) = {
@@ -56,6 +69,10 @@ pub async fn handle_api_request(
                 };
                 let json = serde_json::json!({
                     "connections": connections,
+                    "instance_id": state.instance_id,
+                    "send_queues": state.send_queue_stats.snapshot(),
+                    "rate_limits": rate_limits,
+                    "invite_lookups": invite_lookups,
                     "uptime_secs": uptime_secs,
                     "started_at_utc": started_at_utc,
                     "downtime_secs": state.downtime_secs,
@@ -75,8 +92,10 @@ pub async fn handle_api_request(
                 .unwrap());
         }
         // GET /api/invites/{code} - Fetch invite token (opaque encrypted payload)
-        // POST /api/invites/{code}/redeem - Atomically redeem (decrement remaining_uses) and return payload
-        // POST /api/invites/{code}/revoke - Revoke (delete) the invite token
+        // POST /api/invites/{code}/redeem - Atomically redeem (decrement remaining_uses) and return payload.
+        //   Optional body {"member_pubkey": hex} authorizes the redeemer to sign hints for the house.
+        // POST /api/invites/{code}/revoke - Revoke (delete) the invite token.
+        //   Body {"signature": b64} must be signed by the owning house key.
         "invites" => {
             if path_parts.len() < 4 {
                 return Ok(Response::builder()
@@ -88,128 +107,39 @@ pub async fn handle_api_request(
             let code = decode_path_segment(path_parts[3]).trim().to_string();
             let maybe_sub = path_parts.get(4).copied();
 
-            #[cfg(feature = "postgres")]
-            {
-                // LOCK BOUNDARY: Extract data here, unlock before IO
-                let db = {
-                    let backends = state.backends.lock().await;
-                    backends.db.clone()
-                };
-                if let Some(pool) = db {
-                    // IO operation happens after lock is released
-                    let _ = gc_expired_invites_db(&pool).await;
-                    return match (method, maybe_sub) {
-                        (Method::POST, Some("redeem")) => {
-                            match redeem_invite_db(&pool, &code).await.unwrap_or(None) {
-                                Some(rec) => {
-                                    let json = serde_json::to_string(&rec).unwrap();
-                                    Ok(Response::builder()
-                                        .status(StatusCode::OK)
-                                        .header("Content-Type", "application/json")
-                                        .body(Body::from(json))
-                                        .unwrap())
-                                }
-                                None => Ok(Response::builder()
-                                    .status(StatusCode::NOT_FOUND)
-                                    .body(Body::from("Invite expired or fully redeemed"))
-                                    .unwrap()),
-                            }
-                        }
-                        (Method::POST, Some("revoke")) => {
-                            let existed = revoke_invite_db(&pool, &code).await.unwrap_or(false);
-                            if existed {
-                                Ok(Response::builder()
-                                    .status(StatusCode::OK)
-                                    .header("Content-Type", "application/json")
-                                    .body(Body::from(r#"{"status":"revoked"}"#))
-                                    .unwrap())
-                            } else {
-                                Ok(Response::builder()
-                                    .status(StatusCode::NOT_FOUND)
-                                    .body(Body::from("Invite not found"))
-                                    .unwrap())
-                            }
-                        }
-                        (Method::GET, None) => {
-                            match get_invite_db(&pool, &code).await.unwrap_or(None) {
-                                Some(rec) => {
-                                    let json = serde_json::to_string(&rec).unwrap();
-                                    Ok(Response::builder()
-                                        .status(StatusCode::OK)
-                                        .header("Content-Type", "application/json")
-                                        .body(Body::from(json))
-                                        .unwrap())
-                                }
-                                None => Ok(Response::builder()
-                                    .status(StatusCode::NOT_FOUND)
-                                    .body(Body::from("Invite not found"))
-                                    .unwrap()),
-                            }
-                        }
-                        _ => Ok(Response::builder()
-                            .status(StatusCode::METHOD_NOT_ALLOWED)
-                            .body(Body::from("Method not allowed"))
-                            .unwrap()),
-                    };
+            // Lookups and redemptions are guesses at the code: back off an IP that keeps
+            // missing. Revocations are signed, so they don't count.
+            let guess = maybe_sub.is_none() || maybe_sub == Some("redeem");
+            if guess {
+                if let Some(remaining) = state.begin_invite_lookup(ip).await {
+                    if maybe_sub == Some("redeem") {
+                        metrics().invite_redemption("refused");
+                    }
+                    let retry_after_secs = remaining.num_seconds().max(1);
+                    let body = serde_json::json!({
+                        "error": "invite_lockout",
+                        "message": "Too many failed invite lookups, try again later",
+                        "retry_after_secs": retry_after_secs,
+                    });
+                    return Ok(Response::builder()
+                        .status(StatusCode::TOO_MANY_REQUESTS)
+                        .header("Content-Type", "application/json")
+                        .header("Retry-After", retry_after_secs.to_string())
+                        .body(Body::from(body.to_string()))
+                        .unwrap());
                 }
             }
 
-            let mut events = state.events.lock().await;
-            events.gc_expired_invites();
-
-            match (method, maybe_sub) {
-                (Method::POST, Some("redeem")) => {
-                    match events.redeem_invite_token(&code) {
-                        Some(rec) => {
-                            let json = serde_json::to_string(&rec).unwrap();
-                            Ok(Response::builder()
-                                .status(StatusCode::OK)
-                                .header("Content-Type", "application/json")
-                                .body(Body::from(json))
-                                .unwrap())
-                        }
-                        None => Ok(Response::builder()
-                            .status(StatusCode::NOT_FOUND)
-                            .body(Body::from("Invite expired or fully redeemed"))
-                            .unwrap()),
-                    }
-                }
-                (Method::POST, Some("revoke")) => {
-                    let existed = events.invite_tokens.remove(&code).is_some();
-                    if existed {
-                        Ok(Response::builder()
-                            .status(StatusCode::OK)
-                            .header("Content-Type", "application/json")
-                            .body(Body::from(r#"{"status":"revoked"}"#))
-                            .unwrap())
-                    } else {
-                        Ok(Response::builder()
-                            .status(StatusCode::NOT_FOUND)
-                            .body(Body::from("Invite not found"))
-                            .unwrap())
-                    }
+            let resp = handle_invite_request(req, method, &code, maybe_sub, &state).await?;
+            if guess {
+                // Only a miss keeps the failure counted up front; errors aren't the guesser's doing
+                let found = resp.status() != StatusCode::NOT_FOUND;
+                if maybe_sub == Some("redeem") && matches!(resp.status(), StatusCode::OK | StatusCode::NOT_FOUND) {
+                    metrics().invite_redemption(if found { "redeemed" } else { "not_found" });
                 }
-                (Method::GET, None) => {
-                    match events.get_invite_token(&code) {
-                        Some(rec) => {
-                            let json = serde_json::to_string(rec).unwrap();
-                            Ok(Response::builder()
-                                .status(StatusCode::OK)
-                                .header("Content-Type", "application/json")
-                                .body(Body::from(json))
-                                .unwrap())
-                        }
-                        None => Ok(Response::builder()
-                            .status(StatusCode::NOT_FOUND)
-                            .body(Body::from("Invite not found"))
-                            .unwrap()),
-                    }
-                }
-                _ => Ok(Response::builder()
-                    .status(StatusCode::METHOD_NOT_ALLOWED)
-                    .body(Body::from("Method not allowed"))
-                    .unwrap()),
+                state.finish_invite_lookup(ip, found).await;
             }
+            Ok(resp)
         }
         // /api/servers/{signing_pubkey}/...
         "servers" => {
@@ -229,31 +159,11 @@ pub async fn handle_api_request(
             let body_bytes = hyper::body::to_bytes(req.into_body()).await?;
             match serde_json::from_slice::<EncryptedServerHint>(&body_bytes) {
                 Ok(hint) => {
-                    #[cfg(feature = "postgres")]
-                    {
-                        let db = {
-                            let backends = state.backends.lock().await;
-                            backends.db.clone()
-                        };
-                        if let Some(pool) = db {
-                            if let Err(e) = upsert_server_hint_db(&pool, &hint).await {
-                                warn!("Failed to persist server hint: {}", e);
-                            }
-                        } else {
-                            let mut events = state.events.lock().await;
-                            events.register_server_hint(signing_pubkey.clone(), hint.clone());
-                        }
-                    }
-                    #[cfg(not(feature = "postgres"))]
-                    {
-                        let mut events = state.events.lock().await;
-                        events.register_server_hint(signing_pubkey.clone(), hint.clone());
+                    if let Err(e) = state.store().await.register_server_hint(&signing_pubkey, &hint).await {
+                        return Ok(reject_server_hint(e));
                     }
                     // Broadcast snapshot update to any subscribed peers
-                    {
-                        let signaling = state.signaling.lock().await;
-                        signaling.broadcast_server_hint_updated(&signing_pubkey, &hint);
-                    }
+                    state.broadcast_server_hint_updated(&signing_pubkey, &hint).await;
                     info!("Registered server hint");
                     Ok(Response::builder()
                         .status(StatusCode::OK)
@@ -276,33 +186,8 @@ pub async fn handle_api_request(
             let body_bytes = hyper::body::to_bytes(req.into_body()).await?;
             match serde_json::from_slice::<InviteTokenCreateRequest>(&body_bytes) {
                 Ok(inv) => {
-                    #[cfg(feature = "postgres")]
-                    {
-                        let db = {
-                            let backends = state.backends.lock().await;
-                            backends.db.clone()
-                        };
-                        if let Some(pool) = db {
-                            let _ = gc_expired_invites_db(&pool).await;
-                            return match upsert_invite_db(&pool, &signing_pubkey, inv).await {
-                                Ok(record) => {
-                                    let json = serde_json::to_string(&record).unwrap();
-                                    Ok(Response::builder()
-                                        .status(StatusCode::OK)
-                                        .header("Content-Type", "application/json")
-                                        .body(Body::from(json))
-                                        .unwrap())
-                                }
-                                Err(e) => Ok(Response::builder()
-                                    .status(StatusCode::BAD_REQUEST)
-                                    .body(Body::from(e))
-                                    .unwrap()),
-                            };
-                        }
-                    }
-                    let mut events = state.events.lock().await;
-                    events.gc_expired_invites();
-                    match events.put_invite_token(&signing_pubkey, inv) {
+                    let ttl_days = state.events.lock().await.invite_ttl_days;
+                    match state.store().await.create_invite(&signing_pubkey, inv, ttl_days).await {
                         Ok(record) => {
                             let json = serde_json::to_string(&record).unwrap();
                             Ok(Response::builder()
@@ -311,10 +196,7 @@ pub async fn handle_api_request(
                                 .body(Body::from(json))
                                 .unwrap())
                         }
-                        Err(e) => Ok(Response::builder()
-                            .status(StatusCode::BAD_REQUEST)
-                            .body(Body::from(e))
-                            .unwrap()),
+                        Err(e) => Ok(e.into_response()),
                     }
                 }
                 Err(e) => {
@@ -327,45 +209,106 @@ pub async fn handle_api_request(
             }
         }
 
-        // GET /api/servers/{signing_pubkey}/hint - Get server hint
-        (Method::GET, Some("hint")) => {
-            #[cfg(feature = "postgres")]
-            {
-                let db = {
-                    let backends = state.backends.lock().await;
-                    backends.db.clone()
-                };
-                if let Some(pool) = db {
-                    return match get_server_hint_db(&pool, &signing_pubkey).await.unwrap_or(None) {
-                        Some(hint) => {
-                            let json = serde_json::to_string(&hint).unwrap();
+        // POST /api/servers/{signing_pubkey}/members - Owner authorizes a member key directly (sealed invites)
+        (Method::POST, Some("members")) => {
+            let body_bytes = hyper::body::to_bytes(req.into_body()).await?;
+            let authorize = match serde_json::from_slice::<MemberAuthorizeRequest>(&body_bytes) {
+                Ok(authorize) => authorize,
+                Err(e) => {
+                    warn!("Failed to parse member authorization: {}", e);
+                    return Ok(Response::builder()
+                        .status(StatusCode::BAD_REQUEST)
+                        .body(Body::from(format!("Invalid request body: {}", e)))
+                        .unwrap());
+                }
+            };
+            if let Err(e) = auth::verify_member_authorization(&signing_pubkey, &authorize.member_pubkey, &authorize.signature) {
+                warn!("Rejected member authorization: {}", e);
+                return Ok(e.into_response());
+            }
+
+            // verify_member_authorization has checked the member key
+            match state.store().await.authorize_member_signer(&signing_pubkey, &authorize.member_pubkey).await {
+                Ok(()) => Ok(Response::builder()
+                    .status(StatusCode::OK)
+                    .header("Content-Type", "application/json")
+                    .body(Body::from(r#"{"status":"ok"}"#))
+                    .unwrap()),
+                Err(e) => Ok(storage_failure("Failed to authorize member", &e)),
+            }
+        }
+
+        // POST /api/servers/{signing_pubkey}/keys - Store a house key rotation (house key signed)
+        (Method::POST, Some("keys")) => {
+            let body_bytes = hyper::body::to_bytes(req.into_body()).await?;
+            match serde_json::from_slice::<KeyRotationRequest>(&body_bytes) {
+                Ok(rotation) => {
+                    match state.store().await.put_key_rotation(&signing_pubkey, &rotation).await {
+                        Ok(()) => {
+                            info!("Stored key rotation (epoch {})", rotation.key_epoch);
                             Ok(Response::builder()
                                 .status(StatusCode::OK)
                                 .header("Content-Type", "application/json")
-                                .body(Body::from(json))
+                                .body(Body::from(r#"{"status":"ok"}"#))
                                 .unwrap())
                         }
-                        None => Ok(Response::builder()
-                            .status(StatusCode::NOT_FOUND)
-                            .body(Body::from("Server hint not found"))
-                            .unwrap()),
-                    };
+                        Err(e) => {
+                            warn!("Rejected key rotation: {:?}", e);
+                            Ok(e.into_response())
+                        }
+                    }
+                }
+                Err(e) => {
+                    warn!("Failed to parse key rotation: {}", e);
+                    Ok(Response::builder()
+                        .status(StatusCode::BAD_REQUEST)
+                        .body(Body::from(format!("Invalid request body: {}", e)))
+                        .unwrap())
                 }
             }
-            let events = state.events.lock().await;
-            match events.get_server_hint(&signing_pubkey) {
-                Some(hint) => {
-                    let json = serde_json::to_string(hint).unwrap();
+        }
+
+        // GET /api/servers/{signing_pubkey}/keys/{user_id} - A member's sealed copy of the latest key
+        (Method::GET, Some("keys")) => {
+            let Some(user_id) = path_parts.get(5).map(|s| decode_path_segment(s)) else {
+                return Ok(Response::builder()
+                    .status(StatusCode::NOT_FOUND)
+                    .body(Body::from("Missing user_id"))
+                    .unwrap());
+            };
+            match state.store().await.get_sealed_key(&signing_pubkey, &user_id).await {
+                Ok(Some(record)) => {
+                    let json = serde_json::to_string(&record).unwrap();
                     Ok(Response::builder()
                         .status(StatusCode::OK)
                         .header("Content-Type", "application/json")
                         .body(Body::from(json))
                         .unwrap())
                 }
-                None => Ok(Response::builder()
+                Ok(None) => Ok(Response::builder()
+                    .status(StatusCode::NOT_FOUND)
+                    .body(Body::from("No sealed key for this member"))
+                    .unwrap()),
+                Err(e) => Ok(storage_failure("Failed to load sealed key", &e)),
+            }
+        }
+
+        // GET /api/servers/{signing_pubkey}/hint - Get server hint
+        (Method::GET, Some("hint")) => {
+            match state.store().await.get_server_hint(&signing_pubkey).await {
+                Ok(Some(hint)) => {
+                    let json = serde_json::to_string(&hint).unwrap();
+                    Ok(Response::builder()
+                        .status(StatusCode::OK)
+                        .header("Content-Type", "application/json")
+                        .body(Body::from(json))
+                        .unwrap())
+                }
+                Ok(None) => Ok(Response::builder()
                     .status(StatusCode::NOT_FOUND)
                     .body(Body::from("Server hint not found"))
                     .unwrap()),
+                Err(e) => Ok(storage_failure("Failed to load server hint", &e)),
             }
         }
 
@@ -375,24 +318,10 @@ pub async fn handle_api_request(
             let body_bytes = hyper::body::to_bytes(req.into_body()).await?;
             match serde_json::from_slice::<AckRequest>(&body_bytes) {
                 Ok(ack) => {
-                    #[cfg(feature = "postgres")]
-                    {
-                        let db = {
-                            let backends = state.backends.lock().await;
-                            backends.db.clone()
-                        };
-                        if let Some(pool) = db {
-                            let _ = ack_events_db(&pool, &signing_pubkey, &ack.user_id, &ack.last_event_id).await;
-                            info!("Acknowledged events (db)");
-                            return Ok(Response::builder()
-                                .status(StatusCode::OK)
-                                .header("Content-Type", "application/json")
-                                .body(Body::from(r#"{"status":"ok"}"#))
-                                .unwrap());
-                        }
+                    if let Err(e) = state.ack_events(&signing_pubkey, &ack).await {
+                        warn!("Refused ack: {:?}", e);
+                        return Ok(e.into_response());
                     }
-                    let mut events = state.events.lock().await;
-                    events.ack_events(signing_pubkey, ack.user_id, ack.last_event_id);
                     info!("Acknowledged events");
                     Ok(Response::builder()
                         .status(StatusCode::OK)
@@ -414,38 +343,30 @@ pub async fn handle_api_request(
         (Method::POST, Some("events")) => {
             let body_bytes = hyper::body::to_bytes(req.into_body()).await?;
             match serde_json::from_slice::<ServerEvent>(&body_bytes) {
-                Ok(event) => {
-                    let mut event = event;
+                Ok(mut event) => {
                     event.signing_pubkey = signing_pubkey.clone();
                     event.timestamp = Utc::now();
                     if event.event_id.is_empty() {
                         event.event_id = uuid::Uuid::new_v4().to_string();
                     }
 
-                    #[cfg(feature = "postgres")]
-                    {
-                        let db = {
-                            let backends = state.backends.lock().await;
-                            backends.db.clone()
-                        };
-                        if let Some(pool) = db {
-                            let _ = insert_event_db(&pool, &event).await;
-                            info!("Posted server event (db)");
-                            return Ok(Response::builder()
-                                .status(StatusCode::CREATED)
-                                .header("Content-Type", "application/json")
-                                .body(Body::from(r#"{"status":"created"}"#))
-                                .unwrap());
-                        }
-                    }
-
-                    let mut events = state.events.lock().await;
-                    events.post_event(signing_pubkey, event);
+                    let event = match state.insert_event(&event).await {
+                        Ok(stored) => stored,
+                        Err(e) => return Ok(storage_failure("Failed to persist server event", &e)),
+                    };
                     info!("Posted server event");
+
+                    // Push to subscribed peers so they don't have to poll
+                    state.broadcast_event_posted(&signing_pubkey, &event).await;
+                    let body = serde_json::json!({
+                        "status": "created",
+                        "event_id": event.event_id,
+                        "seq": event.seq,
+                    });
                     Ok(Response::builder()
                         .status(StatusCode::CREATED)
                         .header("Content-Type", "application/json")
-                        .body(Body::from(r#"{"status":"created"}"#))
+                        .body(Body::from(body.to_string()))
                         .unwrap())
                 }
                 Err(e) => {
@@ -458,38 +379,55 @@ pub async fn handle_api_request(
             }
         }
 
-        // GET /api/servers/{signing_pubkey}/events?since={event_id} - Poll events
+        // GET /api/servers/{signing_pubkey}/events/stats - Queue depth and oldest unacked event
+        (Method::GET, Some("events")) if path_parts.get(5) == Some(&"stats") => {
+            match state.event_queue_stats(&signing_pubkey).await {
+                Ok(stats) => {
+                    let json = serde_json::to_string(&stats).unwrap();
+                    Ok(Response::builder()
+                        .status(StatusCode::OK)
+                        .header("Content-Type", "application/json")
+                        .body(Body::from(json))
+                        .unwrap())
+                }
+                Err(e) => {
+                    warn!("Failed to load event queue stats: {}", e);
+                    Ok(Response::builder()
+                        .status(StatusCode::INTERNAL_SERVER_ERROR)
+                        .body(Body::from("Failed to load event queue stats"))
+                        .unwrap())
+                }
+            }
+        }
+
+        // GET /api/servers/{signing_pubkey}/events?after_seq={seq}&limit={n} - Page through events
+        // (`since={event_id}` is still accepted from older clients)
         (Method::GET, Some("events")) => {
             let query = req.uri().query().unwrap_or("");
-            let since: Option<&str> = query
-                .split('&')
-                .find(|p| p.starts_with("since="))
-                .map(|p| &p[6..]);
-            #[cfg(feature = "postgres")]
-            {
-                let db = {
-                    let backends = state.backends.lock().await;
-                    backends.db.clone()
-                };
-                if let Some(pool) = db {
-                    let events = get_events_db(&pool, &signing_pubkey, since).await.unwrap_or_default();
-                    let json = serde_json::to_string(&events).unwrap();
-                    return Ok(Response::builder()
+            let param = |name: &str| {
+                query
+                    .split('&')
+                    .find_map(|p| p.strip_prefix(name).and_then(|rest| rest.strip_prefix('=')))
+                    .map(decode_path_segment)
+            };
+            let after_seq = param("after_seq").and_then(|v| v.parse::<u64>().ok()).unwrap_or(0);
+            let limit = clamp_event_limit(param("limit").and_then(|v| v.parse::<usize>().ok()));
+            let since = param("since");
+
+            match state.load_events_page(&signing_pubkey, after_seq, since.as_deref(), limit).await {
+                Ok(page) => {
+                    let json = serde_json::to_string(&page).unwrap();
+                    Ok(Response::builder()
                         .status(StatusCode::OK)
                         .header("Content-Type", "application/json")
                         .body(Body::from(json))
-                        .unwrap());
+                        .unwrap())
+                }
+                Err(e) => {
+                    info!("Event query refused: {:?}", e);
+                    Ok(e.into_response())
                 }
             }
-
-            let events = state.events.lock().await;
-            let events_list = events.get_events(&signing_pubkey, since);
-            let json = serde_json::to_string(&events_list).unwrap();
-            Ok(Response::builder()
-                .status(StatusCode::OK)
-                .header("Content-Type", "application/json")
-                .body(Body::from(json))
-                .unwrap())
         }
 
         // POST /api/servers/{signing_pubkey}/ack - Acknowledge events (alternative path)
@@ -497,24 +435,10 @@ pub async fn handle_api_request(
             let body_bytes = hyper::body::to_bytes(req.into_body()).await?;
             match serde_json::from_slice::<AckRequest>(&body_bytes) {
                 Ok(ack) => {
-                    #[cfg(feature = "postgres")]
-                    {
-                        let db = {
-                            let backends = state.backends.lock().await;
-                            backends.db.clone()
-                        };
-                        if let Some(pool) = db {
-                            let _ = ack_events_db(&pool, &signing_pubkey, &ack.user_id, &ack.last_event_id).await;
-                            info!("Acknowledged events (db)");
-                            return Ok(Response::builder()
-                                .status(StatusCode::OK)
-                                .header("Content-Type", "application/json")
-                                .body(Body::from(r#"{"status":"ok"}"#))
-                                .unwrap());
-                        }
+                    if let Err(e) = state.ack_events(&signing_pubkey, &ack).await {
+                        warn!("Refused ack: {:?}", e);
+                        return Ok(e.into_response());
                     }
-                    let mut events = state.events.lock().await;
-                    events.ack_events(signing_pubkey, ack.user_id, ack.last_event_id);
                     info!("Acknowledged events");
                     Ok(Response::builder()
                         .status(StatusCode::OK)
@@ -546,3 +470,125 @@ pub async fn handle_api_request(
             .unwrap()),
     }
 }
+
+/// A stale write is an expected race, not an attack: log it quietly and hand back the current hint.
+fn reject_server_hint(e: HintError) -> Response<Body> {
+    match &e {
+        HintError::Conflict(current) => info!("Server hint write lost the race (stored revision {})", current.revision),
+        other => warn!("Rejected server hint: {:?}", other),
+    }
+    e.into_response()
+}
+
+/// The store failed (not the request): log it and answer 500 with a fixed message.
+pub(crate) fn storage_failure(message: &'static str, e: &str) -> Response<Body> {
+    warn!("{}: {}", message, e);
+    Response::builder()
+        .status(StatusCode::INTERNAL_SERVER_ERROR)
+        .body(Body::from(message))
+        .unwrap()
+}
+
+async fn handle_invite_request(
+    req: Request<Body>,
+    method: Method,
+    code: &str,
+    maybe_sub: Option<&str>,
+    state: &SharedState,
+) -> Result<Response<Body>, hyper::Error> {
+    let body_bytes = if method == Method::POST {
+        hyper::body::to_bytes(req.into_body()).await?
+    } else {
+        Default::default()
+    };
+
+    // A missing or unparsable body leaves the signature empty, which verification rejects
+    let revoke: InviteRevokeRequest = if maybe_sub == Some("revoke") {
+        serde_json::from_slice(&body_bytes).unwrap_or_default()
+    } else {
+        InviteRevokeRequest::default()
+    };
+
+    let redeem = if method == Method::POST && maybe_sub == Some("redeem") {
+        let redeem = if body_bytes.is_empty() {
+            InviteRedeemRequest::default()
+        } else {
+            match serde_json::from_slice::<InviteRedeemRequest>(&body_bytes) {
+                Ok(r) => r,
+                Err(e) => {
+                    return Ok(Response::builder()
+                        .status(StatusCode::BAD_REQUEST)
+                        .body(Body::from(format!("Invalid request body: {}", e)))
+                        .unwrap());
+                }
+            }
+        };
+        // Reject bad keys before a use is consumed
+        if let Some(member) = redeem.member_pubkey.as_deref() {
+            if let Err(e) = auth::decode_member_key(member) {
+                return Ok(e.into_response());
+            }
+        }
+        redeem
+    } else {
+        InviteRedeemRequest::default()
+    };
+
+    let store = state.store().await;
+    match (method, maybe_sub) {
+        (Method::POST, Some("redeem")) => {
+            match store.redeem_invite(code).await {
+                Ok(Some(rec)) => {
+                    // The key was checked above, before a use was consumed
+                    if let Some(member) = redeem.member_pubkey.as_deref() {
+                        if let Err(e) = store.authorize_member_signer(&rec.signing_pubkey, member).await {
+                            warn!("Failed to authorize hint signer: {}", e);
+                        }
+                    }
+                    let json = serde_json::to_string(&rec).unwrap();
+                    Ok(Response::builder()
+                        .status(StatusCode::OK)
+                        .header("Content-Type", "application/json")
+                        .body(Body::from(json))
+                        .unwrap())
+                }
+                Ok(None) => Ok(Response::builder()
+                    .status(StatusCode::NOT_FOUND)
+                    .body(Body::from("Invite expired or fully redeemed"))
+                    .unwrap()),
+                Err(e) => Ok(storage_failure("Failed to redeem invite", &e)),
+            }
+        }
+        (Method::POST, Some("revoke")) => {
+            match store.revoke_invite(code, &revoke.signature).await {
+                Ok(()) => Ok(Response::builder()
+                    .status(StatusCode::OK)
+                    .header("Content-Type", "application/json")
+                    .body(Body::from(r#"{"status":"revoked"}"#))
+                    .unwrap()),
+                Err(e) => Ok(e.into_response()),
+            }
+        }
+        (Method::GET, None) => {
+            match store.get_invite(code).await {
+                Ok(Some(rec)) => {
+                    let json = serde_json::to_string(&rec).unwrap();
+                    Ok(Response::builder()
+                        .status(StatusCode::OK)
+                        .header("Content-Type", "application/json")
+                        .body(Body::from(json))
+                        .unwrap())
+                }
+                Ok(None) => Ok(Response::builder()
+                    .status(StatusCode::NOT_FOUND)
+                    .body(Body::from("Invite not found"))
+                    .unwrap()),
+                Err(e) => Ok(storage_failure("Failed to load invite", &e)),
+            }
+        }
+        _ => Ok(Response::builder()
+            .status(StatusCode::METHOD_NOT_ALLOWED)
+            .body(Body::from("Method not allowed"))
+            .unwrap()),
+    }
+}
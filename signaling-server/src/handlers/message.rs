@@ -1,16 +1,17 @@
 use std::sync::Arc;
 use log::{info, warn};
 use crate::{
-    SignalingMessage, ConnId, ServerId, SigningPubkey, WebSocketSender,
+    auth,
+    SignalingMessage, ConnId, ServerId, SigningPubkey, WebSocketSender, EventQueryError,
     ProfileRecord, ProfileSnapshotRecord,
     state::AppState,
     state::presence::PresenceUserStatus,
+    state::events::clamp_event_limit,
+    metrics::metrics,
 };
 
 type SharedState = Arc<AppState>;
 
-#[cfg(feature = "postgres")]
-use crate::handlers::db::{upsert_profile_db, load_profiles_db};
 #[cfg(feature = "redis-backend")]
 use crate::handlers::redis::{redis_presence_hello, redis_presence_active, redis_presence_snapshot};
 
@@ -20,8 +21,61 @@ pub async fn handle_message(
     state: &SharedState,
     sender: &WebSocketSender,
 ) -> Result<(), String> {
+    // Everything except the handshake and keepalive requires a proven identity,
+    // and messages that carry a user_id must carry the one bound to this connection.
+    let claimed_user_id = match &msg {
+        SignalingMessage::AuthResponse { .. } | SignalingMessage::Ping | SignalingMessage::Pong => None,
+        SignalingMessage::PresenceHello { user_id, .. }
+        | SignalingMessage::PresenceActive { user_id, .. }
+        | SignalingMessage::ProfileAnnounce { user_id, .. }
+        | SignalingMessage::VoiceRegister { user_id, .. } => Some(Some(user_id.as_str())),
+        _ => Some(None),
+    };
+    if let Some(claimed) = claimed_user_id {
+        let signaling = state.signaling.lock().await;
+        signaling.require_conn_user(conn_id, claimed)?;
+    }
+
     match msg {
+        SignalingMessage::AuthResponse { user_id, public_key, signature } => {
+            let mut signaling = state.signaling.lock().await;
+            let nonce = signaling
+                .take_auth_challenge(conn_id)
+                .ok_or_else(|| "No outstanding auth challenge".to_string())?;
+
+            if let Err(e) = auth::verify_auth_response(&nonce, &user_id, &public_key, &signature) {
+                // Issue a new challenge so the client can retry on the same connection
+                let nonce = signaling.issue_auth_challenge(conn_id);
+                drop(signaling);
+                if let Ok(json) = serde_json::to_string(&SignalingMessage::AuthChallenge { nonce }) {
+                    let _ = sender.send(hyper_tungstenite::tungstenite::Message::Text(json));
+                }
+                return Err(format!("Authentication failed: {}", e));
+            }
+
+            signaling.bind_conn_user(conn_id, user_id.clone());
+            let resume_token = signaling.open_session(conn_id, user_id.clone(), sender);
+            drop(signaling);
+
+            info!("Authenticated connection {} as user {}", conn_id, user_id);
+
+            let json = serde_json::to_string(&SignalingMessage::AuthOk { user_id, resume_token: Some(resume_token) })
+                .map_err(|e| format!("Failed to serialize AuthOk: {}", e))?;
+            sender
+                .send(hyper_tungstenite::tungstenite::Message::Text(json))
+                .map_err(|e| format!("Failed to send AuthOk: {}", e))?;
+
+            Ok(())
+        }
         SignalingMessage::Register { server_id, peer_id, signing_pubkey } => {
+            // Claim the peer_id here and across instances before taking it over
+            let user_id = {
+                let signaling = state.signaling.lock().await;
+                signaling.check_peer_owner(&peer_id, conn_id)?;
+                signaling.conn_user(conn_id).cloned().unwrap_or_default()
+            };
+            state.route_peer(&peer_id, &user_id).await?;
+
             let mut signaling = state.signaling.lock().await;
             let peers = signaling.register_peer(peer_id.clone(), server_id.clone(), signing_pubkey, conn_id.clone());
 
@@ -85,11 +139,11 @@ pub async fn handle_message(
             // IO operations happen after lock is released
             #[cfg(feature = "redis-backend")]
             if let Some(client) = redis_client.as_ref() {
-                if let Err(e) = redis_presence_hello(client, redis_ttl, &user_id, &signing_pubkeys, &active_signing_pubkey).await {
+                if let Err(e) = crate::metrics::timed("redis", "presence_hello", redis_presence_hello(client, redis_ttl, &user_id, &signing_pubkeys, &active_signing_pubkey)).await {
                     warn!("Redis presence hello failed: {}", e);
                 }
                 for spk in signing_pubkeys.iter() {
-                    let users = redis_presence_snapshot(client, spk).await.unwrap_or_default();
+                    let users = crate::metrics::timed("redis", "presence_snapshot", redis_presence_snapshot(client, spk)).await.unwrap_or_default();
                     let snap = SignalingMessage::PresenceSnapshot {
                         signing_pubkey: spk.clone(),
                         users,
@@ -155,7 +209,7 @@ pub async fn handle_message(
 
             #[cfg(feature = "redis-backend")]
             if let Some(client) = redis_client.as_ref() {
-                if let Err(e) = redis_presence_active(client, redis_ttl, &user_id, &active_signing_pubkey).await {
+                if let Err(e) = crate::metrics::timed("redis", "presence_active", redis_presence_active(client, redis_ttl, &user_id, &active_signing_pubkey)).await {
                     warn!("Redis presence active failed: {}", e);
                 }
             }
@@ -168,7 +222,7 @@ pub async fn handle_message(
             Ok(())
         }
         SignalingMessage::ProfileAnnounce { user_id, display_name, real_name, show_real_name, rev, signing_pubkeys } => {
-            let (rec_opt, db_opt) = {
+            let (rec_opt, durable) = {
                 let mut profiles = state.profiles.lock().await;
                 let update = match profiles.profiles.get(&user_id) {
                     Some(existing) => rev > existing.rev,
@@ -197,66 +251,27 @@ pub async fn handle_message(
                     }
                 }
 
-                // LOCK BOUNDARY: Extract data here, unlock before IO
-                #[cfg(feature = "postgres")]
-                let db = {
-                    let backends = state.backends.lock().await;
-                    backends.db.clone()
-                };
-                #[cfg(not(feature = "postgres"))]
-                let db: Option<()> = None;
-
-                (rec, db)
+                (rec, state.durable_store().await)
             };
 
             // Persist the latest profile so offline users can catch up even after restarts.
-            // IO operation happens after lock is released
-            #[cfg(feature = "postgres")]
-            if let (Some(pool), Some(rec)) = (db_opt, rec_opt.as_ref()) {
-                let _ = upsert_profile_db(&pool, &user_id, rec).await;
+            // (The cache updated above is what the in-memory store serves.)
+            if let (Some(store), Some(rec)) = (durable, rec_opt.as_ref()) {
+                if let Err(e) = store.upsert_profile(&user_id, rec).await {
+                    warn!("Failed to persist profile: {}", e);
+                }
             }
 
             Ok(())
         }
         SignalingMessage::ProfileHello { signing_pubkey, user_ids } => {
-            // Prefer DB if available; otherwise fall back to in-memory cache.
-            // LOCK BOUNDARY: Extract data here, unlock before IO
-            #[cfg(feature = "postgres")]
-            let db = {
-                let backends = state.backends.lock().await;
-                backends.db.clone()
-            };
-
-            #[cfg(feature = "postgres")]
-            let out: Vec<ProfileSnapshotRecord> = if let Some(pool) = db {
-                load_profiles_db(&pool, &user_ids).await.unwrap_or_default()
-            } else {
-                let profiles = state.profiles.lock().await;
-                user_ids
-                    .iter()
-                    .filter_map(|uid| profiles.profiles.get(uid).map(|rec| ProfileSnapshotRecord {
-                        user_id: uid.clone(),
-                        display_name: rec.display_name.clone(),
-                        real_name: rec.real_name.clone(),
-                        show_real_name: rec.show_real_name,
-                        rev: rec.rev,
-                    }))
-                    .collect()
-            };
-
-            #[cfg(not(feature = "postgres"))]
-            let out: Vec<ProfileSnapshotRecord> = {
-                let profiles = state.profiles.lock().await;
-                user_ids
-                    .iter()
-                    .filter_map(|uid| profiles.profiles.get(uid).map(|rec| ProfileSnapshotRecord {
-                        user_id: uid.clone(),
-                        display_name: rec.display_name.clone(),
-                        real_name: rec.real_name.clone(),
-                        show_real_name: rec.show_real_name,
-                        rev: rec.rev,
-                    }))
-                    .collect()
+            // The durable store if available; otherwise the in-memory cache
+            let out: Vec<ProfileSnapshotRecord> = match state.store().await.load_profiles(&user_ids).await {
+                Ok(profiles) => profiles,
+                Err(e) => {
+                    warn!("Failed to load profiles: {}", e);
+                    Vec::new()
+                }
             };
 
             let snap = SignalingMessage::ProfileSnapshot { signing_pubkey, profiles: out };
@@ -265,6 +280,31 @@ pub async fn handle_message(
             }
             Ok(())
         }
+        SignalingMessage::EventReplayRequest { signing_pubkey, after_seq, limit } => {
+            // Events are public ciphertext, like hints (see SIGNALING_SETUP.md)
+            let reply = match state.load_events_page(&signing_pubkey, after_seq, None, clamp_event_limit(limit)).await {
+                Ok(page) => SignalingMessage::EventReplay {
+                    signing_pubkey,
+                    events: page.events,
+                    next_seq: page.next_seq,
+                    has_more: page.has_more,
+                },
+                Err(EventQueryError::CursorExpired { latest_seq }) => {
+                    SignalingMessage::EventCursorExpired { signing_pubkey, latest_seq }
+                }
+                Err(EventQueryError::Storage(e)) => {
+                    // Errors go back to the client; keep backend details in the log
+                    warn!("Failed to load events: {}", e);
+                    return Err("Failed to load events".to_string());
+                }
+            };
+            let json = serde_json::to_string(&reply)
+                .map_err(|e| format!("Failed to serialize EventReplay: {}", e))?;
+            sender
+                .send(hyper_tungstenite::tungstenite::Message::Text(json))
+                .map_err(|e| format!("Failed to send EventReplay: {}", e))?;
+            Ok(())
+        }
         SignalingMessage::Offer { from_peer, to_peer, sdp } => {
             info!("Forwarding offer from {} to {}", from_peer, to_peer);
 
@@ -276,21 +316,22 @@ pub async fn handle_message(
                 }
             }
 
-            let signaling = state.signaling.lock().await;
-            if let Some(target_sender) = signaling.peer_senders.get(&to_peer) {
-                let forward_msg = SignalingMessage::Offer {
-                    from_peer,
-                    to_peer,
-                    sdp,
-                };
-                let json = serde_json::to_string(&forward_msg)
-                    .map_err(|e| format!("Failed to serialize offer: {}", e))?;
-
-                target_sender
-                    .send(hyper_tungstenite::tungstenite::Message::Text(json))
-                    .map_err(|e| format!("Failed to forward offer: {}", e))?;
-            } else {
+            let forward_msg = SignalingMessage::Offer {
+                from_peer,
+                to_peer: to_peer.clone(),
+                sdp,
+            };
+            let json = serde_json::to_string(&forward_msg)
+                .map_err(|e| format!("Failed to serialize offer: {}", e))?;
+
+            // Local socket first, otherwise whichever beacon instance the peer is on
+            let forwarded = state.forward_to_peer(&to_peer, json).await.map_err(|e| {
+                metrics().forward_failure("send_failed");
+                format!("Failed to forward offer: {}", e)
+            })?;
+            if !forwarded {
                 warn!("Target peer {} not found for offer", to_peer);
+                metrics().forward_failure("peer_not_found");
             }
 
             Ok(())
@@ -306,21 +347,22 @@ pub async fn handle_message(
                 }
             }
 
-            let signaling = state.signaling.lock().await;
-            if let Some(target_sender) = signaling.peer_senders.get(&to_peer) {
-                let forward_msg = SignalingMessage::Answer {
-                    from_peer,
-                    to_peer,
-                    sdp,
-                };
-                let json = serde_json::to_string(&forward_msg)
-                    .map_err(|e| format!("Failed to serialize answer: {}", e))?;
-
-                target_sender
-                    .send(hyper_tungstenite::tungstenite::Message::Text(json))
-                    .map_err(|e| format!("Failed to forward answer: {}", e))?;
-            } else {
+            let forward_msg = SignalingMessage::Answer {
+                from_peer,
+                to_peer: to_peer.clone(),
+                sdp,
+            };
+            let json = serde_json::to_string(&forward_msg)
+                .map_err(|e| format!("Failed to serialize answer: {}", e))?;
+
+            // Local socket first, otherwise whichever beacon instance the peer is on
+            let forwarded = state.forward_to_peer(&to_peer, json).await.map_err(|e| {
+                metrics().forward_failure("send_failed");
+                format!("Failed to forward answer: {}", e)
+            })?;
+            if !forwarded {
                 warn!("Target peer {} not found for answer", to_peer);
+                metrics().forward_failure("peer_not_found");
             }
 
             Ok(())
@@ -336,21 +378,22 @@ pub async fn handle_message(
                 }
             }
 
-            let signaling = state.signaling.lock().await;
-            if let Some(target_sender) = signaling.peer_senders.get(&to_peer) {
-                let forward_msg = SignalingMessage::IceCandidate {
-                    from_peer,
-                    to_peer,
-                    candidate,
-                };
-                let json = serde_json::to_string(&forward_msg)
-                    .map_err(|e| format!("Failed to serialize ICE candidate: {}", e))?;
-
-                target_sender
-                    .send(hyper_tungstenite::tungstenite::Message::Text(json))
-                    .map_err(|e| format!("Failed to forward ICE candidate: {}", e))?;
-            } else {
+            let forward_msg = SignalingMessage::IceCandidate {
+                from_peer,
+                to_peer: to_peer.clone(),
+                candidate,
+            };
+            let json = serde_json::to_string(&forward_msg)
+                .map_err(|e| format!("Failed to serialize ICE candidate: {}", e))?;
+
+            // Local socket first, otherwise whichever beacon instance the peer is on
+            let forwarded = state.forward_to_peer(&to_peer, json).await.map_err(|e| {
+                metrics().forward_failure("send_failed");
+                format!("Failed to forward ICE candidate: {}", e)
+            })?;
+            if !forwarded {
                 warn!("Target peer {} not found for ICE candidate", to_peer);
+                metrics().forward_failure("peer_not_found");
             }
 
             Ok(())
@@ -361,16 +404,22 @@ pub async fn handle_message(
         SignalingMessage::VoiceRegister { server_id, chat_id, peer_id, user_id, signing_pubkey } => {
             info!("Voice register: peer={} user={} server={} chat={}", peer_id, user_id, server_id, chat_id);
 
+            {
+                let signaling = state.signaling.lock().await;
+                if signaling.peers.contains_key(&peer_id) && !signaling.validate_peer_connection(&peer_id, conn_id) {
+                    return Err(format!("Invalid peer_id {} for connection {}", peer_id, conn_id));
+                }
+            }
+            state.route_peer(&peer_id, &user_id).await?;
+
             let peers = {
                 let mut signaling = state.signaling.lock().await;
 
                 // Register peer if not already registered (allows voice-first registration)
                 if !signaling.peers.contains_key(&peer_id) {
                     signaling.register_peer(peer_id.clone(), server_id.clone(), Some(signing_pubkey.clone()), conn_id.clone());
-                } else {
-                    if !signaling.validate_peer_connection(&peer_id, conn_id) {
-                        return Err(format!("Invalid peer_id {} for connection {}", peer_id, conn_id));
-                    }
+                } else if !signaling.validate_peer_connection(&peer_id, conn_id) {
+                    return Err(format!("Invalid peer_id {} for connection {}", peer_id, conn_id));
                 }
 
                 signaling.peer_senders.insert(peer_id.clone(), sender.clone());
@@ -391,6 +440,14 @@ pub async fn handle_message(
                     conn_id.clone(),
                 )
             };
+            // Members on other beacon instances come from Redis
+            let peers = match state.voice_room_join(&server_id, &chat_id, &peer_id, &user_id, peers).await {
+                Ok(peers) => peers,
+                Err(e) => {
+                    state.voice.lock().await.unregister_voice_peer(&peer_id, &server_id, &chat_id);
+                    return Err(e);
+                }
+            };
 
             let response = SignalingMessage::VoiceRegistered {
                 peer_id: peer_id.clone(),
@@ -450,6 +507,7 @@ pub async fn handle_message(
             };
 
             if let Some((server_id, user_id, signing_pubkey_opt)) = removed {
+                state.voice_room_leave(&[(server_id.clone(), chat_id.clone(), peer_id.clone())]).await;
                 let leave_msg = SignalingMessage::VoicePeerLeft {
                     peer_id,
                     user_id: user_id.clone(),
@@ -474,38 +532,32 @@ pub async fn handle_message(
                 }
             }
 
-            let target_sender = {
+            let server_id = {
                 let voice = state.voice.lock().await;
-                let mut found_server: Option<ServerId> = None;
-                for ((server_id, c), _) in voice.voice_chats.iter() {
-                    if c == &chat_id {
-                        found_server = Some(server_id.clone());
-                        break;
-                    }
-                }
-                drop(voice);
-                if let Some(server_id) = found_server {
-                    state.get_voice_peer_sender(&server_id, &chat_id, &to_peer).await
-                } else {
-                    None
-                }
+                voice.voice_chats.keys().find(|(_, c)| c == &chat_id).map(|(server_id, _)| server_id.clone())
             };
 
-            if let Some(target) = target_sender {
-                let forward_msg = SignalingMessage::VoiceOffer {
-                    from_peer,
-                    from_user,
-                    to_peer,
-                    chat_id,
-                    sdp,
-                };
-                let json = serde_json::to_string(&forward_msg)
-                    .map_err(|e| format!("Failed to serialize VoiceOffer: {}", e))?;
-                target
-                    .send(hyper_tungstenite::tungstenite::Message::Text(json))
-                    .map_err(|e| format!("Failed to forward VoiceOffer: {}", e))?;
-            } else {
+            let forwarded = match server_id {
+                Some(server_id) => {
+                    let forward_msg = SignalingMessage::VoiceOffer {
+                        from_peer,
+                        from_user,
+                        to_peer: to_peer.clone(),
+                        chat_id: chat_id.clone(),
+                        sdp,
+                    };
+                    let json = serde_json::to_string(&forward_msg)
+                        .map_err(|e| format!("Failed to serialize VoiceOffer: {}", e))?;
+                    state.forward_to_voice_peer(&server_id, &chat_id, &to_peer, json).await.map_err(|e| {
+                        metrics().forward_failure("send_failed");
+                        format!("Failed to forward VoiceOffer: {}", e)
+                    })?
+                }
+                None => false,
+            };
+            if !forwarded {
                 warn!("Target peer {} not found in chat {} for VoiceOffer", to_peer, chat_id);
+                metrics().forward_failure("peer_not_found");
             }
 
             Ok(())
@@ -521,38 +573,32 @@ pub async fn handle_message(
                 }
             }
 
-            let target_sender = {
+            let server_id = {
                 let voice = state.voice.lock().await;
-                let mut found_server: Option<ServerId> = None;
-                for ((server_id, c), _) in voice.voice_chats.iter() {
-                    if c == &chat_id {
-                        found_server = Some(server_id.clone());
-                        break;
-                    }
-                }
-                drop(voice);
-                if let Some(server_id) = found_server {
-                    state.get_voice_peer_sender(&server_id, &chat_id, &to_peer).await
-                } else {
-                    None
-                }
+                voice.voice_chats.keys().find(|(_, c)| c == &chat_id).map(|(server_id, _)| server_id.clone())
             };
 
-            if let Some(target) = target_sender {
-                let forward_msg = SignalingMessage::VoiceAnswer {
-                    from_peer,
-                    from_user,
-                    to_peer,
-                    chat_id,
-                    sdp,
-                };
-                let json = serde_json::to_string(&forward_msg)
-                    .map_err(|e| format!("Failed to serialize VoiceAnswer: {}", e))?;
-                target
-                    .send(hyper_tungstenite::tungstenite::Message::Text(json))
-                    .map_err(|e| format!("Failed to forward VoiceAnswer: {}", e))?;
-            } else {
+            let forwarded = match server_id {
+                Some(server_id) => {
+                    let forward_msg = SignalingMessage::VoiceAnswer {
+                        from_peer,
+                        from_user,
+                        to_peer: to_peer.clone(),
+                        chat_id: chat_id.clone(),
+                        sdp,
+                    };
+                    let json = serde_json::to_string(&forward_msg)
+                        .map_err(|e| format!("Failed to serialize VoiceAnswer: {}", e))?;
+                    state.forward_to_voice_peer(&server_id, &chat_id, &to_peer, json).await.map_err(|e| {
+                        metrics().forward_failure("send_failed");
+                        format!("Failed to forward VoiceAnswer: {}", e)
+                    })?
+                }
+                None => false,
+            };
+            if !forwarded {
                 warn!("Target peer {} not found in chat {} for VoiceAnswer", to_peer, chat_id);
+                metrics().forward_failure("peer_not_found");
             }
 
             Ok(())
@@ -566,37 +612,32 @@ pub async fn handle_message(
                 }
             }
 
-            let target_sender = {
+            let server_id = {
                 let voice = state.voice.lock().await;
-                let mut found_server: Option<ServerId> = None;
-                for ((server_id, c), _) in voice.voice_chats.iter() {
-                    if c == &chat_id {
-                        found_server = Some(server_id.clone());
-                        break;
-                    }
-                }
-                drop(voice);
-                if let Some(server_id) = found_server {
-                    state.get_voice_peer_sender(&server_id, &chat_id, &to_peer).await
-                } else {
-                    None
-                }
+                voice.voice_chats.keys().find(|(_, c)| c == &chat_id).map(|(server_id, _)| server_id.clone())
             };
 
-            if let Some(target) = target_sender {
-                let forward_msg = SignalingMessage::VoiceIceCandidate {
-                    from_peer,
-                    to_peer,
-                    chat_id,
-                    candidate,
-                };
-                let json = serde_json::to_string(&forward_msg)
-                    .map_err(|e| format!("Failed to serialize VoiceIceCandidate: {}", e))?;
-                target
-                    .send(hyper_tungstenite::tungstenite::Message::Text(json))
-                    .map_err(|e| format!("Failed to forward VoiceIceCandidate: {}", e))?;
+            let forwarded = match server_id {
+                Some(server_id) => {
+                    let forward_msg = SignalingMessage::VoiceIceCandidate {
+                        from_peer,
+                        to_peer: to_peer.clone(),
+                        chat_id: chat_id.clone(),
+                        candidate,
+                    };
+                    let json = serde_json::to_string(&forward_msg)
+                        .map_err(|e| format!("Failed to serialize VoiceIceCandidate: {}", e))?;
+                    state.forward_to_voice_peer(&server_id, &chat_id, &to_peer, json).await.map_err(|e| {
+                        metrics().forward_failure("send_failed");
+                        format!("Failed to forward VoiceIceCandidate: {}", e)
+                    })?
+                }
+                None => false,
+            };
+            if !forwarded {
+                // Don't warn on missing peer for ICE candidates - they may have left
+                metrics().forward_failure("peer_not_found");
             }
-            // Don't warn on missing peer for ICE candidates - they may have left
 
             Ok(())
         }
@@ -619,3 +660,77 @@ pub async fn handle_message(
         _ => Err("Invalid message type".to_string()),
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use base64::Engine;
+    use ed25519_dalek::{Signer, SigningKey};
+    use crate::send_queue::{self, ConnReceiver, SendQueueStats};
+
+    fn socket() -> (WebSocketSender, ConnReceiver) {
+        send_queue::channel(8, Arc::new(SendQueueStats::default()))
+    }
+
+    fn register() -> SignalingMessage {
+        SignalingMessage::Register { server_id: "s".to_string(), peer_id: "p1".to_string(), signing_pubkey: None }
+    }
+
+    fn presence_hello(user_id: &str) -> SignalingMessage {
+        SignalingMessage::PresenceHello { user_id: user_id.to_string(), signing_pubkeys: Vec::new(), active_signing_pubkey: None }
+    }
+
+    /// Answer the connection's challenge with `key`, claiming `user_id`
+    async fn authenticate(state: &SharedState, conn_id: &ConnId, sender: &WebSocketSender, key: &SigningKey, user_id: &str) -> Result<(), String> {
+        let nonce = state.signaling.lock().await.issue_auth_challenge(conn_id);
+        let signature = base64::engine::general_purpose::STANDARD.encode(key.sign(&auth::auth_challenge_payload(&nonce)).to_bytes());
+        let msg = SignalingMessage::AuthResponse {
+            user_id: user_id.to_string(),
+            public_key: hex::encode(key.verifying_key().to_bytes()),
+            signature,
+        };
+        handle_message(msg, conn_id, state, sender).await
+    }
+
+    #[tokio::test]
+    async fn unauthenticated_connections_can_only_authenticate() {
+        let state: SharedState = Arc::new(AppState::new(None));
+        let conn_id = "c1".to_string();
+        let (sender, _rx) = socket();
+
+        assert_eq!(handle_message(register(), &conn_id, &state, &sender).await, Err("Not authenticated".to_string()));
+        assert_eq!(handle_message(presence_hello("alice"), &conn_id, &state, &sender).await, Err("Not authenticated".to_string()));
+        assert!(state.signaling.lock().await.peer_senders.is_empty());
+        // Keepalives are fine before the handshake
+        assert!(handle_message(SignalingMessage::Ping, &conn_id, &state, &sender).await.is_ok());
+    }
+
+    #[tokio::test]
+    async fn failed_authentication_leaves_the_connection_unauthenticated() {
+        let state: SharedState = Arc::new(AppState::new(None));
+        let conn_id = "c1".to_string();
+        let (sender, _rx) = socket();
+        let key = SigningKey::from_bytes(&[1; 32]);
+        let other_user_id = auth::user_id_for_key(&SigningKey::from_bytes(&[2; 32]).verifying_key());
+
+        assert!(authenticate(&state, &conn_id, &sender, &key, &other_user_id).await.is_err());
+        assert_eq!(handle_message(register(), &conn_id, &state, &sender).await, Err("Not authenticated".to_string()));
+        // The failed attempt was answered with a fresh challenge to retry on
+        assert!(state.signaling.lock().await.take_auth_challenge(&conn_id).is_some());
+    }
+
+    #[tokio::test]
+    async fn authenticated_connections_may_only_speak_for_their_user() {
+        let state: SharedState = Arc::new(AppState::new(None));
+        let conn_id = "c1".to_string();
+        let (sender, _rx) = socket();
+        let key = SigningKey::from_bytes(&[1; 32]);
+        let user_id = auth::user_id_for_key(&key.verifying_key());
+
+        authenticate(&state, &conn_id, &sender, &key, &user_id).await.unwrap();
+        assert!(handle_message(register(), &conn_id, &state, &sender).await.is_ok());
+        assert!(handle_message(presence_hello(&user_id), &conn_id, &state, &sender).await.is_ok());
+        let err = handle_message(presence_hello("mallory"), &conn_id, &state, &sender).await.unwrap_err();
+        assert!(err.contains("does not match"), "{}", err);
+    }
+}
@@ -0,0 +1,198 @@
+//! Versioned database schema (Postgres and SQLite).
+//!
+//! Migrations are plain SQL files under `migrations/`, embedded in the binary and applied in
+//! order. `schema_version` records each one applied. Every migration runs in its own
+//! transaction; on Postgres under an advisory lock so beacons starting together don't race. A
+//! database already migrated past what this binary knows is refused rather than used.
+//!
+//! Never edit a released migration; add a new one with the next version number. The two
+//! dialects are numbered separately.
+
+use std::fmt;
+
+use log::info;
+#[cfg(any(feature = "postgres", feature = "sqlite"))]
+use sqlx::Executor;
+#[cfg(feature = "postgres")]
+use sqlx::PgPool;
+#[cfg(feature = "sqlite")]
+use sqlx::SqlitePool;
+
+pub struct Migration {
+    pub version: i32,
+    pub name: &'static str,
+    pub sql: &'static str,
+}
+
+/// Ordered by version, starting at 1 with no gaps
+#[cfg(feature = "postgres")]
+pub const MIGRATIONS: &[Migration] = &[
+    Migration {
+        version: 1,
+        name: "base_tables",
+        sql: include_str!("migrations/0001_base_tables.sql"),
+    },
+    Migration {
+        version: 2,
+        name: "house_signers_and_key_rotation",
+        sql: include_str!("migrations/0002_house_signers_and_key_rotation.sql"),
+    },
+    Migration {
+        version: 3,
+        name: "event_sequence_numbers",
+        sql: include_str!("migrations/0003_event_sequence_numbers.sql"),
+    },
+];
+
+/// SQLite starts from the schema Postgres reached at version 3
+#[cfg(feature = "sqlite")]
+pub const SQLITE_MIGRATIONS: &[Migration] = &[Migration {
+    version: 1,
+    name: "initial",
+    sql: include_str!("migrations/sqlite/0001_initial.sql"),
+}];
+
+/// Advisory lock held while migrating (arbitrary, shared by every beacon)
+#[cfg(feature = "postgres")]
+const MIGRATION_LOCK_KEY: i64 = 0x0062_6561_636f_6e00;
+
+#[derive(Debug)]
+pub enum MigrateError {
+    /// The database was migrated by a newer beacon
+    SchemaTooNew { found: i32, supported: i32 },
+    Failed(String),
+}
+
+impl fmt::Display for MigrateError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            MigrateError::SchemaTooNew { found, supported } => write!(
+                f,
+                "database schema is version {} but this beacon only knows up to {}; upgrade the beacon",
+                found, supported
+            ),
+            MigrateError::Failed(e) => write!(f, "{}", e),
+        }
+    }
+}
+
+/// Highest schema version this binary can migrate to
+pub fn latest_version(migrations: &[Migration]) -> i32 {
+    migrations.last().map(|m| m.version).unwrap_or(0)
+}
+
+const CREATE_SCHEMA_VERSION: &str = r#"
+    CREATE TABLE IF NOT EXISTS schema_version (
+      version INTEGER PRIMARY KEY,
+      name TEXT NOT NULL,
+      applied_at TIMESTAMPTZ NOT NULL DEFAULT CURRENT_TIMESTAMP
+    );
+"#;
+
+const READ_SCHEMA_VERSION: &str = "SELECT COALESCE(MAX(version), 0) FROM schema_version";
+
+#[cfg(feature = "postgres")]
+async fn current_version<'e, E>(executor: E) -> Result<i32, MigrateError>
+where
+    E: sqlx::PgExecutor<'e>,
+{
+    sqlx::query_scalar::<_, i32>(READ_SCHEMA_VERSION)
+        .fetch_one(executor)
+        .await
+        .map_err(|e| MigrateError::Failed(format!("read schema_version: {}", e)))
+}
+
+/// Apply every pending Postgres migration. Returns the resulting schema version.
+#[cfg(feature = "postgres")]
+pub async fn migrate(pool: &PgPool) -> Result<i32, MigrateError> {
+    // CREATE TABLE IF NOT EXISTS can still collide on the catalog when two beacons run it at once
+    let create_failed = |e: sqlx::Error| MigrateError::Failed(format!("create schema_version: {}", e));
+    let mut tx = pool.begin().await.map_err(create_failed)?;
+    sqlx::query("SELECT pg_advisory_xact_lock($1)")
+        .bind(MIGRATION_LOCK_KEY)
+        .execute(&mut *tx)
+        .await
+        .map_err(create_failed)?;
+    (&mut *tx).execute(CREATE_SCHEMA_VERSION).await.map_err(create_failed)?;
+    tx.commit().await.map_err(create_failed)?;
+
+    let supported = latest_version(MIGRATIONS);
+    let mut version = current_version(pool).await?;
+    if version > supported {
+        return Err(MigrateError::SchemaTooNew { found: version, supported });
+    }
+
+    let pending = MIGRATIONS.iter().filter(|m| m.version > version).collect::<Vec<_>>();
+    for migration in pending {
+        let failed = |step: &str, e: sqlx::Error| {
+            MigrateError::Failed(format!("migration {} ({}) {}: {}", migration.version, migration.name, step, e))
+        };
+        let mut tx = pool.begin().await.map_err(|e| failed("begin", e))?;
+        sqlx::query("SELECT pg_advisory_xact_lock($1)")
+            .bind(MIGRATION_LOCK_KEY)
+            .execute(&mut *tx)
+            .await
+            .map_err(|e| failed("lock", e))?;
+
+        // Another beacon may have migrated while we waited for the lock
+        let applied = current_version(&mut *tx).await?;
+        if applied > supported {
+            return Err(MigrateError::SchemaTooNew { found: applied, supported });
+        }
+        if applied >= migration.version {
+            version = applied;
+            continue;
+        }
+
+        info!("Applying schema migration {} ({})", migration.version, migration.name);
+        (&mut *tx).execute(migration.sql).await.map_err(|e| failed("apply", e))?;
+        sqlx::query("INSERT INTO schema_version (version, name) VALUES ($1, $2)")
+            .bind(migration.version)
+            .bind(migration.name)
+            .execute(&mut *tx)
+            .await
+            .map_err(|e| failed("record", e))?;
+        tx.commit().await.map_err(|e| failed("commit", e))?;
+        version = migration.version;
+    }
+
+    Ok(version)
+}
+
+/// Apply every pending SQLite migration. Returns the resulting schema version.
+#[cfg(feature = "sqlite")]
+pub async fn migrate_sqlite(pool: &SqlitePool) -> Result<i32, MigrateError> {
+    pool.execute(CREATE_SCHEMA_VERSION)
+        .await
+        .map_err(|e| MigrateError::Failed(format!("create schema_version: {}", e)))?;
+
+    let supported = latest_version(SQLITE_MIGRATIONS);
+    let mut version = sqlx::query_scalar::<_, i32>(READ_SCHEMA_VERSION)
+        .fetch_one(pool)
+        .await
+        .map_err(|e| MigrateError::Failed(format!("read schema_version: {}", e)))?;
+    if version > supported {
+        return Err(MigrateError::SchemaTooNew { found: version, supported });
+    }
+
+    // The file belongs to one beacon, so there's nobody to race with
+    let pending = SQLITE_MIGRATIONS.iter().filter(|m| m.version > version).collect::<Vec<_>>();
+    for migration in pending {
+        let failed = |step: &str, e: sqlx::Error| {
+            MigrateError::Failed(format!("migration {} ({}) {}: {}", migration.version, migration.name, step, e))
+        };
+        info!("Applying SQLite schema migration {} ({})", migration.version, migration.name);
+        let mut tx = pool.begin().await.map_err(|e| failed("begin", e))?;
+        (&mut *tx).execute(migration.sql).await.map_err(|e| failed("apply", e))?;
+        sqlx::query("INSERT INTO schema_version (version, name) VALUES (?, ?)")
+            .bind(migration.version)
+            .bind(migration.name)
+            .execute(&mut *tx)
+            .await
+            .map_err(|e| failed("record", e))?;
+        tx.commit().await.map_err(|e| failed("commit", e))?;
+        version = migration.version;
+    }
+
+    Ok(version)
+}
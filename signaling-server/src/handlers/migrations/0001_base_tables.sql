@@ -0,0 +1,46 @@
+-- Tables as created by beacons before versioned migrations. IF NOT EXISTS so databases set up
+-- by those beacons adopt this version without changes.
+
+CREATE TABLE IF NOT EXISTS profiles (
+  user_id TEXT PRIMARY KEY,
+  display_name TEXT NOT NULL,
+  real_name TEXT,
+  show_real_name BOOLEAN NOT NULL,
+  rev BIGINT NOT NULL,
+  updated_at TIMESTAMPTZ NOT NULL
+);
+
+CREATE TABLE IF NOT EXISTS server_hints (
+  signing_pubkey TEXT PRIMARY KEY,
+  encrypted_state TEXT NOT NULL,
+  signature TEXT NOT NULL,
+  last_updated TIMESTAMPTZ NOT NULL
+);
+
+CREATE TABLE IF NOT EXISTS invite_tokens (
+  code TEXT PRIMARY KEY,
+  signing_pubkey TEXT NOT NULL,
+  encrypted_payload TEXT NOT NULL,
+  signature TEXT NOT NULL,
+  created_at TIMESTAMPTZ NOT NULL,
+  expires_at TIMESTAMPTZ NOT NULL,
+  max_uses INTEGER NOT NULL,
+  remaining_uses INTEGER NOT NULL
+);
+
+CREATE TABLE IF NOT EXISTS server_events (
+  event_id TEXT PRIMARY KEY,
+  signing_pubkey TEXT NOT NULL,
+  event_type TEXT NOT NULL,
+  encrypted_payload TEXT NOT NULL,
+  signature TEXT NOT NULL,
+  timestamp TIMESTAMPTZ NOT NULL
+);
+
+CREATE TABLE IF NOT EXISTS member_acks (
+  signing_pubkey TEXT NOT NULL,
+  user_id TEXT NOT NULL,
+  last_event_id TEXT NOT NULL,
+  updated_at TIMESTAMPTZ NOT NULL,
+  PRIMARY KEY (signing_pubkey, user_id)
+);
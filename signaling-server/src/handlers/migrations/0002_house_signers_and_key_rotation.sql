@@ -0,0 +1,19 @@
+-- Signed hints, signer authorization and key rotation
+
+ALTER TABLE server_hints ADD COLUMN IF NOT EXISTS signer_pubkey TEXT;
+ALTER TABLE server_hints ADD COLUMN IF NOT EXISTS key_epoch INTEGER NOT NULL DEFAULT 0;
+ALTER TABLE server_hints ADD COLUMN IF NOT EXISTS revision BIGINT NOT NULL DEFAULT 0;
+
+CREATE TABLE IF NOT EXISTS house_signers (
+  signing_pubkey TEXT NOT NULL,
+  signer_pubkey TEXT NOT NULL,
+  authorized_at TIMESTAMPTZ NOT NULL,
+  PRIMARY KEY (signing_pubkey, signer_pubkey)
+);
+
+CREATE TABLE IF NOT EXISTS house_key_rotations (
+  signing_pubkey TEXT PRIMARY KEY,
+  key_epoch INTEGER NOT NULL,
+  sealed_keys TEXT NOT NULL,
+  created_at TIMESTAMPTZ NOT NULL
+);
@@ -0,0 +1,27 @@
+-- Per-house event sequence numbers and seq-based acks
+
+ALTER TABLE server_events ADD COLUMN IF NOT EXISTS seq BIGINT NOT NULL DEFAULT 0;
+
+-- Number events stored before seqs existed, in their old (timestamp, event_id) order
+UPDATE server_events e
+SET seq = r.n
+FROM (
+  SELECT event_id, ROW_NUMBER() OVER (PARTITION BY signing_pubkey ORDER BY timestamp, event_id) AS n
+  FROM server_events
+) r
+WHERE e.event_id = r.event_id
+  AND e.signing_pubkey IN (SELECT signing_pubkey FROM server_events WHERE seq = 0);
+
+CREATE INDEX IF NOT EXISTS server_events_seq_idx ON server_events (signing_pubkey, seq);
+
+CREATE TABLE IF NOT EXISTS house_event_seqs (
+  signing_pubkey TEXT PRIMARY KEY,
+  last_seq BIGINT NOT NULL
+);
+
+INSERT INTO house_event_seqs (signing_pubkey, last_seq)
+SELECT signing_pubkey, MAX(seq) FROM server_events GROUP BY signing_pubkey
+ON CONFLICT (signing_pubkey) DO UPDATE
+SET last_seq = GREATEST(house_event_seqs.last_seq, EXCLUDED.last_seq);
+
+ALTER TABLE member_acks ADD COLUMN IF NOT EXISTS last_seq BIGINT NOT NULL DEFAULT 0;
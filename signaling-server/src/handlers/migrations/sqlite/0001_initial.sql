@@ -0,0 +1,72 @@
+-- Same tables as the Postgres schema at version 3. Timestamps are RFC 3339 text in UTC, which
+-- sorts chronologically.
+
+CREATE TABLE IF NOT EXISTS profiles (
+  user_id TEXT PRIMARY KEY,
+  display_name TEXT NOT NULL,
+  real_name TEXT,
+  show_real_name BOOLEAN NOT NULL,
+  rev INTEGER NOT NULL,
+  updated_at TEXT NOT NULL
+);
+
+CREATE TABLE IF NOT EXISTS server_hints (
+  signing_pubkey TEXT PRIMARY KEY,
+  encrypted_state TEXT NOT NULL,
+  signature TEXT NOT NULL,
+  signer_pubkey TEXT,
+  key_epoch INTEGER NOT NULL DEFAULT 0,
+  revision INTEGER NOT NULL DEFAULT 0,
+  last_updated TEXT NOT NULL
+);
+
+CREATE TABLE IF NOT EXISTS house_signers (
+  signing_pubkey TEXT NOT NULL,
+  signer_pubkey TEXT NOT NULL,
+  authorized_at TEXT NOT NULL,
+  PRIMARY KEY (signing_pubkey, signer_pubkey)
+);
+
+CREATE TABLE IF NOT EXISTS house_key_rotations (
+  signing_pubkey TEXT PRIMARY KEY,
+  key_epoch INTEGER NOT NULL,
+  sealed_keys TEXT NOT NULL,
+  created_at TEXT NOT NULL
+);
+
+CREATE TABLE IF NOT EXISTS invite_tokens (
+  code TEXT PRIMARY KEY,
+  signing_pubkey TEXT NOT NULL,
+  encrypted_payload TEXT NOT NULL,
+  signature TEXT NOT NULL,
+  created_at TEXT NOT NULL,
+  expires_at TEXT NOT NULL,
+  max_uses INTEGER NOT NULL,
+  remaining_uses INTEGER NOT NULL
+);
+
+CREATE TABLE IF NOT EXISTS server_events (
+  event_id TEXT PRIMARY KEY,
+  signing_pubkey TEXT NOT NULL,
+  event_type TEXT NOT NULL,
+  encrypted_payload TEXT NOT NULL,
+  signature TEXT NOT NULL,
+  timestamp TEXT NOT NULL,
+  seq INTEGER NOT NULL DEFAULT 0
+);
+
+CREATE INDEX IF NOT EXISTS server_events_seq_idx ON server_events (signing_pubkey, seq);
+
+CREATE TABLE IF NOT EXISTS house_event_seqs (
+  signing_pubkey TEXT PRIMARY KEY,
+  last_seq INTEGER NOT NULL
+);
+
+CREATE TABLE IF NOT EXISTS member_acks (
+  signing_pubkey TEXT NOT NULL,
+  user_id TEXT NOT NULL,
+  last_event_id TEXT NOT NULL,
+  last_seq INTEGER NOT NULL DEFAULT 0,
+  updated_at TEXT NOT NULL,
+  PRIMARY KEY (signing_pubkey, user_id)
+);
@@ -3,6 +3,10 @@ pub mod http;
 
 #[cfg(feature = "postgres")]
 pub mod db;
+#[cfg(feature = "sqlite")]
+pub mod sqlite;
+#[cfg(any(feature = "postgres", feature = "sqlite"))]
+pub mod migrations;
 #[cfg(feature = "redis-backend")]
 pub mod redis;
 
@@ -1,7 +1,13 @@
 #[cfg(feature = "redis-backend")]
-use crate::{SigningPubkey, state::presence::PresenceUserStatus};
+use std::collections::{HashMap, HashSet};
 #[cfg(feature = "redis-backend")]
-use redis::AsyncCommands;
+use crate::{PeerId, ServerId, SigningPubkey, state::presence::PresenceUserStatus, state::voice::VoicePeerInfo};
+#[cfg(feature = "redis-backend")]
+use crate::state::events::{INVITE_BACKOFF_BASE_SECS, INVITE_BACKOFF_MAX_SECS, INVITE_FAILURE_DECAY_SECS, INVITE_FREE_FAILURES_PER_IP};
+#[cfg(feature = "redis-backend")]
+use redis::{aio::ConnectionManager, AsyncCommands};
+#[cfg(feature = "redis-backend")]
+use serde::{Deserialize, Serialize};
 
 #[cfg(feature = "redis-backend")]
 pub fn redis_user_key(user_id: &str) -> String {
@@ -173,3 +179,395 @@ pub async fn redis_presence_refresh(
         .map_err(|e| format!("redis_presence_refresh query: {}", e))?;
     Ok(())
 }
+
+#[cfg(feature = "redis-backend")]
+pub fn redis_invite_failure_key(key: &str) -> String {
+    format!("invite_fail:{}", key)
+}
+
+/// `EventState::begin_invite_lookup` against Redis, atomically across instances. The key is
+/// checked against the `invite_backoff` schedule and, unless locked out, charged a failure.
+/// Returns the lockout left in seconds, 0 if the lookup may go ahead.
+#[cfg(feature = "redis-backend")]
+pub async fn redis_begin_invite_lookup(
+    client: &redis::Client,
+    key: &str,
+    now_ts: i64,
+) -> Result<i64, String> {
+    let mut conn = client
+        .get_multiplexed_tokio_connection()
+        .await
+        .map_err(|e| format!("redis_begin_invite_lookup conn: {}", e))?;
+    let script = redis::Script::new(
+        r#"
+        local now, decay, base, cap, free = tonumber(ARGV[1]), tonumber(ARGV[2]), tonumber(ARGV[3]), tonumber(ARGV[4]), tonumber(ARGV[5])
+        local row = redis.call('HMGET', KEYS[1], 'count', 'last')
+        local last = tonumber(row[2]) or now
+        local count = math.max((tonumber(row[1]) or 0) - math.floor((now - last) / decay), 0)
+        if count > free then
+          local wait = last + math.min(base * 2 ^ math.min(count - free - 1, 20), cap) - now
+          if wait > 0 then return wait end
+        end
+        redis.call('HSET', KEYS[1], 'count', count + 1, 'last', now)
+        redis.call('EXPIRE', KEYS[1], (count + 1) * decay)
+        return 0
+        "#,
+    );
+    script
+        .key(redis_invite_failure_key(key))
+        .arg(now_ts)
+        .arg(INVITE_FAILURE_DECAY_SECS)
+        .arg(INVITE_BACKOFF_BASE_SECS)
+        .arg(INVITE_BACKOFF_MAX_SECS)
+        .arg(INVITE_FREE_FAILURES_PER_IP)
+        .invoke_async::<_, i64>(&mut conn)
+        .await
+        .map_err(|e| format!("redis_begin_invite_lookup query: {}", e))
+}
+
+/// `EventState::refund_invite_lookup` against Redis
+#[cfg(feature = "redis-backend")]
+pub async fn redis_refund_invite_lookup(client: &redis::Client, key: &str) -> Result<(), String> {
+    let mut conn = client
+        .get_multiplexed_tokio_connection()
+        .await
+        .map_err(|e| format!("redis_refund_invite_lookup conn: {}", e))?;
+    let script = redis::Script::new(
+        r#"
+        if redis.call('HINCRBY', KEYS[1], 'count', -1) < 0 then redis.call('HSET', KEYS[1], 'count', 0) end
+        return 0
+        "#,
+    );
+    script
+        .key(redis_invite_failure_key(key))
+        .invoke_async::<_, i64>(&mut conn)
+        .await
+        .map_err(|e| format!("redis_refund_invite_lookup query: {}", e))?;
+    Ok(())
+}
+
+// ============================================
+// Cross-instance routing (see crate::cluster)
+// ============================================
+
+#[cfg(feature = "redis-backend")]
+pub fn redis_peer_route_key(peer_id: &str) -> String {
+    format!("route:peer:{}", peer_id)
+}
+
+/// Route value: the owning user and the instance their socket is on (`{user_id}@{instance_id}`)
+#[cfg(feature = "redis-backend")]
+pub fn redis_peer_route_value(user_id: &str, instance_id: &str) -> String {
+    format!("{}@{}", user_id, instance_id)
+}
+
+/// Set a peer route unless another user holds it. Routes written before they carried a user
+/// (no `@`) can be taken over.
+#[cfg(feature = "redis-backend")]
+const CLAIM_PEER_ROUTE_SCRIPT: &str = r#"
+local cur = redis.call('GET', KEYS[1])
+if cur and string.find(cur, '@', 1, true) and string.sub(cur, 1, #ARGV[2]) ~= ARGV[2] then
+  return 0
+end
+redis.call('SET', KEYS[1], ARGV[1], 'EX', ARGV[3])
+return 1
+"#;
+
+#[cfg(feature = "redis-backend")]
+pub fn redis_instance_alive_key(instance_id: &str) -> String {
+    format!("beacon:alive:{}", instance_id)
+}
+
+#[cfg(feature = "redis-backend")]
+pub fn redis_voice_room_key(server_id: &str, chat_id: &str) -> String {
+    format!("voice:room:{}:{}", server_id, chat_id)
+}
+
+/// Value stored per peer_id in a voice room hash
+#[cfg(feature = "redis-backend")]
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct RedisVoiceMember {
+    pub user_id: String,
+    /// Beacon instance the peer's socket is connected to
+    pub instance_id: String,
+}
+
+#[cfg(feature = "redis-backend")]
+pub async fn redis_publish(conn: &ConnectionManager, channel: &str, payload: &str) -> Result<(), String> {
+    let mut conn = conn.clone();
+    conn.publish::<_, _, ()>(channel, payload)
+        .await
+        .map_err(|e| format!("redis_publish: {}", e))
+}
+
+/// Point `routes` ((peer_id, user_id)) at this instance for `ttl_secs`, unless another user
+/// holds the peer_id. Returns the peer_ids that are taken.
+#[cfg(feature = "redis-backend")]
+pub async fn redis_claim_peer_routes(
+    conn: &ConnectionManager,
+    instance_id: &str,
+    ttl_secs: u64,
+    routes: &[(PeerId, String)],
+) -> Result<Vec<PeerId>, String> {
+    let mut conn = conn.clone();
+    let script = redis::Script::new(CLAIM_PEER_ROUTE_SCRIPT);
+    let mut taken = Vec::new();
+    for (peer_id, user_id) in routes {
+        let claimed: i64 = script
+            .key(redis_peer_route_key(peer_id))
+            .arg(redis_peer_route_value(user_id, instance_id))
+            .arg(format!("{}@", user_id))
+            .arg(ttl_secs)
+            .invoke_async(&mut conn)
+            .await
+            .map_err(|e| format!("redis_claim_peer_routes: {}", e))?;
+        if claimed == 0 {
+            taken.push(peer_id.clone());
+        }
+    }
+    Ok(taken)
+}
+
+/// Mark this instance alive and refresh its peers' routes, both for `ttl_secs`.
+#[cfg(feature = "redis-backend")]
+pub async fn redis_cluster_heartbeat(
+    conn: &ConnectionManager,
+    instance_id: &str,
+    ttl_secs: u64,
+    routes: &[(PeerId, String)],
+) -> Result<(), String> {
+    let mut alive = conn.clone();
+    alive.set_ex::<_, _, ()>(redis_instance_alive_key(instance_id), 1, ttl_secs)
+        .await
+        .map_err(|e| format!("redis_cluster_heartbeat: {}", e))?;
+    let taken = redis_claim_peer_routes(conn, instance_id, ttl_secs, routes).await?;
+    if !taken.is_empty() {
+        log::warn!("Peer routes held by other users, not refreshed: {:?}", taken);
+    }
+    Ok(())
+}
+
+/// Instance a peer is connected to
+#[cfg(feature = "redis-backend")]
+pub async fn redis_peer_route(conn: &ConnectionManager, peer_id: &str) -> Result<Option<String>, String> {
+    let mut conn = conn.clone();
+    let value = conn.get::<_, Option<String>>(redis_peer_route_key(peer_id))
+        .await
+        .map_err(|e| format!("redis_peer_route: {}", e))?;
+    Ok(value.map(|v| match v.rsplit_once('@') {
+        Some((_, instance_id)) => instance_id.to_string(),
+        None => v,
+    }))
+}
+
+/// Remove routes for peers that left, unless another instance has taken the peer_id over.
+#[cfg(feature = "redis-backend")]
+pub async fn redis_delete_peer_routes(
+    conn: &ConnectionManager,
+    instance_id: &str,
+    peer_ids: &[PeerId],
+) -> Result<(), String> {
+    let mut conn = conn.clone();
+    let script = redis::Script::new(
+        r#"
+local cur = redis.call('GET', KEYS[1])
+if cur == ARGV[1] or (cur and string.sub(cur, -#ARGV[2]) == ARGV[2]) then
+  return redis.call('DEL', KEYS[1])
+end
+return 0
+"#,
+    );
+    for peer_id in peer_ids {
+        script
+            .key(redis_peer_route_key(peer_id))
+            .arg(instance_id)
+            .arg(format!("@{}", instance_id))
+            .invoke_async::<_, i64>(&mut conn)
+            .await
+            .map_err(|e| format!("redis_delete_peer_routes: {}", e))?;
+    }
+    Ok(())
+}
+
+/// Why a voice room join was refused
+#[cfg(feature = "redis-backend")]
+#[derive(Debug)]
+pub enum RedisVoiceJoinError {
+    /// Another user's live peer already has this peer_id in the room
+    PeerTaken,
+    Redis(String),
+}
+
+/// Add a peer to a voice room and return the other members on live instances. Entries for
+/// the same user (reconnect with a new peer_id) and for dead instances are dropped; a peer_id
+/// held by another user on a live instance is refused.
+#[cfg(feature = "redis-backend")]
+pub async fn redis_voice_join(
+    conn: &ConnectionManager,
+    server_id: &str,
+    chat_id: &str,
+    peer_id: &str,
+    member: &RedisVoiceMember,
+) -> Result<Vec<VoicePeerInfo>, RedisVoiceJoinError> {
+    let err = |what: &str, e: redis::RedisError| RedisVoiceJoinError::Redis(format!("redis_voice_join {}: {}", what, e));
+    let mut conn = conn.clone();
+    let room_key = redis_voice_room_key(server_id, chat_id);
+    let entries: HashMap<String, String> = conn
+        .hgetall(&room_key)
+        .await
+        .map_err(|e| err("hgetall", e))?;
+
+    let mut stale: Vec<String> = Vec::new();
+    let mut members: Vec<(String, RedisVoiceMember)> = Vec::new();
+    // Another user's entry under our peer_id: refused below if their instance is alive
+    let mut holder: Option<RedisVoiceMember> = None;
+    for (other_peer, value) in entries {
+        match serde_json::from_str::<RedisVoiceMember>(&value) {
+            Ok(m) if other_peer == peer_id => {
+                if m.user_id != member.user_id {
+                    holder = Some(m);
+                }
+            }
+            Ok(m) if m.user_id != member.user_id => members.push((other_peer, m)),
+            _ => stale.push(other_peer),
+        }
+    }
+
+    let instances: Vec<String> = members
+        .iter()
+        .map(|(_, m)| m.instance_id.clone())
+        .chain(holder.iter().map(|m| m.instance_id.clone()))
+        .collect::<HashSet<_>>()
+        .into_iter()
+        .collect();
+    let mut alive: HashSet<String> = HashSet::new();
+    if !instances.is_empty() {
+        let mut pipe = redis::pipe();
+        for instance_id in &instances {
+            pipe.exists(redis_instance_alive_key(instance_id));
+        }
+        let flags: Vec<bool> = pipe
+            .query_async(&mut conn)
+            .await
+            .map_err(|e| err("alive", e))?;
+        alive.extend(instances.into_iter().zip(flags).filter(|(_, up)| *up).map(|(id, _)| id));
+    }
+    if let Some(holder) = holder {
+        if alive.contains(&holder.instance_id) {
+            return Err(RedisVoiceJoinError::PeerTaken);
+        }
+    }
+
+    let mut others = Vec::new();
+    for (other_peer, m) in members {
+        if alive.contains(&m.instance_id) {
+            others.push(VoicePeerInfo { peer_id: other_peer, user_id: m.user_id });
+        } else {
+            stale.push(other_peer);
+        }
+    }
+
+    if !stale.is_empty() {
+        conn.hdel::<_, _, ()>(&room_key, stale)
+            .await
+            .map_err(|e| err("hdel", e))?;
+    }
+    // Set our entry unless a live user took the peer_id since we looked; entries from dead
+    // instances were dropped above
+    let value = serde_json::to_string(member)
+        .map_err(|e| RedisVoiceJoinError::Redis(format!("redis_voice_join encode: {}", e)))?;
+    let script = redis::Script::new(
+        r#"
+local cur = redis.call('HGET', KEYS[1], ARGV[1])
+if cur then
+  local ok, m = pcall(cjson.decode, cur)
+  if ok and m.user_id ~= ARGV[3] and redis.call('EXISTS', ARGV[4] .. m.instance_id) == 1 then
+    return 0
+  end
+end
+redis.call('HSET', KEYS[1], ARGV[1], ARGV[2])
+return 1
+"#,
+    );
+    let set: i64 = script
+        .key(&room_key)
+        .arg(peer_id)
+        .arg(value)
+        .arg(&member.user_id)
+        .arg(redis_instance_alive_key(""))
+        .invoke_async(&mut conn)
+        .await
+        .map_err(|e| err("hset", e))?;
+    if set == 0 {
+        return Err(RedisVoiceJoinError::PeerTaken);
+    }
+    Ok(others)
+}
+
+/// Remove (server_id, chat_id, peer_id) entries from their voice rooms, unless another
+/// instance has taken the peer_id over.
+#[cfg(feature = "redis-backend")]
+pub async fn redis_voice_leave(conn: &ConnectionManager, instance_id: &str, entries: &[(ServerId, String, PeerId)]) -> Result<(), String> {
+    let mut conn = conn.clone();
+    let script = redis::Script::new(
+        r#"
+local cur = redis.call('HGET', KEYS[1], ARGV[1])
+if not cur then
+  return 0
+end
+local ok, m = pcall(cjson.decode, cur)
+if ok and m.instance_id ~= ARGV[2] then
+  return 0
+end
+return redis.call('HDEL', KEYS[1], ARGV[1])
+"#,
+    );
+    for (server_id, chat_id, peer_id) in entries {
+        script
+            .key(redis_voice_room_key(server_id, chat_id))
+            .arg(peer_id)
+            .arg(instance_id)
+            .invoke_async::<_, i64>(&mut conn)
+            .await
+            .map_err(|e| format!("redis_voice_leave: {}", e))?;
+    }
+    Ok(())
+}
+
+#[cfg(feature = "redis-backend")]
+pub async fn redis_voice_member(
+    conn: &ConnectionManager,
+    server_id: &str,
+    chat_id: &str,
+    peer_id: &str,
+) -> Result<Option<RedisVoiceMember>, String> {
+    let mut conn = conn.clone();
+    let value: Option<String> = conn
+        .hget(redis_voice_room_key(server_id, chat_id), peer_id)
+        .await
+        .map_err(|e| format!("redis_voice_member: {}", e))?;
+    Ok(value.and_then(|v| serde_json::from_str(&v).ok()))
+}
+
+/// Re-add this instance's voice members, given as (server_id, chat_id, peer_id, user_id),
+/// after Redis lost them (restart or outage).
+#[cfg(feature = "redis-backend")]
+pub async fn redis_voice_restore(
+    conn: &ConnectionManager,
+    instance_id: &str,
+    members: &[(ServerId, String, PeerId, String)],
+) -> Result<(), String> {
+    if members.is_empty() {
+        return Ok(());
+    }
+    let mut conn = conn.clone();
+    let mut pipe = redis::pipe();
+    for (server_id, chat_id, peer_id, user_id) in members {
+        let member = RedisVoiceMember { user_id: user_id.clone(), instance_id: instance_id.to_string() };
+        let value = serde_json::to_string(&member).map_err(|e| format!("redis_voice_restore encode: {}", e))?;
+        pipe.hset(redis_voice_room_key(server_id, chat_id), peer_id, value).ignore();
+    }
+    pipe.query_async::<_, ()>(&mut conn)
+        .await
+        .map_err(|e| format!("redis_voice_restore: {}", e))
+}
@@ -0,0 +1,803 @@
+//! SQLite storage: the operations of `handlers/db.rs` against a single local file, for
+//! self-hosters who want durability without running Postgres.
+//!
+//! Function names and signatures match `db.rs`, so call sites reach whichever one the build
+//! enables through `handlers::db`. Queries are the Postgres ones translated where the dialects
+//! differ (`NOW()`, `ANY`, `GREATEST`, `OFFSET` without `LIMIT`).
+
+use std::collections::{HashMap, HashSet};
+use std::path::Path;
+use std::str::FromStr;
+
+use chrono::{DateTime, Duration, Utc};
+use sqlx::sqlite::{SqliteConnectOptions, SqliteJournalMode, SqlitePoolOptions, SqliteRow};
+use sqlx::{Row, SqlitePool};
+
+use crate::{auth, ProfileRecord, ProfileSnapshotRecord, EncryptedServerHint, InviteTokenCreateRequest, InviteTokenRecord, InviteError, HintError, KeyRotationRequest, KeyRotationError, SealedKeyRecord, ServerEvent, EventPage, EventQueryError, EventQueueStats};
+use crate::state::events::{event_cursor_expired, min_acked_seq};
+
+/// Label for backend metrics
+pub const BACKEND: &str = "sqlite";
+
+/// File name of the database inside `SIGNALING_DATA_DIR`
+pub const SQLITE_FILE_NAME: &str = "cordia-beacon.sqlite3";
+
+/// Open (creating if needed) and migrate the database at `path`.
+pub async fn open_sqlite(path: &Path) -> Result<SqlitePool, String> {
+    let options = SqliteConnectOptions::new()
+        .filename(path)
+        .create_if_missing(true)
+        .journal_mode(SqliteJournalMode::Wal)
+        .foreign_keys(true);
+    connect(options).await
+}
+
+/// A private in-memory database (tests)
+pub async fn open_sqlite_memory() -> Result<SqlitePool, String> {
+    let options = SqliteConnectOptions::from_str("sqlite::memory:").map_err(|e| format!("open_sqlite: {}", e))?;
+    connect(options).await
+}
+
+async fn connect(options: SqliteConnectOptions) -> Result<SqlitePool, String> {
+    // One connection: SQLite serializes writers anyway, and this keeps multi-statement
+    // transactions (event seqs, invite redemption) free of SQLITE_BUSY retries. It also keeps
+    // an in-memory database alive and shared for the pool's lifetime.
+    let pool = SqlitePoolOptions::new()
+        .max_connections(1)
+        .idle_timeout(None)
+        .max_lifetime(None)
+        .connect_with(options)
+        .await
+        .map_err(|e| format!("open_sqlite: {}", e))?;
+    crate::handlers::migrations::migrate_sqlite(&pool)
+        .await
+        .map_err(|e| format!("open_sqlite: {}", e))?;
+    Ok(pool)
+}
+
+fn invite_from_row(r: &SqliteRow) -> InviteTokenRecord {
+    InviteTokenRecord {
+        code: r.try_get("code").unwrap_or_default(),
+        signing_pubkey: r.try_get("signing_pubkey").unwrap_or_default(),
+        encrypted_payload: r.try_get("encrypted_payload").unwrap_or_default(),
+        signature: r.try_get("signature").unwrap_or_default(),
+        created_at: r.try_get("created_at").unwrap_or_else(|_| Utc::now()),
+        expires_at: r.try_get("expires_at").unwrap_or_else(|_| Utc::now()),
+        max_uses: r.try_get::<i64, _>("max_uses").unwrap_or(0) as u32,
+        remaining_uses: r.try_get::<i64, _>("remaining_uses").unwrap_or(0) as u32,
+    }
+}
+
+pub async fn upsert_profile_db(pool: &SqlitePool, user_id: &str, rec: &ProfileRecord) -> Result<(), String> {
+    sqlx::query(
+        r#"
+        INSERT INTO profiles (user_id, display_name, real_name, show_real_name, rev, updated_at)
+        VALUES (?1, ?2, ?3, ?4, ?5, ?6)
+        ON CONFLICT (user_id) DO UPDATE
+        SET display_name = excluded.display_name,
+            real_name = excluded.real_name,
+            show_real_name = excluded.show_real_name,
+            rev = excluded.rev,
+            updated_at = excluded.updated_at
+        WHERE profiles.rev < excluded.rev;
+        "#,
+    )
+    .bind(user_id)
+    .bind(&rec.display_name)
+    .bind(&rec.real_name)
+    .bind(rec.show_real_name)
+    .bind(rec.rev)
+    .bind(Utc::now())
+    .execute(pool)
+    .await
+    .map_err(|e| format!("upsert_profile_db: {}", e))?;
+    Ok(())
+}
+
+pub async fn load_profiles_db(pool: &SqlitePool, user_ids: &[String]) -> Result<Vec<ProfileSnapshotRecord>, String> {
+    if user_ids.is_empty() {
+        return Ok(Vec::new());
+    }
+    let placeholders = vec!["?"; user_ids.len()].join(", ");
+    let sql = format!(
+        "SELECT user_id, display_name, real_name, show_real_name, rev FROM profiles WHERE user_id IN ({})",
+        placeholders
+    );
+    let mut query = sqlx::query(&sql);
+    for user_id in user_ids {
+        query = query.bind(user_id);
+    }
+    let rows = query
+        .fetch_all(pool)
+        .await
+        .map_err(|e| format!("load_profiles_db: {}", e))?;
+
+    let mut out = Vec::with_capacity(rows.len());
+    for row in rows {
+        out.push(ProfileSnapshotRecord {
+            user_id: row.try_get("user_id").map_err(|e| format!("load_profiles_db user_id: {}", e))?,
+            display_name: row
+                .try_get("display_name")
+                .map_err(|e| format!("load_profiles_db display_name: {}", e))?,
+            real_name: row
+                .try_get::<Option<String>, _>("real_name")
+                .map_err(|e| format!("load_profiles_db real_name: {}", e))?,
+            show_real_name: row
+                .try_get("show_real_name")
+                .map_err(|e| format!("load_profiles_db show_real_name: {}", e))?,
+            rev: row.try_get("rev").map_err(|e| format!("load_profiles_db rev: {}", e))?,
+        });
+    }
+    Ok(out)
+}
+
+pub async fn upsert_server_hint_db(pool: &SqlitePool, hint: &EncryptedServerHint) -> Result<(), HintError> {
+    // Compare-and-swap: only replace the stored hint if this write is based on it
+    let result = sqlx::query(
+        r#"
+        INSERT INTO server_hints (signing_pubkey, encrypted_state, signature, signer_pubkey, key_epoch, revision, last_updated)
+        VALUES (?1, ?2, ?3, ?4, ?5, ?6, ?7)
+        ON CONFLICT (signing_pubkey) DO UPDATE
+        SET encrypted_state = excluded.encrypted_state,
+            signature = excluded.signature,
+            signer_pubkey = excluded.signer_pubkey,
+            key_epoch = excluded.key_epoch,
+            revision = excluded.revision,
+            last_updated = excluded.last_updated
+        WHERE server_hints.revision + 1 = excluded.revision;
+        "#,
+    )
+    .bind(&hint.signing_pubkey)
+    .bind(&hint.encrypted_state)
+    .bind(&hint.signature)
+    .bind(&hint.signer_pubkey)
+    .bind(hint.key_epoch as i64)
+    .bind(hint.revision as i64)
+    .bind(hint.last_updated)
+    .execute(pool)
+    .await
+    .map_err(|e| HintError::Storage(format!("upsert_server_hint_db: {}", e)))?;
+
+    if result.rows_affected() == 0 {
+        let current = get_server_hint_db(pool, &hint.signing_pubkey)
+            .await
+            .map_err(HintError::Storage)?
+            .ok_or_else(|| HintError::Storage("upsert_server_hint_db: hint vanished".to_string()))?;
+        return Err(HintError::Conflict(Box::new(current)));
+    }
+    Ok(())
+}
+
+pub async fn get_server_hint_db(pool: &SqlitePool, signing_pubkey: &str) -> Result<Option<EncryptedServerHint>, String> {
+    let row = sqlx::query(
+        r#"
+        SELECT signing_pubkey, encrypted_state, signature, signer_pubkey, key_epoch, revision, last_updated
+        FROM server_hints
+        WHERE signing_pubkey = ?1
+        "#,
+    )
+    .bind(signing_pubkey)
+    .fetch_optional(pool)
+    .await
+    .map_err(|e| format!("get_server_hint_db: {}", e))?;
+
+    Ok(row.map(|r| EncryptedServerHint {
+        signing_pubkey: r.try_get("signing_pubkey").unwrap_or_default(),
+        encrypted_state: r.try_get("encrypted_state").unwrap_or_default(),
+        signature: r.try_get("signature").unwrap_or_default(),
+        signer_pubkey: r.try_get::<Option<String>, _>("signer_pubkey").unwrap_or(None),
+        key_epoch: r.try_get::<i64, _>("key_epoch").unwrap_or(0) as u32,
+        revision: r.try_get::<i64, _>("revision").unwrap_or(0) as u64,
+        last_updated: r.try_get("last_updated").unwrap_or_else(|_| Utc::now()),
+    }))
+}
+
+pub async fn authorize_member_signer_db(pool: &SqlitePool, signing_pubkey: &str, signer_pubkey: &str) -> Result<(), String> {
+    sqlx::query(
+        r#"
+        INSERT INTO house_signers (signing_pubkey, signer_pubkey, authorized_at)
+        VALUES (?1, ?2, ?3)
+        ON CONFLICT (signing_pubkey, signer_pubkey) DO NOTHING;
+        "#,
+    )
+    .bind(signing_pubkey)
+    .bind(signer_pubkey)
+    .bind(Utc::now())
+    .execute(pool)
+    .await
+    .map_err(|e| format!("authorize_member_signer_db: {}", e))?;
+    Ok(())
+}
+
+pub async fn load_house_signers_db(pool: &SqlitePool, signing_pubkey: &str) -> Result<HashSet<String>, String> {
+    let rows = sqlx::query("SELECT signer_pubkey FROM house_signers WHERE signing_pubkey = ?1")
+        .bind(signing_pubkey)
+        .fetch_all(pool)
+        .await
+        .map_err(|e| format!("load_house_signers_db: {}", e))?;
+
+    Ok(rows
+        .into_iter()
+        .filter_map(|r| r.try_get::<String, _>("signer_pubkey").ok())
+        .collect())
+}
+
+pub async fn put_key_rotation_db(pool: &SqlitePool, signing_pubkey: &str, req: &KeyRotationRequest) -> Result<(), KeyRotationError> {
+    let payload = auth::key_rotation_payload(signing_pubkey, req.key_epoch, &req.sealed_keys, &req.removed_user_ids);
+    auth::verify_house_signature(signing_pubkey, &payload, &req.signature)?;
+
+    let sealed_json = serde_json::to_string(&req.sealed_keys)
+        .map_err(|e| KeyRotationError::Storage(format!("put_key_rotation_db: {}", e)))?;
+
+    // Only move forward: an older or replayed rotation must not roll members back to a leaked key
+    let result = sqlx::query(
+        r#"
+        INSERT INTO house_key_rotations (signing_pubkey, key_epoch, sealed_keys, created_at)
+        VALUES (?1, ?2, ?3, ?4)
+        ON CONFLICT (signing_pubkey) DO UPDATE
+        SET key_epoch = excluded.key_epoch,
+            sealed_keys = excluded.sealed_keys,
+            created_at = excluded.created_at
+        WHERE house_key_rotations.key_epoch < excluded.key_epoch;
+        "#,
+    )
+    .bind(signing_pubkey)
+    .bind(req.key_epoch as i64)
+    .bind(&sealed_json)
+    .bind(Utc::now())
+    .execute(pool)
+    .await
+    .map_err(|e| KeyRotationError::Storage(format!("put_key_rotation_db: {}", e)))?;
+
+    if result.rows_affected() == 0 {
+        let current: i64 = sqlx::query_scalar("SELECT key_epoch FROM house_key_rotations WHERE signing_pubkey = ?1")
+            .bind(signing_pubkey)
+            .fetch_one(pool)
+            .await
+            .map_err(|e| KeyRotationError::Storage(format!("put_key_rotation_db: {}", e)))?;
+        return Err(KeyRotationError::StaleEpoch { current: current as u32 });
+    }
+
+    if !req.removed_user_ids.is_empty() {
+        let signers = load_house_signers_db(pool, signing_pubkey)
+            .await
+            .map_err(KeyRotationError::Storage)?;
+        for signer in signers {
+            let removed = match auth::decode_member_key(&signer) {
+                Ok(key) => req.removed_user_ids.contains(&auth::user_id_for_key(&key)),
+                Err(_) => true,
+            };
+            if removed {
+                sqlx::query("DELETE FROM house_signers WHERE signing_pubkey = ?1 AND signer_pubkey = ?2")
+                    .bind(signing_pubkey)
+                    .bind(&signer)
+                    .execute(pool)
+                    .await
+                    .map_err(|e| KeyRotationError::Storage(format!("put_key_rotation_db: {}", e)))?;
+            }
+        }
+    }
+
+    Ok(())
+}
+
+pub async fn get_sealed_key_db(pool: &SqlitePool, signing_pubkey: &str, user_id: &str) -> Result<Option<SealedKeyRecord>, String> {
+    let row = sqlx::query(
+        r#"
+        SELECT key_epoch, sealed_keys, created_at
+        FROM house_key_rotations
+        WHERE signing_pubkey = ?1
+        "#,
+    )
+    .bind(signing_pubkey)
+    .fetch_optional(pool)
+    .await
+    .map_err(|e| format!("get_sealed_key_db: {}", e))?;
+
+    let Some(r) = row else {
+        return Ok(None);
+    };
+    let sealed_json: String = r.try_get("sealed_keys").unwrap_or_default();
+    let sealed_keys: std::collections::BTreeMap<String, String> = serde_json::from_str(&sealed_json)
+        .map_err(|e| format!("get_sealed_key_db: {}", e))?;
+
+    Ok(sealed_keys.get(user_id).map(|sealed_key| SealedKeyRecord {
+        signing_pubkey: signing_pubkey.to_string(),
+        key_epoch: r.try_get::<i64, _>("key_epoch").unwrap_or(0) as u32,
+        sealed_key: sealed_key.clone(),
+        created_at: r.try_get("created_at").unwrap_or_else(|_| Utc::now()),
+    }))
+}
+
+pub async fn gc_expired_invites_db(pool: &SqlitePool) -> Result<(), String> {
+    sqlx::query("DELETE FROM invite_tokens WHERE expires_at <= ?1")
+        .bind(Utc::now())
+        .execute(pool)
+        .await
+        .map_err(|e| format!("gc_expired_invites_db: {}", e))?;
+    Ok(())
+}
+
+pub async fn upsert_invite_db(pool: &SqlitePool, signing_pubkey: &str, req: InviteTokenCreateRequest, ttl_days: i64) -> Result<InviteTokenRecord, InviteError> {
+    let code = req.code.trim().to_string();
+    if code.len() < 6 || code.len() > 64 {
+        return Err(InviteError::InvalidCode);
+    }
+    let payload = auth::invite_create_payload(signing_pubkey, &code, req.max_uses, &req.encrypted_payload);
+    auth::verify_house_signature(signing_pubkey, &payload, &req.signature)?;
+    let now = Utc::now();
+    let expires_at = now + Duration::days(ttl_days);
+    let max_uses = req.max_uses;
+    let remaining_uses = req.max_uses;
+
+    let res = sqlx::query(
+        r#"
+        INSERT INTO invite_tokens (code, signing_pubkey, encrypted_payload, signature, created_at, expires_at, max_uses, remaining_uses)
+        VALUES (?1, ?2, ?3, ?4, ?5, ?6, ?7, ?8)
+        ON CONFLICT (code) DO UPDATE
+        SET signing_pubkey = excluded.signing_pubkey,
+            encrypted_payload = excluded.encrypted_payload,
+            signature = excluded.signature,
+            created_at = excluded.created_at,
+            expires_at = excluded.expires_at,
+            max_uses = excluded.max_uses,
+            remaining_uses = excluded.remaining_uses
+        WHERE invite_tokens.signing_pubkey = excluded.signing_pubkey OR invite_tokens.expires_at <= ?5;
+        "#,
+    )
+    .bind(&code)
+    .bind(signing_pubkey)
+    .bind(&req.encrypted_payload)
+    .bind(&req.signature)
+    .bind(now)
+    .bind(expires_at)
+    .bind(max_uses as i64)
+    .bind(remaining_uses as i64)
+    .execute(pool)
+    .await
+    .map_err(|e| InviteError::Storage(format!("upsert_invite_db: {}", e)))?;
+
+    // The conditional update touches nothing when another house holds the code (expired codes are free)
+    if res.rows_affected() == 0 {
+        return Err(InviteError::CodeTaken);
+    }
+
+    Ok(InviteTokenRecord {
+        code,
+        signing_pubkey: signing_pubkey.to_string(),
+        encrypted_payload: req.encrypted_payload,
+        signature: req.signature,
+        created_at: now,
+        expires_at,
+        max_uses,
+        remaining_uses,
+    })
+}
+
+/// Copy an invite created while the DB was unreachable; an existing row for the code wins.
+pub async fn restore_invite_db(pool: &SqlitePool, rec: &InviteTokenRecord) -> Result<(), String> {
+    sqlx::query(
+        r#"
+        INSERT INTO invite_tokens (code, signing_pubkey, encrypted_payload, signature, created_at, expires_at, max_uses, remaining_uses)
+        VALUES (?1, ?2, ?3, ?4, ?5, ?6, ?7, ?8)
+        ON CONFLICT (code) DO NOTHING;
+        "#,
+    )
+    .bind(&rec.code)
+    .bind(&rec.signing_pubkey)
+    .bind(&rec.encrypted_payload)
+    .bind(&rec.signature)
+    .bind(rec.created_at)
+    .bind(rec.expires_at)
+    .bind(rec.max_uses as i64)
+    .bind(rec.remaining_uses as i64)
+    .execute(pool)
+    .await
+    .map_err(|e| format!("restore_invite_db: {}", e))?;
+    Ok(())
+}
+
+pub async fn get_invite_db(pool: &SqlitePool, code: &str) -> Result<Option<InviteTokenRecord>, String> {
+    let row = sqlx::query(
+        r#"
+        SELECT code, signing_pubkey, encrypted_payload, signature, created_at, expires_at, max_uses, remaining_uses
+        FROM invite_tokens
+        WHERE code = ?1 AND expires_at > ?2
+        "#,
+    )
+    .bind(code)
+    .bind(Utc::now())
+    .fetch_optional(pool)
+    .await
+    .map_err(|e| format!("get_invite_db: {}", e))?;
+
+    Ok(row.as_ref().map(invite_from_row))
+}
+
+pub async fn redeem_invite_db(pool: &SqlitePool, code: &str) -> Result<Option<InviteTokenRecord>, String> {
+    let row = sqlx::query(
+        r#"
+        UPDATE invite_tokens
+        SET remaining_uses = CASE WHEN max_uses = 0 THEN remaining_uses ELSE remaining_uses - 1 END
+        WHERE code = ?1
+          AND expires_at > ?2
+          AND (max_uses = 0 OR remaining_uses > 0)
+        RETURNING code, signing_pubkey, encrypted_payload, signature, created_at, expires_at, max_uses, remaining_uses
+        "#,
+    )
+    .bind(code)
+    .bind(Utc::now())
+    .fetch_optional(pool)
+    .await
+    .map_err(|e| format!("redeem_invite_db: {}", e))?;
+
+    Ok(row.as_ref().map(invite_from_row))
+}
+
+pub async fn revoke_invite_db(pool: &SqlitePool, code: &str, signature: &str) -> Result<(), InviteError> {
+    let row = sqlx::query("SELECT signing_pubkey FROM invite_tokens WHERE code = ?1")
+        .bind(code)
+        .fetch_optional(pool)
+        .await
+        .map_err(|e| InviteError::Storage(format!("revoke_invite_db: {}", e)))?;
+    let Some(row) = row else {
+        return Err(InviteError::NotFound);
+    };
+    let signing_pubkey: String = row
+        .try_get("signing_pubkey")
+        .map_err(|e| InviteError::Storage(format!("revoke_invite_db signing_pubkey: {}", e)))?;
+
+    let payload = auth::invite_revoke_payload(&signing_pubkey, code);
+    auth::verify_house_signature(&signing_pubkey, &payload, signature)?;
+
+    let res = sqlx::query("DELETE FROM invite_tokens WHERE code = ?1 AND signing_pubkey = ?2")
+        .bind(code)
+        .bind(&signing_pubkey)
+        .execute(pool)
+        .await
+        .map_err(|e| InviteError::Storage(format!("revoke_invite_db: {}", e)))?;
+    if res.rows_affected() == 0 {
+        return Err(InviteError::NotFound);
+    }
+    Ok(())
+}
+
+pub async fn insert_event_db(pool: &SqlitePool, event: &ServerEvent) -> Result<ServerEvent, String> {
+    let mut tx = pool.begin().await.map_err(|e| format!("insert_event_db: {}", e))?;
+
+    let seq: i64 = sqlx::query_scalar(
+        r#"
+        INSERT INTO house_event_seqs (signing_pubkey, last_seq)
+        VALUES (?1, 1)
+        ON CONFLICT (signing_pubkey) DO UPDATE
+        SET last_seq = house_event_seqs.last_seq + 1
+        RETURNING last_seq;
+        "#,
+    )
+    .bind(&event.signing_pubkey)
+    .fetch_one(&mut *tx)
+    .await
+    .map_err(|e| format!("insert_event_db seq: {}", e))?;
+
+    let inserted = sqlx::query(
+        r#"
+        INSERT INTO server_events (event_id, signing_pubkey, event_type, encrypted_payload, signature, timestamp, seq)
+        VALUES (?1, ?2, ?3, ?4, ?5, ?6, ?7)
+        ON CONFLICT (event_id) DO NOTHING;
+        "#,
+    )
+    .bind(&event.event_id)
+    .bind(&event.signing_pubkey)
+    .bind(&event.event_type)
+    .bind(&event.encrypted_payload)
+    .bind(&event.signature)
+    .bind(event.timestamp)
+    .bind(seq)
+    .execute(&mut *tx)
+    .await
+    .map_err(|e| format!("insert_event_db: {}", e))?;
+
+    if inserted.rows_affected() == 0 {
+        // A retried post: give the seq back and return the event as first stored
+        tx.rollback().await.map_err(|e| format!("insert_event_db: {}", e))?;
+        let row = sqlx::query("SELECT timestamp, seq FROM server_events WHERE signing_pubkey = ?1 AND event_id = ?2")
+            .bind(&event.signing_pubkey)
+            .bind(&event.event_id)
+            .fetch_optional(pool)
+            .await
+            .map_err(|e| format!("insert_event_db existing: {}", e))?
+            .ok_or_else(|| format!("insert_event_db: event_id {} belongs to another house", event.event_id))?;
+        let mut stored = event.clone();
+        stored.timestamp = row.try_get("timestamp").unwrap_or(event.timestamp);
+        stored.seq = row.try_get::<i64, _>("seq").unwrap_or(0) as u64;
+        return Ok(stored);
+    }
+
+    tx.commit().await.map_err(|e| format!("insert_event_db: {}", e))?;
+
+    let mut stored = event.clone();
+    stored.seq = seq as u64;
+    Ok(stored)
+}
+
+/// Seq of a retained event (for clients that still send an event_id cursor)
+pub async fn get_event_seq_db(pool: &SqlitePool, signing_pubkey: &str, event_id: &str) -> Result<Option<u64>, String> {
+    let seq: Option<i64> = sqlx::query_scalar("SELECT seq FROM server_events WHERE signing_pubkey = ?1 AND event_id = ?2")
+        .bind(signing_pubkey)
+        .bind(event_id)
+        .fetch_optional(pool)
+        .await
+        .map_err(|e| format!("get_event_seq_db: {}", e))?;
+    Ok(seq.map(|s| s as u64))
+}
+
+/// Up to `limit` events with seq > `after_seq`, oldest first
+pub async fn get_events_page_db(pool: &SqlitePool, signing_pubkey: &str, after_seq: u64, limit: usize) -> Result<EventPage, EventQueryError> {
+    let storage = |e: sqlx::Error| EventQueryError::Storage(format!("get_events_page_db: {}", e));
+
+    let latest_seq: Option<i64> = sqlx::query_scalar("SELECT last_seq FROM house_event_seqs WHERE signing_pubkey = ?1")
+        .bind(signing_pubkey)
+        .fetch_optional(pool)
+        .await
+        .map_err(storage)?;
+    let latest_seq = latest_seq.unwrap_or(0) as u64;
+    let oldest: Option<i64> = sqlx::query_scalar("SELECT MIN(seq) FROM server_events WHERE signing_pubkey = ?1")
+        .bind(signing_pubkey)
+        .fetch_one(pool)
+        .await
+        .map_err(storage)?;
+    if event_cursor_expired(after_seq, oldest.map(|s| s as u64), latest_seq) {
+        return Err(EventQueryError::CursorExpired { latest_seq });
+    }
+
+    let rows = sqlx::query(
+        r#"
+        SELECT event_id, signing_pubkey, event_type, encrypted_payload, signature, timestamp, seq
+        FROM server_events
+        WHERE signing_pubkey = ?1 AND seq > ?2
+        ORDER BY seq ASC
+        LIMIT ?3
+        "#,
+    )
+    .bind(signing_pubkey)
+    .bind(after_seq as i64)
+    .bind(limit as i64 + 1)
+    .fetch_all(pool)
+    .await
+    .map_err(storage)?;
+
+    let has_more = rows.len() > limit;
+    let events: Vec<ServerEvent> = rows
+        .into_iter()
+        .take(limit)
+        .map(|row| ServerEvent {
+            event_id: row.try_get("event_id").unwrap_or_default(),
+            signing_pubkey: row.try_get("signing_pubkey").unwrap_or_default(),
+            event_type: row.try_get("event_type").unwrap_or_default(),
+            encrypted_payload: row.try_get("encrypted_payload").unwrap_or_default(),
+            signature: row.try_get("signature").unwrap_or_default(),
+            timestamp: row.try_get("timestamp").unwrap_or_else(|_| Utc::now()),
+            seq: row.try_get::<i64, _>("seq").unwrap_or(0) as u64,
+        })
+        .collect();
+
+    Ok(EventPage {
+        next_seq: events.last().map(|e| e.seq).unwrap_or(after_seq),
+        events,
+        has_more,
+    })
+}
+
+pub async fn ack_events_db(pool: &SqlitePool, signing_pubkey: &str, user_id: &str, last_seq: u64) -> Result<(), String> {
+    sqlx::query(
+        r#"
+        INSERT INTO member_acks (signing_pubkey, user_id, last_event_id, last_seq, updated_at)
+        VALUES (?1, ?2, '', MIN(?3, COALESCE((SELECT last_seq FROM house_event_seqs WHERE signing_pubkey = ?1), 0)), ?4)
+        ON CONFLICT (signing_pubkey, user_id) DO UPDATE
+        SET last_seq = MAX(member_acks.last_seq, excluded.last_seq),
+            updated_at = excluded.updated_at;
+        "#,
+    )
+    .bind(signing_pubkey)
+    .bind(user_id)
+    .bind(last_seq as i64)
+    .bind(Utc::now())
+    .execute(pool)
+    .await
+    .map_err(|e| format!("ack_events_db: {}", e))?;
+    Ok(())
+}
+
+async fn load_member_acks_db(pool: &SqlitePool, signing_pubkey: &str) -> Result<HashMap<String, u64>, String> {
+    let rows = sqlx::query("SELECT user_id, last_seq FROM member_acks WHERE signing_pubkey = ?1")
+        .bind(signing_pubkey)
+        .fetch_all(pool)
+        .await
+        .map_err(|e| format!("load_member_acks_db: {}", e))?;
+
+    Ok(rows
+        .into_iter()
+        .filter_map(|r| {
+            let user_id: String = r.try_get("user_id").ok()?;
+            let seq: i64 = r.try_get("last_seq").ok()?;
+            Some((user_id, seq as u64))
+        })
+        .collect())
+}
+
+/// Highest seq every known member of the house has acked (see `min_acked_seq`)
+async fn acked_seq_db(pool: &SqlitePool, signing_pubkey: &str) -> Result<u64, String> {
+    let signers = load_house_signers_db(pool, signing_pubkey).await?;
+    let acks = load_member_acks_db(pool, signing_pubkey).await?;
+    Ok(min_acked_seq(Some(&signers), &acks))
+}
+
+pub async fn event_queue_stats_db(pool: &SqlitePool, signing_pubkey: &str) -> Result<EventQueueStats, String> {
+    let acked_seq = acked_seq_db(pool, signing_pubkey).await?;
+
+    let latest_seq: Option<i64> = sqlx::query_scalar("SELECT last_seq FROM house_event_seqs WHERE signing_pubkey = ?1")
+        .bind(signing_pubkey)
+        .fetch_optional(pool)
+        .await
+        .map_err(|e| format!("event_queue_stats_db: {}", e))?;
+
+    let counts = sqlx::query(
+        r#"
+        SELECT COUNT(*) AS depth, COUNT(*) FILTER (WHERE seq > ?2) AS unacked
+        FROM server_events
+        WHERE signing_pubkey = ?1
+        "#,
+    )
+    .bind(signing_pubkey)
+    .bind(acked_seq as i64)
+    .fetch_one(pool)
+    .await
+    .map_err(|e| format!("event_queue_stats_db: {}", e))?;
+
+    let oldest = sqlx::query(
+        r#"
+        SELECT seq, timestamp
+        FROM server_events
+        WHERE signing_pubkey = ?1 AND seq > ?2
+        ORDER BY seq ASC
+        LIMIT 1
+        "#,
+    )
+    .bind(signing_pubkey)
+    .bind(acked_seq as i64)
+    .fetch_optional(pool)
+    .await
+    .map_err(|e| format!("event_queue_stats_db: {}", e))?;
+    let oldest_timestamp: Option<DateTime<Utc>> = oldest.as_ref().and_then(|r| r.try_get("timestamp").ok());
+
+    Ok(EventQueueStats {
+        signing_pubkey: signing_pubkey.to_string(),
+        queue_depth: counts.try_get::<i64, _>("depth").unwrap_or(0) as usize,
+        latest_seq: latest_seq.unwrap_or(0) as u64,
+        acked_seq,
+        unacked: counts.try_get::<i64, _>("unacked").unwrap_or(0) as usize,
+        oldest_unacked_seq: oldest.as_ref().and_then(|r| r.try_get::<i64, _>("seq").ok()).map(|s| s as u64),
+        oldest_unacked_age_secs: oldest_timestamp.map(|ts| (Utc::now() - ts).num_seconds().max(0)),
+    })
+}
+
+/// Retained events and the number of houses they belong to (for metrics)
+pub async fn event_queue_totals_db(pool: &SqlitePool) -> Result<(u64, u64), String> {
+    let row = sqlx::query("SELECT COUNT(*) AS events, COUNT(DISTINCT signing_pubkey) AS houses FROM server_events")
+        .fetch_one(pool)
+        .await
+        .map_err(|e| format!("event_queue_totals_db: {}", e))?;
+    Ok((
+        row.try_get::<i64, _>("events").unwrap_or(0) as u64,
+        row.try_get::<i64, _>("houses").unwrap_or(0) as u64,
+    ))
+}
+
+/// Drop events older than `cutoff` or acked by every known member, then keep at most
+/// `max_unacked` per house.
+pub async fn gc_old_events_db(pool: &SqlitePool, cutoff: DateTime<Utc>, max_unacked: usize) -> Result<(), String> {
+    sqlx::query("DELETE FROM server_events WHERE timestamp <= ?1")
+        .bind(cutoff)
+        .execute(pool)
+        .await
+        .map_err(|e| format!("gc_old_events_db: {}", e))?;
+
+    let houses: Vec<String> = sqlx::query_scalar("SELECT DISTINCT signing_pubkey FROM server_events")
+        .fetch_all(pool)
+        .await
+        .map_err(|e| format!("gc_old_events_db houses: {}", e))?;
+
+    for signing_pubkey in houses {
+        let acked_seq = acked_seq_db(pool, &signing_pubkey).await?;
+        if acked_seq > 0 {
+            sqlx::query("DELETE FROM server_events WHERE signing_pubkey = ?1 AND seq <= ?2")
+                .bind(&signing_pubkey)
+                .bind(acked_seq as i64)
+                .execute(pool)
+                .await
+                .map_err(|e| format!("gc_old_events_db acked: {}", e))?;
+        }
+
+        // SQLite needs a LIMIT before OFFSET
+        sqlx::query(
+            r#"
+            DELETE FROM server_events
+            WHERE signing_pubkey = ?1
+              AND seq <= (
+                SELECT seq FROM server_events
+                WHERE signing_pubkey = ?1
+                ORDER BY seq DESC
+                LIMIT 1 OFFSET ?2
+              );
+            "#,
+        )
+        .bind(&signing_pubkey)
+        .bind(max_unacked as i64)
+        .execute(pool)
+        .await
+        .map_err(|e| format!("gc_old_events_db cap: {}", e))?;
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn event(signing_pubkey: &str, event_id: &str) -> ServerEvent {
+        ServerEvent {
+            event_id: event_id.to_string(),
+            signing_pubkey: signing_pubkey.to_string(),
+            event_type: "MemberJoin".to_string(),
+            encrypted_payload: "payload".to_string(),
+            signature: "sig".to_string(),
+            timestamp: Utc::now(),
+            seq: 0,
+        }
+    }
+
+    #[tokio::test]
+    async fn migrations_are_recorded_once() {
+        let pool = open_sqlite_memory().await.unwrap();
+        let version = crate::handlers::migrations::migrate_sqlite(&pool).await.unwrap();
+        assert_eq!(version, crate::handlers::migrations::latest_version(crate::handlers::migrations::SQLITE_MIGRATIONS));
+        let rows: i64 = sqlx::query_scalar("SELECT COUNT(*) FROM schema_version").fetch_one(&pool).await.unwrap();
+        assert_eq!(rows, 1);
+    }
+
+    #[tokio::test]
+    async fn refuses_newer_schema() {
+        let pool = open_sqlite_memory().await.unwrap();
+        sqlx::query("INSERT INTO schema_version (version, name) VALUES (99, 'future')")
+            .execute(&pool)
+            .await
+            .unwrap();
+        let err = crate::handlers::migrations::migrate_sqlite(&pool).await.unwrap_err();
+        assert!(matches!(err, crate::handlers::migrations::MigrateError::SchemaTooNew { found: 99, .. }));
+    }
+
+    #[tokio::test]
+    async fn retried_event_ids_stay_in_their_house() {
+        let pool = open_sqlite_memory().await.unwrap();
+        assert_eq!(insert_event_db(&pool, &event("a", "e1")).await.unwrap().seq, 1);
+        assert!(insert_event_db(&pool, &event("b", "e1")).await.is_err());
+        assert_eq!(insert_event_db(&pool, &event("b", "e2")).await.unwrap().seq, 1);
+        assert_eq!(get_event_seq_db(&pool, "b", "e1").await.unwrap(), None);
+    }
+
+    #[tokio::test]
+    async fn survives_reopen_from_file() {
+        let dir = std::env::temp_dir().join(format!("cordia-beacon-test-{}", uuid::Uuid::new_v4()));
+        std::fs::create_dir_all(&dir).unwrap();
+        let path = dir.join(SQLITE_FILE_NAME);
+        {
+            let pool = open_sqlite(&path).await.unwrap();
+            insert_event_db(&pool, &event("h", "e1")).await.unwrap();
+            pool.close().await;
+        }
+        let pool = open_sqlite(&path).await.unwrap();
+        assert_eq!(insert_event_db(&pool, &event("h", "e2")).await.unwrap().seq, 2);
+        pool.close().await;
+        let _ = std::fs::remove_dir_all(&dir);
+    }
+}
@@ -1,10 +1,13 @@
 // Allow unused code during WebRTC scaffolding phase
 #![allow(dead_code, unused_variables)]
 
-use std::collections::HashSet;
+#[cfg(all(feature = "postgres", feature = "sqlite"))]
+compile_error!("features \"postgres\" and \"sqlite\" can't be enabled together; pick one database");
+
+use std::collections::{BTreeMap, HashSet};
 use std::env;
 use std::fs;
-use std::net::SocketAddr;
+use std::net::{IpAddr, SocketAddr};
 use std::path::PathBuf;
 use std::sync::Arc;
 
@@ -14,18 +17,23 @@ use hyper::service::{make_service_fn, service_fn};
 use hyper::{Body, Method, Request, Response, Server, StatusCode};
 use log::{error, info, warn};
 use serde::{Deserialize, Serialize};
-use tokio::sync::mpsc;
 
-#[cfg(feature = "postgres")]
-use sqlx::postgres::PgPoolOptions;
+use config::{BeaconConfig, RunMode};
 
 pub mod state;
 pub mod handlers;
+pub mod auth;
+pub mod send_queue;
+pub mod metrics;
+pub mod config;
+pub mod cluster;
+pub mod supervisor;
+pub mod store;
 
 pub type PeerId = String;
 pub type ServerId = String;
 pub type SigningPubkey = String;
-pub type WebSocketSender = mpsc::UnboundedSender<hyper_tungstenite::tungstenite::Message>;
+pub type WebSocketSender = send_queue::ConnSender;
 pub type ConnId = String;
 
 pub(crate) fn decode_path_segment(seg: &str) -> String {
@@ -44,6 +52,41 @@ pub(crate) fn decode_path_segment(seg: &str) -> String {
 #[derive(Debug, Clone, Serialize, Deserialize)]
 #[serde(tag = "type")]
 pub enum SignalingMessage {
+    // ============================
+    // Identity handshake (must complete before anything else except Ping)
+    // ============================
+
+    /// Server challenge sent as soon as the WebSocket opens
+    AuthChallenge {
+        nonce: String,
+    },
+    /// Client proves its identity by signing the challenge with its identity key
+    AuthResponse {
+        user_id: String,
+        public_key: String, // Hex Ed25519 identity key
+        signature: String,  // Base64 signature over auth::auth_challenge_payload
+    },
+    /// Server confirms the user_id now bound to this connection
+    AuthOk {
+        user_id: String,
+        /// Present this in `Resume` after a reconnect to take over this connection's registrations
+        #[serde(default)]
+        resume_token: Option<String>,
+    },
+    /// Client reattaches to a dropped connection's registrations (after AuthOk, before any Register)
+    Resume {
+        resume_token: String,
+    },
+    /// Server confirms the resume; the peer_ids are live again on this socket, nothing was re-broadcast
+    Resumed {
+        resume_token: String,
+        peer_ids: Vec<PeerId>,
+    },
+    /// Server could not resume (token expired or unknown): register from scratch
+    ResumeRejected {
+        message: String,
+    },
+
     /// Client registers with server_id and peer_id
     Register {
         server_id: ServerId,
@@ -78,6 +121,11 @@ pub enum SignalingMessage {
     Error {
         message: String,
     },
+    /// Server dropped a message for exceeding its rate limit; retry after `retry_after_ms`
+    RateLimited {
+        message_type: String,
+        retry_after_ms: u64,
+    },
     /// Broadcast when a new member joins the server
     ServerMemberJoined {
         server_id: ServerId,
@@ -90,9 +138,50 @@ pub enum SignalingMessage {
         signing_pubkey: SigningPubkey,
         encrypted_state: String,
         signature: String,
+        #[serde(default)]
+        signer_pubkey: Option<String>,
+        #[serde(default)]
+        key_epoch: u32,
+        #[serde(default)]
+        revision: u64,
         last_updated: DateTime<Utc>,
     },
 
+    // ============================
+    // Server events (pushed instead of polled)
+    // ============================
+
+    /// Broadcast when a server event is posted via REST API
+    EventPosted {
+        signing_pubkey: SigningPubkey,
+        event: ServerEvent,
+    },
+
+    /// Client asks for events it missed (e.g. after a reconnect): the page after `after_seq`
+    /// (0 = from the oldest retained event).
+    EventReplayRequest {
+        signing_pubkey: SigningPubkey,
+        #[serde(default)]
+        after_seq: u64,
+        #[serde(default)]
+        limit: Option<usize>,
+    },
+
+    /// Server reply to EventReplayRequest, oldest first. Ask again from `next_seq` while `has_more`.
+    EventReplay {
+        signing_pubkey: SigningPubkey,
+        events: Vec<ServerEvent>,
+        next_seq: u64,
+        has_more: bool,
+    },
+
+    /// Server reply to EventReplayRequest when the cursor is gone: resync from the hint,
+    /// then continue from `latest_seq`.
+    EventCursorExpired {
+        signing_pubkey: SigningPubkey,
+        latest_seq: u64,
+    },
+
     // ============================
     // Presence (online/offline + active house)
     // ============================
@@ -276,7 +365,9 @@ pub struct VoicePeer {
 // ============================================
 
 /// Server hint - NOT authoritative, just a cache/recovery aid
-/// Any member can overwrite at any time (no creator lock)
+/// Any authorized member can overwrite at any time (no creator lock).
+/// Writes must be signed by the house key, or by a member key recorded at invite redemption
+/// (see `auth::verify_server_hint`).
 /// 
 /// Trust boundary: Clients MUST treat local state as authoritative even if server state differs.
 /// The server is not the source of truth - this is just a cache/recovery aid.
@@ -284,16 +375,180 @@ pub struct VoicePeer {
 pub struct EncryptedServerHint {
     pub signing_pubkey: String,
     pub encrypted_state: String,  // Beacon cannot decrypt
-    pub signature: String,        // Signed by member's Ed25519 key
+    pub signature: String,        // Base64 Ed25519 signature over auth::hint_signing_payload
+    /// Hex identity key of the signing member; None means the house signing key signed it.
+    #[serde(default)]
+    pub signer_pubkey: Option<String>,
+    /// House symmetric key generation the state is encrypted under (bumped on each rekey)
+    #[serde(default)]
+    pub key_epoch: u32,
+    /// Write counter for compare-and-swap: a write must carry the stored revision + 1
+    /// (any revision is accepted when nothing is stored yet).
+    #[serde(default)]
+    pub revision: u64,
     pub last_updated: DateTime<Utc>,
 }
 
+/// Why a hint write was refused.
+#[derive(Debug, Clone)]
+pub enum HintError {
+    /// Someone else wrote first; carries the stored hint so the client can merge and retry
+    Conflict(Box<EncryptedServerHint>),
+    Auth(auth::AuthError),
+    Storage(String),
+}
+
+impl HintError {
+    pub fn into_response(self) -> Response<Body> {
+        let (status, body) = match self {
+            HintError::Auth(e) => return e.into_response(),
+            HintError::Conflict(current) => (
+                StatusCode::CONFLICT,
+                serde_json::json!({
+                    "error": "revision_conflict",
+                    "message": "Hint revision must be the current revision + 1",
+                    "current": current,
+                }),
+            ),
+            HintError::Storage(e) => return storage_failure("Failed to store server hint", &e),
+        };
+        Response::builder()
+            .status(status)
+            .header("Content-Type", "application/json")
+            .body(Body::from(body.to_string()))
+            .unwrap()
+    }
+}
+
+impl From<auth::AuthError> for HintError {
+    fn from(e: auth::AuthError) -> Self {
+        HintError::Auth(e)
+    }
+}
+
 #[derive(Debug, Clone, Serialize, Deserialize)]
 pub struct InviteTokenCreateRequest {
     code: String,
     max_uses: u32, // 0 = unlimited
     encrypted_payload: String, // Server cannot decrypt
-    signature: String,
+    signature: String,         // House key signature over auth::invite_create_payload
+}
+
+/// Body of `POST /api/invites/{code}/revoke`.
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
+pub struct InviteRevokeRequest {
+    #[serde(default)]
+    pub signature: String, // House key signature over auth::invite_revoke_payload
+}
+
+/// Why an invite create/revoke was refused.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum InviteError {
+    InvalidCode,
+    /// The code is already held by a different house
+    CodeTaken,
+    NotFound,
+    Auth(auth::AuthError),
+    Storage(String),
+}
+
+impl InviteError {
+    pub fn into_response(self) -> Response<Body> {
+        let (status, code, message) = match self {
+            InviteError::Auth(e) => return e.into_response(),
+            InviteError::InvalidCode => (StatusCode::BAD_REQUEST, "invalid_code", "Invalid invite code length".to_string()),
+            InviteError::CodeTaken => (StatusCode::CONFLICT, "code_taken", "Invite code is in use by another house".to_string()),
+            InviteError::NotFound => (StatusCode::NOT_FOUND, "not_found", "Invite not found".to_string()),
+            InviteError::Storage(e) => return storage_failure("Failed to store invite", &e),
+        };
+        let body = serde_json::json!({ "error": code, "message": message });
+        Response::builder()
+            .status(status)
+            .header("Content-Type", "application/json")
+            .body(Body::from(body.to_string()))
+            .unwrap()
+    }
+}
+
+impl From<auth::AuthError> for InviteError {
+    fn from(e: auth::AuthError) -> Self {
+        InviteError::Auth(e)
+    }
+}
+
+/// Body of `POST /api/servers/{signing_pubkey}/keys`: a new house symmetric key,
+/// sealed by the owner to each remaining member's X25519 key. Signed with the house key.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct KeyRotationRequest {
+    pub key_epoch: u32,
+    /// user_id -> base64 sealed key blob (beacon cannot open these)
+    pub sealed_keys: BTreeMap<String, String>,
+    /// Members removed by this rotation; their hint-signing rights are revoked
+    #[serde(default)]
+    pub removed_user_ids: Vec<String>,
+    pub signature: String,
+}
+
+/// One member's copy of the latest rotated key, returned by `GET /api/servers/{signing_pubkey}/keys/{user_id}`
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct SealedKeyRecord {
+    pub signing_pubkey: String,
+    pub key_epoch: u32,
+    pub sealed_key: String,
+    pub created_at: DateTime<Utc>,
+}
+
+/// Why a key rotation was refused.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum KeyRotationError {
+    /// Epoch is not newer than the stored rotation
+    StaleEpoch { current: u32 },
+    Auth(auth::AuthError),
+    Storage(String),
+}
+
+impl KeyRotationError {
+    pub fn into_response(self) -> Response<Body> {
+        let (status, body) = match self {
+            KeyRotationError::Auth(e) => return e.into_response(),
+            KeyRotationError::StaleEpoch { current } => (
+                StatusCode::CONFLICT,
+                serde_json::json!({
+                    "error": "stale_epoch",
+                    "message": "Key epoch must be newer than the current rotation",
+                    "current_epoch": current,
+                }),
+            ),
+            KeyRotationError::Storage(e) => return storage_failure("Failed to store key rotation", &e),
+        };
+        Response::builder()
+            .status(status)
+            .header("Content-Type", "application/json")
+            .body(Body::from(body.to_string()))
+            .unwrap()
+    }
+}
+
+impl From<auth::AuthError> for KeyRotationError {
+    fn from(e: auth::AuthError) -> Self {
+        KeyRotationError::Auth(e)
+    }
+}
+
+/// Body of `POST /api/servers/{signing_pubkey}/members`: the owner authorizes a member
+/// identity key as a hint signer without an invite code. Signed with the house key.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct MemberAuthorizeRequest {
+    pub member_pubkey: String,
+    pub signature: String,
+}
+
+/// Optional body of `POST /api/invites/{code}/redeem`.
+/// The redeemer's identity key becomes an authorized hint signer for the house.
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
+pub struct InviteRedeemRequest {
+    #[serde(default)]
+    pub member_pubkey: Option<String>,
 }
 
 #[derive(Debug, Clone, Serialize, Deserialize)]
@@ -301,6 +556,9 @@ pub struct InviteTokenRecord {
     pub code: String,
     pub signing_pubkey: String,
     pub encrypted_payload: String,
+    /// House signature of the create request. Never sent to clients: anyone holding it could
+    /// re-create the invite after it was revoked or used up.
+    #[serde(skip_serializing, default)]
     pub signature: String,
     pub created_at: DateTime<Utc>,
     pub expires_at: DateTime<Utc>,
@@ -316,12 +574,101 @@ pub struct ServerEvent {
     pub encrypted_payload: String, // Beacon cannot decrypt
     pub signature: String,         // Signed by member's Ed25519 key
     pub timestamp: DateTime<Utc>,
+    /// Position in the house's event queue, assigned by the beacon (1, 2, 3, ... never reused)
+    #[serde(default)]
+    pub seq: u64,
 }
 
+/// One page of `GET /api/servers/{signing_pubkey}/events?after_seq=&limit=`
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct EventPage {
+    pub events: Vec<ServerEvent>,
+    /// Cursor for the next request: the seq of the last event returned (or the cursor given)
+    pub next_seq: u64,
+    pub has_more: bool,
+}
+
+/// Why an event query could not be answered.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum EventQueryError {
+    /// Events after the cursor were garbage-collected (or the cursor is from another beacon run).
+    /// The client must resync from the server hint and continue from `latest_seq`.
+    CursorExpired { latest_seq: u64 },
+    Storage(String),
+}
+
+impl EventQueryError {
+    pub fn into_response(self) -> Response<Body> {
+        let (status, body) = match self {
+            EventQueryError::CursorExpired { latest_seq } => (
+                StatusCode::GONE,
+                serde_json::json!({
+                    "error": "cursor_expired",
+                    "message": "Events after this cursor are no longer retained; resync from the server hint",
+                    "latest_seq": latest_seq,
+                }),
+            ),
+            EventQueryError::Storage(e) => return storage_failure("Failed to load events", &e),
+        };
+        Response::builder()
+            .status(status)
+            .header("Content-Type", "application/json")
+            .body(Body::from(body.to_string()))
+            .unwrap()
+    }
+}
+
+/// Body of `POST /api/servers/{signing_pubkey}/events/ack`, signed by the member's identity key
+/// over `auth::event_ack_payload`.
 #[derive(Debug, Clone, Serialize, Deserialize)]
 pub struct AckRequest {
-    user_id: String,
-    last_event_id: String,
+    pub user_id: String,
+    /// Older clients ack by event_id; it is resolved to a seq while the event is retained
+    #[serde(default)]
+    pub last_event_id: String,
+    #[serde(default)]
+    pub last_seq: Option<u64>,
+    /// Hex identity key of the acking member (must be an authorized hint signer)
+    #[serde(default)]
+    pub member_pubkey: String,
+    #[serde(default)]
+    pub signature: String,
+}
+
+/// Why an ack was refused.
+#[derive(Debug)]
+pub enum AckError {
+    Auth(auth::AuthError),
+    Storage(String),
+}
+
+impl AckError {
+    pub fn into_response(self) -> Response<Body> {
+        match self {
+            AckError::Auth(e) => e.into_response(),
+            AckError::Storage(e) => storage_failure("Failed to record ack", &e),
+        }
+    }
+}
+
+impl From<auth::AuthError> for AckError {
+    fn from(e: auth::AuthError) -> Self {
+        AckError::Auth(e)
+    }
+}
+
+/// Response of `GET /api/servers/{signing_pubkey}/events/stats`
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct EventQueueStats {
+    pub signing_pubkey: String,
+    /// Events currently retained
+    pub queue_depth: usize,
+    pub latest_seq: u64,
+    /// Highest seq acked by every known member
+    pub acked_seq: u64,
+    pub unacked: usize,
+    pub oldest_unacked_seq: Option<u64>,
+    pub oldest_unacked_age_secs: Option<i64>,
 }
 
 // ============================================
@@ -366,9 +713,30 @@ pub struct ProfileRecord {
 // ============================================
 // Moved to handlers/db.rs and handlers/redis.rs
 
-const EVENT_RETENTION_DAYS: i64 = 30;
-#[cfg(feature = "redis-backend")]
-pub const DEFAULT_REDIS_PRESENCE_TTL_SECS: u64 = 120;
+pub const DEFAULT_PING_INTERVAL_SECS: u64 = 20;
+pub const DEFAULT_CONN_TIMEOUT_SECS: u64 = 60;
+pub const DEFAULT_RESUME_GRACE_SECS: u64 = 15;
+
+/// WebSocket liveness: the beacon pings every connection and drops ones it hasn't heard from
+/// (half-open TCP otherwise keeps presence and voice entries around until the OS notices).
+#[derive(Debug, Clone, Copy)]
+pub struct HeartbeatConfig {
+    pub ping_interval: std::time::Duration,
+    /// Close a connection after this long without any frame from the client (Pongs count)
+    pub conn_timeout: std::time::Duration,
+    /// How long a dropped connection's voice and presence registrations wait for a `Resume`
+    pub resume_grace: std::time::Duration,
+}
+
+impl Default for HeartbeatConfig {
+    fn default() -> Self {
+        Self {
+            ping_interval: std::time::Duration::from_secs(DEFAULT_PING_INTERVAL_SECS),
+            conn_timeout: std::time::Duration::from_secs(DEFAULT_CONN_TIMEOUT_SECS),
+            resume_grace: std::time::Duration::from_secs(DEFAULT_RESUME_GRACE_SECS),
+        }
+    }
+}
 
 /// Shared state across all connections
 // ServerState has been migrated to AppState with modular subsystems
@@ -377,14 +745,13 @@ pub const DEFAULT_REDIS_PRESENCE_TTL_SECS: u64 = 120;
 use state::AppState;
 use state::presence::PresenceUserStatus;
 use state::voice::VoicePeerInfo;
+use state::signaling::SessionDetach;
+use state::ratelimit::{api_route_class, client_ip, ws_message_class, RateDecision, METRICS_CLASS};
 use handlers::{handle_message, handle_api_request};
+use handlers::http::storage_failure;
 
-#[cfg(feature = "postgres")]
-use handlers::db::init_db;
-#[cfg(feature = "postgres")]
-use handlers::db::gc_old_events_db;
 #[cfg(feature = "redis-backend")]
-use handlers::redis::{redis_presence_disconnect, redis_presence_refresh};
+use handlers::redis::redis_presence_disconnect;
 
 type SharedState = Arc<AppState>;
 
@@ -394,16 +761,20 @@ type SharedState = Arc<AppState>;
 
 async fn handle_connection(
     ws: hyper_tungstenite::WebSocketStream<hyper::upgrade::Upgraded>,
-    addr: SocketAddr,
+    ip: IpAddr,
     state: SharedState,
 ) {
-    info!("WebSocket connection established from {}", addr);
+    info!("WebSocket connection established from {}", ip);
+    metrics::metrics().ws_connected();
 
     let (mut ws_sender, mut ws_receiver) = ws.split();
-    let conn_id: ConnId = uuid::Uuid::new_v4().to_string();
+    // socket_id identifies this WebSocket; conn_id is the logical connection it drives,
+    // which becomes an older one's if the client resumes a dropped session.
+    let socket_id: ConnId = uuid::Uuid::new_v4().to_string();
+    let mut conn_id: ConnId = socket_id.clone();
 
-    // Create channel for sending messages to this WebSocket
-    let (tx, mut rx) = mpsc::unbounded_channel();
+    // Create bounded queue for sending messages to this WebSocket
+    let (tx, mut rx) = send_queue::channel(state.send_queue_capacity, state.send_queue_stats.clone());
 
     // Spawn task to forward messages from channel to WebSocket
     let mut send_task = tokio::spawn(async move {
@@ -414,13 +785,85 @@ async fn handle_connection(
         }
     });
 
+    // Challenge the client to prove its identity before it can register or announce presence
+    let nonce = {
+        let mut signaling = state.signaling.lock().await;
+        signaling.issue_auth_challenge(&conn_id)
+    };
+    if let Ok(json) = serde_json::to_string(&SignalingMessage::AuthChallenge { nonce }) {
+        let _ = tx.send(hyper_tungstenite::tungstenite::Message::Text(json));
+    }
+
+    // Any frame from the client (including Pongs to our pings) counts as a sign of life
+    let heartbeat_config = state.heartbeat;
+    let mut heartbeat = tokio::time::interval(heartbeat_config.ping_interval);
+    heartbeat.set_missed_tick_behavior(tokio::time::MissedTickBehavior::Delay);
+    let mut last_seen = std::time::Instant::now();
+    // A Close frame is a deliberate goodbye (and a rate-limit kick isn't a blip);
+    // only silent drops are held for resumption
+    let mut closed_deliberately = false;
+
     // Handle incoming messages
     loop {
         tokio::select! {
             msg_result = ws_receiver.next() => {
+                last_seen = std::time::Instant::now();
                 match msg_result {
                     Some(Ok(hyper_tungstenite::tungstenite::Message::Text(text))) => {
-                        match serde_json::from_str::<SignalingMessage>(&text) {
+                        // Closed by a resume on another socket: it speaks for the session now
+                        if tx.is_closed() {
+                            break;
+                        }
+                        let message_type = ws_message_class(&text);
+                        let decision = state.rate_limits.lock().await.check(Some(&socket_id), ip, &message_type);
+                        match decision {
+                            RateDecision::Allow => {}
+                            RateDecision::Limited { retry_after, disconnect } => {
+                                if disconnect {
+                                    info!("Disconnecting {} ({}): keeps exceeding rate limits", socket_id, ip);
+                                    closed_deliberately = true;
+                                    break;
+                                }
+                                let limited = SignalingMessage::RateLimited {
+                                    message_type,
+                                    retry_after_ms: retry_after.as_millis() as u64,
+                                };
+                                if let Ok(json) = serde_json::to_string(&limited) {
+                                    let _ = tx.send_droppable(hyper_tungstenite::tungstenite::Message::Text(json));
+                                }
+                                continue;
+                            }
+                            RateDecision::Banned { .. } => {
+                                info!("Disconnecting {} ({}): IP is banned", socket_id, ip);
+                                closed_deliberately = true;
+                                break;
+                            }
+                        }
+                        let parsed = serde_json::from_str::<SignalingMessage>(&text);
+                        metrics::metrics().ws_message(if parsed.is_ok() { &message_type } else { "invalid" });
+                        match parsed {
+                            Ok(SignalingMessage::Resume { resume_token }) => {
+                                let reply = {
+                                    let mut signaling = state.signaling.lock().await;
+                                    signaling.resume_session(&resume_token, &socket_id, &tx)
+                                };
+                                let reply = match reply {
+                                    Ok((resumed_conn_id, resume_token, peer_ids)) => {
+                                        info!("Socket {} resumed connection {} ({} peers)", socket_id, resumed_conn_id, peer_ids.len());
+                                        // Drop anything the socket announced on its own before resuming
+                                        let _ = state.presence.lock().await.remove_presence_conn(&socket_id);
+                                        conn_id = resumed_conn_id;
+                                        SignalingMessage::Resumed { resume_token, peer_ids }
+                                    }
+                                    Err(message) => {
+                                        info!("Resume rejected on socket {}: {}", socket_id, message);
+                                        SignalingMessage::ResumeRejected { message }
+                                    }
+                                };
+                                if let Ok(json) = serde_json::to_string(&reply) {
+                                    let _ = tx.send(hyper_tungstenite::tungstenite::Message::Text(json));
+                                }
+                            }
                             Ok(msg) => {
                                 match handle_message(msg, &conn_id, &state, &tx).await {
                                     Ok(_) => {}
@@ -447,7 +890,8 @@ async fn handle_connection(
                         }
                     }
                     Some(Ok(hyper_tungstenite::tungstenite::Message::Close(_))) => {
-                        info!("Client {} closed connection", addr);
+                        info!("Client {} closed connection", ip);
+                        closed_deliberately = true;
                         break;
                     }
                     Some(Ok(hyper_tungstenite::tungstenite::Message::Ping(data))) => {
@@ -455,7 +899,7 @@ async fn handle_connection(
                     }
                     Some(Ok(_)) => {}
                     Some(Err(e)) => {
-                        error!("WebSocket error from {}: {}", addr, e);
+                        error!("WebSocket error from {}: {}", ip, e);
                         break;
                     }
                     None => break,
@@ -464,20 +908,62 @@ async fn handle_connection(
             _ = &mut send_task => {
                 break;
             }
+            _ = heartbeat.tick() => {
+                if last_seen.elapsed() > heartbeat_config.conn_timeout {
+                    info!("Reaping connection {} from {}: silent for {}s", conn_id, ip, last_seen.elapsed().as_secs());
+                    break;
+                }
+                if tx.send_droppable(hyper_tungstenite::tungstenite::Message::Ping(Vec::new())).is_err() {
+                    break;
+                }
+            }
         }
     }
 
-    // Clean up when connection closes
+    send_task.abort();
+    metrics::metrics().ws_disconnected();
+    state.rate_limits.lock().await.forget_conn(&socket_id);
+
+    // An authenticated connection keeps its registrations for a grace period so the client
+    // can resume; peers only hear about the disconnect if it doesn't come back in time.
+    let detach = {
+        let mut signaling = state.signaling.lock().await;
+        signaling.detach_session(&conn_id, &socket_id)
+    };
+    match detach {
+        SessionDetach::NoSession => cleanup_connection(&state, &conn_id).await,
+        SessionDetach::Suspended(_) if closed_deliberately => cleanup_connection(&state, &conn_id).await,
+        SessionDetach::Suspended(suspended_at) => {
+            let grace = heartbeat_config.resume_grace;
+            tokio::spawn(async move {
+                tokio::time::sleep(grace).await;
+                let expired = {
+                    let mut signaling = state.signaling.lock().await;
+                    signaling.expire_session(&conn_id, suspended_at)
+                };
+                if expired {
+                    info!("Connection {} was not resumed within {}s", conn_id, grace.as_secs());
+                    cleanup_connection(&state, &conn_id).await;
+                }
+            });
+        }
+        SessionDetach::TakenOver => {}
+    }
+}
+
+/// Drop a connection's registrations and tell its peers it left.
+async fn cleanup_connection(state: &SharedState, conn_id: &ConnId) {
     // Get signing_pubkeys BEFORE disconnecting to ensure we have them for presence updates
     let server_signing_map = {
         let voice = state.voice.lock().await;
         voice.server_signing_pubkeys.clone()
     };
 
-    let (presence_removed, voice_removed, redis_client) = {
+    let (peer_ids, presence_removed, voice_removed, redis_client) = {
         let mut signaling = state.signaling.lock().await;
+        signaling.clear_conn_auth(conn_id);
 
-        let peer_ids = if let Some(peer_ids) = signaling.conn_peers.remove(&conn_id) {
+        let peer_ids = if let Some(peer_ids) = signaling.conn_peers.remove(conn_id) {
             let ids: Vec<_> = peer_ids.iter().cloned().collect();
             for peer_id in &ids {
                 signaling.unregister_peer(peer_id);
@@ -491,12 +977,12 @@ async fn handle_connection(
 
         // Handle voice disconnect
         let mut voice = state.voice.lock().await;
-        let voice_removed = voice.handle_voice_disconnect(&conn_id);
+        let voice_removed = voice.handle_voice_disconnect(conn_id);
         drop(voice);
 
         // Handle presence disconnect
         let mut presence = state.presence.lock().await;
-        let presence_removed = presence.remove_presence_conn(&conn_id);
+        let presence_removed = presence.remove_presence_conn(conn_id);
         drop(presence);
 
         #[cfg(feature = "redis-backend")]
@@ -507,11 +993,21 @@ async fn handle_connection(
         #[cfg(not(feature = "redis-backend"))]
         let redis_client: Option<()> = None;
 
-        (presence_removed, voice_removed, redis_client)
+        (peer_ids, presence_removed, voice_removed, redis_client)
     };
 
+    if !peer_ids.is_empty() {
+        state.unroute_peers(&peer_ids).await;
+    }
+
     // Broadcast VoicePeerLeft to remaining peers in each affected chat
     if !voice_removed.is_empty() {
+        let memberships: Vec<_> = voice_removed
+            .iter()
+            .map(|(server_id, chat_id, peer_id, _)| (server_id.clone(), chat_id.clone(), peer_id.clone()))
+            .collect();
+        state.voice_room_leave(&memberships).await;
+
         for (server_id, chat_id, peer_id, user_id) in voice_removed.clone() {
             info!("Voice peer {} (user {}) disconnected from chat {}", peer_id, user_id, chat_id);
             let msg = SignalingMessage::VoicePeerLeft {
@@ -534,7 +1030,7 @@ async fn handle_connection(
     if let Some((user_id, spks)) = presence_removed {
         #[cfg(feature = "redis-backend")]
         if let Some(client) = redis_client.as_ref() {
-            if let Err(e) = redis_presence_disconnect(client, &user_id, &spks).await {
+            if let Err(e) = crate::metrics::timed("redis", "presence_disconnect", redis_presence_disconnect(client, &user_id, &spks)).await {
                 warn!("Redis presence disconnect failed: {}", e);
             }
         }
@@ -544,7 +1040,6 @@ async fn handle_connection(
         }
     }
 
-    send_task.abort();
 }
 
 
@@ -557,12 +1052,44 @@ async fn handle_connection(
 // Main Request Handler
 // ============================================
 
+fn rate_limit_response(decision: RateDecision) -> Option<Response<Body>> {
+    let (status, error, message, retry_after) = match decision {
+        RateDecision::Allow => return None,
+        RateDecision::Limited { retry_after, .. } => (
+            StatusCode::TOO_MANY_REQUESTS,
+            "rate_limited",
+            "Too many requests",
+            retry_after,
+        ),
+        RateDecision::Banned { remaining } => (
+            StatusCode::FORBIDDEN,
+            "ip_banned",
+            "Temporarily banned for exceeding rate limits",
+            remaining,
+        ),
+    };
+    let retry_after_secs = retry_after.as_secs_f64().ceil() as u64;
+    let body = serde_json::json!({
+        "error": error,
+        "message": message,
+        "retry_after_secs": retry_after_secs,
+    });
+    Some(Response::builder()
+        .status(status)
+        .header("Content-Type", "application/json")
+        .header("Retry-After", retry_after_secs.to_string())
+        .body(Body::from(body.to_string()))
+        .unwrap())
+}
+
 async fn handle_request(
     mut req: Request<Body>,
+    remote: SocketAddr,
     state: SharedState,
 ) -> Result<Response<Body>, hyper::Error> {
     let path = req.uri().path();
     let method = req.method().clone();
+    let ip = client_ip(req.headers(), remote, state.proxy_ip_header.as_deref());
 
     // CORS preflight (needed for browser fetch from the Tauri/React frontend)
     if method == Method::OPTIONS {
@@ -576,8 +1103,8 @@ async fn handle_request(
             .unwrap());
     }
 
-    // Health check endpoint
-    if path == "/health" {
+    // Liveness: the process is up and serving
+    if path == "/health/live" {
         return Ok(Response::builder()
             .status(StatusCode::OK)
             .header("Access-Control-Allow-Origin", "*")
@@ -587,14 +1114,70 @@ async fn handle_request(
             .unwrap());
     }
 
+    // Health check with backend status. /health stays 200 while degraded (clients use it as a
+    // liveness probe); /health/ready returns 503 until every configured backend is connected.
+    if path == "/health" || path == "/health/ready" {
+        let (ready, postgres, redis) = {
+            let backends = state.backends.lock().await;
+            (backends.ready(), backends.postgres_health.clone(), backends.redis_health.clone())
+        };
+        let body = serde_json::json!({
+            "status": if ready { "ok" } else { "degraded" },
+            "ready": ready,
+            "uptime_secs": state.started_at.elapsed().as_secs(),
+            "backends": {
+                "postgres": postgres,
+                "redis": redis,
+            },
+        });
+        let status = if path == "/health/ready" && !ready {
+            StatusCode::SERVICE_UNAVAILABLE
+        } else {
+            StatusCode::OK
+        };
+        return Ok(Response::builder()
+            .status(status)
+            .header("Content-Type", "application/json")
+            .header("Access-Control-Allow-Origin", "*")
+            .header("Access-Control-Allow-Methods", "GET, POST, OPTIONS")
+            .header("Access-Control-Allow-Headers", "Content-Type")
+            .body(Body::from(body.to_string()))
+            .unwrap());
+    }
+
+    // Prometheus scrape endpoint: token (or unproxied loopback) only, and rate-limited like the API
+    if path == "/metrics" && method == Method::GET {
+        let decision = state.rate_limits.lock().await.check(None, ip, METRICS_CLASS);
+        if let Some(resp) = rate_limit_response(decision) {
+            return Ok(resp);
+        }
+        if !metrics::scrape_allowed(req.headers(), ip, state.metrics_token.as_deref()) {
+            return Ok(Response::builder()
+                .status(StatusCode::UNAUTHORIZED)
+                .header("WWW-Authenticate", "Bearer")
+                .body(Body::from("Unauthorized"))
+                .unwrap());
+        }
+        return Ok(Response::builder()
+            .status(StatusCode::OK)
+            .header("Content-Type", "text/plain; version=0.0.4")
+            .body(Body::from(metrics::render(&state).await))
+            .unwrap());
+    }
+
     // WebSocket upgrade MUST be checked before GET / so app connections to wss://host/ get 101, not 200 HTML
     if hyper_tungstenite::is_upgrade_request(&req) {
+        let banned = state.rate_limits.lock().await.ban_remaining(ip);
+        if let Some(remaining) = banned {
+            let mut resp = rate_limit_response(RateDecision::Banned { remaining }).unwrap();
+            resp.headers_mut().insert("Access-Control-Allow-Origin", "*".parse().unwrap());
+            return Ok(resp);
+        }
         match hyper_tungstenite::upgrade(&mut req, None) {
             Ok((response, websocket)) => {
                 tokio::spawn(async move {
                     if let Ok(ws) = websocket.await {
-                        let addr = "0.0.0.0:9001".parse().unwrap();
-                        handle_connection(ws, addr, state).await;
+                        handle_connection(ws, ip, state).await;
                     }
                 });
                 return Ok(response);
@@ -674,7 +1257,13 @@ async fn handle_request(
 
     // API endpoints (REST)
     if path.starts_with("/api/") {
-        let mut resp = handle_api_request(req, state).await?;
+        let class = api_route_class(&method, path);
+        let decision = state.rate_limits.lock().await.check(None, ip, &class);
+        let mut resp = match rate_limit_response(decision) {
+            Some(resp) => resp,
+            None => handle_api_request(req, ip, state).await?,
+        };
+        metrics::metrics().http_request(&class, resp.status().as_u16());
         let headers = resp.headers_mut();
         headers.insert("Access-Control-Allow-Origin", "*".parse().unwrap());
         headers.insert("Access-Control-Allow-Methods", "GET, POST, OPTIONS".parse().unwrap());
@@ -685,7 +1274,7 @@ async fn handle_request(
     // Default response for other requests
     Ok(Response::builder()
         .status(StatusCode::NOT_FOUND)
-        .body(Body::from("Not found. Use / or /status for live connection count, /health for health check, /api/* for REST API, or upgrade to WebSocket."))
+        .body(Body::from("Not found. Use / or /status for live connection count, /health for health check, /health/ready for readiness, /metrics for Prometheus, /api/* for REST API, or upgrade to WebSocket."))
         .unwrap())
 }
 
@@ -693,11 +1282,19 @@ async fn handle_request(
 // Last-stop file (for downtime on status page)
 // ============================================
 
-fn last_stop_file_path() -> PathBuf {
+fn data_dir() -> PathBuf {
     env::var("SIGNALING_DATA_DIR")
         .map(PathBuf::from)
         .unwrap_or_else(|_| env::temp_dir())
-        .join("cordia-beacon-last-stop")
+}
+
+fn last_stop_file_path() -> PathBuf {
+    data_dir().join("cordia-beacon-last-stop")
+}
+
+#[cfg(feature = "sqlite")]
+fn sqlite_file_path() -> PathBuf {
+    data_dir().join(handlers::sqlite::SQLITE_FILE_NAME)
 }
 
 /// Read last-stop timestamp and return previous shutdown duration in seconds (started_at - last_stopped).
@@ -728,102 +1325,168 @@ fn write_last_stop_file() {
 // Main Entry Point
 // ============================================
 
+/// `--migrate-only`: apply pending database migrations and return the process exit code
+#[cfg(all(feature = "postgres", not(feature = "sqlite")))]
+async fn migrate_only(config: &BeaconConfig) -> i32 {
+    let Some(db_url) = &config.db_url else {
+        error!("--migrate-only needs db_url");
+        return 2;
+    };
+    let pool = match sqlx::postgres::PgPoolOptions::new().max_connections(1).connect(db_url).await {
+        Ok(pool) => pool,
+        Err(e) => {
+            error!("Failed to connect to Postgres: {}", e);
+            return 1;
+        }
+    };
+    match handlers::migrations::migrate(&pool).await {
+        Ok(version) => {
+            info!("Database schema is at version {}.", version);
+            0
+        }
+        Err(e) => {
+            error!("Migration failed: {}", e);
+            1
+        }
+    }
+}
+
+#[cfg(all(feature = "sqlite", not(feature = "postgres")))]
+async fn migrate_only(_config: &BeaconConfig) -> i32 {
+    let path = sqlite_file_path();
+    match handlers::sqlite::open_sqlite(&path).await {
+        Ok(_) => {
+            info!("SQLite schema at {} is up to date.", path.display());
+            0
+        }
+        Err(e) => {
+            error!("Migration failed: {}", e);
+            1
+        }
+    }
+}
+
+// Also stands in when both databases are enabled, so the build stops at the compile_error above
+#[cfg(any(not(any(feature = "postgres", feature = "sqlite")), all(feature = "postgres", feature = "sqlite")))]
+async fn migrate_only(_config: &BeaconConfig) -> i32 {
+    error!("--migrate-only needs a build with the postgres or sqlite feature");
+    2
+}
+
 #[tokio::main]
 async fn main() {
-    // Healthcheck mode: exit 0 if server is running (port in use), exit 1 if not
-    if std::env::args().any(|a| a == "--healthcheck") {
-        use std::net::TcpListener;
-        match TcpListener::bind("127.0.0.1:9001") {
-            Ok(_) => std::process::exit(1), // Port free = server NOT running
-            Err(_) => std::process::exit(0), // Port in use = server IS running (healthy)
+    let args: Vec<String> = env::args().skip(1).collect();
+    let (config, mode) = match BeaconConfig::load(&args) {
+        Ok(loaded) => loaded,
+        Err(e) => {
+            eprintln!("cordia-beacon: {}", e);
+            eprintln!("Run with --help for the list of settings.");
+            std::process::exit(2);
         }
+    };
+
+    match mode {
+        RunMode::Help => {
+            print!("{}", BeaconConfig::usage());
+            return;
+        }
+        // Healthcheck mode: exit 0 if something accepts connections on the bind address, 1 if not
+        RunMode::Healthcheck => {
+            use std::net::TcpStream;
+            match TcpStream::connect_timeout(&config.healthcheck_addr(), std::time::Duration::from_secs(3)) {
+                Ok(_) => std::process::exit(0),
+                Err(_) => std::process::exit(1),
+            }
+        }
+        RunMode::Serve | RunMode::MigrateOnly => {}
     }
 
     env_logger::init();
+    config.log_effective();
+
+    if mode == RunMode::MigrateOnly {
+        std::process::exit(migrate_only(&config).await);
+    }
 
     let downtime_secs = read_downtime_secs();
-    let addr: SocketAddr = "0.0.0.0:9001".parse().expect("Invalid address");
-    let state = Arc::new(AppState::new(downtime_secs));
+    let addr: SocketAddr = config.bind_addr;
+    let mut app_state = AppState::new(downtime_secs);
+    app_state.heartbeat = config.heartbeat();
+    app_state.send_queue_capacity = config.send_queue_capacity;
+    app_state.proxy_ip_header = config.trust_proxy_headers.then(|| config.proxy_ip_header.clone());
+    app_state.metrics_token = config.metrics_token.clone();
+    app_state.rate_limits = Arc::new(tokio::sync::Mutex::new(state::RateLimitState::new(config.rate_limit_config())));
+    let state = Arc::new(app_state);
+
+    {
+        let mut events = state.events.lock().await;
+        events.max_unacked_events = config.max_unacked_events;
+        events.invite_ttl_days = config.invite_ttl_days;
+        events.event_retention_days = config.event_retention_days;
+    }
+
+    // Optional backends. Each one is supervised: connected in the background, probed, and
+    // reconnected with backoff; until then the beacon runs on in-memory state.
+    supervisor::register_backends(&state, config.db_url.as_deref(), config.redis_url.as_deref()).await;
 
-    // Optional Postgres durability (profiles first; others later)
     #[cfg(feature = "postgres")]
     {
-        if let Ok(db_url) = std::env::var("SIGNALING_DB_URL") {
-            match PgPoolOptions::new().max_connections(8).connect(&db_url).await {
-                Ok(pool) => {
-                    if let Err(e) = init_db(&pool).await {
-                        warn!("DB init failed; continuing without DB: {}", e);
-                    } else {
-                        let mut backends = state.backends.lock().await;
-                        backends.db = Some(pool);
-                        info!("Postgres enabled (SIGNALING_DB_URL set).");
-                    }
-                }
-                Err(e) => warn!("Failed to connect to Postgres; continuing without DB: {}", e),
-            }
+        if let Some(db_url) = config.db_url.clone() {
+            tokio::spawn(supervisor::supervise_postgres(state.clone(), db_url, config.db_max_connections));
         } else {
-            info!("Postgres disabled (SIGNALING_DB_URL not set).");
+            info!("Postgres disabled (db_url not set).");
         }
     }
 
-    // Optional Redis presence backend (ephemeral data with TTL)
-    #[cfg(feature = "redis-backend")]
+    // SQLite is a local file: open it once, and don't start without it
+    #[cfg(feature = "sqlite")]
     {
-        let ttl_secs = std::env::var("SIGNALING_REDIS_PRESENCE_TTL_SECS")
-            .ok()
-            .and_then(|v| v.parse::<u64>().ok())
-            .unwrap_or(DEFAULT_REDIS_PRESENCE_TTL_SECS);
-
-        if let Ok(redis_url) = std::env::var("SIGNALING_REDIS_URL") {
-            match redis::Client::open(redis_url.as_str()) {
-                Ok(client) => {
-                    match client.get_multiplexed_tokio_connection().await {
-                        Ok(mut conn) => {
-                            let pong: Result<String, _> = redis::cmd("PING").query_async(&mut conn).await;
-                            match pong {
-                                Ok(_) => {
-                                    let mut backends = state.backends.lock().await;
-                                    backends.redis = Some(client);
-                                    backends.redis_presence_ttl_secs = ttl_secs;
-                                    info!("Redis presence enabled (SIGNALING_REDIS_URL set).");
-                                }
-                                Err(e) => warn!("Redis PING failed; continuing without Redis: {}", e),
-                            }
-                        }
-                        Err(e) => warn!("Failed to connect to Redis; continuing without Redis: {}", e),
-                    }
-                }
-                Err(e) => warn!("Invalid Redis URL; continuing without Redis: {}", e),
+        let path = sqlite_file_path();
+        if let Some(parent) = path.parent() {
+            let _ = fs::create_dir_all(parent);
+        }
+        match handlers::sqlite::open_sqlite(&path).await {
+            Ok(pool) => {
+                state.backends.lock().await.store = Some(Arc::new(store::SqliteStore::new(pool)));
+                info!("SQLite storage enabled ({}).", path.display());
+            }
+            Err(e) => {
+                error!("Failed to open SQLite database {}: {}", path.display(), e);
+                std::process::exit(1);
             }
+        }
+    }
+
+    #[cfg(feature = "redis-backend")]
+    {
+        if let Some(redis_url) = config.redis_url.clone() {
+            tokio::spawn(supervisor::supervise_redis(state.clone(), redis_url, config.redis_presence_ttl_secs));
         } else {
-            info!("Redis presence disabled (SIGNALING_REDIS_URL not set).");
+            info!("Redis presence disabled (redis_url not set).");
         }
     }
 
     // Spawn background task for garbage collection
     let gc_state = state.clone();
+    let gc_interval = tokio::time::Duration::from_secs(config.gc_interval_secs);
     tokio::spawn(async move {
         loop {
-            tokio::time::sleep(tokio::time::Duration::from_secs(3600)).await; // Every hour
-            let (db, cutoff) = {
+            tokio::time::sleep(gc_interval).await;
+            let (cutoff, max_unacked) = {
                 let mut events = gc_state.events.lock().await;
-                events.gc_old_events();
-                drop(events);
-                #[cfg(feature = "postgres")]
-                let db = {
-                    let backends = gc_state.backends.lock().await;
-                    backends.db.clone()
-                };
-                #[cfg(not(feature = "postgres"))]
-                let db: Option<()> = None;
-                let cutoff = Utc::now() - Duration::days(EVENT_RETENTION_DAYS);
-                (db, cutoff)
+                events.prune_invite_failures();
+                (Utc::now() - Duration::days(events.event_retention_days), events.max_unacked_events)
             };
 
-            #[cfg(feature = "postgres")]
-            if let Some(pool) = db {
-                if let Err(e) = gc_old_events_db(&pool, cutoff).await {
-                    warn!("DB GC failed: {}", e);
+            // Memory too while a database is installed: it holds whatever is waiting for resync
+            let mut stores: Vec<Arc<dyn store::Store>> = vec![gc_state.memory_store.clone()];
+            stores.extend(gc_state.durable_store().await);
+            for store in stores {
+                if let Err(e) = store.gc_old_events(cutoff, max_unacked).await {
+                    warn!("{} event GC failed: {}", store.name(), e);
+                }
+                if let Err(e) = store.gc_expired_invites().await {
+                    warn!("{} invite GC failed: {}", store.name(), e);
                 }
             }
 
@@ -831,46 +1494,39 @@ async fn main() {
         }
     });
 
+    // Forget refilled rate-limit buckets and expired bans
+    let prune_state = state.clone();
+    tokio::spawn(async move {
+        loop {
+            tokio::time::sleep(tokio::time::Duration::from_secs(60)).await;
+            prune_state.rate_limits.lock().await.prune();
+        }
+    });
+
     #[cfg(feature = "redis-backend")]
     {
         let refresh_state = state.clone();
         tokio::spawn(async move {
             loop {
                 tokio::time::sleep(tokio::time::Duration::from_secs(30)).await;
-                let (client, ttl, users) = {
+                let (client, conn, ttl) = {
                     let backends = refresh_state.backends.lock().await;
-                    let presence = refresh_state.presence.lock().await;
-                    let client = backends.redis.clone();
-                    let ttl = backends.redis_presence_ttl_secs;
-                    let users = presence
-                        .presence_users
-                        .iter()
-                        .map(|(user_id, u)| {
-                            (
-                                user_id.clone(),
-                                u.signing_pubkeys.iter().cloned().collect::<Vec<_>>(),
-                                u.active_signing_pubkey.clone(),
-                            )
-                        })
-                        .collect::<Vec<_>>();
-                    (client, ttl, users)
+                    (backends.redis.clone(), backends.redis_conn.clone(), backends.redis_presence_ttl_secs)
                 };
-
-                if let Some(client) = client {
-                    if let Err(e) = redis_presence_refresh(&client, ttl, &users).await {
-                        warn!("Redis presence refresh failed: {}", e);
-                    }
+                if let (Some(client), Some(conn)) = (client, conn) {
+                    supervisor::refresh_redis(&refresh_state, &client, &conn, ttl).await;
                 }
             }
         });
     }
 
-    let make_svc = make_service_fn(move |_conn| {
+    let make_svc = make_service_fn(move |conn: &hyper::server::conn::AddrStream| {
         let state = state.clone();
+        let remote = conn.remote_addr();
         async move {
             Ok::<_, hyper::Error>(service_fn(move |req| {
                 let state = state.clone();
-                handle_request(req, state)
+                handle_request(req, remote, state)
             }))
         }
     });
@@ -881,6 +1537,7 @@ async fn main() {
     info!("WebSocket endpoint: ws://{}", addr);
     info!("REST API: http://{}/api/servers/{{signing_pubkey}}/... (server hints)", addr);
     info!("Health check: http://{}/health", addr);
+    info!("Readiness: http://{}/health/ready", addr);
 
     let graceful = server.with_graceful_shutdown(async {
         tokio::signal::ctrl_c().await.ok();
@@ -0,0 +1,362 @@
+//! Prometheus text exposition for `GET /metrics`.
+//!
+//! Counters live in a process-wide registry (storage helpers record into it without access to
+//! `AppState`); gauges are read from the state subsystems when scraped.
+
+use std::collections::BTreeMap;
+use std::fmt::Write as _;
+use std::future::Future;
+use std::sync::atomic::{AtomicI64, AtomicU64, Ordering};
+use std::sync::{Mutex, OnceLock};
+use std::net::IpAddr;
+use std::time::{Duration, Instant};
+
+use crate::state::AppState;
+use crate::{EventQueryError, HintError, InviteError, KeyRotationError};
+
+/// Upper bounds (seconds) of the backend latency histogram buckets
+const LATENCY_BUCKETS: [f64; 9] = [0.001, 0.0025, 0.005, 0.01, 0.025, 0.05, 0.1, 0.5, 1.0];
+
+/// How long event queue totals are reused between scrapes (they are counted in the database)
+const EVENT_TOTALS_TTL: Duration = Duration::from_secs(30);
+
+/// REST routes reported by name; anything else is counted as `other` to bound label values
+const KNOWN_ROUTES: &[&str] = &[
+    "api.get.status",
+    "api.get.invites",
+    "api.post.invites.redeem",
+    "api.post.invites.revoke",
+    "api.post.servers.register",
+    "api.get.servers.hint",
+    "api.post.servers.invites",
+    "api.post.servers.members",
+    "api.post.servers.keys",
+    "api.get.servers.keys",
+    "api.post.servers.events",
+    "api.get.servers.events",
+    "api.post.servers.events.ack",
+    "api.get.servers.events.stats",
+    "api.post.servers.ack",
+];
+
+#[derive(Default)]
+struct LatencyStats {
+    count: u64,
+    errors: u64,
+    sum_secs: f64,
+    buckets: [u64; LATENCY_BUCKETS.len()],
+}
+
+#[derive(Default)]
+pub struct Metrics {
+    ws_connections: AtomicI64,
+    ws_connections_total: AtomicU64,
+    ws_messages: Mutex<BTreeMap<String, u64>>,
+    forward_failures: Mutex<BTreeMap<&'static str, u64>>,
+    http_requests: Mutex<BTreeMap<(&'static str, u16), u64>>,
+    invite_redemptions: Mutex<BTreeMap<&'static str, u64>>,
+    backend_ops: Mutex<BTreeMap<(&'static str, &'static str), LatencyStats>>,
+    /// Last event queue totals (retained events, houses) and when they were counted
+    event_totals: Mutex<Option<(Instant, (u64, u64))>>,
+}
+
+static METRICS: OnceLock<Metrics> = OnceLock::new();
+
+pub fn metrics() -> &'static Metrics {
+    METRICS.get_or_init(Metrics::default)
+}
+
+fn bump<K: Ord>(map: &Mutex<BTreeMap<K, u64>>, key: K) {
+    let mut map = map.lock().unwrap_or_else(|e| e.into_inner());
+    *map.entry(key).or_insert(0) += 1;
+}
+
+impl Metrics {
+    pub fn ws_connected(&self) {
+        self.ws_connections.fetch_add(1, Ordering::Relaxed);
+        self.ws_connections_total.fetch_add(1, Ordering::Relaxed);
+    }
+
+    pub fn ws_disconnected(&self) {
+        self.ws_connections.fetch_sub(1, Ordering::Relaxed);
+    }
+
+    /// A message received over WebSocket; `message_type` must be a parsed `SignalingMessage` type
+    /// (or "invalid") so label values stay bounded.
+    pub fn ws_message(&self, message_type: &str) {
+        let mut map = self.ws_messages.lock().unwrap_or_else(|e| e.into_inner());
+        match map.get_mut(message_type) {
+            Some(count) => *count += 1,
+            None => {
+                map.insert(message_type.to_string(), 1);
+            }
+        }
+    }
+
+    /// A relayed message (Offer/Answer/ICE, voice or not) that could not be delivered
+    pub fn forward_failure(&self, reason: &'static str) {
+        bump(&self.forward_failures, reason);
+    }
+
+    pub fn http_request(&self, route: &str, status: u16) {
+        let route = KNOWN_ROUTES.iter().find(|r| **r == route).copied().unwrap_or("other");
+        bump(&self.http_requests, (route, status));
+    }
+
+    /// `redeemed`, `not_found` or `refused` (lockout)
+    pub fn invite_redemption(&self, outcome: &'static str) {
+        bump(&self.invite_redemptions, outcome);
+    }
+
+    fn observe_backend(&self, backend: &'static str, op: &'static str, secs: f64, failed: bool) {
+        let mut ops = self.backend_ops.lock().unwrap_or_else(|e| e.into_inner());
+        let stats = ops.entry((backend, op)).or_default();
+        stats.count += 1;
+        stats.sum_secs += secs;
+        if failed {
+            stats.errors += 1;
+        }
+        for (bucket, bound) in stats.buckets.iter_mut().zip(LATENCY_BUCKETS) {
+            if secs <= bound {
+                *bucket += 1;
+            }
+        }
+    }
+}
+
+/// Errors that mean the backend itself failed (as opposed to a conflict or a bad signature)
+pub trait BackendFailure {
+    fn is_backend_failure(&self) -> bool;
+}
+
+impl BackendFailure for String {
+    fn is_backend_failure(&self) -> bool {
+        true
+    }
+}
+
+impl BackendFailure for HintError {
+    fn is_backend_failure(&self) -> bool {
+        matches!(self, HintError::Storage(_))
+    }
+}
+
+impl BackendFailure for InviteError {
+    fn is_backend_failure(&self) -> bool {
+        matches!(self, InviteError::Storage(_))
+    }
+}
+
+impl BackendFailure for KeyRotationError {
+    fn is_backend_failure(&self) -> bool {
+        matches!(self, KeyRotationError::Storage(_))
+    }
+}
+
+impl BackendFailure for EventQueryError {
+    fn is_backend_failure(&self) -> bool {
+        matches!(self, EventQueryError::Storage(_))
+    }
+}
+
+#[cfg(feature = "redis-backend")]
+impl BackendFailure for crate::handlers::redis::RedisVoiceJoinError {
+    fn is_backend_failure(&self) -> bool {
+        matches!(self, crate::handlers::redis::RedisVoiceJoinError::Redis(_))
+    }
+}
+
+/// Run a Postgres/Redis operation, recording its latency and whether it failed
+pub async fn timed<T, E: BackendFailure>(
+    backend: &'static str,
+    op: &'static str,
+    fut: impl Future<Output = Result<T, E>>,
+) -> Result<T, E> {
+    let start = Instant::now();
+    let result = fut.await;
+    let failed = matches!(&result, Err(e) if e.is_backend_failure());
+    metrics().observe_backend(backend, op, start.elapsed().as_secs_f64(), failed);
+    result
+}
+
+fn escape_label(value: &str) -> String {
+    value.replace('\\', "\\\\").replace('"', "\\\"").replace('\n', "\\n")
+}
+
+fn header(out: &mut String, name: &str, kind: &str, help: &str) {
+    let _ = writeln!(out, "# HELP {} {}", name, help);
+    let _ = writeln!(out, "# TYPE {} {}", name, kind);
+}
+
+fn single(out: &mut String, name: &str, kind: &str, help: &str, value: impl std::fmt::Display) {
+    header(out, name, kind, help);
+    let _ = writeln!(out, "{} {}", name, value);
+}
+
+/// Headers a reverse proxy or tunnel adds; a request carrying any of them came from elsewhere
+/// even when it reaches us over loopback.
+const FORWARDING_HEADERS: &[&str] = &["forwarded", "x-forwarded-for", "x-real-ip", "cf-connecting-ip", "true-client-ip"];
+
+/// Whether a `/metrics` request may be served: with a token configured it must come as
+/// `Authorization: Bearer <token>`, otherwise only direct loopback clients are answered
+/// (not proxied ones, which all look local behind a tunnel).
+pub fn scrape_allowed(headers: &hyper::HeaderMap, ip: IpAddr, token: Option<&str>) -> bool {
+    let Some(token) = token else {
+        return ip.is_loopback() && !FORWARDING_HEADERS.iter().any(|h| headers.contains_key(*h));
+    };
+    let presented = headers
+        .get(hyper::header::AUTHORIZATION)
+        .and_then(|v| v.to_str().ok())
+        .and_then(|v| v.strip_prefix("Bearer "))
+        .unwrap_or("");
+    // Compare without an early exit so the time taken doesn't reveal the matching prefix
+    presented.len() == token.len()
+        && presented.bytes().zip(token.bytes()).fold(0u8, |diff, (a, b)| diff | (a ^ b)) == 0
+}
+
+/// Retained events and houses with any, recounted at most every `EVENT_TOTALS_TTL`
+async fn event_queue_totals(state: &AppState) -> (u64, u64) {
+    let m = metrics();
+    if let Some((at, totals)) = *m.event_totals.lock().unwrap_or_else(|e| e.into_inner()) {
+        if at.elapsed() < EVENT_TOTALS_TTL {
+            return totals;
+        }
+    }
+    let totals = state.store().await.event_queue_totals().await.unwrap_or((0, 0));
+    *m.event_totals.lock().unwrap_or_else(|e| e.into_inner()) = Some((Instant::now(), totals));
+    totals
+}
+
+/// Render every metric in the Prometheus text format (version 0.0.4)
+pub async fn render(state: &AppState) -> String {
+    let m = metrics();
+    let mut out = String::new();
+
+    single(&mut out, "beacon_uptime_seconds", "gauge", "Seconds since the beacon started", state.started_at.elapsed().as_secs());
+
+    // Connections and registrations
+    let (registered_peers, subscribed_houses, suspended_sessions) = {
+        let signaling = state.signaling.lock().await;
+        let suspended = signaling.sessions.values().filter(|s| s.attached_socket.is_none()).count();
+        (signaling.peers.len(), signaling.signing_servers.len(), suspended)
+    };
+    single(&mut out, "beacon_ws_connections", "gauge", "Open WebSocket connections", m.ws_connections.load(Ordering::Relaxed));
+    single(&mut out, "beacon_ws_connections_total", "counter", "WebSocket connections accepted", m.ws_connections_total.load(Ordering::Relaxed));
+    single(&mut out, "beacon_ws_suspended_sessions", "gauge", "Dropped connections held for resumption", suspended_sessions);
+    single(&mut out, "beacon_registered_peers", "gauge", "Registered peer_ids", registered_peers);
+    single(&mut out, "beacon_subscribed_houses", "gauge", "Houses with at least one subscribed peer", subscribed_houses);
+
+    let (voice_rooms, voice_participants) = {
+        let voice = state.voice.lock().await;
+        (voice.voice_chats.len(), voice.voice_chats.values().map(|peers| peers.len()).sum::<usize>())
+    };
+    single(&mut out, "beacon_voice_rooms", "gauge", "Voice chats with at least one participant", voice_rooms);
+    single(&mut out, "beacon_voice_participants", "gauge", "Peers in voice chats", voice_participants);
+
+    let presence_users = state.presence.lock().await.presence_users.len();
+    single(&mut out, "beacon_presence_users", "gauge", "Users with a live presence connection", presence_users);
+
+    // WebSocket traffic
+    header(&mut out, "beacon_ws_messages_total", "counter", "WebSocket messages received by type");
+    for (message_type, count) in m.ws_messages.lock().unwrap_or_else(|e| e.into_inner()).iter() {
+        let _ = writeln!(out, "beacon_ws_messages_total{{type=\"{}\"}} {}", escape_label(message_type), count);
+    }
+    header(&mut out, "beacon_ws_forward_failures_total", "counter", "Relayed signaling messages that could not be delivered");
+    for (reason, count) in m.forward_failures.lock().unwrap_or_else(|e| e.into_inner()).iter() {
+        let _ = writeln!(out, "beacon_ws_forward_failures_total{{reason=\"{}\"}} {}", reason, count);
+    }
+    let queues = state.send_queue_stats.snapshot();
+    single(&mut out, "beacon_send_queue_dropped_total", "counter", "Droppable messages skipped because a send queue was full", queues.dropped);
+    single(&mut out, "beacon_send_queue_coalesced_total", "counter", "State updates replaced by a newer one while waiting", queues.coalesced);
+    single(&mut out, "beacon_send_queue_overflow_disconnects_total", "counter", "Connections closed because their send queue overflowed", queues.overflow_disconnects);
+
+    // REST
+    header(&mut out, "beacon_http_requests_total", "counter", "REST requests by route and status");
+    for ((route, status), count) in m.http_requests.lock().unwrap_or_else(|e| e.into_inner()).iter() {
+        let _ = writeln!(out, "beacon_http_requests_total{{route=\"{}\",status=\"{}\"}} {}", route, status, count);
+    }
+    {
+        let limits = state.rate_limits.lock().await;
+        single(&mut out, "beacon_rate_limited_total", "counter", "Requests and messages rejected by rate limits", limits.limited_total);
+        single(&mut out, "beacon_ip_bans_total", "counter", "Temporary IP bans issued", limits.bans_total);
+        single(&mut out, "beacon_banned_ips", "gauge", "IPs currently banned", limits.banned_count());
+    }
+
+    // Invites
+    header(&mut out, "beacon_invite_redemptions_total", "counter", "Invite redemption attempts by outcome");
+    for (outcome, count) in m.invite_redemptions.lock().unwrap_or_else(|e| e.into_inner()).iter() {
+        let _ = writeln!(out, "beacon_invite_redemptions_total{{outcome=\"{}\"}} {}", outcome, count);
+    }
+    let (lookup_failures, lookups_refused) = {
+        let events = state.events.lock().await;
+        (events.invite_lookup_failures_total, events.invite_lookups_refused_total)
+    };
+    single(&mut out, "beacon_invite_lookup_failures_total", "counter", "Invite lookups for codes that don't exist", lookup_failures);
+    single(&mut out, "beacon_invite_lookups_refused_total", "counter", "Invite lookups refused during a lockout", lookups_refused);
+
+    // Event queues (database when configured, counted at most every EVENT_TOTALS_TTL)
+    let (queued_events, queue_houses) = event_queue_totals(state).await;
+    single(&mut out, "beacon_event_queue_events", "gauge", "Retained house events", queued_events);
+    single(&mut out, "beacon_event_queue_houses", "gauge", "Houses with retained events", queue_houses);
+
+    // Storage backends
+    let health = {
+        let backends = state.backends.lock().await;
+        [("postgres", backends.postgres_health.clone()), ("redis", backends.redis_health.clone())]
+    };
+    header(&mut out, "beacon_backend_up", "gauge", "Configured backends that are currently connected");
+    for (backend, h) in health.iter().filter(|(_, h)| h.configured) {
+        let _ = writeln!(out, "beacon_backend_up{{backend=\"{}\"}} {}", backend, h.connected as u8);
+    }
+    header(&mut out, "beacon_backend_reconnects_total", "counter", "Backend connections re-established after a drop");
+    for (backend, h) in health.iter().filter(|(_, h)| h.configured) {
+        let _ = writeln!(out, "beacon_backend_reconnects_total{{backend=\"{}\"}} {}", backend, h.connects.saturating_sub(1));
+    }
+    let ops = m.backend_ops.lock().unwrap_or_else(|e| e.into_inner());
+    header(&mut out, "beacon_backend_op_duration_seconds", "histogram", "Postgres/Redis operation latency");
+    for ((backend, op), stats) in ops.iter() {
+        for (bound, count) in LATENCY_BUCKETS.iter().zip(stats.buckets) {
+            let _ = writeln!(out, "beacon_backend_op_duration_seconds_bucket{{backend=\"{}\",op=\"{}\",le=\"{}\"}} {}", backend, op, bound, count);
+        }
+        let _ = writeln!(out, "beacon_backend_op_duration_seconds_bucket{{backend=\"{}\",op=\"{}\",le=\"+Inf\"}} {}", backend, op, stats.count);
+        let _ = writeln!(out, "beacon_backend_op_duration_seconds_sum{{backend=\"{}\",op=\"{}\"}} {}", backend, op, stats.sum_secs);
+        let _ = writeln!(out, "beacon_backend_op_duration_seconds_count{{backend=\"{}\",op=\"{}\"}} {}", backend, op, stats.count);
+    }
+    header(&mut out, "beacon_backend_op_errors_total", "counter", "Postgres/Redis operations that failed");
+    for ((backend, op), stats) in ops.iter() {
+        let _ = writeln!(out, "beacon_backend_op_errors_total{{backend=\"{}\",op=\"{}\"}} {}", backend, op, stats.errors);
+    }
+
+    out
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn scrapes_need_the_token_or_loopback() {
+        let local: IpAddr = "127.0.0.1".parse().unwrap();
+        let remote: IpAddr = "203.0.113.7".parse().unwrap();
+        let mut headers = hyper::HeaderMap::new();
+        assert!(scrape_allowed(&headers, local, None));
+        assert!(!scrape_allowed(&headers, remote, None));
+
+        // With a token even loopback clients must present it
+        assert!(!scrape_allowed(&headers, local, Some("s3cret")));
+        headers.insert(hyper::header::AUTHORIZATION, "Bearer s3cre".parse().unwrap());
+        assert!(!scrape_allowed(&headers, remote, Some("s3cret")));
+        headers.insert(hyper::header::AUTHORIZATION, "Bearer s3cret".parse().unwrap());
+        assert!(scrape_allowed(&headers, remote, Some("s3cret")));
+    }
+
+    #[test]
+    fn proxied_loopback_scrapes_need_the_token() {
+        let local: IpAddr = "127.0.0.1".parse().unwrap();
+        for name in ["cf-connecting-ip", "x-forwarded-for"] {
+            let mut headers = hyper::HeaderMap::new();
+            headers.insert(name, "203.0.113.7".parse().unwrap());
+            assert!(!scrape_allowed(&headers, local, None), "{}", name);
+        }
+    }
+}
@@ -0,0 +1,285 @@
+//! Bounded per-connection send queues.
+//!
+//! Every WebSocket gets a fixed-size queue drained by its send task. What happens when a
+//! slow client lets it fill up depends on the message:
+//! - signaling (the default `send`) is never dropped; the connection is closed instead
+//! - state broadcasts (presence, profiles, voice presence) are coalesced per key, so only
+//!   the latest value per user waits while the queue is full
+//! - keepalive pings are simply dropped
+
+use std::collections::HashMap;
+use std::sync::atomic::{AtomicBool, AtomicU64, Ordering};
+use std::sync::{Arc, Mutex};
+
+use hyper_tungstenite::tungstenite::Message;
+use serde::Serialize;
+use tokio::sync::{mpsc, Notify};
+
+pub const DEFAULT_SEND_QUEUE_CAPACITY: usize = 256;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum SendError {
+    /// The connection is gone (or was closed for overflowing)
+    Closed,
+    /// The queue was full; the connection is being closed
+    Overflow,
+}
+
+impl std::fmt::Display for SendError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            SendError::Closed => write!(f, "connection closed"),
+            SendError::Overflow => write!(f, "send queue full, disconnecting slow client"),
+        }
+    }
+}
+
+/// Overflow counters across all connections (for /api/status and metrics)
+#[derive(Debug, Default)]
+pub struct SendQueueStats {
+    pub dropped: AtomicU64,
+    pub coalesced: AtomicU64,
+    pub overflow_disconnects: AtomicU64,
+}
+
+#[derive(Debug, Clone, Copy, Serialize)]
+pub struct SendQueueStatsSnapshot {
+    pub dropped: u64,
+    pub coalesced: u64,
+    pub overflow_disconnects: u64,
+}
+
+impl SendQueueStats {
+    pub fn snapshot(&self) -> SendQueueStatsSnapshot {
+        SendQueueStatsSnapshot {
+            dropped: self.dropped.load(Ordering::Relaxed),
+            coalesced: self.coalesced.load(Ordering::Relaxed),
+            overflow_disconnects: self.overflow_disconnects.load(Ordering::Relaxed),
+        }
+    }
+}
+
+struct Shared {
+    /// Latest coalesced message per key, waiting for room in the queue
+    pending: Mutex<HashMap<String, Message>>,
+    wake: Notify,
+    closed: AtomicBool,
+    stats: Arc<SendQueueStats>,
+}
+
+/// Sending half of a connection's queue. Cheap to clone; all clones feed the same socket.
+#[derive(Clone)]
+pub struct ConnSender {
+    tx: mpsc::Sender<Message>,
+    shared: Arc<Shared>,
+}
+
+/// Receiving half, owned by the connection's send task.
+pub struct ConnReceiver {
+    rx: mpsc::Receiver<Message>,
+    shared: Arc<Shared>,
+}
+
+pub fn channel(capacity: usize, stats: Arc<SendQueueStats>) -> (ConnSender, ConnReceiver) {
+    let (tx, rx) = mpsc::channel(capacity.max(1));
+    let shared = Arc::new(Shared {
+        pending: Mutex::new(HashMap::new()),
+        wake: Notify::new(),
+        closed: AtomicBool::new(false),
+        stats,
+    });
+    (
+        ConnSender { tx, shared: shared.clone() },
+        ConnReceiver { rx, shared },
+    )
+}
+
+impl ConnSender {
+    /// Queue a message that must not be lost. If the queue is full the connection is closed.
+    pub fn send(&self, msg: Message) -> Result<(), SendError> {
+        if self.shared.closed.load(Ordering::Relaxed) {
+            return Err(SendError::Closed);
+        }
+        match self.tx.try_send(msg) {
+            Ok(()) => Ok(()),
+            Err(mpsc::error::TrySendError::Closed(_)) => Err(SendError::Closed),
+            Err(mpsc::error::TrySendError::Full(_)) => {
+                if !self.shared.closed.swap(true, Ordering::Relaxed) {
+                    self.shared.stats.overflow_disconnects.fetch_add(1, Ordering::Relaxed);
+                    self.shared.wake.notify_one();
+                }
+                Err(SendError::Overflow)
+            }
+        }
+    }
+
+    /// Queue a state update where only the latest value per `key` matters. While the queue is
+    /// full (or an older update for the key is still waiting) it replaces the waiting one.
+    pub fn send_coalesced(&self, key: String, msg: Message) -> Result<(), SendError> {
+        if self.shared.closed.load(Ordering::Relaxed) || self.tx.is_closed() {
+            return Err(SendError::Closed);
+        }
+        let mut pending = self.shared.pending.lock().unwrap_or_else(|e| e.into_inner());
+        // Keep per-key order: once an update is waiting, newer ones must wait behind it
+        if let Some(waiting) = pending.get_mut(&key) {
+            *waiting = msg;
+            self.shared.stats.coalesced.fetch_add(1, Ordering::Relaxed);
+            return Ok(());
+        }
+        match self.tx.try_send(msg) {
+            Ok(()) => Ok(()),
+            Err(mpsc::error::TrySendError::Closed(_)) => Err(SendError::Closed),
+            Err(mpsc::error::TrySendError::Full(msg)) => {
+                pending.insert(key, msg);
+                drop(pending);
+                self.shared.wake.notify_one();
+                Ok(())
+            }
+        }
+    }
+
+    /// Close the connection on purpose: anything still queued is dropped and the send task ends.
+    pub fn close(&self) {
+        if !self.shared.closed.swap(true, Ordering::Relaxed) {
+            self.shared.wake.notify_one();
+        }
+    }
+
+    pub fn is_closed(&self) -> bool {
+        self.shared.closed.load(Ordering::Relaxed)
+    }
+
+    /// Queue a message that can be skipped when the client is behind (e.g. keepalive pings).
+    pub fn send_droppable(&self, msg: Message) -> Result<(), SendError> {
+        if self.shared.closed.load(Ordering::Relaxed) {
+            return Err(SendError::Closed);
+        }
+        match self.tx.try_send(msg) {
+            Ok(()) => Ok(()),
+            Err(mpsc::error::TrySendError::Closed(_)) => Err(SendError::Closed),
+            Err(mpsc::error::TrySendError::Full(_)) => {
+                self.shared.stats.dropped.fetch_add(1, Ordering::Relaxed);
+                Ok(())
+            }
+        }
+    }
+}
+
+impl ConnReceiver {
+    /// Next message to write to the socket, or None once the connection should close
+    /// (all senders dropped, or a must-deliver message overflowed).
+    pub async fn recv(&mut self) -> Option<Message> {
+        loop {
+            if self.shared.closed.load(Ordering::Relaxed) {
+                return None;
+            }
+            // Queued messages go first; coalesced updates are flushed once the queue drains
+            if let Ok(msg) = self.rx.try_recv() {
+                return Some(msg);
+            }
+            if let Some(msg) = self.take_pending() {
+                return Some(msg);
+            }
+            tokio::select! {
+                biased;
+                msg = self.rx.recv() => return msg,
+                _ = self.shared.wake.notified() => {}
+            }
+        }
+    }
+
+    fn take_pending(&self) -> Option<Message> {
+        let mut pending = self.shared.pending.lock().unwrap_or_else(|e| e.into_inner());
+        let key = pending.keys().next().cloned()?;
+        pending.remove(&key)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn text(s: &str) -> Message {
+        Message::Text(s.to_string())
+    }
+
+    fn queue(capacity: usize) -> (ConnSender, ConnReceiver, Arc<SendQueueStats>) {
+        let stats = Arc::new(SendQueueStats::default());
+        let (tx, rx) = channel(capacity, stats.clone());
+        (tx, rx, stats)
+    }
+
+    #[tokio::test]
+    async fn delivers_in_order() {
+        let (tx, mut rx, _) = queue(4);
+        for s in ["a", "b", "c"] {
+            tx.send(text(s)).unwrap();
+        }
+        for s in ["a", "b", "c"] {
+            assert_eq!(rx.recv().await, Some(text(s)));
+        }
+    }
+
+    #[tokio::test]
+    async fn overflowing_signaling_closes_the_connection() {
+        let (tx, mut rx, stats) = queue(2);
+        tx.send(text("a")).unwrap();
+        tx.send(text("b")).unwrap();
+        assert_eq!(tx.send(text("c")), Err(SendError::Overflow));
+        assert_eq!(tx.send(text("d")), Err(SendError::Closed));
+        assert_eq!(tx.send_droppable(text("ping")), Err(SendError::Closed));
+        // Whatever was still queued is abandoned with the connection
+        assert_eq!(rx.recv().await, None);
+        assert_eq!(stats.snapshot().overflow_disconnects, 1);
+    }
+
+    #[tokio::test]
+    async fn droppable_messages_are_skipped_when_full() {
+        let (tx, mut rx, stats) = queue(1);
+        tx.send(text("a")).unwrap();
+        assert_eq!(tx.send_droppable(text("ping")), Ok(()));
+        assert_eq!(stats.snapshot().dropped, 1);
+        assert_eq!(rx.recv().await, Some(text("a")));
+        tx.send(text("b")).unwrap();
+        assert_eq!(rx.recv().await, Some(text("b")));
+    }
+
+    #[tokio::test]
+    async fn coalesced_updates_keep_the_latest_per_key() {
+        let (tx, mut rx, stats) = queue(1);
+        tx.send(text("signal")).unwrap();
+        tx.send_coalesced("user-1".to_string(), text("u1 v1")).unwrap();
+        tx.send_coalesced("user-1".to_string(), text("u1 v2")).unwrap();
+        tx.send_coalesced("user-2".to_string(), text("u2 v1")).unwrap();
+        assert_eq!(stats.snapshot().coalesced, 1);
+
+        assert_eq!(rx.recv().await, Some(text("signal")));
+        let mut flushed = vec![rx.recv().await.unwrap(), rx.recv().await.unwrap()];
+        flushed.sort_by_key(|m| m.to_string());
+        assert_eq!(flushed, vec![text("u1 v2"), text("u2 v1")]);
+
+        // With room again, updates go straight into the queue
+        tx.send_coalesced("user-1".to_string(), text("u1 v3")).unwrap();
+        assert_eq!(rx.recv().await, Some(text("u1 v3")));
+    }
+
+    #[tokio::test]
+    async fn closes_after_draining_once_senders_are_gone() {
+        let (tx, mut rx, _) = queue(1);
+        tx.send(text("a")).unwrap();
+        tx.send_coalesced("k".to_string(), text("latest")).unwrap();
+        drop(tx);
+        assert_eq!(rx.recv().await, Some(text("a")));
+        assert_eq!(rx.recv().await, Some(text("latest")));
+        assert_eq!(rx.recv().await, None);
+    }
+
+    #[tokio::test]
+    async fn sending_to_a_closed_connection_fails() {
+        let (tx, rx, _) = queue(4);
+        drop(rx);
+        assert_eq!(tx.send(text("a")), Err(SendError::Closed));
+        assert_eq!(tx.send_coalesced("k".to_string(), text("b")), Err(SendError::Closed));
+        assert_eq!(tx.send_droppable(text("c")), Err(SendError::Closed));
+    }
+}
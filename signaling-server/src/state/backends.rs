@@ -1,27 +1,84 @@
-#[cfg(feature = "postgres")]
-use sqlx::PgPool;
+use std::sync::Arc;
+use chrono::{DateTime, Utc};
+use serde::Serialize;
+use crate::store::Store;
 #[cfg(feature = "redis-backend")]
-use redis::Client;
+use redis::{aio::ConnectionManager, Client};
 
-/// Backend state (db, redis)
+/// Connection status of one backend, maintained by the supervisor
+#[derive(Debug, Clone, Default, Serialize)]
+pub struct BackendHealth {
+    /// A URL is set and the backend is compiled in
+    pub configured: bool,
+    pub connected: bool,
+    /// When `connected` last changed
+    pub since: Option<DateTime<Utc>>,
+    pub last_error: Option<String>,
+    pub connects: u64,
+    pub disconnects: u64,
+}
+
+impl BackendHealth {
+    pub fn configured() -> Self {
+        Self { configured: true, ..Self::default() }
+    }
+
+    pub fn mark_up(&mut self) {
+        if !self.connected {
+            self.connected = true;
+            self.since = Some(Utc::now());
+            self.connects += 1;
+        }
+    }
+
+    pub fn mark_down(&mut self, error: String) {
+        if self.connected {
+            self.connected = false;
+            self.since = Some(Utc::now());
+            self.disconnects += 1;
+        }
+        self.last_error = Some(error);
+    }
+
+    /// Unconfigured backends don't hold readiness back
+    pub fn ready(&self) -> bool {
+        !self.configured || self.connected
+    }
+}
+
+/// Backend state (db, redis). Handles are None while the backend is down; callers fall back
+/// to in-memory state.
 pub struct BackendState {
-    #[cfg(feature = "postgres")]
-    pub db: Option<PgPool>,
+    /// Postgres or SQLite store, whichever the build enables (see `AppState::store`)
+    pub store: Option<Arc<dyn Store>>,
     #[cfg(feature = "redis-backend")]
     pub redis: Option<Client>,
     #[cfg(feature = "redis-backend")]
     pub redis_presence_ttl_secs: u64,
+    /// Long-lived connection for cross-instance routing (publishes on every forwarded message)
+    #[cfg(feature = "redis-backend")]
+    pub redis_conn: Option<ConnectionManager>,
+    pub postgres_health: BackendHealth,
+    pub redis_health: BackendHealth,
 }
 
 impl BackendState {
     pub fn new() -> Self {
         Self {
-            #[cfg(feature = "postgres")]
-            db: None,
+            store: None,
             #[cfg(feature = "redis-backend")]
             redis: None,
             #[cfg(feature = "redis-backend")]
-            redis_presence_ttl_secs: 120, // Matches DEFAULT_REDIS_PRESENCE_TTL_SECS in main.rs
+            redis_presence_ttl_secs: crate::config::DEFAULT_REDIS_PRESENCE_TTL_SECS,
+            #[cfg(feature = "redis-backend")]
+            redis_conn: None,
+            postgres_health: BackendHealth::default(),
+            redis_health: BackendHealth::default(),
         }
     }
+
+    /// All configured backends are connected
+    pub fn ready(&self) -> bool {
+        self.postgres_health.ready() && self.redis_health.ready()
+    }
 }
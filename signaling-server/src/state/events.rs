@@ -1,8 +1,100 @@
-use std::collections::HashMap;
-use chrono::{Duration, Utc};
-use crate::{SigningPubkey, EncryptedServerHint, InviteTokenRecord, ServerEvent, InviteTokenCreateRequest};
+use std::collections::{HashMap, HashSet};
+use std::net::IpAddr;
+use chrono::{DateTime, Duration, Utc};
+use crate::auth;
+use crate::{SigningPubkey, EncryptedServerHint, InviteTokenRecord, ServerEvent, EventPage, EventQueryError, EventQueueStats, InviteTokenCreateRequest, InviteError, HintError, KeyRotationRequest, KeyRotationError, SealedKeyRecord};
 
-const EVENT_RETENTION_DAYS: i64 = 30;
+/// Unacked events kept per house when `SIGNALING_MAX_UNACKED_EVENTS` is not set
+pub const DEFAULT_MAX_UNACKED_EVENTS: usize = 10_000;
+/// Page size for event queries when the client doesn't ask for one
+pub const EVENT_PAGE_DEFAULT_LIMIT: usize = 100;
+pub const EVENT_PAGE_MAX_LIMIT: usize = 500;
+
+/// Failed invite lookups allowed per source IP before backoff starts
+pub const INVITE_FREE_FAILURES_PER_IP: u32 = 5;
+/// Lockout after the first failure past the free ones; doubles with each further failure
+pub const INVITE_BACKOFF_BASE_SECS: i64 = 2;
+pub const INVITE_BACKOFF_MAX_SECS: i64 = 3600;
+/// One counted failure is forgiven per this long without new failures. Successful lookups
+/// forgive nothing, so mixing in a known code doesn't buy more guesses.
+pub const INVITE_FAILURE_DECAY_SECS: i64 = 600;
+
+/// Key failed invite lookups from `ip` are counted under. Only the guesser is backed off, never
+/// the codes, so no one can lock honest holders out. None for loopback: behind a local tunnel
+/// without trusted proxy headers it is every client at once.
+pub fn invite_failure_key(ip: IpAddr) -> Option<String> {
+    (!ip.to_canonical().is_loopback()).then(|| format!("ip:{}", ip))
+}
+
+/// Failures still counted at `now` out of `count`, the last at `last_failure`
+pub fn decayed_invite_failures(count: u32, last_failure: DateTime<Utc>, now: DateTime<Utc>) -> u32 {
+    let forgiven = ((now - last_failure).num_seconds() / INVITE_FAILURE_DECAY_SECS).clamp(0, u32::MAX as i64);
+    count.saturating_sub(forgiven as u32)
+}
+
+/// How much longer lookups are refused after `failures` failures, the last at `last_failure`.
+/// `redis_begin_invite_lookup` follows the same schedule.
+pub fn invite_backoff(failures: u32, free: u32, last_failure: DateTime<Utc>, now: DateTime<Utc>) -> Option<Duration> {
+    let failures = decayed_invite_failures(failures, last_failure, now);
+    if failures <= free {
+        return None;
+    }
+    let doublings = (failures - free - 1).min(20);
+    let secs = (INVITE_BACKOFF_BASE_SECS << doublings).min(INVITE_BACKOFF_MAX_SECS);
+    let remaining = last_failure + Duration::seconds(secs) - now;
+    (remaining > Duration::zero()).then_some(remaining)
+}
+
+pub fn clamp_event_limit(limit: Option<usize>) -> usize {
+    limit.unwrap_or(EVENT_PAGE_DEFAULT_LIMIT).clamp(1, EVENT_PAGE_MAX_LIMIT)
+}
+
+/// A cursor has expired if events after it were garbage-collected, or if it is ahead of
+/// the queue (it was issued before the beacon lost its state). `after_seq` 0 never expires:
+/// it asks for everything still retained (a client starting from the hint).
+pub fn event_cursor_expired(after_seq: u64, oldest_retained: Option<u64>, latest_seq: u64) -> bool {
+    if after_seq == 0 {
+        return false;
+    }
+    let gc_floor = oldest_retained.map(|seq| seq.saturating_sub(1)).unwrap_or(latest_seq);
+    after_seq < gc_floor || after_seq > latest_seq
+}
+
+/// Highest seq every known member of a house has acked.
+/// Known members are the hint signers (by user_id); acks from anyone else (say, a member removed
+/// since) are ignored, and a signer who never acked holds this at 0.
+pub fn min_acked_seq(signers: Option<&HashSet<String>>, acks: &HashMap<String, u64>) -> u64 {
+    let known: HashSet<String> = signers
+        .into_iter()
+        .flatten()
+        .filter_map(|pk| auth::decode_member_key(pk).ok())
+        .map(|key| auth::user_id_for_key(&key))
+        .collect();
+    known
+        .iter()
+        .map(|user_id| acks.get(user_id).copied().unwrap_or(0))
+        .min()
+        .unwrap_or(0)
+}
+
+/// Queue stats from the retained events (oldest first) and the all-members ack point.
+pub fn event_queue_stats(
+    signing_pubkey: &str,
+    events: &[ServerEvent],
+    latest_seq: u64,
+    acked_seq: u64,
+) -> EventQueueStats {
+    let oldest_unacked = events.iter().find(|e| e.seq > acked_seq);
+    EventQueueStats {
+        signing_pubkey: signing_pubkey.to_string(),
+        queue_depth: events.len(),
+        latest_seq,
+        acked_seq,
+        unacked: events.iter().filter(|e| e.seq > acked_seq).count(),
+        oldest_unacked_seq: oldest_unacked.map(|e| e.seq),
+        oldest_unacked_age_secs: oldest_unacked.map(|e| (Utc::now() - e.timestamp).num_seconds().max(0)),
+    }
+}
 
 /// Event queue state (REST API)
 /// Hints only - clients treat local state as authoritative
@@ -14,8 +106,26 @@ pub struct EventState {
     /// Event queue - time-limited, not consensus-based
     /// Best-effort sync - timestamp collisions possible
     pub event_queues: HashMap<SigningPubkey, Vec<ServerEvent>>,
-    /// Best-effort acks - soft tracking, not hard requirement
-    pub member_acks: HashMap<(SigningPubkey, String), String>, // (signing_pubkey, user_id) -> last_event_id
+    /// Last seq assigned per house (kept after GC so seqs are never reused)
+    pub event_seqs: HashMap<SigningPubkey, u64>,
+    /// Best-effort acks: (signing_pubkey, user_id) -> highest seq acked.
+    /// Events every known member has acked are pruned by GC.
+    pub member_acks: HashMap<(SigningPubkey, String), u64>,
+    /// Unacked events kept per house before the oldest are dropped anyway
+    pub max_unacked_events: usize,
+    /// Events older than this are dropped by GC even if unacked
+    pub event_retention_days: i64,
+    /// Lifetime of newly created invite tokens
+    pub invite_ttl_days: i64,
+    /// Member identity keys (hex) allowed to sign hints, recorded on invite redemption
+    pub house_signers: HashMap<SigningPubkey, HashSet<String>>,
+    /// Latest house key rotation per house and when it was stored (older epochs are dropped)
+    pub key_rotations: HashMap<SigningPubkey, (KeyRotationRequest, DateTime<Utc>)>,
+    /// Failed invite lookups per `invite_failure_keys` key: (count, last failure)
+    pub invite_failures: HashMap<String, (u32, DateTime<Utc>)>,
+    /// Invite lookups that found nothing, and lookups refused during a lockout (since startup)
+    pub invite_lookup_failures_total: u64,
+    pub invite_lookups_refused_total: u64,
 }
 
 impl EventState {
@@ -24,13 +134,72 @@ impl EventState {
             server_hints: HashMap::new(),
             invite_tokens: HashMap::new(),
             event_queues: HashMap::new(),
+            event_seqs: HashMap::new(),
             member_acks: HashMap::new(),
+            max_unacked_events: DEFAULT_MAX_UNACKED_EVENTS,
+            event_retention_days: crate::config::DEFAULT_EVENT_RETENTION_DAYS,
+            invite_ttl_days: crate::config::DEFAULT_INVITE_TTL_DAYS,
+            house_signers: HashMap::new(),
+            key_rotations: HashMap::new(),
+            invite_failures: HashMap::new(),
+            invite_lookup_failures_total: 0,
+            invite_lookups_refused_total: 0,
         }
     }
 
-    /// Register/update server hint (any member can call this at any time)
-    pub fn register_server_hint(&mut self, signing_pubkey: String, hint: EncryptedServerHint) {
+    /// Register/update server hint (any authorized member can call this at any time).
+    /// Compare-and-swap on `revision`: a write based on an older hint gets the current one back.
+    pub fn register_server_hint(&mut self, signing_pubkey: String, hint: EncryptedServerHint) -> Result<(), HintError> {
+        let empty = HashSet::new();
+        let signers = self.house_signers.get(&signing_pubkey).unwrap_or(&empty);
+        auth::verify_server_hint(&signing_pubkey, &hint, signers)?;
+        if let Some(current) = self.server_hints.get(&signing_pubkey) {
+            if hint.revision != current.revision + 1 {
+                return Err(HintError::Conflict(Box::new(current.clone())));
+            }
+        }
         self.server_hints.insert(signing_pubkey, hint);
+        Ok(())
+    }
+
+    /// Allow a member identity key to sign hints for a house (callers validate the key).
+    pub fn authorize_member_signer(&mut self, signing_pubkey: &str, member_pubkey: &str) {
+        self.house_signers
+            .entry(signing_pubkey.to_string())
+            .or_default()
+            .insert(member_pubkey.to_string());
+    }
+
+    /// Store a house key rotation. Must be signed by the house key and newer than the stored one.
+    /// Removed members lose their hint-signing rights.
+    pub fn put_key_rotation(&mut self, signing_pubkey: &str, req: KeyRotationRequest) -> Result<(), KeyRotationError> {
+        let payload = auth::key_rotation_payload(signing_pubkey, req.key_epoch, &req.sealed_keys, &req.removed_user_ids);
+        auth::verify_house_signature(signing_pubkey, &payload, &req.signature)?;
+        if let Some((current, _)) = self.key_rotations.get(signing_pubkey) {
+            if req.key_epoch <= current.key_epoch {
+                return Err(KeyRotationError::StaleEpoch { current: current.key_epoch });
+            }
+        }
+        if let Some(signers) = self.house_signers.get_mut(signing_pubkey) {
+            signers.retain(|pk| match auth::decode_member_key(pk) {
+                Ok(key) => !req.removed_user_ids.contains(&auth::user_id_for_key(&key)),
+                Err(_) => false,
+            });
+        }
+        self.key_rotations.insert(signing_pubkey.to_string(), (req, Utc::now()));
+        Ok(())
+    }
+
+    /// A member's sealed copy of the latest rotated key
+    pub fn get_sealed_key(&self, signing_pubkey: &str, user_id: &str) -> Option<SealedKeyRecord> {
+        let (rotation, created_at) = self.key_rotations.get(signing_pubkey)?;
+        let sealed_key = rotation.sealed_keys.get(user_id)?;
+        Some(SealedKeyRecord {
+            signing_pubkey: signing_pubkey.to_string(),
+            key_epoch: rotation.key_epoch,
+            sealed_key: sealed_key.clone(),
+            created_at: *created_at,
+        })
     }
 
     /// Get server hint
@@ -38,14 +207,23 @@ impl EventState {
         self.server_hints.get(signing_pubkey)
     }
 
-    pub fn put_invite_token(&mut self, signing_pubkey: &str, req: InviteTokenCreateRequest) -> Result<InviteTokenRecord, String> {
+    /// Create/replace an invite. Must be signed by the house key; codes cannot be taken over by
+    /// another house until its invite expires.
+    pub fn put_invite_token(&mut self, signing_pubkey: &str, req: InviteTokenCreateRequest, ttl_days: i64) -> Result<InviteTokenRecord, InviteError> {
         let code = req.code.trim().to_string();
         if code.len() < 6 || code.len() > 64 {
-            return Err("Invalid invite code length".to_string());
+            return Err(InviteError::InvalidCode);
         }
+        let payload = auth::invite_create_payload(signing_pubkey, &code, req.max_uses, &req.encrypted_payload);
+        auth::verify_house_signature(signing_pubkey, &payload, &req.signature)?;
         let now = Utc::now();
+        if let Some(existing) = self.invite_tokens.get(&code) {
+            if existing.signing_pubkey != signing_pubkey && existing.expires_at > now {
+                return Err(InviteError::CodeTaken);
+            }
+        }
         // Keep server-side cleanup; not user-facing.
-        let expires_at = now + Duration::days(30);
+        let expires_at = now + Duration::days(ttl_days);
         let max_uses = req.max_uses;
         let remaining_uses = req.max_uses; // 0 = unlimited
         let record = InviteTokenRecord {
@@ -62,14 +240,14 @@ impl EventState {
         Ok(record)
     }
 
+    /// Expired invites are treated as gone even before GC removes them
     pub fn get_invite_token(&self, code: &str) -> Option<&InviteTokenRecord> {
-        self.invite_tokens.get(code)
+        self.invite_tokens.get(code).filter(|rec| rec.expires_at > Utc::now())
     }
 
     pub fn redeem_invite_token(&mut self, code: &str) -> Option<InviteTokenRecord> {
-        let Some(rec) = self.invite_tokens.get_mut(code) else {
-            return None;
-        };
+        let now = Utc::now();
+        let rec = self.invite_tokens.get_mut(code).filter(|rec| rec.expires_at > now)?;
         // unlimited
         if rec.max_uses == 0 {
             return Some(rec.clone());
@@ -81,55 +259,223 @@ impl EventState {
         Some(rec.clone())
     }
 
+    /// Delete an invite. Must be signed by the key of the house that owns it.
+    pub fn revoke_invite_token(&mut self, code: &str, signature: &str) -> Result<(), InviteError> {
+        let Some(rec) = self.invite_tokens.get(code) else {
+            return Err(InviteError::NotFound);
+        };
+        let payload = auth::invite_revoke_payload(&rec.signing_pubkey, code);
+        auth::verify_house_signature(&rec.signing_pubkey, &payload, signature)?;
+        self.invite_tokens.remove(code);
+        Ok(())
+    }
+
     pub fn gc_expired_invites(&mut self) {
         let now = Utc::now();
         self.invite_tokens.retain(|_, v| v.expires_at > now);
     }
 
-    /// Post event to queue
-    pub fn post_event(&mut self, signing_pubkey: String, mut event: ServerEvent) {
-        event.timestamp = Utc::now();
+    /// Forget failure counts that have fully decayed
+    pub fn prune_invite_failures(&mut self) {
+        let now = Utc::now();
+        self.invite_failures.retain(|_, (count, last)| decayed_invite_failures(*count, *last, now) > 0);
+    }
+
+    /// Refuse an invite lookup from `ip` while locked out; otherwise count it as a failure up
+    /// front, so concurrent guesses can't all get past the check before any of them is
+    /// recorded. `refund_invite_lookup` gives the failure back if the code exists.
+    pub fn begin_invite_lookup(&mut self, ip: IpAddr) -> Result<(), Duration> {
+        let Some(key) = invite_failure_key(ip) else {
+            return Ok(());
+        };
+        let now = Utc::now();
+        if let Some((count, last)) = self.invite_failures.get(&key) {
+            if let Some(remaining) = invite_backoff(*count, INVITE_FREE_FAILURES_PER_IP, *last, now) {
+                return Err(remaining);
+            }
+        }
+        let entry = self.invite_failures.entry(key).or_insert((0, now));
+        entry.0 = decayed_invite_failures(entry.0, entry.1, now) + 1;
+        entry.1 = now;
+        Ok(())
+    }
+
+    /// Undo the failure `begin_invite_lookup` counted for a lookup that found its code.
+    /// Earlier failures stay counted.
+    pub fn refund_invite_lookup(&mut self, ip: IpAddr) {
+        if let Some(entry) = invite_failure_key(ip).and_then(|key| self.invite_failures.get_mut(&key)) {
+            entry.0 = entry.0.saturating_sub(1);
+        }
+    }
+
+    /// Post event to queue. Returns the stored event with its id and seq filled in; a retried
+    /// post (same event_id) gets the event as first stored.
+    pub fn post_event(&mut self, signing_pubkey: String, mut event: ServerEvent) -> ServerEvent {
         if event.event_id.is_empty() {
             event.event_id = uuid::Uuid::new_v4().to_string();
         }
+        let retried = self
+            .event_queues
+            .get(&signing_pubkey)
+            .and_then(|queue| queue.iter().find(|e| e.event_id == event.event_id));
+        if let Some(stored) = retried {
+            return stored.clone();
+        }
+        let seq = self.event_seqs.entry(signing_pubkey.clone()).or_insert(0);
+        *seq += 1;
+        event.seq = *seq;
         self.event_queues
             .entry(signing_pubkey)
             .or_insert_with(Vec::new)
-            .push(event);
+            .push(event.clone());
+        event
     }
 
-    /// Get events since a given event ID
-    pub fn get_events(&self, signing_pubkey: &str, since: Option<&str>) -> Vec<ServerEvent> {
-        let events = match self.event_queues.get(signing_pubkey) {
-            Some(events) => events.clone(),
-            None => return Vec::new(),
-        };
+    /// Raise a house's seq counter to one the durable store has handed out, so events
+    /// accepted here during an outage continue after it instead of restarting at 1
+    pub fn note_event_seq(&mut self, signing_pubkey: &str, seq: u64) {
+        let latest = self.event_seqs.entry(signing_pubkey.to_string()).or_insert(0);
+        *latest = (*latest).max(seq);
+    }
 
-        if let Some(since_id) = since {
-            events
-                .into_iter()
-                .skip_while(|e| e.event_id != since_id)
-                .skip(1)
-                .collect()
-        } else {
-            events
+    /// Seq of a retained event (for clients that still send an event_id cursor)
+    pub fn event_seq(&self, signing_pubkey: &str, event_id: &str) -> Option<u64> {
+        self.event_queues
+            .get(signing_pubkey)?
+            .iter()
+            .find(|e| e.event_id == event_id)
+            .map(|e| e.seq)
+    }
+
+    /// Up to `limit` events with seq > `after_seq`, oldest first
+    pub fn get_events_page(&self, signing_pubkey: &str, after_seq: u64, limit: usize) -> Result<EventPage, EventQueryError> {
+        let empty = Vec::new();
+        let events = self.event_queues.get(signing_pubkey).unwrap_or(&empty);
+        let latest_seq = self.event_seqs.get(signing_pubkey).copied().unwrap_or(0);
+        if event_cursor_expired(after_seq, events.first().map(|e| e.seq), latest_seq) {
+            return Err(EventQueryError::CursorExpired { latest_seq });
         }
+
+        let mut page: Vec<ServerEvent> = events
+            .iter()
+            .filter(|e| e.seq > after_seq)
+            .take(limit + 1)
+            .cloned()
+            .collect();
+        let has_more = page.len() > limit;
+        page.truncate(limit);
+        Ok(EventPage {
+            next_seq: page.last().map(|e| e.seq).unwrap_or(after_seq),
+            events: page,
+            has_more,
+        })
+    }
+
+    /// Acknowledge events up to `last_seq` (best-effort; acks never move backwards)
+    pub fn ack_events(&mut self, signing_pubkey: String, user_id: String, last_seq: u64) {
+        let last_seq = last_seq.min(self.event_seqs.get(&signing_pubkey).copied().unwrap_or(0));
+        let acked = self.member_acks.entry((signing_pubkey, user_id)).or_insert(0);
+        *acked = (*acked).max(last_seq);
     }
 
-    /// Acknowledge events (best-effort)
-    pub fn ack_events(&mut self, signing_pubkey: String, user_id: String, last_event_id: String) {
-        self.member_acks.insert((signing_pubkey, user_id), last_event_id);
+    fn house_acks(&self, signing_pubkey: &str) -> HashMap<String, u64> {
+        self.member_acks
+            .iter()
+            .filter(|((spk, _), _)| spk == signing_pubkey)
+            .map(|((_, user_id), seq)| (user_id.clone(), *seq))
+            .collect()
     }
 
-    /// Garbage collect old events (called periodically)
-    pub fn gc_old_events(&mut self) {
-        let cutoff = Utc::now() - Duration::days(EVENT_RETENTION_DAYS);
+    fn acked_seq(&self, signing_pubkey: &str) -> u64 {
+        min_acked_seq(self.house_signers.get(signing_pubkey), &self.house_acks(signing_pubkey))
+    }
+
+    pub fn event_queue_stats(&self, signing_pubkey: &str) -> EventQueueStats {
+        let events = self.event_queues.get(signing_pubkey).map(Vec::as_slice).unwrap_or(&[]);
+        let latest_seq = self.event_seqs.get(signing_pubkey).copied().unwrap_or(0);
+        event_queue_stats(signing_pubkey, events, latest_seq, self.acked_seq(signing_pubkey))
+    }
 
-        for events in self.event_queues.values_mut() {
-            events.retain(|e| e.timestamp > cutoff);
+    /// Garbage collect events (called periodically): drop events from before `cutoff` or acked
+    /// by every known member, then cap what is left per house at `max_unacked`.
+    pub fn gc_old_events(&mut self, cutoff: DateTime<Utc>, max_unacked: usize) {
+        let acked: HashMap<SigningPubkey, u64> = self
+            .event_queues
+            .keys()
+            .map(|spk| (spk.clone(), self.acked_seq(spk)))
+            .collect();
+
+        for (spk, events) in self.event_queues.iter_mut() {
+            let acked_seq = acked.get(spk).copied().unwrap_or(0);
+            events.retain(|e| e.timestamp > cutoff && e.seq > acked_seq);
+            if events.len() > max_unacked {
+                let excess = events.len() - max_unacked;
+                events.drain(..excess);
+            }
         }
 
         // Also clean up empty queues
         self.event_queues.retain(|_, events| !events.is_empty());
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn invite_hits_do_not_reset_failures() {
+        let mut events = EventState::new();
+        let ip: IpAddr = "192.0.2.1".parse().unwrap();
+        for _ in 0..INVITE_FREE_FAILURES_PER_IP {
+            assert!(events.begin_invite_lookup(ip).is_ok());
+            // Interleaving lookups of a real code only gives back their own charge
+            assert!(events.begin_invite_lookup(ip).is_ok());
+            events.refund_invite_lookup(ip);
+        }
+        assert!(events.begin_invite_lookup(ip).is_ok());
+        assert!(events.begin_invite_lookup(ip).is_err());
+        // Other sources are unaffected, whatever codes they look up
+        assert!(events.begin_invite_lookup("192.0.2.2".parse().unwrap()).is_ok());
+    }
+
+    #[test]
+    fn loopback_invite_lookups_are_never_locked_out() {
+        let mut events = EventState::new();
+        for ip in ["127.0.0.1", "::1"] {
+            let ip: IpAddr = ip.parse().unwrap();
+            for _ in 0..INVITE_FREE_FAILURES_PER_IP * 4 {
+                assert!(events.begin_invite_lookup(ip).is_ok());
+            }
+        }
+        assert!(events.invite_failures.is_empty());
+    }
+
+    #[test]
+    fn invite_failures_decay() {
+        let now = Utc::now();
+        let decay = Duration::seconds(INVITE_FAILURE_DECAY_SECS);
+        assert_eq!(decayed_invite_failures(8, now, now), 8);
+        assert_eq!(decayed_invite_failures(8, now - decay * 3, now), 5);
+        assert_eq!(decayed_invite_failures(8, now - decay * 100, now), 0);
+        assert!(invite_backoff(7, 5, now, now).is_some());
+        assert!(invite_backoff(7, 5, now - decay * 2, now).is_none());
+    }
+
+    #[test]
+    fn events_continue_after_seqs_seen_in_the_durable_store() {
+        let mut events = EventState::new();
+        events.note_event_seq("house", 41);
+        events.note_event_seq("house", 7);
+        let event = ServerEvent {
+            event_id: "e1".to_string(),
+            signing_pubkey: "house".to_string(),
+            event_type: "NameChange".to_string(),
+            encrypted_payload: String::new(),
+            signature: String::new(),
+            timestamp: Utc::now(),
+            seq: 0,
+        };
+        assert_eq!(events.post_event("house".to_string(), event).seq, 42);
+    }
+}
@@ -4,6 +4,7 @@ pub mod presence;
 pub mod profiles;
 pub mod events;
 pub mod backends;
+pub mod ratelimit;
 
 pub use signaling::SignalingState;
 pub use voice::VoiceState;
@@ -11,11 +12,21 @@ pub use presence::PresenceState;
 pub use profiles::ProfileState;
 pub use events::EventState;
 pub use backends::BackendState;
+pub use ratelimit::RateLimitState;
 
+use std::net::IpAddr;
 use std::sync::Arc;
 use std::time::Instant;
 use tokio::sync::Mutex;
-use crate::{SigningPubkey, SignalingMessage, ProfileRecord, PeerId, ServerId, WebSocketSender};
+use crate::{auth, SigningPubkey, SignalingMessage, ProfileRecord, PeerId, ServerId, WebSocketSender, AckRequest, AckError, EventPage, EventQueryError, EventQueueStats, HeartbeatConfig, EncryptedServerHint, ServerEvent};
+use crate::send_queue::{SendError, SendQueueStats, DEFAULT_SEND_QUEUE_CAPACITY};
+use crate::cluster::ClusterEnvelope;
+use voice::VoicePeerInfo;
+#[cfg(feature = "redis-backend")]
+use crate::metrics::timed;
+use crate::store::{MemoryStore, Store};
+#[cfg(feature = "redis-backend")]
+use crate::handlers::redis;
 use hyper_tungstenite::tungstenite::Message;
 
 /// Main application state wrapping all subsystems.
@@ -28,38 +39,181 @@ pub struct AppState {
     pub profiles: Arc<Mutex<ProfileState>>,
     pub events: Arc<Mutex<EventState>>,
     pub backends: Arc<Mutex<BackendState>>,
+    pub rate_limits: Arc<Mutex<RateLimitState>>,
+    /// `events` and `profiles` as a `Store`, used whenever no durable store is installed
+    pub memory_store: Arc<MemoryStore>,
     /// When the beacon process started (for uptime / status page).
     pub started_at: Instant,
     /// ISO8601 timestamp when the beacon started (for status).
     pub started_at_utc: String,
     /// Duration of previous shutdown in seconds (from last-stop file), if any.
     pub downtime_secs: Option<u64>,
+    /// Server-side ping interval and idle timeout for WebSocket connections.
+    pub heartbeat: HeartbeatConfig,
+    /// Messages each connection can have queued before overflow handling kicks in.
+    pub send_queue_capacity: usize,
+    /// Overflow counters shared by all connection queues.
+    pub send_queue_stats: Arc<SendQueueStats>,
+    /// Header the trusted proxy puts the client IP in; None to use the socket peer.
+    pub proxy_ip_header: Option<String>,
+    /// Bearer token `/metrics` requires; None to serve it to loopback clients only.
+    pub metrics_token: Option<String>,
+    /// Random id of this process, used to route messages between beacon instances.
+    pub instance_id: String,
 }
 
 impl AppState {
     pub fn new(downtime_secs: Option<u64>) -> Self {
         let now_utc = chrono::Utc::now();
+        let profiles = Arc::new(Mutex::new(ProfileState::new()));
+        let events = Arc::new(Mutex::new(EventState::new()));
         Self {
             signaling: Arc::new(Mutex::new(SignalingState::new())),
             voice: Arc::new(Mutex::new(VoiceState::new())),
             presence: Arc::new(Mutex::new(PresenceState::new())),
-            profiles: Arc::new(Mutex::new(ProfileState::new())),
-            events: Arc::new(Mutex::new(EventState::new())),
+            memory_store: Arc::new(MemoryStore::new(events.clone(), profiles.clone())),
+            profiles,
+            events,
             backends: Arc::new(Mutex::new(BackendState::new())),
+            rate_limits: Arc::new(Mutex::new(RateLimitState::new(ratelimit::RateLimitConfig::default()))),
             started_at: Instant::now(),
             started_at_utc: now_utc.to_rfc3339(),
             downtime_secs,
+            heartbeat: HeartbeatConfig::default(),
+            send_queue_capacity: DEFAULT_SEND_QUEUE_CAPACITY,
+            send_queue_stats: Arc::new(SendQueueStats::default()),
+            proxy_ip_header: None,
+            metrics_token: None,
+            instance_id: uuid::Uuid::new_v4().to_string(),
         }
     }
 
+    /// The durable store (Postgres or SQLite) while one is installed
+    pub async fn durable_store(&self) -> Option<Arc<dyn Store>> {
+        self.backends.lock().await.store.clone()
+    }
+
+    /// Where durable state is read and written: the durable store when installed, otherwise
+    /// in-memory state.
+    pub async fn store(&self) -> Arc<dyn Store> {
+        match self.durable_store().await {
+            Some(store) => store,
+            None => self.memory_store.clone(),
+        }
+    }
+
+    /// Append an event to its house's queue. Seqs handed out by the durable store are mirrored
+    /// in memory so that, if it goes away, in-memory events carry on from the same count.
+    pub async fn insert_event(&self, event: &ServerEvent) -> Result<ServerEvent, String> {
+        let Some(store) = self.durable_store().await else {
+            return self.memory_store.insert_event(event).await;
+        };
+        let stored = store.insert_event(event).await?;
+        self.events.lock().await.note_event_seq(&stored.signing_pubkey, stored.seq);
+        Ok(stored)
+    }
+
+    /// Page of house events after `after_seq`.
+    /// `since_event_id` is the older event_id cursor and takes precedence when given.
+    pub async fn load_events_page(
+        &self,
+        signing_pubkey: &str,
+        after_seq: u64,
+        since_event_id: Option<&str>,
+        limit: usize,
+    ) -> Result<EventPage, EventQueryError> {
+        let store = self.store().await;
+        let after_seq = match since_event_id {
+            // An event_id we no longer have can't be placed in the queue: report it as expired
+            Some(id) => store
+                .event_seq(signing_pubkey, id)
+                .await
+                .map_err(EventQueryError::Storage)?
+                .unwrap_or(u64::MAX),
+            None => after_seq,
+        };
+        store.events_page(signing_pubkey, after_seq, limit).await
+    }
+
+    /// Record a signed ack from a current member of the house. Acks by event_id are dropped once
+    /// the event is no longer retained.
+    pub async fn ack_events(&self, signing_pubkey: &str, ack: &AckRequest) -> Result<(), AckError> {
+        let store = self.store().await;
+        let signers = store.house_signers(signing_pubkey).await.map_err(AckError::Storage)?;
+        auth::verify_event_ack(signing_pubkey, ack, &signers)?;
+        let last_seq = match ack.last_seq {
+            Some(seq) => Some(seq),
+            None => store.event_seq(signing_pubkey, &ack.last_event_id).await.map_err(AckError::Storage)?,
+        };
+        if let Some(seq) = last_seq {
+            store.ack_events(signing_pubkey, &ack.user_id, seq).await.map_err(AckError::Storage)?;
+        }
+        Ok(())
+    }
+
+    pub async fn event_queue_stats(&self, signing_pubkey: &str) -> Result<EventQueueStats, String> {
+        self.store().await.event_queue_stats(signing_pubkey).await
+    }
+
+    /// Start an invite lookup from `ip` (Redis when configured, otherwise in-memory): the
+    /// remaining lockout if refused, otherwise the lookup is counted as a failure until
+    /// `finish_invite_lookup` says it found the code.
+    pub async fn begin_invite_lookup(&self, ip: IpAddr) -> Option<chrono::Duration> {
+        #[cfg(feature = "redis-backend")]
+        let lockout = {
+            let redis = {
+                let backends = self.backends.lock().await;
+                backends.redis.clone()
+            };
+            match (redis, events::invite_failure_key(ip)) {
+                (Some(client), Some(key)) => {
+                    let begin = redis::redis_begin_invite_lookup(&client, &key, chrono::Utc::now().timestamp());
+                    match timed("redis", "begin_invite_lookup", begin).await {
+                        Ok(wait) => (wait > 0).then(|| chrono::Duration::seconds(wait)),
+                        Err(e) => {
+                            log::warn!("Redis invite lockout check failed: {}", e);
+                            None
+                        }
+                    }
+                }
+                _ => self.events.lock().await.begin_invite_lookup(ip).err(),
+            }
+        };
+        #[cfg(not(feature = "redis-backend"))]
+        let lockout = self.events.lock().await.begin_invite_lookup(ip).err();
+
+        if lockout.is_some() {
+            self.events.lock().await.invite_lookups_refused_total += 1;
+        }
+        lockout
+    }
+
+    /// Settle a lookup started with `begin_invite_lookup`: a hit gets its failure back.
+    pub async fn finish_invite_lookup(&self, ip: IpAddr, found: bool) {
+        if !found {
+            self.events.lock().await.invite_lookup_failures_total += 1;
+            return;
+        }
+        #[cfg(feature = "redis-backend")]
+        {
+            let redis = {
+                let backends = self.backends.lock().await;
+                backends.redis.clone()
+            };
+            if let (Some(client), Some(key)) = (redis, events::invite_failure_key(ip)) {
+                let refund = redis::redis_refund_invite_lookup(&client, &key);
+                if let Err(e) = timed("redis", "refund_invite_lookup", refund).await {
+                    log::warn!("Redis invite failure refund failed: {}", e);
+                }
+                return;
+            }
+        }
+        self.events.lock().await.refund_invite_lookup(ip);
+    }
+
     /// Broadcast a presence update to all peers subscribed to a server.
     /// This coordinates between PresenceState and SignalingState.
     pub async fn broadcast_presence_update(&self, signing_pubkey: &SigningPubkey, user_id: &str, online: bool, active: Option<SigningPubkey>) {
-        let signaling = self.signaling.lock().await;
-        let Some(peers) = signaling.signing_servers.get(signing_pubkey) else {
-            return;
-        };
-
         let msg = SignalingMessage::PresenceUpdate {
             signing_pubkey: signing_pubkey.clone(),
             user_id: user_id.to_string(),
@@ -71,21 +225,14 @@ impl AppState {
             return;
         };
 
-        for peer_id in peers {
-            if let Some(sender) = signaling.peer_senders.get(peer_id) {
-                let _ = sender.send(Message::Text(json.clone()));
-            }
-        }
+        // Only the latest presence per user matters to a client that is falling behind
+        let key = format!("presence:{}:{}", signing_pubkey, user_id);
+        self.broadcast_to_house(signing_pubkey, json, Some(key)).await;
     }
 
     /// Broadcast a profile update to all peers subscribed to a server.
     /// This coordinates between ProfileState and SignalingState.
     pub async fn broadcast_profile_update(&self, signing_pubkey: &SigningPubkey, user_id: &str, rec: &ProfileRecord) {
-        let signaling = self.signaling.lock().await;
-        let Some(peers) = signaling.signing_servers.get(signing_pubkey) else {
-            return;
-        };
-
         let msg = SignalingMessage::ProfileUpdate {
             signing_pubkey: signing_pubkey.clone(),
             user_id: user_id.to_string(),
@@ -99,23 +246,69 @@ impl AppState {
             return;
         };
 
-        for peer_id in peers {
-            if let Some(sender) = signaling.peer_senders.get(peer_id) {
-                let _ = sender.send(Message::Text(json.clone()));
-            }
+        let key = format!("profile:{}:{}", signing_pubkey, user_id);
+        self.broadcast_to_house(signing_pubkey, json, Some(key)).await;
+    }
+
+    pub async fn broadcast_server_hint_updated(&self, signing_pubkey: &SigningPubkey, hint: &EncryptedServerHint) {
+        let msg = SignalingMessage::ServerHintUpdated {
+            signing_pubkey: signing_pubkey.clone(),
+            encrypted_state: hint.encrypted_state.clone(),
+            signature: hint.signature.clone(),
+            signer_pubkey: hint.signer_pubkey.clone(),
+            key_epoch: hint.key_epoch,
+            revision: hint.revision,
+            last_updated: hint.last_updated,
+        };
+        if let Ok(json) = serde_json::to_string(&msg) {
+            self.broadcast_to_house(signing_pubkey, json, None).await;
+        }
+    }
+
+    pub async fn broadcast_event_posted(&self, signing_pubkey: &SigningPubkey, event: &ServerEvent) {
+        let msg = SignalingMessage::EventPosted {
+            signing_pubkey: signing_pubkey.clone(),
+            event: event.clone(),
+        };
+        if let Ok(json) = serde_json::to_string(&msg) {
+            self.broadcast_to_house(signing_pubkey, json, None).await;
         }
     }
 
+    /// Send to every peer subscribed to a house, on this instance and on the others.
+    pub async fn broadcast_to_house(&self, signing_pubkey: &SigningPubkey, json: String, coalesce_key: Option<String>) {
+        {
+            let signaling = self.signaling.lock().await;
+            signaling.send_to_house(signing_pubkey, &json, coalesce_key.as_deref());
+        }
+        self.publish_cluster(None, ClusterEnvelope::House {
+            signing_pubkey: signing_pubkey.clone(),
+            coalesce_key,
+            json,
+        }).await;
+    }
+
     /// Broadcast a message to all peers in a voice chat.
     /// This coordinates between VoiceState and SignalingState.
     pub async fn broadcast_to_voice_room(&self, server_id: &ServerId, chat_id: &str, msg: &SignalingMessage, exclude_peer: Option<&PeerId>) {
-        let voice = self.voice.lock().await;
-        let key = (server_id.clone(), chat_id.to_string());
-        let Some(peers) = voice.voice_chats.get(&key) else {
+        let Ok(json) = serde_json::to_string(msg) else {
             return;
         };
 
-        let Ok(json) = serde_json::to_string(msg) else {
+        self.send_to_voice_room(server_id, chat_id, &json, exclude_peer).await;
+        self.publish_cluster(None, ClusterEnvelope::VoiceRoom {
+            server_id: server_id.clone(),
+            chat_id: chat_id.to_string(),
+            exclude_peer: exclude_peer.cloned(),
+            json,
+        }).await;
+    }
+
+    /// Send to the members of a voice chat connected to this instance.
+    async fn send_to_voice_room(&self, server_id: &ServerId, chat_id: &str, json: &str, exclude_peer: Option<&PeerId>) {
+        let voice = self.voice.lock().await;
+        let key = (server_id.clone(), chat_id.to_string());
+        let Some(peers) = voice.voice_chats.get(&key) else {
             return;
         };
 
@@ -130,7 +323,7 @@ impl AppState {
             }
 
             if let Some(sender) = signaling.peer_senders.get(&peer.peer_id) {
-                let _ = sender.send(Message::Text(json.clone()));
+                let _ = sender.send(Message::Text(json.to_string()));
             }
         }
     }
@@ -154,11 +347,6 @@ impl AppState {
     /// Broadcast voice presence update to all presence connections for a server.
     /// This coordinates between VoiceState and SignalingState.
     pub async fn broadcast_voice_presence(&self, signing_pubkey: &SigningPubkey, user_id: &str, chat_id: &str, in_voice: bool) {
-        let signaling = self.signaling.lock().await;
-        let Some(peers) = signaling.signing_servers.get(signing_pubkey) else {
-            return;
-        };
-
         let msg = SignalingMessage::VoicePresenceUpdate {
             signing_pubkey: signing_pubkey.clone(),
             user_id: user_id.to_string(),
@@ -171,9 +359,171 @@ impl AppState {
         };
 
         // Send to all peer connections subscribed to this house (same mechanism as presence updates)
-        for peer_id in peers {
-            if let Some(sender) = signaling.peer_senders.get(peer_id) {
-                let _ = sender.send(Message::Text(json.clone()));
+        let key = format!("voice:{}:{}:{}", signing_pubkey, user_id, chat_id);
+        self.broadcast_to_house(signing_pubkey, json, Some(key)).await;
+    }
+
+    /// Forward a message to a peer, wherever it is connected. Ok(false) = no such peer.
+    pub async fn forward_to_peer(&self, peer_id: &PeerId, json: String) -> Result<bool, SendError> {
+        let local = {
+            let signaling = self.signaling.lock().await;
+            signaling.peer_senders.get(peer_id).cloned()
+        };
+        if let Some(sender) = local {
+            return sender.send(Message::Text(json)).map(|_| true);
+        }
+
+        #[cfg(feature = "redis-backend")]
+        if let Some(conn) = self.cluster_conn().await {
+            match timed("redis", "peer_route", redis::redis_peer_route(&conn, peer_id)).await {
+                Ok(Some(instance_id)) => {
+                    self.publish_cluster(Some(instance_id), ClusterEnvelope::Peer { peer_id: peer_id.clone(), json }).await;
+                    return Ok(true);
+                }
+                Ok(None) => {}
+                Err(e) => log::warn!("Redis peer route lookup failed: {}", e),
+            }
+        }
+        Ok(false)
+    }
+
+    /// Forward a message to a member of a voice chat, wherever it is connected.
+    /// Ok(false) = the peer isn't in that chat.
+    pub async fn forward_to_voice_peer(&self, server_id: &ServerId, chat_id: &str, peer_id: &PeerId, json: String) -> Result<bool, SendError> {
+        if let Some(sender) = self.get_voice_peer_sender(server_id, chat_id, peer_id).await {
+            return sender.send(Message::Text(json)).map(|_| true);
+        }
+
+        #[cfg(feature = "redis-backend")]
+        if let Some(conn) = self.cluster_conn().await {
+            match timed("redis", "voice_member", redis::redis_voice_member(&conn, server_id, chat_id, peer_id)).await {
+                Ok(Some(member)) => {
+                    self.publish_cluster(Some(member.instance_id), ClusterEnvelope::Peer { peer_id: peer_id.clone(), json }).await;
+                    return Ok(true);
+                }
+                Ok(None) => {}
+                Err(e) => log::warn!("Redis voice member lookup failed: {}", e),
+            }
+        }
+        Ok(false)
+    }
+
+    /// Record a new voice chat member in Redis and return the chat's other members across all
+    /// instances. Without Redis (or if it fails) the local members are returned as-is.
+    /// Err if another user's peer holds `peer_id` in the chat.
+    pub async fn voice_room_join(
+        &self,
+        server_id: &ServerId,
+        chat_id: &str,
+        peer_id: &PeerId,
+        user_id: &str,
+        local_peers: Vec<VoicePeerInfo>,
+    ) -> Result<Vec<VoicePeerInfo>, String> {
+        #[cfg(feature = "redis-backend")]
+        if let Some(conn) = self.cluster_conn().await {
+            let member = redis::RedisVoiceMember {
+                user_id: user_id.to_string(),
+                instance_id: self.instance_id.clone(),
+            };
+            match timed("redis", "voice_join", redis::redis_voice_join(&conn, server_id, chat_id, peer_id, &member)).await {
+                Ok(peers) => return Ok(peers),
+                Err(redis::RedisVoiceJoinError::PeerTaken) => {
+                    return Err(format!("peer_id {} is in this voice chat as another user", peer_id));
+                }
+                Err(redis::RedisVoiceJoinError::Redis(e)) => log::warn!("Redis voice join failed: {}", e),
+            }
+        }
+        Ok(local_peers)
+    }
+
+    /// Drop (server_id, chat_id, peer_id) voice memberships from Redis.
+    pub async fn voice_room_leave(&self, entries: &[(ServerId, String, PeerId)]) {
+        #[cfg(feature = "redis-backend")]
+        if let Some(conn) = self.cluster_conn().await {
+            if let Err(e) = timed("redis", "voice_leave", redis::redis_voice_leave(&conn, &self.instance_id, entries)).await {
+                log::warn!("Redis voice leave failed: {}", e);
+            }
+        }
+    }
+
+    /// Claim `peer_id` for `user_id` on this instance so others can route to it.
+    /// Err if another user holds it on any instance (a failing Redis doesn't block registration).
+    pub async fn route_peer(&self, peer_id: &PeerId, user_id: &str) -> Result<(), String> {
+        #[cfg(feature = "redis-backend")]
+        if let Some(conn) = self.cluster_conn().await {
+            let ttl = self.backends.lock().await.redis_presence_ttl_secs;
+            let routes = [(peer_id.clone(), user_id.to_string())];
+            match timed("redis", "route_peer", redis::redis_claim_peer_routes(&conn, &self.instance_id, ttl, &routes)).await {
+                Ok(taken) if !taken.is_empty() => {
+                    return Err(format!("peer_id {} is registered by another user", peer_id));
+                }
+                Ok(_) => {}
+                Err(e) => log::warn!("Redis peer route update failed: {}", e),
+            }
+        }
+        #[cfg(not(feature = "redis-backend"))]
+        let _ = (peer_id, user_id);
+        Ok(())
+    }
+
+    pub async fn unroute_peers(&self, peer_ids: &[PeerId]) {
+        #[cfg(feature = "redis-backend")]
+        if let Some(conn) = self.cluster_conn().await {
+            if let Err(e) = timed("redis", "unroute_peers", redis::redis_delete_peer_routes(&conn, &self.instance_id, peer_ids)).await {
+                log::warn!("Redis peer route removal failed: {}", e);
+            }
+        }
+    }
+
+    /// Deliver a message published by another instance to this instance's sockets.
+    pub async fn deliver_cluster_envelope(&self, envelope: ClusterEnvelope) {
+        match envelope {
+            ClusterEnvelope::House { signing_pubkey, coalesce_key, json } => {
+                let signaling = self.signaling.lock().await;
+                signaling.send_to_house(&signing_pubkey, &json, coalesce_key.as_deref());
+            }
+            ClusterEnvelope::VoiceRoom { server_id, chat_id, exclude_peer, json } => {
+                self.send_to_voice_room(&server_id, &chat_id, &json, exclude_peer.as_ref()).await;
+            }
+            ClusterEnvelope::Peer { peer_id, json } => {
+                let sender = {
+                    let signaling = self.signaling.lock().await;
+                    signaling.peer_senders.get(&peer_id).cloned()
+                };
+                match sender {
+                    Some(sender) => {
+                        if sender.send(Message::Text(json)).is_err() {
+                            crate::metrics::metrics().forward_failure("send_failed");
+                        }
+                    }
+                    None => crate::metrics::metrics().forward_failure("peer_not_found"),
+                }
+            }
+        }
+    }
+
+    #[cfg(feature = "redis-backend")]
+    async fn cluster_conn(&self) -> Option<::redis::aio::ConnectionManager> {
+        let backends = self.backends.lock().await;
+        backends.redis_conn.clone()
+    }
+
+    /// Hand a message to other instances: one instance's channel, or all of them.
+    async fn publish_cluster(&self, instance_id: Option<String>, envelope: ClusterEnvelope) {
+        #[cfg(feature = "redis-backend")]
+        if let Some(conn) = self.cluster_conn().await {
+            let channel = match instance_id {
+                Some(id) => crate::cluster::instance_channel(&id),
+                None => crate::cluster::BROADCAST_CHANNEL.to_string(),
+            };
+            let frame = crate::cluster::ClusterFrame { origin: self.instance_id.clone(), envelope };
+            match serde_json::to_string(&frame) {
+                Ok(payload) => {
+                    if let Err(e) = timed("redis", "publish", redis::redis_publish(&conn, &channel, &payload)).await {
+                        log::warn!("Cluster publish failed: {}", e);
+                    }
+                }
+                Err(e) => log::warn!("Failed to encode cluster frame: {}", e),
             }
         }
     }
@@ -0,0 +1,450 @@
+use std::collections::HashMap;
+use std::net::{IpAddr, SocketAddr};
+use std::time::{Duration, Instant};
+use log::warn;
+use serde::Deserialize;
+use crate::ConnId;
+
+pub const WS_DEFAULT_CLASS: &str = "ws.default";
+pub const API_DEFAULT_CLASS: &str = "api.default";
+pub const METRICS_CLASS: &str = "metrics";
+/// Per-IP buckets are this many times larger than per-connection ones (several devices behind one NAT)
+pub const DEFAULT_RATE_LIMIT_IP_FACTOR: f64 = 4.0;
+/// Rate-limited requests from one IP (or one connection) within the window before a ban (or disconnect)
+pub const DEFAULT_RATE_LIMIT_STRIKES: u32 = 50;
+pub const DEFAULT_RATE_LIMIT_STRIKE_WINDOW_SECS: u64 = 60;
+pub const DEFAULT_RATE_LIMIT_BAN_SECS: u64 = 600;
+
+/// Token bucket parameters: `rate` tokens per second, up to `burst` saved up
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct Limit {
+    pub rate: f64,
+    pub burst: f64,
+}
+
+const fn limit(rate: f64, burst: f64) -> Limit {
+    Limit { rate, burst }
+}
+
+/// Built-in limits per WebSocket message type and REST route class.
+/// Route classes are `api.<method>.<path segments without ids>`, e.g. `api.post.invites.redeem`.
+const DEFAULT_LIMITS: &[(&str, Limit)] = &[
+    (WS_DEFAULT_CLASS, limit(20.0, 100.0)),
+    ("AuthResponse", limit(1.0, 5.0)),
+    ("Resume", limit(1.0, 5.0)),
+    // One Register / EventReplayRequest per house on (re)connect
+    ("Register", limit(5.0, 100.0)),
+    ("EventReplayRequest", limit(10.0, 200.0)),
+    ("Offer", limit(5.0, 20.0)),
+    ("Answer", limit(5.0, 20.0)),
+    ("IceCandidate", limit(50.0, 200.0)),
+    ("VoiceRegister", limit(1.0, 10.0)),
+    ("VoiceOffer", limit(5.0, 20.0)),
+    ("VoiceAnswer", limit(5.0, 20.0)),
+    ("VoiceIceCandidate", limit(50.0, 200.0)),
+    ("PresenceHello", limit(1.0, 10.0)),
+    ("PresenceActive", limit(2.0, 10.0)),
+    ("ProfileAnnounce", limit(0.2, 5.0)),
+    ("ProfileHello", limit(1.0, 10.0)),
+    (API_DEFAULT_CLASS, limit(10.0, 50.0)),
+    ("api.get.status", limit(2.0, 20.0)),
+    ("api.get.invites", limit(0.5, 10.0)),
+    ("api.post.invites.redeem", limit(0.1, 5.0)),
+    ("api.post.servers.invites", limit(0.5, 10.0)),
+    ("api.post.servers.events", limit(5.0, 50.0)),
+    // A scrape every few seconds is plenty
+    (METRICS_CLASS, limit(0.5, 5.0)),
+];
+
+#[derive(Debug, Clone)]
+pub struct RateLimitConfig {
+    pub limits: HashMap<String, Limit>,
+    pub ip_factor: f64,
+    pub strikes: u32,
+    pub strike_window: Duration,
+    pub ban_duration: Duration,
+}
+
+impl Default for RateLimitConfig {
+    fn default() -> Self {
+        Self {
+            limits: DEFAULT_LIMITS.iter().map(|(class, l)| (class.to_string(), *l)).collect(),
+            ip_factor: DEFAULT_RATE_LIMIT_IP_FACTOR,
+            strikes: DEFAULT_RATE_LIMIT_STRIKES,
+            strike_window: Duration::from_secs(DEFAULT_RATE_LIMIT_STRIKE_WINDOW_SECS),
+            ban_duration: Duration::from_secs(DEFAULT_RATE_LIMIT_BAN_SECS),
+        }
+    }
+}
+
+impl RateLimitConfig {
+    /// The configured class for a message type / route, or the matching default class.
+    /// Unknown names share the default bucket so clients can't create buckets at will.
+    fn resolve<'a>(&'a self, class: &'a str) -> (&'a str, Limit) {
+        if let Some(l) = self.limits.get(class) {
+            return (class, *l);
+        }
+        let fallback = if class.starts_with("api.") { API_DEFAULT_CLASS } else { WS_DEFAULT_CLASS };
+        (fallback, self.limits.get(fallback).copied().unwrap_or(limit(10.0, 50.0)))
+    }
+}
+
+pub fn parse_limits(spec: &str) -> Result<Vec<(String, Limit)>, String> {
+    spec.split(',')
+        .map(str::trim)
+        .filter(|s| !s.is_empty())
+        .map(|entry| {
+            let (class, value) = entry.split_once('=').ok_or_else(|| format!("expected class=rate:burst in '{}'", entry))?;
+            let (rate, burst) = value.split_once(':').ok_or_else(|| format!("expected rate:burst in '{}'", entry))?;
+            let rate = rate.trim().parse::<f64>().map_err(|e| format!("bad rate in '{}': {}", entry, e))?;
+            let burst = burst.trim().parse::<f64>().map_err(|e| format!("bad burst in '{}': {}", entry, e))?;
+            if !(rate > 0.0 && burst >= 1.0) {
+                return Err(format!("rate must be > 0 and burst >= 1 in '{}'", entry));
+            }
+            Ok((class.trim().to_string(), limit(rate, burst)))
+        })
+        .collect()
+}
+
+/// Message type of a WebSocket text frame, read without parsing the whole message
+pub fn ws_message_class(text: &str) -> String {
+    #[derive(Deserialize)]
+    struct Tag {
+        #[serde(rename = "type")]
+        kind: String,
+    }
+    serde_json::from_str::<Tag>(text)
+        .map(|t| t.kind)
+        .unwrap_or_else(|_| WS_DEFAULT_CLASS.to_string())
+}
+
+/// Rate limit class of a REST request: `api.<method>.<path segments>` with ids left out
+pub fn api_route_class(method: &hyper::Method, path: &str) -> String {
+    let parts: Vec<&str> = path.split('/').filter(|s| !s.is_empty()).collect();
+    // /api/invites/{code}/... and /api/servers/{signing_pubkey}/...
+    let has_id = matches!(parts.get(1), Some(&"invites") | Some(&"servers"));
+    // /api/servers/{signing_pubkey}/keys/{user_id}
+    let has_trailing_id = parts.get(1) == Some(&"servers") && parts.get(3) == Some(&"keys");
+    let named: Vec<&str> = parts
+        .iter()
+        .enumerate()
+        .filter(|(i, _)| *i != 0 && !(has_id && *i == 2) && !(has_trailing_id && *i == 4))
+        .map(|(_, part)| *part)
+        .collect();
+    format!("api.{}.{}", method.as_str().to_ascii_lowercase(), named.join("."))
+}
+
+/// Source IP of a request: the socket peer, or the address the trusted proxy wrote into
+/// `proxy_header`. Only the last X-Forwarded-For entry is used; the proxy appends it, while
+/// anything before it came from the client.
+pub fn client_ip(headers: &hyper::HeaderMap, remote: SocketAddr, proxy_header: Option<&str>) -> IpAddr {
+    let forwarded = proxy_header
+        .and_then(|name| headers.get_all(name).iter().next_back())
+        .and_then(|v| v.to_str().ok())
+        .and_then(|v| v.rsplit(',').next())
+        .and_then(|v| v.trim().parse::<IpAddr>().ok());
+    forwarded.unwrap_or_else(|| remote.ip())
+}
+
+struct TokenBucket {
+    tokens: f64,
+    last: Instant,
+}
+
+impl TokenBucket {
+    fn full(limit: Limit, now: Instant) -> Self {
+        Self { tokens: limit.burst, last: now }
+    }
+
+    /// Take one token, or say how long until one is available
+    fn take(&mut self, limit: Limit, now: Instant) -> Result<(), Duration> {
+        let elapsed = now.saturating_duration_since(self.last).as_secs_f64();
+        self.tokens = (self.tokens + elapsed * limit.rate).min(limit.burst);
+        self.last = now;
+        if self.tokens >= 1.0 {
+            self.tokens -= 1.0;
+            Ok(())
+        } else {
+            Err(Duration::from_secs_f64((1.0 - self.tokens) / limit.rate))
+        }
+    }
+
+    fn is_idle(&self, limit: Limit, now: Instant) -> bool {
+        self.tokens + now.saturating_duration_since(self.last).as_secs_f64() * limit.rate >= limit.burst
+    }
+}
+
+/// Rate-limited requests within the current window
+struct Strikes {
+    count: u32,
+    window_start: Instant,
+}
+
+impl Strikes {
+    fn add(&mut self, window: Duration, now: Instant) -> u32 {
+        if now.saturating_duration_since(self.window_start) > window {
+            self.count = 0;
+            self.window_start = now;
+        }
+        self.count += 1;
+        self.count
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum RateDecision {
+    Allow,
+    /// Rejected; `disconnect` once the connection keeps ignoring the limit
+    Limited { retry_after: Duration, disconnect: bool },
+    /// The source IP is on the ban list
+    Banned { remaining: Duration },
+}
+
+/// Token buckets per connection and per source IP, keyed by message type / route class,
+/// plus the temporary ban list for IPs that keep hitting their limits.
+pub struct RateLimitState {
+    pub config: RateLimitConfig,
+    conn_buckets: HashMap<(ConnId, String), TokenBucket>,
+    ip_buckets: HashMap<(IpAddr, String), TokenBucket>,
+    conn_strikes: HashMap<ConnId, Strikes>,
+    ip_strikes: HashMap<IpAddr, Strikes>,
+    /// Banned IP -> ban expiry
+    bans: HashMap<IpAddr, Instant>,
+    /// Requests rejected since startup
+    pub limited_total: u64,
+    /// Bans issued since startup
+    pub bans_total: u64,
+}
+
+impl RateLimitState {
+    pub fn new(config: RateLimitConfig) -> Self {
+        Self {
+            config,
+            conn_buckets: HashMap::new(),
+            ip_buckets: HashMap::new(),
+            conn_strikes: HashMap::new(),
+            ip_strikes: HashMap::new(),
+            bans: HashMap::new(),
+            limited_total: 0,
+            bans_total: 0,
+        }
+    }
+
+    pub fn ban_remaining(&mut self, ip: IpAddr) -> Option<Duration> {
+        let now = Instant::now();
+        match self.bans.get(&ip) {
+            Some(until) if *until > now => Some(*until - now),
+            Some(_) => {
+                self.bans.remove(&ip);
+                None
+            }
+            None => None,
+        }
+    }
+
+    /// Count one request of `class` from `ip` (and `conn`, for WebSocket messages).
+    pub fn check(&mut self, conn: Option<&ConnId>, ip: IpAddr, class: &str) -> RateDecision {
+        if let Some(remaining) = self.ban_remaining(ip) {
+            return RateDecision::Banned { remaining };
+        }
+
+        let now = Instant::now();
+        let (class, conn_limit) = self.config.resolve(class);
+        let class = class.to_string();
+        let ip_limit = limit(conn_limit.rate * self.config.ip_factor, conn_limit.burst * self.config.ip_factor);
+
+        let conn_result = match conn {
+            Some(conn) => self
+                .conn_buckets
+                .entry((conn.clone(), class.clone()))
+                .or_insert_with(|| TokenBucket::full(conn_limit, now))
+                .take(conn_limit, now),
+            None => Ok(()),
+        };
+        let ip_result = self
+            .ip_buckets
+            .entry((ip, class.clone()))
+            .or_insert_with(|| TokenBucket::full(ip_limit, now))
+            .take(ip_limit, now);
+
+        let retry_after = match (conn_result, ip_result) {
+            (Ok(()), Ok(())) => return RateDecision::Allow,
+            (Err(a), Err(b)) => a.max(b),
+            (Err(a), Ok(())) | (Ok(()), Err(a)) => a,
+        };
+        self.limited_total += 1;
+
+        let window = self.config.strike_window;
+        let ip_strikes = self
+            .ip_strikes
+            .entry(ip)
+            .or_insert(Strikes { count: 0, window_start: now })
+            .add(window, now);
+        // Never ban loopback: behind a local tunnel or proxy (without trusted proxy headers)
+        // it is every client at once
+        if ip_strikes >= self.config.strikes && !self.config.ban_duration.is_zero() && !ip.to_canonical().is_loopback() {
+            warn!("Banning {} for {}s after {} rate-limited requests ({})", ip, self.config.ban_duration.as_secs(), ip_strikes, class);
+            self.ip_strikes.remove(&ip);
+            self.bans.insert(ip, now + self.config.ban_duration);
+            self.bans_total += 1;
+            return RateDecision::Banned { remaining: self.config.ban_duration };
+        }
+
+        let disconnect = match conn {
+            Some(conn) => {
+                self.conn_strikes
+                    .entry(conn.clone())
+                    .or_insert(Strikes { count: 0, window_start: now })
+                    .add(window, now)
+                    >= self.config.strikes
+            }
+            None => false,
+        };
+        RateDecision::Limited { retry_after, disconnect }
+    }
+
+    /// Drop a closed connection's buckets
+    pub fn forget_conn(&mut self, conn: &ConnId) {
+        self.conn_buckets.retain(|(c, _), _| c != conn);
+        self.conn_strikes.remove(conn);
+    }
+
+    /// Drop refilled IP buckets, stale strike windows and expired bans
+    pub fn prune(&mut self) {
+        let now = Instant::now();
+        let config = &self.config;
+        self.ip_buckets.retain(|(_, class), bucket| {
+            let (_, l) = config.resolve(class);
+            let ip_limit = limit(l.rate * config.ip_factor, l.burst * config.ip_factor);
+            !bucket.is_idle(ip_limit, now)
+        });
+        self.ip_strikes.retain(|_, s| now.saturating_duration_since(s.window_start) <= config.strike_window);
+        self.bans.retain(|_, until| *until > now);
+    }
+
+    pub fn banned_count(&self) -> usize {
+        let now = Instant::now();
+        self.bans.values().filter(|until| **until > now).count()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn config(class: &str, l: Limit, strikes: u32) -> RateLimitConfig {
+        let mut config = RateLimitConfig { strikes, ..RateLimitConfig::default() };
+        config.limits.insert(class.to_string(), l);
+        config
+    }
+
+    #[test]
+    fn bucket_allows_a_burst_then_refills_at_rate() {
+        let l = limit(2.0, 3.0);
+        let start = Instant::now();
+        let mut bucket = TokenBucket::full(l, start);
+        for _ in 0..3 {
+            assert!(bucket.take(l, start).is_ok());
+        }
+        let wait = bucket.take(l, start).unwrap_err();
+        assert_eq!(wait, Duration::from_millis(500));
+
+        assert!(bucket.take(l, start + Duration::from_millis(500)).is_ok());
+        assert!(bucket.take(l, start + Duration::from_millis(500)).is_err());
+        // An idle bucket never saves more than its burst
+        let later = start + Duration::from_secs(60);
+        assert!(bucket.is_idle(l, later));
+        for _ in 0..3 {
+            assert!(bucket.take(l, later).is_ok());
+        }
+        assert!(bucket.take(l, later).is_err());
+    }
+
+    #[test]
+    fn connections_are_limited_separately_and_disconnected_on_repeat() {
+        let mut state = RateLimitState::new(RateLimitConfig {
+            ip_factor: 100.0,
+            ban_duration: Duration::ZERO,
+            ..config("Offer", limit(0.001, 2.0), 3)
+        });
+        let ip: IpAddr = "192.0.2.1".parse().unwrap();
+        let (a, b) = ("conn-a".to_string(), "conn-b".to_string());
+        assert_eq!(state.check(Some(&a), ip, "Offer"), RateDecision::Allow);
+        assert_eq!(state.check(Some(&a), ip, "Offer"), RateDecision::Allow);
+        assert!(matches!(state.check(Some(&a), ip, "Offer"), RateDecision::Limited { disconnect: false, .. }));
+        assert_eq!(state.check(Some(&b), ip, "Offer"), RateDecision::Allow);
+        assert!(matches!(state.check(Some(&a), ip, "Offer"), RateDecision::Limited { disconnect: false, .. }));
+        assert!(matches!(state.check(Some(&a), ip, "Offer"), RateDecision::Limited { disconnect: true, .. }));
+        assert_eq!(state.limited_total, 3);
+    }
+
+    #[test]
+    fn repeat_offenders_are_banned() {
+        let mut state = RateLimitState::new(config("api.post.invites.redeem", limit(0.001, 1.0), 3));
+        let ip: IpAddr = "192.0.2.2".parse().unwrap();
+        let other: IpAddr = "192.0.2.3".parse().unwrap();
+        let class = "api.post.invites.redeem";
+        for _ in 0..4 {
+            assert_eq!(state.check(None, ip, class), RateDecision::Allow);
+        }
+        assert!(matches!(state.check(None, ip, class), RateDecision::Limited { .. }));
+        assert!(matches!(state.check(None, ip, class), RateDecision::Limited { .. }));
+        assert!(matches!(state.check(None, ip, class), RateDecision::Banned { .. }));
+        // Every route is refused while banned; other IPs are unaffected
+        assert!(matches!(state.check(None, ip, "api.get.status"), RateDecision::Banned { .. }));
+        assert_eq!(state.check(None, other, class), RateDecision::Allow);
+        assert_eq!((state.bans_total, state.banned_count()), (1, 1));
+    }
+
+    #[test]
+    fn loopback_is_never_banned() {
+        let mut state = RateLimitState::new(config("api.post.invites.redeem", limit(0.001, 1.0), 3));
+        let class = "api.post.invites.redeem";
+        for ip in ["127.0.0.1", "::1", "::ffff:127.0.0.1"] {
+            let ip: IpAddr = ip.parse().unwrap();
+            for _ in 0..4 {
+                assert_eq!(state.check(None, ip, class), RateDecision::Allow);
+            }
+            for _ in 0..10 {
+                assert!(matches!(state.check(None, ip, class), RateDecision::Limited { .. }));
+            }
+            assert_eq!(state.check(None, ip, "api.get.status"), RateDecision::Allow);
+        }
+        assert_eq!(state.bans_total, 0);
+    }
+
+    #[test]
+    fn route_classes_leave_out_ids() {
+        let class = |method: hyper::Method, path: &str| api_route_class(&method, path);
+        assert_eq!(class(hyper::Method::POST, "/api/invites/AbCd1234/redeem"), "api.post.invites.redeem");
+        assert_eq!(class(hyper::Method::GET, "/api/servers/c3BrCg==/events"), "api.get.servers.events");
+        assert_eq!(class(hyper::Method::GET, "/api/servers/c3BrCg==/keys/0123abcd"), "api.get.servers.keys");
+        assert_eq!(class(hyper::Method::POST, "/api/servers/c3BrCg==/keys"), "api.post.servers.keys");
+        assert_eq!(class(hyper::Method::GET, "/api/status"), "api.get.status");
+    }
+
+    #[test]
+    fn unknown_classes_share_the_default_bucket() {
+        let config = RateLimitConfig::default();
+        assert_eq!(config.resolve("NoSuchMessage").0, WS_DEFAULT_CLASS);
+        assert_eq!(config.resolve("api.get.whatever").0, API_DEFAULT_CLASS);
+        assert_eq!(config.resolve("Offer").0, "Offer");
+    }
+
+    #[test]
+    fn client_ip_uses_the_proxy_appended_entry() {
+        let remote: SocketAddr = "10.0.0.1:4000".parse().unwrap();
+        let mut headers = hyper::HeaderMap::new();
+        headers.append("x-forwarded-for", "6.6.6.6, 203.0.113.9".parse().unwrap());
+        headers.insert("cf-connecting-ip", "6.6.6.6".parse().unwrap());
+        assert_eq!(client_ip(&headers, remote, None), remote.ip());
+        assert_eq!(client_ip(&headers, remote, Some("x-forwarded-for")), "203.0.113.9".parse::<IpAddr>().unwrap());
+
+        // A second header line is the one the proxy added
+        headers.append("x-forwarded-for", "198.51.100.4".parse().unwrap());
+        assert_eq!(client_ip(&headers, remote, Some("x-forwarded-for")), "198.51.100.4".parse::<IpAddr>().unwrap());
+        assert_eq!(client_ip(&headers, remote, Some("x-real-ip")), remote.ip());
+        headers.insert("x-real-ip", "garbage".parse().unwrap());
+        assert_eq!(client_ip(&headers, remote, Some("x-real-ip")), remote.ip());
+    }
+}
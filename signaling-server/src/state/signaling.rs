@@ -1,6 +1,11 @@
 use std::collections::{HashMap, HashSet};
-use crate::{PeerId, ServerId, SigningPubkey, WebSocketSender, ConnId, PeerConnection, EncryptedServerHint, SignalingMessage};
+use crate::{PeerId, ServerId, SigningPubkey, WebSocketSender, ConnId, PeerConnection};
 use hyper_tungstenite::tungstenite::Message;
+use std::time::Instant;
+
+fn new_resume_token() -> String {
+    format!("{}{}", uuid::Uuid::new_v4().simple(), uuid::Uuid::new_v4().simple())
+}
 
 /// WebSocket signaling state (peer ↔ peer)
 pub struct SignalingState {
@@ -14,6 +19,38 @@ pub struct SignalingState {
     pub peer_senders: HashMap<PeerId, WebSocketSender>,
     /// Map of conn_id -> peer_ids registered on that websocket connection (allows correct cleanup)
     pub conn_peers: HashMap<ConnId, HashSet<PeerId>>,
+    /// Map of conn_id -> outstanding auth challenge nonce
+    pub auth_challenges: HashMap<ConnId, String>,
+    /// Map of conn_id -> user_id proven by the auth handshake
+    pub conn_users: HashMap<ConnId, String>,
+    /// Map of conn_id -> resumable session (authenticated connections only)
+    pub sessions: HashMap<ConnId, ResumableSession>,
+    /// Map of resume token -> conn_id of the session it resumes
+    pub resume_tokens: HashMap<String, ConnId>,
+}
+
+/// Registrations of an authenticated connection, kept for a grace period after its socket
+/// drops so a reconnect can take them over without peers seeing a leave and rejoin.
+#[derive(Clone)]
+pub struct ResumableSession {
+    pub user_id: String,
+    pub resume_token: String,
+    /// Socket currently driving the session (its own initial conn_id); None while suspended
+    pub attached_socket: Option<ConnId>,
+    /// Send queue of the attached socket, closed if another socket takes the session over
+    pub attached_sender: Option<WebSocketSender>,
+    pub suspended_at: Option<Instant>,
+}
+
+/// What happened to a connection's session when its socket went away
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum SessionDetach {
+    /// No session (never authenticated): clean up now
+    NoSession,
+    /// Held for resumption; clean up only if still suspended at this instant after the grace period
+    Suspended(Instant),
+    /// Another socket has already resumed the session: nothing to clean up
+    TakenOver,
 }
 
 impl SignalingState {
@@ -24,7 +61,182 @@ impl SignalingState {
             signing_servers: HashMap::new(),
             peer_senders: HashMap::new(),
             conn_peers: HashMap::new(),
+            auth_challenges: HashMap::new(),
+            conn_users: HashMap::new(),
+            sessions: HashMap::new(),
+            resume_tokens: HashMap::new(),
+        }
+    }
+
+    /// Create a fresh challenge nonce for a connection (replaces any outstanding one).
+    pub fn issue_auth_challenge(&mut self, conn_id: &ConnId) -> String {
+        let nonce = format!("{}{}", uuid::Uuid::new_v4().simple(), uuid::Uuid::new_v4().simple());
+        self.auth_challenges.insert(conn_id.clone(), nonce.clone());
+        nonce
+    }
+
+    /// Consume the outstanding challenge for a connection. Each nonce can be answered once.
+    pub fn take_auth_challenge(&mut self, conn_id: &ConnId) -> Option<String> {
+        self.auth_challenges.remove(conn_id)
+    }
+
+    pub fn bind_conn_user(&mut self, conn_id: &ConnId, user_id: String) {
+        self.conn_users.insert(conn_id.clone(), user_id);
+    }
+
+    /// Check that the connection has authenticated, and (if given) as `claimed_user_id`.
+    pub fn require_conn_user(&self, conn_id: &ConnId, claimed_user_id: Option<&str>) -> Result<(), String> {
+        let Some(bound) = self.conn_users.get(conn_id) else {
+            return Err("Not authenticated".to_string());
+        };
+        match claimed_user_id {
+            Some(claimed) if claimed != bound => {
+                Err(format!("user_id {} does not match authenticated identity", claimed))
+            }
+            _ => Ok(()),
+        }
+    }
+
+    /// User a connection authenticated as
+    pub fn conn_user(&self, conn_id: &ConnId) -> Option<&String> {
+        self.conn_users.get(conn_id)
+    }
+
+    /// Refuse to register `peer_id` for `conn_id` while another user's connection holds it.
+    /// The same user may take it over (e.g. reconnecting before the old socket is cleaned up).
+    pub fn check_peer_owner(&self, peer_id: &PeerId, conn_id: &ConnId) -> Result<(), String> {
+        let Some(peer) = self.peers.get(peer_id) else {
+            return Ok(());
+        };
+        if peer.conn_id == *conn_id || self.conn_users.get(&peer.conn_id) == self.conn_users.get(conn_id) {
+            return Ok(());
+        }
+        Err(format!("peer_id {} is registered by another user", peer_id))
+    }
+
+    /// (peer_id, user_id) of every peer with a live socket here, for route refreshes
+    pub fn peer_routes(&self) -> Vec<(PeerId, String)> {
+        self.peer_senders
+            .keys()
+            .filter_map(|peer_id| {
+                let peer = self.peers.get(peer_id)?;
+                Some((peer_id.clone(), self.conn_users.get(&peer.conn_id)?.clone()))
+            })
+            .collect()
+    }
+
+    pub fn clear_conn_auth(&mut self, conn_id: &ConnId) {
+        self.auth_challenges.remove(conn_id);
+        self.conn_users.remove(conn_id);
+        if let Some(session) = self.sessions.remove(conn_id) {
+            self.resume_tokens.remove(&session.resume_token);
+        }
+    }
+
+    /// Start a resumable session for a freshly authenticated connection and return its token.
+    pub fn open_session(&mut self, conn_id: &ConnId, user_id: String, sender: &WebSocketSender) -> String {
+        let resume_token = new_resume_token();
+        let previous = self.sessions.insert(
+            conn_id.clone(),
+            ResumableSession {
+                user_id,
+                resume_token: resume_token.clone(),
+                attached_socket: Some(conn_id.clone()),
+                attached_sender: Some(sender.clone()),
+                suspended_at: None,
+            },
+        );
+        if let Some(previous) = previous {
+            self.resume_tokens.remove(&previous.resume_token);
+        }
+        self.resume_tokens.insert(resume_token.clone(), conn_id.clone());
+        resume_token
+    }
+
+    /// Called when `socket_id` stops driving the session of `conn_id`.
+    pub fn detach_session(&mut self, conn_id: &ConnId, socket_id: &ConnId) -> SessionDetach {
+        let Some(session) = self.sessions.get_mut(conn_id) else {
+            return SessionDetach::NoSession;
+        };
+        if session.attached_socket.as_ref() != Some(socket_id) {
+            return SessionDetach::TakenOver;
         }
+        let now = Instant::now();
+        session.attached_socket = None;
+        session.attached_sender = None;
+        session.suspended_at = Some(now);
+        SessionDetach::Suspended(now)
+    }
+
+    /// End a session whose grace period ran out. Returns false if it was resumed meanwhile.
+    pub fn expire_session(&mut self, conn_id: &ConnId, suspended_at: Instant) -> bool {
+        match self.sessions.get(conn_id) {
+            Some(session) if session.suspended_at == Some(suspended_at) => {
+                let session = self.sessions.remove(conn_id).expect("session present");
+                self.resume_tokens.remove(&session.resume_token);
+                true
+            }
+            _ => false,
+        }
+    }
+
+    /// Re-attach a session to the socket `socket_id`, which has authenticated as the same user
+    /// but not registered anything yet. Its peers' senders are pointed at `sender`. A socket
+    /// still attached (the client reconnected before we noticed the drop) is closed, so it can't
+    /// act for the session any more. Returns the session's conn_id (which the socket adopts), a
+    /// fresh token, and its peer_ids.
+    pub fn resume_session(
+        &mut self,
+        resume_token: &str,
+        socket_id: &ConnId,
+        sender: &WebSocketSender,
+    ) -> Result<(ConnId, String, Vec<PeerId>), String> {
+        let user_id = self.conn_users.get(socket_id).cloned().ok_or("Not authenticated")?;
+        if self.conn_peers.get(socket_id).is_some_and(|peers| !peers.is_empty()) {
+            return Err("Resume must come before any registration".to_string());
+        }
+        let conn_id = self
+            .resume_tokens
+            .get(resume_token)
+            .cloned()
+            .ok_or("Unknown or expired resume token")?;
+        if conn_id == *socket_id {
+            return Err("Session is already attached to this connection".to_string());
+        }
+        let session = self.sessions.get(&conn_id).ok_or("Unknown or expired resume token")?;
+        if session.user_id != user_id {
+            return Err("Resume token belongs to a different identity".to_string());
+        }
+
+        // The socket's own session from AuthOk is replaced by the one it resumes
+        if let Some(own) = self.sessions.remove(socket_id) {
+            self.resume_tokens.remove(&own.resume_token);
+        }
+        self.conn_users.remove(socket_id);
+        self.conn_peers.remove(socket_id);
+
+        let new_token = new_resume_token();
+        let session = self.sessions.get_mut(&conn_id).expect("session present");
+        self.resume_tokens.remove(&session.resume_token);
+        session.resume_token = new_token.clone();
+        session.attached_socket = Some(socket_id.clone());
+        if let Some(evicted) = session.attached_sender.replace(sender.clone()) {
+            evicted.close();
+        }
+        session.suspended_at = None;
+        self.resume_tokens.insert(new_token.clone(), conn_id.clone());
+        self.conn_users.insert(conn_id.clone(), user_id);
+
+        let peer_ids: Vec<PeerId> = self
+            .conn_peers
+            .get(&conn_id)
+            .map(|peers| peers.iter().cloned().collect())
+            .unwrap_or_default();
+        for peer_id in &peer_ids {
+            self.peer_senders.insert(peer_id.clone(), sender.clone());
+        }
+
+        Ok((conn_id, new_token, peer_ids))
     }
 
     /// Validates that a peer_id belongs to the connection sending the message.
@@ -109,26 +321,139 @@ impl SignalingState {
         self.peers.get(peer_id).map(|c| c.server_id.clone())
     }
 
-    pub fn broadcast_server_hint_updated(&self, signing_pubkey: &SigningPubkey, hint: &EncryptedServerHint) {
+    /// Send to every local peer subscribed to `signing_pubkey`. With a `coalesce_key` only the
+    /// latest update per key waits for a client that is falling behind.
+    pub fn send_to_house(&self, signing_pubkey: &str, json: &str, coalesce_key: Option<&str>) {
         let Some(peers) = self.signing_servers.get(signing_pubkey) else {
             return;
         };
 
-        let msg = SignalingMessage::ServerHintUpdated {
-            signing_pubkey: signing_pubkey.clone(),
-            encrypted_state: hint.encrypted_state.clone(),
-            signature: hint.signature.clone(),
-            last_updated: hint.last_updated,
-        };
-
-        let Ok(json) = serde_json::to_string(&msg) else {
-            return;
-        };
-
         for peer_id in peers {
             if let Some(sender) = self.peer_senders.get(peer_id) {
-                let _ = sender.send(Message::Text(json.clone()));
+                let msg = Message::Text(json.to_string());
+                let _ = match coalesce_key {
+                    Some(key) => sender.send_coalesced(key.to_string(), msg),
+                    None => sender.send(msg),
+                };
             }
         }
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::sync::Arc;
+    use crate::send_queue::{self, SendQueueStats};
+
+    fn sender() -> (WebSocketSender, send_queue::ConnReceiver) {
+        send_queue::channel(8, Arc::new(SendQueueStats::default()))
+    }
+
+    /// An authenticated socket with one registered peer, as after AuthOk and Register
+    fn connect(signaling: &mut SignalingState, conn_id: &str, user_id: &str, peer_id: &str) -> String {
+        let (tx, _rx) = sender();
+        signaling.bind_conn_user(&conn_id.to_string(), user_id.to_string());
+        let token = signaling.open_session(&conn_id.to_string(), user_id.to_string(), &tx);
+        signaling.register_peer(peer_id.to_string(), "server".to_string(), None, conn_id.to_string());
+        signaling.peer_senders.insert(peer_id.to_string(), tx);
+        token
+    }
+
+    /// A fresh socket that has authenticated as `user_id` but registered nothing
+    fn reconnect(signaling: &mut SignalingState, socket_id: &str, user_id: &str) {
+        let (tx, _rx) = sender();
+        signaling.bind_conn_user(&socket_id.to_string(), user_id.to_string());
+        signaling.open_session(&socket_id.to_string(), user_id.to_string(), &tx);
+    }
+
+    #[test]
+    fn resume_takes_over_a_suspended_session() {
+        let mut signaling = SignalingState::new();
+        let token = connect(&mut signaling, "c1", "alice", "p1");
+        assert!(matches!(signaling.detach_session(&"c1".to_string(), &"c1".to_string()), SessionDetach::Suspended(_)));
+
+        reconnect(&mut signaling, "s2", "alice");
+        let (tx, _rx) = sender();
+        let (conn_id, new_token, peers) = signaling.resume_session(&token, &"s2".to_string(), &tx).unwrap();
+        assert_eq!((conn_id.as_str(), peers), ("c1", vec!["p1".to_string()]));
+        assert_ne!(new_token, token);
+        assert!(signaling.sessions.get("c1").unwrap().suspended_at.is_none());
+        // The socket's own AuthOk session is gone, and the old token can't be used again
+        assert!(!signaling.sessions.contains_key("s2"));
+        reconnect(&mut signaling, "s3", "alice");
+        assert!(signaling.resume_session(&token, &"s3".to_string(), &tx).is_err());
+    }
+
+    #[test]
+    fn expired_sessions_cannot_be_resumed() {
+        let mut signaling = SignalingState::new();
+        let token = connect(&mut signaling, "c1", "alice", "p1");
+        let SessionDetach::Suspended(at) = signaling.detach_session(&"c1".to_string(), &"c1".to_string()) else {
+            panic!("session should be suspended");
+        };
+        assert!(signaling.expire_session(&"c1".to_string(), at));
+
+        reconnect(&mut signaling, "s2", "alice");
+        let (tx, _rx) = sender();
+        assert!(signaling.resume_session(&token, &"s2".to_string(), &tx).is_err());
+    }
+
+    #[test]
+    fn resume_requires_the_same_identity() {
+        let mut signaling = SignalingState::new();
+        let token = connect(&mut signaling, "c1", "alice", "p1");
+        signaling.detach_session(&"c1".to_string(), &"c1".to_string());
+
+        let (tx, _rx) = sender();
+        assert!(signaling.resume_session(&token, &"anon".to_string(), &tx).is_err());
+        reconnect(&mut signaling, "s2", "mallory");
+        assert!(signaling.resume_session(&token, &"s2".to_string(), &tx).is_err());
+        // A failed attempt leaves the session waiting for its owner
+        reconnect(&mut signaling, "s3", "alice");
+        assert!(signaling.resume_session(&token, &"s3".to_string(), &tx).is_ok());
+    }
+
+    #[test]
+    fn resuming_an_attached_session_evicts_the_old_socket() {
+        let mut signaling = SignalingState::new();
+        let token = connect(&mut signaling, "c1", "alice", "p1");
+        let old = signaling.sessions["c1"].attached_sender.clone().unwrap();
+
+        reconnect(&mut signaling, "s2", "alice");
+        let (tx, _rx) = sender();
+        signaling.resume_session(&token, &"s2".to_string(), &tx).unwrap();
+        assert_eq!(signaling.sessions["c1"].attached_socket.as_deref(), Some("s2"));
+        // The old socket is closed, and the session's peers now go to the new one
+        assert!(old.is_closed());
+        assert!(!tx.is_closed());
+        assert!(!signaling.peer_senders["p1"].is_closed());
+        // When the old socket finally drops it must not suspend the session it lost
+        assert_eq!(signaling.detach_session(&"c1".to_string(), &"c1".to_string()), SessionDetach::TakenOver);
+        assert!(matches!(signaling.detach_session(&"c1".to_string(), &"s2".to_string()), SessionDetach::Suspended(_)));
+    }
+
+    #[test]
+    fn resume_must_precede_registration() {
+        let mut signaling = SignalingState::new();
+        let token = connect(&mut signaling, "c1", "alice", "p1");
+        signaling.detach_session(&"c1".to_string(), &"c1".to_string());
+
+        connect(&mut signaling, "s2", "alice", "p2");
+        let (tx, _rx) = sender();
+        assert!(signaling.resume_session(&token, &"s2".to_string(), &tx).is_err());
+    }
+    #[test]
+    fn peer_ids_cannot_be_taken_from_another_user() {
+        let mut signaling = SignalingState::new();
+        connect(&mut signaling, "c1", "alice", "p1");
+
+        reconnect(&mut signaling, "s2", "mallory");
+        assert!(signaling.check_peer_owner(&"p1".to_string(), &"s2".to_string()).is_err());
+        // The owner can take it over from a new socket, and anyone can claim a free peer_id
+        reconnect(&mut signaling, "s3", "alice");
+        assert!(signaling.check_peer_owner(&"p1".to_string(), &"s3".to_string()).is_ok());
+        assert!(signaling.check_peer_owner(&"p2".to_string(), &"s2".to_string()).is_ok());
+        assert_eq!(signaling.peer_routes(), vec![("p1".to_string(), "alice".to_string())]);
+    }
+}
@@ -0,0 +1,359 @@
+//! Behaviour every `Store` must share. Each implementation's test module runs all of it with
+//! `store_conformance!(open())`, where `open()` returns a fresh, empty store (or None to skip).
+//! Attributes after `open()`, as in `store_conformance!(open(), #[ignore])`, go on every case.
+
+use base64::engine::general_purpose::STANDARD as BASE64;
+use base64::Engine;
+use chrono::{Duration, Utc};
+use ed25519_dalek::{Signer, SigningKey};
+use crate::auth::{self, AuthError};
+use crate::{
+    EncryptedServerHint, EventQueryError, HintError, InviteError, InviteTokenCreateRequest, KeyRotationError,
+    KeyRotationRequest, ProfileRecord, ServerEvent,
+};
+use super::Store;
+
+macro_rules! store_conformance {
+    (@cases $attrs:tt $open:expr; $($case:ident),+ $(,)?) => {
+        $( store_conformance!(@case $attrs $open; $case); )+
+    };
+    (@case [$(#[$attr:meta])*] $open:expr; $case:ident) => {
+        $(#[$attr])*
+        #[tokio::test]
+        async fn $case() {
+            let Some(store) = $open.await else { return };
+            crate::store::conformance::$case(&store).await;
+        }
+    };
+    ($open:expr $(, #[$attr:meta])* $(,)?) => {
+        store_conformance!(@cases [$(#[$attr])*] $open;
+            profiles_keep_highest_rev,
+            hints_need_an_authorized_signer,
+            hints_compare_and_swap,
+            invites_redeem_until_used_up,
+            unlimited_invites_never_run_out,
+            invites_are_revoked_by_their_house,
+            expired_invites_are_hidden_and_free,
+            events_get_sequential_seqs_and_pages,
+            retried_events_keep_their_seq,
+            acks_drive_stats_and_gc,
+            acks_stop_at_the_latest_seq,
+            key_rotation_only_moves_forward,
+        );
+    };
+}
+pub(crate) use store_conformance;
+
+fn house(seed: u8) -> (SigningKey, String) {
+    let key = SigningKey::from_bytes(&[seed; 32]);
+    let pubkey = BASE64.encode(key.verifying_key().as_bytes());
+    (key, pubkey)
+}
+
+fn member(seed: u8) -> (SigningKey, String) {
+    let key = SigningKey::from_bytes(&[seed; 32]);
+    let pubkey = hex::encode(key.verifying_key().as_bytes());
+    (key, pubkey)
+}
+
+fn sign(key: &SigningKey, payload: &[u8]) -> String {
+    BASE64.encode(key.sign(payload).to_bytes())
+}
+
+fn profile(name: &str, rev: i64) -> ProfileRecord {
+    ProfileRecord {
+        display_name: name.to_string(),
+        real_name: None,
+        show_real_name: false,
+        rev,
+    }
+}
+
+/// A hint signed by `signer`: the house key when `signer_pubkey` is None, else that member
+fn hint(signer: &SigningKey, signing_pubkey: &str, signer_pubkey: Option<&str>, revision: u64) -> EncryptedServerHint {
+    let mut hint = EncryptedServerHint {
+        signing_pubkey: signing_pubkey.to_string(),
+        encrypted_state: format!("state-{}", revision),
+        signature: String::new(),
+        signer_pubkey: signer_pubkey.map(str::to_string),
+        key_epoch: 0,
+        revision,
+        last_updated: Utc::now(),
+    };
+    let payload = auth::hint_signing_payload(&hint.signing_pubkey, &hint.encrypted_state, hint.key_epoch, revision, &hint.last_updated);
+    hint.signature = sign(signer, &payload);
+    hint
+}
+
+fn invite(key: &SigningKey, signing_pubkey: &str, code: &str, max_uses: u32) -> InviteTokenCreateRequest {
+    let payload = auth::invite_create_payload(signing_pubkey, code, max_uses, "sealed");
+    InviteTokenCreateRequest {
+        code: code.to_string(),
+        max_uses,
+        encrypted_payload: "sealed".to_string(),
+        signature: sign(key, &payload),
+    }
+}
+
+fn event(signing_pubkey: &str, event_id: &str) -> ServerEvent {
+    ServerEvent {
+        event_id: event_id.to_string(),
+        signing_pubkey: signing_pubkey.to_string(),
+        event_type: "MemberJoin".to_string(),
+        encrypted_payload: "payload".to_string(),
+        signature: "sig".to_string(),
+        timestamp: Utc::now(),
+        seq: 0,
+    }
+}
+
+fn seqs(page: &crate::EventPage) -> Vec<u64> {
+    page.events.iter().map(|e| e.seq).collect()
+}
+
+pub async fn profiles_keep_highest_rev(store: &dyn Store) {
+    store.upsert_profile("u1", &profile("new", 2)).await.unwrap();
+    store.upsert_profile("u1", &profile("old", 1)).await.unwrap();
+    store.upsert_profile("u2", &profile("other", 1)).await.unwrap();
+
+    let mut profiles = store.load_profiles(&["u1".to_string(), "u2".to_string(), "u3".to_string()]).await.unwrap();
+    profiles.sort_by(|a, b| a.user_id.cmp(&b.user_id));
+    assert_eq!(profiles.len(), 2);
+    assert_eq!((profiles[0].display_name.as_str(), profiles[0].rev), ("new", 2));
+    assert!(store.load_profiles(&[]).await.unwrap().is_empty());
+}
+
+pub async fn hints_need_an_authorized_signer(store: &dyn Store) {
+    let (house_key, house_pk) = house(7);
+    let (member_key, member_pk) = member(3);
+
+    let stranger = store.register_server_hint(&house_pk, &hint(&member_key, &house_pk, Some(&member_pk), 1)).await;
+    assert!(matches!(stranger, Err(HintError::Auth(AuthError::UnauthorizedSigner))));
+    let (_, other_pk) = house(9);
+    let misfiled = store.register_server_hint(&other_pk, &hint(&house_key, &house_pk, None, 1)).await;
+    assert!(matches!(misfiled, Err(HintError::Auth(AuthError::PubkeyMismatch))));
+    let mut forged = hint(&house_key, &house_pk, None, 1);
+    forged.encrypted_state = "tampered".to_string();
+    assert!(matches!(store.register_server_hint(&house_pk, &forged).await, Err(HintError::Auth(AuthError::BadSignature))));
+    assert!(store.get_server_hint(&house_pk).await.unwrap().is_none());
+
+    store.register_server_hint(&house_pk, &hint(&house_key, &house_pk, None, 1)).await.unwrap();
+    store.authorize_member_signer(&house_pk, &member_pk).await.unwrap();
+    store.authorize_member_signer(&house_pk, &member_pk).await.unwrap();
+    store.register_server_hint(&house_pk, &hint(&member_key, &house_pk, Some(&member_pk), 2)).await.unwrap();
+
+    let stored = store.get_server_hint(&house_pk).await.unwrap().unwrap();
+    assert_eq!(stored.signer_pubkey.as_deref(), Some(member_pk.as_str()));
+    assert_eq!(stored.revision, 2);
+}
+
+pub async fn hints_compare_and_swap(store: &dyn Store) {
+    let (key, house_pk) = house(7);
+    store.register_server_hint(&house_pk, &hint(&key, &house_pk, None, 5)).await.unwrap();
+    store.register_server_hint(&house_pk, &hint(&key, &house_pk, None, 6)).await.unwrap();
+
+    for stale in [6, 8] {
+        match store.register_server_hint(&house_pk, &hint(&key, &house_pk, None, stale)).await {
+            Err(HintError::Conflict(current)) => assert_eq!(current.revision, 6),
+            other => panic!("expected conflict, got {:?}", other),
+        }
+    }
+    let stored = store.get_server_hint(&house_pk).await.unwrap().unwrap();
+    assert_eq!(stored.encrypted_state, "state-6");
+    assert!(store.get_server_hint("missing").await.unwrap().is_none());
+}
+
+pub async fn invites_redeem_until_used_up(store: &dyn Store) {
+    let (key, house_pk) = house(7);
+    let created = store.create_invite(&house_pk, invite(&key, &house_pk, "abcdef", 2), 30).await.unwrap();
+    assert_eq!((created.max_uses, created.remaining_uses), (2, 2));
+    assert!(created.expires_at > Utc::now() + Duration::days(29));
+
+    assert_eq!(store.redeem_invite("abcdef").await.unwrap().unwrap().remaining_uses, 1);
+    assert_eq!(store.redeem_invite("abcdef").await.unwrap().unwrap().remaining_uses, 0);
+    assert!(store.redeem_invite("abcdef").await.unwrap().is_none());
+    // Used up, but still there for the house to see
+    assert_eq!(store.get_invite("abcdef").await.unwrap().unwrap().remaining_uses, 0);
+    assert!(store.redeem_invite("nosuchcode").await.unwrap().is_none());
+
+    // The owner can re-issue its code; another house can't take it
+    store.create_invite(&house_pk, invite(&key, &house_pk, "abcdef", 1), 30).await.unwrap();
+    assert_eq!(store.get_invite("abcdef").await.unwrap().unwrap().remaining_uses, 1);
+    let (other, other_pk) = house(9);
+    let taken = store.create_invite(&other_pk, invite(&other, &other_pk, "abcdef", 1), 30).await;
+    assert!(matches!(taken, Err(InviteError::CodeTaken)));
+
+    let short = store.create_invite(&house_pk, invite(&key, &house_pk, "abc", 1), 30).await;
+    assert!(matches!(short, Err(InviteError::InvalidCode)));
+    let unsigned = store.create_invite(&house_pk, invite(&other, &house_pk, "ghijkl", 1), 30).await;
+    assert!(matches!(unsigned, Err(InviteError::Auth(_))));
+    assert!(store.get_invite("ghijkl").await.unwrap().is_none());
+}
+
+pub async fn unlimited_invites_never_run_out(store: &dyn Store) {
+    let (key, house_pk) = house(7);
+    store.create_invite(&house_pk, invite(&key, &house_pk, "forever", 0), 30).await.unwrap();
+    for _ in 0..3 {
+        let rec = store.redeem_invite("forever").await.unwrap().unwrap();
+        assert_eq!((rec.max_uses, rec.remaining_uses), (0, 0));
+    }
+}
+
+pub async fn invites_are_revoked_by_their_house(store: &dyn Store) {
+    let (key, house_pk) = house(7);
+    let (other, _) = house(9);
+    store.create_invite(&house_pk, invite(&key, &house_pk, "abcdef", 1), 30).await.unwrap();
+
+    let forged = sign(&other, &auth::invite_revoke_payload(&house_pk, "abcdef"));
+    assert!(matches!(store.revoke_invite("abcdef", &forged).await, Err(InviteError::Auth(_))));
+    assert!(store.get_invite("abcdef").await.unwrap().is_some());
+
+    let signature = sign(&key, &auth::invite_revoke_payload(&house_pk, "abcdef"));
+    store.revoke_invite("abcdef", &signature).await.unwrap();
+    assert!(store.get_invite("abcdef").await.unwrap().is_none());
+    assert!(matches!(store.revoke_invite("abcdef", &signature).await, Err(InviteError::NotFound)));
+}
+
+pub async fn expired_invites_are_hidden_and_free(store: &dyn Store) {
+    let (key, house_pk) = house(7);
+    store.create_invite(&house_pk, invite(&key, &house_pk, "expired", 0), -1).await.unwrap();
+    assert!(store.get_invite("expired").await.unwrap().is_none());
+    assert!(store.redeem_invite("expired").await.unwrap().is_none());
+
+    // Before GC has run, another house may already reuse the code
+    let (other, other_pk) = house(9);
+    store.create_invite(&other_pk, invite(&other, &other_pk, "expired", 1), 30).await.unwrap();
+    store.gc_expired_invites().await.unwrap();
+    assert_eq!(store.get_invite("expired").await.unwrap().unwrap().signing_pubkey, other_pk);
+}
+
+pub async fn events_get_sequential_seqs_and_pages(store: &dyn Store) {
+    for i in 1..=6 {
+        let stored = store.insert_event(&event("h", &format!("e{}", i))).await.unwrap();
+        assert_eq!(stored.seq, i);
+    }
+    assert_eq!(store.insert_event(&event("other", "x1")).await.unwrap().seq, 1);
+
+    let page = store.events_page("h", 0, 4).await.unwrap();
+    assert_eq!(seqs(&page), vec![1, 2, 3, 4]);
+    assert!(page.has_more);
+    assert_eq!(page.next_seq, 4);
+    let page = store.events_page("h", 4, 4).await.unwrap();
+    assert_eq!(seqs(&page), vec![5, 6]);
+    assert!(!page.has_more);
+    let page = store.events_page("h", 6, 4).await.unwrap();
+    assert!(page.events.is_empty());
+    assert_eq!(page.next_seq, 6);
+    assert!(store.events_page("empty", 0, 4).await.unwrap().events.is_empty());
+
+    assert_eq!(store.event_seq("h", "e2").await.unwrap(), Some(2));
+    assert_eq!(store.event_seq("h", "x1").await.unwrap(), None);
+    assert_eq!(store.event_queue_totals().await.unwrap(), (7, 2));
+    assert!(matches!(
+        store.events_page("h", 9, 4).await,
+        Err(EventQueryError::CursorExpired { latest_seq: 6 })
+    ));
+}
+
+pub async fn retried_events_keep_their_seq(store: &dyn Store) {
+    for i in 1..=3 {
+        store.insert_event(&event("h", &format!("e{}", i))).await.unwrap();
+    }
+    let retried = store.insert_event(&event("h", "e2")).await.unwrap();
+    assert_eq!(retried.seq, 2);
+    // No seq was burnt on the retry
+    assert_eq!(store.insert_event(&event("h", "e4")).await.unwrap().seq, 4);
+    assert_eq!(seqs(&store.events_page("h", 0, 10).await.unwrap()), vec![1, 2, 3, 4]);
+}
+
+/// Authorize a member of house "h" and return their user_id
+async fn known_member(store: &dyn Store, seed: u8) -> String {
+    let (key, pubkey) = member(seed);
+    store.authorize_member_signer("h", &pubkey).await.unwrap();
+    auth::user_id_for_key(&key.verifying_key())
+}
+
+pub async fn acks_drive_stats_and_gc(store: &dyn Store) {
+    let alice = known_member(store, 11).await;
+    let bob = known_member(store, 12).await;
+    for i in 1..=6 {
+        store.insert_event(&event("h", &format!("e{}", i))).await.unwrap();
+    }
+    store.ack_events("h", &alice, 4).await.unwrap();
+    store.ack_events("h", &alice, 2).await.unwrap();
+    store.ack_events("h", &bob, 3).await.unwrap();
+    // Not a member: doesn't hold the ack point back
+    store.ack_events("h", "stranger", 1).await.unwrap();
+
+    let stats = store.event_queue_stats("h").await.unwrap();
+    assert_eq!(stats.latest_seq, 6);
+    assert_eq!(stats.acked_seq, 3);
+    assert_eq!(stats.queue_depth, 6);
+    assert_eq!(stats.unacked, 3);
+    assert_eq!(stats.oldest_unacked_seq, Some(4));
+
+    // Acked by everyone: 1..=3 go; the cap of 2 then drops 4
+    store.gc_old_events(Utc::now() - Duration::days(1), 2).await.unwrap();
+    assert_eq!(seqs(&store.events_page("h", 4, 10).await.unwrap()), vec![5, 6]);
+    assert!(matches!(
+        store.events_page("h", 1, 10).await,
+        Err(EventQueryError::CursorExpired { .. })
+    ));
+    // Starting from 0 means "whatever is still retained"
+    assert_eq!(seqs(&store.events_page("h", 0, 10).await.unwrap()), vec![5, 6]);
+
+    // Past the retention cutoff everything goes, but seqs are never reused
+    store.gc_old_events(Utc::now() + Duration::seconds(1), 100).await.unwrap();
+    assert_eq!(store.event_queue_totals().await.unwrap(), (0, 0));
+    assert_eq!(store.insert_event(&event("h", "e7")).await.unwrap().seq, 7);
+}
+
+pub async fn acks_stop_at_the_latest_seq(store: &dyn Store) {
+    let alice = known_member(store, 11).await;
+    store.insert_event(&event("h", "e1")).await.unwrap();
+    store.insert_event(&event("h", "e2")).await.unwrap();
+    store.ack_events("h", &alice, 50).await.unwrap();
+
+    // Events posted after the ack are still unacked
+    store.insert_event(&event("h", "e3")).await.unwrap();
+    let stats = store.event_queue_stats("h").await.unwrap();
+    assert_eq!((stats.latest_seq, stats.acked_seq, stats.unacked), (3, 2, 1));
+}
+
+pub async fn key_rotation_only_moves_forward(store: &dyn Store) {
+    let (key, house_pk) = house(7);
+    let (member_key, member_pk) = member(3);
+    let member_id = auth::user_id_for_key(&member_key.verifying_key());
+    store.authorize_member_signer(&house_pk, &member_pk).await.unwrap();
+    store.register_server_hint(&house_pk, &hint(&member_key, &house_pk, Some(&member_pk), 1)).await.unwrap();
+
+    let rotation = |epoch: u32, removed: Vec<String>| {
+        let sealed_keys = std::collections::BTreeMap::from([("keeper".to_string(), format!("blob-{}", epoch))]);
+        let payload = auth::key_rotation_payload(&house_pk, epoch, &sealed_keys, &removed);
+        KeyRotationRequest {
+            key_epoch: epoch,
+            sealed_keys,
+            removed_user_ids: removed,
+            signature: sign(&key, &payload),
+        }
+    };
+    let mut unsigned = rotation(2, vec![]);
+    unsigned.signature = sign(&member_key, b"not the payload");
+    assert!(matches!(store.put_key_rotation(&house_pk, &unsigned).await, Err(KeyRotationError::Auth(_))));
+
+    // Removing the member takes away its right to sign hints
+    store.put_key_rotation(&house_pk, &rotation(2, vec![member_id])).await.unwrap();
+    let removed = store.register_server_hint(&house_pk, &hint(&member_key, &house_pk, Some(&member_pk), 2)).await;
+    assert!(matches!(removed, Err(HintError::Auth(AuthError::UnauthorizedSigner))));
+    for stale in [1, 2] {
+        assert!(matches!(
+            store.put_key_rotation(&house_pk, &rotation(stale, vec![])).await,
+            Err(KeyRotationError::StaleEpoch { current: 2 })
+        ));
+    }
+
+    let sealed = store.get_sealed_key(&house_pk, "keeper").await.unwrap().unwrap();
+    assert_eq!((sealed.key_epoch, sealed.sealed_key.as_str()), (2, "blob-2"));
+    assert!(store.get_sealed_key(&house_pk, "nobody").await.unwrap().is_none());
+    assert!(store.get_sealed_key("other", "keeper").await.unwrap().is_none());
+}
@@ -0,0 +1,152 @@
+use std::collections::HashSet;
+use std::sync::Arc;
+use async_trait::async_trait;
+use chrono::{DateTime, Utc};
+use tokio::sync::Mutex;
+use crate::state::{EventState, ProfileState};
+use crate::{
+    EncryptedServerHint, EventPage, EventQueryError, EventQueueStats, HintError, InviteError, InviteTokenCreateRequest,
+    InviteTokenRecord, KeyRotationError, KeyRotationRequest, ProfileRecord, ProfileSnapshotRecord, SealedKeyRecord,
+    ServerEvent,
+};
+use super::Store;
+
+/// State kept in the beacon process. Shares its maps with `AppState`, which also uses them as
+/// the profile cache and as the backlog written to Postgres when it comes back.
+pub struct MemoryStore {
+    events: Arc<Mutex<EventState>>,
+    profiles: Arc<Mutex<ProfileState>>,
+}
+
+impl MemoryStore {
+    pub fn new(events: Arc<Mutex<EventState>>, profiles: Arc<Mutex<ProfileState>>) -> Self {
+        Self { events, profiles }
+    }
+}
+
+#[async_trait]
+impl Store for MemoryStore {
+    fn name(&self) -> &'static str {
+        "memory"
+    }
+
+    async fn upsert_profile(&self, user_id: &str, rec: &ProfileRecord) -> Result<(), String> {
+        let mut profiles = self.profiles.lock().await;
+        let newer = match profiles.profiles.get(user_id) {
+            Some(existing) => rec.rev > existing.rev,
+            None => true,
+        };
+        if newer {
+            profiles.profiles.insert(user_id.to_string(), rec.clone());
+        }
+        Ok(())
+    }
+
+    async fn load_profiles(&self, user_ids: &[String]) -> Result<Vec<ProfileSnapshotRecord>, String> {
+        let profiles = self.profiles.lock().await;
+        Ok(user_ids
+            .iter()
+            .filter_map(|uid| profiles.profiles.get(uid).map(|rec| ProfileSnapshotRecord {
+                user_id: uid.clone(),
+                display_name: rec.display_name.clone(),
+                real_name: rec.real_name.clone(),
+                show_real_name: rec.show_real_name,
+                rev: rec.rev,
+            }))
+            .collect())
+    }
+
+    async fn register_server_hint(&self, signing_pubkey: &str, hint: &EncryptedServerHint) -> Result<(), HintError> {
+        self.events.lock().await.register_server_hint(signing_pubkey.to_string(), hint.clone())
+    }
+
+    async fn get_server_hint(&self, signing_pubkey: &str) -> Result<Option<EncryptedServerHint>, String> {
+        Ok(self.events.lock().await.get_server_hint(signing_pubkey).cloned())
+    }
+
+    async fn house_signers(&self, signing_pubkey: &str) -> Result<HashSet<String>, String> {
+        Ok(self.events.lock().await.house_signers.get(signing_pubkey).cloned().unwrap_or_default())
+    }
+
+    async fn authorize_member_signer(&self, signing_pubkey: &str, member_pubkey: &str) -> Result<(), String> {
+        self.events.lock().await.authorize_member_signer(signing_pubkey, member_pubkey);
+        Ok(())
+    }
+
+    async fn put_key_rotation(&self, signing_pubkey: &str, req: &KeyRotationRequest) -> Result<(), KeyRotationError> {
+        self.events.lock().await.put_key_rotation(signing_pubkey, req.clone())
+    }
+
+    async fn get_sealed_key(&self, signing_pubkey: &str, user_id: &str) -> Result<Option<SealedKeyRecord>, String> {
+        Ok(self.events.lock().await.get_sealed_key(signing_pubkey, user_id))
+    }
+
+    async fn create_invite(&self, signing_pubkey: &str, req: InviteTokenCreateRequest, ttl_days: i64) -> Result<InviteTokenRecord, InviteError> {
+        self.events.lock().await.put_invite_token(signing_pubkey, req, ttl_days)
+    }
+
+    async fn get_invite(&self, code: &str) -> Result<Option<InviteTokenRecord>, String> {
+        Ok(self.events.lock().await.get_invite_token(code).cloned())
+    }
+
+    async fn redeem_invite(&self, code: &str) -> Result<Option<InviteTokenRecord>, String> {
+        Ok(self.events.lock().await.redeem_invite_token(code))
+    }
+
+    async fn revoke_invite(&self, code: &str, signature: &str) -> Result<(), InviteError> {
+        self.events.lock().await.revoke_invite_token(code, signature)
+    }
+
+    async fn gc_expired_invites(&self) -> Result<(), String> {
+        self.events.lock().await.gc_expired_invites();
+        Ok(())
+    }
+
+    async fn insert_event(&self, event: &ServerEvent) -> Result<ServerEvent, String> {
+        Ok(self.events.lock().await.post_event(event.signing_pubkey.clone(), event.clone()))
+    }
+
+    async fn event_seq(&self, signing_pubkey: &str, event_id: &str) -> Result<Option<u64>, String> {
+        Ok(self.events.lock().await.event_seq(signing_pubkey, event_id))
+    }
+
+    async fn events_page(&self, signing_pubkey: &str, after_seq: u64, limit: usize) -> Result<EventPage, EventQueryError> {
+        self.events.lock().await.get_events_page(signing_pubkey, after_seq, limit)
+    }
+
+    async fn ack_events(&self, signing_pubkey: &str, user_id: &str, last_seq: u64) -> Result<(), String> {
+        self.events.lock().await.ack_events(signing_pubkey.to_string(), user_id.to_string(), last_seq);
+        Ok(())
+    }
+
+    async fn event_queue_stats(&self, signing_pubkey: &str) -> Result<EventQueueStats, String> {
+        Ok(self.events.lock().await.event_queue_stats(signing_pubkey))
+    }
+
+    async fn event_queue_totals(&self) -> Result<(u64, u64), String> {
+        let events = self.events.lock().await;
+        let queued = events.event_queues.values().map(|q| q.len() as u64).sum();
+        let houses = events.event_queues.values().filter(|q| !q.is_empty()).count() as u64;
+        Ok((queued, houses))
+    }
+
+    async fn gc_old_events(&self, cutoff: DateTime<Utc>, max_unacked: usize) -> Result<(), String> {
+        self.events.lock().await.gc_old_events(cutoff, max_unacked);
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::store::conformance::store_conformance;
+
+    async fn open() -> Option<MemoryStore> {
+        Some(MemoryStore::new(
+            Arc::new(Mutex::new(EventState::new())),
+            Arc::new(Mutex::new(ProfileState::new())),
+        ))
+    }
+
+    store_conformance!(open());
+}
@@ -0,0 +1,89 @@
+//! Durable beacon state behind one interface.
+//!
+//! Hints, signers, key rotations, invites, the event queue and profiles all go through a
+//! [`Store`]. `MemoryStore` keeps them in `EventState`/`ProfileState` and is always present;
+//! `PostgresStore` and `SqliteStore` are installed in `BackendState` while their database is
+//! usable. `AppState::store` picks the durable one when it is there, so a route makes one call
+//! instead of branching per backend.
+//!
+//! Every implementation runs the same conformance suite (`conformance.rs`). Things the suite
+//! pins down, which callers rely on:
+//! - Expired invites are invisible: not returned, not redeemable, and their codes are free.
+//! - A retried event (same `event_id`) returns the event as first stored and takes no new seq.
+//! - The caller sets event timestamps and ids; the store only assigns seqs.
+//! - Member keys are validated by the caller before `authorize_member_signer`, and ack
+//!   signatures before `ack_events`.
+
+use std::collections::HashSet;
+use async_trait::async_trait;
+use chrono::{DateTime, Utc};
+use crate::{
+    EncryptedServerHint, EventPage, EventQueryError, EventQueueStats, HintError, InviteError, InviteTokenCreateRequest,
+    InviteTokenRecord, KeyRotationError, KeyRotationRequest, ProfileRecord, ProfileSnapshotRecord, SealedKeyRecord,
+    ServerEvent,
+};
+
+pub mod memory;
+#[cfg(feature = "postgres")]
+pub mod postgres;
+#[cfg(feature = "sqlite")]
+pub mod sqlite;
+#[cfg(test)]
+pub mod conformance;
+
+pub use memory::MemoryStore;
+#[cfg(feature = "postgres")]
+pub use postgres::PostgresStore;
+#[cfg(feature = "sqlite")]
+pub use sqlite::SqliteStore;
+
+#[async_trait]
+pub trait Store: Send + Sync {
+    /// Label for logs and backend metrics
+    fn name(&self) -> &'static str;
+
+    /// Store a profile unless a newer rev is already stored
+    async fn upsert_profile(&self, user_id: &str, rec: &ProfileRecord) -> Result<(), String>;
+    /// Stored profiles among `user_ids` (unknown users are left out)
+    async fn load_profiles(&self, user_ids: &[String]) -> Result<Vec<ProfileSnapshotRecord>, String>;
+
+    /// Verify a hint against the house's signers and store it. Compare-and-swap on `revision`:
+    /// a write based on an older hint gets `HintError::Conflict` with the current one.
+    async fn register_server_hint(&self, signing_pubkey: &str, hint: &EncryptedServerHint) -> Result<(), HintError>;
+    async fn get_server_hint(&self, signing_pubkey: &str) -> Result<Option<EncryptedServerHint>, String>;
+    /// Member identity keys (hex) allowed to sign hints and acks for a house
+    async fn house_signers(&self, signing_pubkey: &str) -> Result<HashSet<String>, String>;
+    /// Allow a member identity key (hex) to sign hints for a house. Idempotent.
+    async fn authorize_member_signer(&self, signing_pubkey: &str, member_pubkey: &str) -> Result<(), String>;
+    /// Store a house-signed key rotation newer than the stored one; removed members lose their
+    /// hint-signing rights.
+    async fn put_key_rotation(&self, signing_pubkey: &str, req: &KeyRotationRequest) -> Result<(), KeyRotationError>;
+    /// A member's sealed copy of the latest rotated key
+    async fn get_sealed_key(&self, signing_pubkey: &str, user_id: &str) -> Result<Option<SealedKeyRecord>, String>;
+
+    /// Create or replace a house-signed invite living `ttl_days`. A code held by another house
+    /// is refused unless that invite has expired.
+    async fn create_invite(&self, signing_pubkey: &str, req: InviteTokenCreateRequest, ttl_days: i64) -> Result<InviteTokenRecord, InviteError>;
+    async fn get_invite(&self, code: &str) -> Result<Option<InviteTokenRecord>, String>;
+    /// Use up one redemption (none for unlimited invites). None when expired or used up.
+    async fn redeem_invite(&self, code: &str) -> Result<Option<InviteTokenRecord>, String>;
+    /// Delete an invite; must be signed by the key of the house that owns it.
+    async fn revoke_invite(&self, code: &str, signature: &str) -> Result<(), InviteError>;
+    /// Drop expired invites (they are already invisible; this only reclaims space)
+    async fn gc_expired_invites(&self) -> Result<(), String>;
+
+    /// Append an event to its house's queue. Returns it with its seq filled in.
+    async fn insert_event(&self, event: &ServerEvent) -> Result<ServerEvent, String>;
+    /// Seq of a retained event (for clients that still send an event_id cursor)
+    async fn event_seq(&self, signing_pubkey: &str, event_id: &str) -> Result<Option<u64>, String>;
+    /// Up to `limit` events with seq > `after_seq`, oldest first
+    async fn events_page(&self, signing_pubkey: &str, after_seq: u64, limit: usize) -> Result<EventPage, EventQueryError>;
+    /// Record a member's ack (acks never move backwards, nor past the latest seq)
+    async fn ack_events(&self, signing_pubkey: &str, user_id: &str, last_seq: u64) -> Result<(), String>;
+    async fn event_queue_stats(&self, signing_pubkey: &str) -> Result<EventQueueStats, String>;
+    /// Retained events and houses that have any, across all houses
+    async fn event_queue_totals(&self) -> Result<(u64, u64), String>;
+    /// Drop events from before `cutoff` or acked by every known member, then keep at most
+    /// `max_unacked` per house.
+    async fn gc_old_events(&self, cutoff: DateTime<Utc>, max_unacked: usize) -> Result<(), String>;
+}
@@ -0,0 +1,150 @@
+use std::collections::HashSet;
+use async_trait::async_trait;
+use chrono::{DateTime, Utc};
+use sqlx::PgPool;
+use crate::auth;
+use crate::handlers::db::{self, BACKEND};
+use crate::metrics::timed;
+use crate::{
+    EncryptedServerHint, EventPage, EventQueryError, EventQueueStats, HintError, InviteError, InviteTokenCreateRequest,
+    InviteTokenRecord, KeyRotationError, KeyRotationRequest, ProfileRecord, ProfileSnapshotRecord, SealedKeyRecord,
+    ServerEvent,
+};
+use super::Store;
+
+/// Postgres, shared by every beacon instance. Installed by the supervisor while connected.
+pub struct PostgresStore {
+    pool: PgPool,
+}
+
+impl PostgresStore {
+    pub fn new(pool: PgPool) -> Self {
+        Self { pool }
+    }
+}
+
+#[async_trait]
+impl Store for PostgresStore {
+    fn name(&self) -> &'static str {
+        BACKEND
+    }
+
+    async fn upsert_profile(&self, user_id: &str, rec: &ProfileRecord) -> Result<(), String> {
+        timed(BACKEND, "upsert_profile", db::upsert_profile_db(&self.pool, user_id, rec)).await
+    }
+
+    async fn load_profiles(&self, user_ids: &[String]) -> Result<Vec<ProfileSnapshotRecord>, String> {
+        timed(BACKEND, "load_profiles", db::load_profiles_db(&self.pool, user_ids)).await
+    }
+
+    async fn register_server_hint(&self, signing_pubkey: &str, hint: &EncryptedServerHint) -> Result<(), HintError> {
+        let signers = timed(BACKEND, "load_house_signers", db::load_house_signers_db(&self.pool, signing_pubkey))
+            .await
+            .map_err(HintError::Storage)?;
+        auth::verify_server_hint(signing_pubkey, hint, &signers)?;
+        timed(BACKEND, "upsert_server_hint", db::upsert_server_hint_db(&self.pool, hint)).await
+    }
+
+    async fn get_server_hint(&self, signing_pubkey: &str) -> Result<Option<EncryptedServerHint>, String> {
+        timed(BACKEND, "get_server_hint", db::get_server_hint_db(&self.pool, signing_pubkey)).await
+    }
+
+    async fn house_signers(&self, signing_pubkey: &str) -> Result<HashSet<String>, String> {
+        timed(BACKEND, "load_house_signers", db::load_house_signers_db(&self.pool, signing_pubkey)).await
+    }
+
+    async fn authorize_member_signer(&self, signing_pubkey: &str, member_pubkey: &str) -> Result<(), String> {
+        timed(BACKEND, "authorize_member_signer", db::authorize_member_signer_db(&self.pool, signing_pubkey, member_pubkey)).await
+    }
+
+    async fn put_key_rotation(&self, signing_pubkey: &str, req: &KeyRotationRequest) -> Result<(), KeyRotationError> {
+        timed(BACKEND, "put_key_rotation", db::put_key_rotation_db(&self.pool, signing_pubkey, req)).await
+    }
+
+    async fn get_sealed_key(&self, signing_pubkey: &str, user_id: &str) -> Result<Option<SealedKeyRecord>, String> {
+        timed(BACKEND, "get_sealed_key", db::get_sealed_key_db(&self.pool, signing_pubkey, user_id)).await
+    }
+
+    async fn create_invite(&self, signing_pubkey: &str, req: InviteTokenCreateRequest, ttl_days: i64) -> Result<InviteTokenRecord, InviteError> {
+        timed(BACKEND, "upsert_invite", db::upsert_invite_db(&self.pool, signing_pubkey, req, ttl_days)).await
+    }
+
+    async fn get_invite(&self, code: &str) -> Result<Option<InviteTokenRecord>, String> {
+        timed(BACKEND, "get_invite", db::get_invite_db(&self.pool, code)).await
+    }
+
+    async fn redeem_invite(&self, code: &str) -> Result<Option<InviteTokenRecord>, String> {
+        timed(BACKEND, "redeem_invite", db::redeem_invite_db(&self.pool, code)).await
+    }
+
+    async fn revoke_invite(&self, code: &str, signature: &str) -> Result<(), InviteError> {
+        timed(BACKEND, "revoke_invite", db::revoke_invite_db(&self.pool, code, signature)).await
+    }
+
+    async fn gc_expired_invites(&self) -> Result<(), String> {
+        timed(BACKEND, "gc_expired_invites", db::gc_expired_invites_db(&self.pool)).await
+    }
+
+    async fn insert_event(&self, event: &ServerEvent) -> Result<ServerEvent, String> {
+        timed(BACKEND, "insert_event", db::insert_event_db(&self.pool, event)).await
+    }
+
+    async fn event_seq(&self, signing_pubkey: &str, event_id: &str) -> Result<Option<u64>, String> {
+        timed(BACKEND, "get_event_seq", db::get_event_seq_db(&self.pool, signing_pubkey, event_id)).await
+    }
+
+    async fn events_page(&self, signing_pubkey: &str, after_seq: u64, limit: usize) -> Result<EventPage, EventQueryError> {
+        timed(BACKEND, "get_events_page", db::get_events_page_db(&self.pool, signing_pubkey, after_seq, limit)).await
+    }
+
+    async fn ack_events(&self, signing_pubkey: &str, user_id: &str, last_seq: u64) -> Result<(), String> {
+        timed(BACKEND, "ack_events", db::ack_events_db(&self.pool, signing_pubkey, user_id, last_seq)).await
+    }
+
+    async fn event_queue_stats(&self, signing_pubkey: &str) -> Result<EventQueueStats, String> {
+        timed(BACKEND, "event_queue_stats", db::event_queue_stats_db(&self.pool, signing_pubkey)).await
+    }
+
+    async fn event_queue_totals(&self) -> Result<(u64, u64), String> {
+        timed(BACKEND, "event_queue_totals", db::event_queue_totals_db(&self.pool)).await
+    }
+
+    async fn gc_old_events(&self, cutoff: DateTime<Utc>, max_unacked: usize) -> Result<(), String> {
+        timed(BACKEND, "gc_old_events", db::gc_old_events_db(&self.pool, cutoff, max_unacked)).await
+    }
+}
+
+/// Needs a real server: run with `BEACON_TEST_POSTGRES_URL=... cargo test -- --ignored`. Without
+/// the variable each case prints a skip notice and returns. Each case migrates a fresh schema,
+/// left behind for inspection.
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use sqlx::Executor;
+    use crate::store::conformance::store_conformance;
+
+    async fn open() -> Option<PostgresStore> {
+        let Ok(url) = std::env::var("BEACON_TEST_POSTGRES_URL") else {
+            eprintln!("skipped: BEACON_TEST_POSTGRES_URL is not set");
+            return None;
+        };
+        let schema = format!("beacon_test_{}", uuid::Uuid::new_v4().simple());
+        let setup = format!("CREATE SCHEMA IF NOT EXISTS {0}; SET search_path TO {0};", schema);
+        let pool = sqlx::postgres::PgPoolOptions::new()
+            .max_connections(2)
+            .after_connect(move |conn, _| {
+                let setup = setup.clone();
+                Box::pin(async move {
+                    conn.execute(setup.as_str()).await?;
+                    Ok(())
+                })
+            })
+            .connect(&url)
+            .await
+            .expect("connect to BEACON_TEST_POSTGRES_URL");
+        crate::handlers::migrations::migrate(&pool).await.expect("migrate test schema");
+        Some(PostgresStore::new(pool))
+    }
+
+    store_conformance!(open(), #[ignore = "needs BEACON_TEST_POSTGRES_URL"]);
+}
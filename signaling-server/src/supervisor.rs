@@ -0,0 +1,404 @@
+//! Keeps Postgres and Redis connected.
+//!
+//! Each configured backend gets a task that connects with exponential backoff, installs the
+//! handle (for Postgres, a `PostgresStore`) in `BackendState` once it works, probes it periodically and takes it out again when
+//! a probe fails. While a handle is out, requests fall back to in-memory state. When a backend
+//! comes (back) up, whatever was kept in memory meanwhile is written to it.
+
+use std::sync::Arc;
+use std::time::Duration;
+
+#[cfg(any(feature = "postgres", feature = "redis-backend"))]
+use log::info;
+use log::warn;
+
+use crate::state::AppState;
+#[cfg(any(feature = "postgres", feature = "redis-backend"))]
+use crate::metrics::timed;
+
+type SharedState = Arc<AppState>;
+
+pub const BACKOFF_MIN_SECS: u64 = 1;
+pub const BACKOFF_MAX_SECS: u64 = 60;
+/// How often a connected backend is probed, and how long a probe or connect may take
+pub const PROBE_INTERVAL_SECS: u64 = 10;
+pub const PROBE_TIMEOUT_SECS: u64 = 5;
+
+fn next_backoff(current: Duration) -> Duration {
+    (current * 2).min(Duration::from_secs(BACKOFF_MAX_SECS))
+}
+
+/// Record which backends will be supervised (for readiness), warning about URLs this build
+/// can't use.
+pub async fn register_backends(state: &SharedState, db_url: Option<&str>, redis_url: Option<&str>) {
+    let mut backends = state.backends.lock().await;
+    if db_url.is_some() {
+        if cfg!(feature = "postgres") {
+            backends.postgres_health = crate::state::backends::BackendHealth::configured();
+        } else {
+            warn!("db_url is set but this build has no postgres feature; ignoring it.");
+        }
+    }
+    if redis_url.is_some() {
+        if cfg!(feature = "redis-backend") {
+            backends.redis_health = crate::state::backends::BackendHealth::configured();
+        } else {
+            warn!("redis_url is set but this build has no redis-backend feature; ignoring it.");
+        }
+    }
+}
+
+// ============================================
+// Postgres
+// ============================================
+
+#[cfg(feature = "postgres")]
+async fn connect_postgres(url: &str, max_connections: u32) -> Result<sqlx::PgPool, String> {
+    let pool = sqlx::postgres::PgPoolOptions::new()
+        .max_connections(max_connections)
+        .acquire_timeout(Duration::from_secs(PROBE_TIMEOUT_SECS))
+        .connect(url)
+        .await
+        .map_err(|e| format!("connect: {}", e))?;
+    match crate::handlers::migrations::migrate(&pool).await {
+        Ok(version) => info!("Postgres schema at version {}.", version),
+        // Running on a schema this binary doesn't understand would corrupt data; stop instead
+        Err(e @ crate::handlers::migrations::MigrateError::SchemaTooNew { .. }) => {
+            log::error!("Refusing to start: {}", e);
+            std::process::exit(1);
+        }
+        Err(e) => return Err(e.to_string()),
+    }
+    Ok(pool)
+}
+
+#[cfg(feature = "postgres")]
+pub async fn supervise_postgres(state: SharedState, url: String, max_connections: u32) {
+    let mut backoff = Duration::from_secs(BACKOFF_MIN_SECS);
+    let mut installed: Option<sqlx::PgPool> = None;
+    loop {
+        match installed.clone() {
+            Some(pool) => {
+                tokio::time::sleep(Duration::from_secs(PROBE_INTERVAL_SECS)).await;
+                let probe = async {
+                    match tokio::time::timeout(Duration::from_secs(PROBE_TIMEOUT_SECS), sqlx::query("SELECT 1").execute(&pool)).await {
+                        Ok(Ok(_)) => Ok(()),
+                        Ok(Err(e)) => Err(e.to_string()),
+                        Err(_) => Err("probe timed out".to_string()),
+                    }
+                };
+                let Err(error) = timed("postgres", "probe", probe).await else {
+                    continue;
+                };
+                warn!("Postgres unreachable, falling back to in-memory state: {}", error);
+                installed = None;
+                let mut backends = state.backends.lock().await;
+                backends.store = None;
+                backends.postgres_health.mark_down(error);
+                backoff = Duration::from_secs(BACKOFF_MIN_SECS);
+            }
+            None => match timed("postgres", "connect", connect_postgres(&url, max_connections)).await {
+                Ok(pool) => {
+                    {
+                        let mut backends = state.backends.lock().await;
+                        backends.store = Some(Arc::new(crate::store::PostgresStore::new(pool.clone())));
+                        backends.postgres_health.mark_up();
+                    }
+                    installed = Some(pool.clone());
+                    info!("Postgres connected.");
+                    resync_postgres(&state, &pool).await;
+                    backoff = Duration::from_secs(BACKOFF_MIN_SECS);
+                }
+                Err(e) => {
+                    warn!("Postgres unavailable (retrying in {}s): {}", backoff.as_secs(), e);
+                    state.backends.lock().await.postgres_health.mark_down(e);
+                    tokio::time::sleep(backoff).await;
+                    backoff = next_backoff(backoff);
+                }
+            },
+        }
+    }
+}
+
+/// Write everything accepted in memory while Postgres was away. Profiles are always cached in
+/// memory and are simply upserted (older revs lose); the rest is removed from memory once
+/// stored so Postgres stays the single source of truth.
+///
+/// First the in-memory seq counters are raised to Postgres's, so a later outage numbers its
+/// events after the ones already stored.
+#[cfg(feature = "postgres")]
+async fn resync_postgres(state: &SharedState, pool: &sqlx::PgPool) {
+    use crate::handlers::db;
+
+    match timed("postgres", "load_event_seqs", db::load_event_seqs_db(pool)).await {
+        Ok(seqs) => {
+            let mut events = state.events.lock().await;
+            for (spk, seq) in seqs {
+                events.note_event_seq(&spk, seq);
+            }
+        }
+        Err(e) => warn!("Postgres resync: could not load event seqs: {}", e),
+    }
+
+    let profiles: Vec<_> = {
+        let profiles = state.profiles.lock().await;
+        profiles.profiles.iter().map(|(user_id, rec)| (user_id.clone(), rec.clone())).collect()
+    };
+    let (signers, rotations, hints, invites, events) = {
+        let events = state.events.lock().await;
+        let signers: Vec<(String, String)> = events
+            .house_signers
+            .iter()
+            .flat_map(|(spk, keys)| keys.iter().map(move |key| (spk.clone(), key.clone())))
+            .collect();
+        let rotations: Vec<_> = events.key_rotations.iter().map(|(spk, (req, _))| (spk.clone(), req.clone())).collect();
+        let hints: Vec<_> = events.server_hints.values().cloned().collect();
+        let invites: Vec<_> = events.invite_tokens.values().cloned().collect();
+        let queued: Vec<_> = events.event_queues.values().flatten().cloned().collect();
+        (signers, rotations, hints, invites, queued)
+    };
+
+    let mut failed = 0usize;
+    for (user_id, rec) in &profiles {
+        if timed("postgres", "resync_profile", db::upsert_profile_db(pool, user_id, rec)).await.is_err() {
+            failed += 1;
+        }
+    }
+
+    // Signers before hints: hint verification in Postgres reads them
+    let mut stored_signers = Vec::new();
+    for (spk, key) in signers {
+        match timed("postgres", "resync_signer", db::authorize_member_signer_db(pool, &spk, &key)).await {
+            Ok(()) => stored_signers.push((spk, key)),
+            Err(_) => failed += 1,
+        }
+    }
+    let mut stored_rotations = Vec::new();
+    for (spk, req) in rotations {
+        match timed("postgres", "resync_key_rotation", db::put_key_rotation_db(pool, &spk, &req)).await {
+            Err(crate::KeyRotationError::Storage(_)) => failed += 1,
+            // Stale epochs lost to a newer rotation in Postgres; either way memory is done with it
+            _ => stored_rotations.push(spk),
+        }
+    }
+    let mut stored_hints = Vec::new();
+    for hint in hints {
+        match timed("postgres", "resync_server_hint", db::upsert_server_hint_db(pool, &hint)).await {
+            Err(crate::HintError::Storage(_)) => failed += 1,
+            // Another instance wrote to Postgres meanwhile and the in-memory hint lost. Tell the
+            // house, so members that still hold the lost changes merge the stored hint and republish.
+            Err(crate::HintError::Conflict(current)) => {
+                warn!(
+                    "Postgres resync: hint for {} (revision {}) conflicts with stored revision {}; notifying the house",
+                    hint.signing_pubkey, hint.revision, current.revision
+                );
+                state.broadcast_server_hint_updated(&hint.signing_pubkey, &current).await;
+                stored_hints.push(hint.signing_pubkey);
+            }
+            _ => stored_hints.push(hint.signing_pubkey),
+        }
+    }
+    let mut stored_invites = Vec::new();
+    for rec in invites {
+        match timed("postgres", "resync_invite", db::restore_invite_db(pool, &rec)).await {
+            Ok(()) => stored_invites.push(rec.code),
+            Err(_) => failed += 1,
+        }
+    }
+    let mut stored_events = std::collections::HashSet::new();
+    let mut restored_seqs = Vec::new();
+    for event in events {
+        match timed("postgres", "resync_event", db::restore_event_db(pool, &event)).await {
+            Ok(stored) => {
+                if stored.seq != event.seq {
+                    warn!(
+                        "Postgres resync: event {} of {} stored as seq {} instead of {}",
+                        event.event_id, event.signing_pubkey, stored.seq, event.seq
+                    );
+                }
+                restored_seqs.push((stored.signing_pubkey, stored.seq));
+                stored_events.insert(event.event_id);
+            }
+            Err(_) => failed += 1,
+        }
+    }
+
+    let moved = stored_signers.len() + stored_rotations.len() + stored_hints.len() + stored_invites.len() + stored_events.len();
+    {
+        let mut events = state.events.lock().await;
+        for (spk, key) in &stored_signers {
+            if let Some(keys) = events.house_signers.get_mut(spk) {
+                keys.remove(key);
+            }
+        }
+        events.house_signers.retain(|_, keys| !keys.is_empty());
+        for spk in &stored_rotations {
+            events.key_rotations.remove(spk);
+        }
+        for spk in &stored_hints {
+            events.server_hints.remove(spk);
+        }
+        for code in &stored_invites {
+            events.invite_tokens.remove(code);
+        }
+        // Seqs stay in memory so a later outage doesn't reuse them
+        for (spk, seq) in &restored_seqs {
+            events.note_event_seq(spk, *seq);
+        }
+        for queue in events.event_queues.values_mut() {
+            queue.retain(|e| !stored_events.contains(&e.event_id));
+        }
+        events.event_queues.retain(|_, queue| !queue.is_empty());
+    }
+
+    if moved > 0 || failed > 0 {
+        info!(
+            "Postgres resync: {} profiles, {} in-memory records moved, {} failed",
+            profiles.len(),
+            moved,
+            failed
+        );
+    }
+}
+
+// ============================================
+// Redis
+// ============================================
+
+#[cfg(feature = "redis-backend")]
+async fn connect_redis(client: &redis::Client) -> Result<redis::aio::ConnectionManager, String> {
+    let connect = async {
+        let mut conn = redis::aio::ConnectionManager::new(client.clone()).await?;
+        redis::cmd("PING").query_async::<_, String>(&mut conn).await?;
+        Ok::<_, redis::RedisError>(conn)
+    };
+    match tokio::time::timeout(Duration::from_secs(PROBE_TIMEOUT_SECS), connect).await {
+        Ok(result) => result.map_err(|e| e.to_string()),
+        Err(_) => Err("connect timed out".to_string()),
+    }
+}
+
+#[cfg(feature = "redis-backend")]
+pub async fn supervise_redis(state: SharedState, url: String, ttl_secs: u64) {
+    let client = match redis::Client::open(url.as_str()) {
+        Ok(client) => client,
+        Err(e) => {
+            warn!("Invalid Redis URL; running without Redis: {}", e);
+            state.backends.lock().await.redis_health.mark_down(format!("invalid URL: {}", e));
+            return;
+        }
+    };
+
+    let mut subscriber_started = false;
+    let mut backoff = Duration::from_secs(BACKOFF_MIN_SECS);
+    loop {
+        let installed = {
+            let backends = state.backends.lock().await;
+            backends.redis_conn.clone()
+        };
+        match installed {
+            Some(mut conn) => {
+                tokio::time::sleep(Duration::from_secs(PROBE_INTERVAL_SECS)).await;
+                let probe = async {
+                    let ping = redis::cmd("PING");
+                    match tokio::time::timeout(Duration::from_secs(PROBE_TIMEOUT_SECS), ping.query_async::<_, String>(&mut conn)).await {
+                        Ok(Ok(_)) => Ok(()),
+                        Ok(Err(e)) => Err(e.to_string()),
+                        Err(_) => Err("probe timed out".to_string()),
+                    }
+                };
+                let Err(error) = timed("redis", "probe", probe).await else {
+                    continue;
+                };
+                warn!("Redis unreachable, falling back to in-memory state: {}", error);
+                let mut backends = state.backends.lock().await;
+                backends.redis = None;
+                backends.redis_conn = None;
+                backends.redis_health.mark_down(error);
+                backoff = Duration::from_secs(BACKOFF_MIN_SECS);
+            }
+            None => match timed("redis", "connect", connect_redis(&client)).await {
+                Ok(conn) => {
+                    {
+                        let mut backends = state.backends.lock().await;
+                        backends.redis = Some(client.clone());
+                        backends.redis_conn = Some(conn.clone());
+                        backends.redis_presence_ttl_secs = ttl_secs;
+                        backends.redis_health.mark_up();
+                    }
+                    info!("Redis connected (presence and cross-instance fan-out, instance {}).", state.instance_id);
+                    if !subscriber_started {
+                        // Reconnects on its own from here on
+                        tokio::spawn(crate::cluster::run_subscriber(client.clone(), state.clone()));
+                        subscriber_started = true;
+                    }
+                    resync_redis(&state, &client, &conn, ttl_secs).await;
+                    backoff = Duration::from_secs(BACKOFF_MIN_SECS);
+                }
+                Err(e) => {
+                    warn!("Redis unavailable (retrying in {}s): {}", backoff.as_secs(), e);
+                    state.backends.lock().await.redis_health.mark_down(e);
+                    tokio::time::sleep(backoff).await;
+                    backoff = next_backoff(backoff);
+                }
+            },
+        }
+    }
+}
+
+/// Push this instance's live presence, peer routes and liveness key to Redis (run
+/// periodically so TTLs don't lapse).
+#[cfg(feature = "redis-backend")]
+pub async fn refresh_redis(state: &SharedState, client: &redis::Client, conn: &redis::aio::ConnectionManager, ttl_secs: u64) {
+    use crate::handlers::redis::{redis_cluster_heartbeat, redis_presence_refresh};
+
+    let users = {
+        let presence = state.presence.lock().await;
+        presence
+            .presence_users
+            .iter()
+            .map(|(user_id, u)| {
+                (
+                    user_id.clone(),
+                    u.signing_pubkeys.iter().cloned().collect::<Vec<_>>(),
+                    u.active_signing_pubkey.clone(),
+                )
+            })
+            .collect::<Vec<_>>()
+    };
+    let routes = {
+        let signaling = state.signaling.lock().await;
+        signaling.peer_routes()
+    };
+
+    if let Err(e) = timed("redis", "presence_refresh", redis_presence_refresh(client, ttl_secs, &users)).await {
+        warn!("Redis presence refresh failed: {}", e);
+    }
+    // Keep this instance marked alive and its peers' routes pointing here
+    let heartbeat = redis_cluster_heartbeat(conn, &state.instance_id, ttl_secs, &routes);
+    if let Err(e) = timed("redis", "cluster_heartbeat", heartbeat).await {
+        warn!("Redis cluster heartbeat failed: {}", e);
+    }
+}
+
+/// After a (re)connect Redis may have lost everything: restore presence, routes and this
+/// instance's voice room members.
+#[cfg(feature = "redis-backend")]
+async fn resync_redis(state: &SharedState, client: &redis::Client, conn: &redis::aio::ConnectionManager, ttl_secs: u64) {
+    refresh_redis(state, client, conn, ttl_secs).await;
+
+    let members: Vec<_> = {
+        let voice = state.voice.lock().await;
+        voice
+            .voice_chats
+            .iter()
+            .flat_map(|((server_id, chat_id), peers)| {
+                peers.iter().map(move |p| (server_id.clone(), chat_id.clone(), p.peer_id.clone(), p.user_id.clone()))
+            })
+            .collect()
+    };
+    let restore = crate::handlers::redis::redis_voice_restore(conn, &state.instance_id, &members);
+    if let Err(e) = timed("redis", "voice_restore", restore).await {
+        warn!("Redis voice membership restore failed: {}", e);
+    }
+}
@@ -0,0 +1,365 @@
+//! Replicated house state.
+//!
+//! Every member keeps a full copy of the house (name, invite, members, chats) and any
+//! member may publish it in the encrypted hint. Each part is a last-writer-wins register
+//! or a set with tombstones, so merging copies gives the same result in any order, and a
+//! stale copy cannot bring back a removed member or a deleted chat.
+
+use std::collections::BTreeMap;
+
+use chrono::{DateTime, Utc};
+use serde::{Deserialize, Serialize};
+use uuid::Uuid;
+
+use crate::server::{Chat, ServerMember};
+
+/// Position of a write in the total order used to resolve conflicts.
+/// Compared by time first; `id` is unique per write and breaks ties. Snapshot entries all
+/// share the zero stamp, so between those the value itself decides (see `newer`).
+#[derive(Serialize, Deserialize, Clone, Debug, Default, PartialEq, Eq, PartialOrd, Ord)]
+pub struct Stamp {
+    pub millis: i64,
+    pub id: String,
+}
+
+/// Single value where the latest write wins
+#[derive(Serialize, Deserialize, Clone, Debug)]
+pub struct Register<T> {
+    pub value: T,
+    pub stamp: Stamp,
+}
+
+impl<T: Clone + Serialize> Register<T> {
+    fn merge(&mut self, other: &Register<T>) {
+        if newer(&other.stamp, &other.value, &self.stamp, &self.value) {
+            *self = other.clone();
+        }
+    }
+}
+
+/// Whether write `a` wins over write `b`: the later stamp, or for equal stamps (two snapshots)
+/// the greater serialized value, so every copy picks the same one.
+fn newer<T: Serialize>(a_stamp: &Stamp, a: &T, b_stamp: &Stamp, b: &T) -> bool {
+    match a_stamp.cmp(b_stamp) {
+        std::cmp::Ordering::Equal => serde_json::to_vec(a).ok() > serde_json::to_vec(b).ok(),
+        ordering => ordering == std::cmp::Ordering::Greater,
+    }
+}
+
+/// Set element with its latest value and, once removed, a tombstone.
+/// It is present while its last write is newer than its removal.
+#[derive(Serialize, Deserialize, Clone, Debug)]
+pub struct SetEntry<T> {
+    pub value: T,
+    pub created: Stamp,
+    pub updated: Stamp,
+    #[serde(default)]
+    pub removed: Option<Stamp>,
+}
+
+impl<T: Clone + Serialize> SetEntry<T> {
+    fn is_live(&self) -> bool {
+        match &self.removed {
+            Some(removed) => self.updated > *removed,
+            None => true,
+        }
+    }
+
+    fn stamps(&self) -> impl Iterator<Item = &Stamp> {
+        std::iter::once(&self.updated).chain(self.removed.as_ref())
+    }
+
+    fn merge(&mut self, other: &SetEntry<T>) {
+        if newer(&other.updated, &other.value, &self.updated, &self.value) {
+            self.value = other.value.clone();
+            self.updated = other.updated.clone();
+        }
+        if other.created < self.created {
+            self.created = other.created.clone();
+        }
+        if other.removed > self.removed {
+            self.removed = other.removed.clone();
+        }
+    }
+}
+
+#[derive(Serialize, Deserialize, Clone, Debug, Default, PartialEq)]
+pub struct InviteState {
+    pub uri: Option<String>,
+    pub expires_at: Option<DateTime<Utc>>,
+}
+
+#[derive(Serialize, Deserialize, Clone, Debug, Default)]
+pub struct HouseState {
+    #[serde(default)]
+    pub name: Option<Register<String>>,
+    #[serde(default)]
+    pub invite: Option<Register<InviteState>>,
+    #[serde(default)]
+    pub members: BTreeMap<String, SetEntry<ServerMember>>,
+    #[serde(default)]
+    pub chats: BTreeMap<String, SetEntry<Chat>>,
+}
+
+impl HouseState {
+    /// State for a plain snapshot (older clients and files written before this existed).
+    /// Every write gets the zero stamp, so any real write or tombstone wins over it.
+    pub fn from_snapshot(
+        name: &str,
+        members: &[ServerMember],
+        chats: &[Chat],
+        invite: InviteState,
+    ) -> Self {
+        HouseState {
+            name: (!name.is_empty()).then(|| Register { value: name.to_string(), stamp: Stamp::default() }),
+            invite: Some(Register { value: invite, stamp: Stamp::default() }),
+            members: members.iter().map(|m| (m.user_id.clone(), snapshot_entry(m.clone()))).collect(),
+            chats: chats.iter().map(|c| (c.id.clone(), snapshot_entry(c.clone()))).collect(),
+        }
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.name.is_none() && self.invite.is_none() && self.members.is_empty() && self.chats.is_empty()
+    }
+
+    /// Merge another copy into this one (commutative, associative and idempotent)
+    pub fn merge(&mut self, other: &HouseState) {
+        merge_register(&mut self.name, &other.name);
+        merge_register(&mut self.invite, &other.invite);
+        merge_set(&mut self.members, &other.members);
+        merge_set(&mut self.chats, &other.chats);
+    }
+
+    /// Stamp for a local write: after every write already seen, so it wins over them
+    fn next_stamp(&self) -> Stamp {
+        let latest = self.stamps().map(|s| s.millis).max().unwrap_or(0);
+        Stamp {
+            millis: Utc::now().timestamp_millis().max(latest + 1),
+            id: Uuid::new_v4().to_string(),
+        }
+    }
+
+    fn stamps(&self) -> impl Iterator<Item = &Stamp> {
+        self.name.iter().map(|r| &r.stamp)
+            .chain(self.invite.iter().map(|r| &r.stamp))
+            .chain(self.members.values().flat_map(SetEntry::stamps))
+            .chain(self.chats.values().flat_map(SetEntry::stamps))
+    }
+
+    pub fn set_name(&mut self, name: String) {
+        let stamp = self.next_stamp();
+        self.name = Some(Register { value: name, stamp });
+    }
+
+    pub fn set_invite(&mut self, invite: InviteState) {
+        let stamp = self.next_stamp();
+        self.invite = Some(Register { value: invite, stamp });
+    }
+
+    /// Add a member, or replace an existing member's details
+    pub fn put_member(&mut self, member: ServerMember) {
+        let stamp = self.next_stamp();
+        put_entry(&mut self.members, member.user_id.clone(), member, stamp);
+    }
+
+    pub fn remove_member(&mut self, user_id: &str) -> bool {
+        let stamp = self.next_stamp();
+        remove_entry(&mut self.members, user_id, stamp)
+    }
+
+    /// Add a chat, or replace an existing chat's details
+    pub fn put_chat(&mut self, chat: Chat) {
+        let stamp = self.next_stamp();
+        put_entry(&mut self.chats, chat.id.clone(), chat, stamp);
+    }
+
+    pub fn remove_chat(&mut self, chat_id: &str) -> bool {
+        let stamp = self.next_stamp();
+        remove_entry(&mut self.chats, chat_id, stamp)
+    }
+
+    pub fn name(&self) -> Option<&str> {
+        self.name.as_ref().map(|r| r.value.as_str())
+    }
+
+    pub fn invite(&self) -> InviteState {
+        self.invite.as_ref().map(|r| r.value.clone()).unwrap_or_default()
+    }
+
+    /// Current members, oldest first
+    pub fn members(&self) -> Vec<ServerMember> {
+        live_values(&self.members)
+    }
+
+    /// Current chats, oldest first
+    pub fn chats(&self) -> Vec<Chat> {
+        live_values(&self.chats)
+    }
+}
+
+fn snapshot_entry<T>(value: T) -> SetEntry<T> {
+    SetEntry {
+        value,
+        created: Stamp::default(),
+        updated: Stamp::default(),
+        removed: None,
+    }
+}
+
+fn merge_register<T: Clone + Serialize>(ours: &mut Option<Register<T>>, theirs: &Option<Register<T>>) {
+    if let Some(theirs) = theirs {
+        match ours {
+            Some(ours) => ours.merge(theirs),
+            None => *ours = Some(theirs.clone()),
+        }
+    }
+}
+
+fn merge_set<T: Clone + Serialize>(ours: &mut BTreeMap<String, SetEntry<T>>, theirs: &BTreeMap<String, SetEntry<T>>) {
+    for (key, entry) in theirs {
+        match ours.get_mut(key) {
+            Some(existing) => existing.merge(entry),
+            None => {
+                ours.insert(key.clone(), entry.clone());
+            }
+        }
+    }
+}
+
+fn put_entry<T>(set: &mut BTreeMap<String, SetEntry<T>>, key: String, value: T, stamp: Stamp) {
+    match set.get_mut(&key) {
+        Some(existing) => {
+            existing.value = value;
+            existing.updated = stamp;
+        }
+        None => {
+            set.insert(key, SetEntry {
+                value,
+                created: stamp.clone(),
+                updated: stamp,
+                removed: None,
+            });
+        }
+    }
+}
+
+fn remove_entry<T: Clone + Serialize>(set: &mut BTreeMap<String, SetEntry<T>>, key: &str, stamp: Stamp) -> bool {
+    match set.get_mut(key) {
+        Some(existing) if existing.is_live() => {
+            existing.removed = Some(stamp);
+            true
+        }
+        _ => false,
+    }
+}
+
+fn live_values<T: Clone + Serialize>(set: &BTreeMap<String, SetEntry<T>>) -> Vec<T> {
+    let mut live: Vec<_> = set.iter().filter(|(_, e)| e.is_live()).collect();
+    live.sort_by(|(ka, a), (kb, b)| a.created.cmp(&b.created).then_with(|| ka.cmp(kb)));
+    live.into_iter().map(|(_, e)| e.value.clone()).collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn member(user_id: &str) -> ServerMember {
+        ServerMember {
+            user_id: user_id.to_string(),
+            display_name: user_id.to_string(),
+            joined_at: Utc::now(),
+            x25519_pubkey: None,
+        }
+    }
+
+    fn chat(id: &str) -> Chat {
+        Chat { id: id.to_string(), name: id.to_string(), description: None }
+    }
+
+    fn ids(state: &HouseState) -> (Vec<String>, Vec<String>) {
+        (
+            state.members().into_iter().map(|m| m.user_id).collect(),
+            state.chats().into_iter().map(|c| c.id).collect(),
+        )
+    }
+
+    #[test]
+    fn stale_copy_does_not_resurrect_removed_entries() {
+        let mut owner = HouseState::default();
+        owner.set_name("house".to_string());
+        owner.put_member(member("alice"));
+        owner.put_member(member("bob"));
+        owner.put_chat(chat("general"));
+        owner.put_chat(chat("random"));
+
+        let stale = owner.clone();
+        owner.remove_member("bob");
+        owner.remove_chat("random");
+
+        owner.merge(&stale);
+        assert_eq!(ids(&owner), (vec!["alice".to_string()], vec!["general".to_string()]));
+
+        // A legacy snapshot listing bob again loses to the tombstone too
+        let legacy = HouseState::from_snapshot("old", &[member("bob")], &[chat("random")], InviteState::default());
+        owner.merge(&legacy);
+        assert_eq!(ids(&owner), (vec!["alice".to_string()], vec!["general".to_string()]));
+        assert_eq!(owner.name(), Some("house"));
+    }
+
+    #[test]
+    fn merge_order_does_not_matter() {
+        let mut base = HouseState::default();
+        base.put_member(member("alice"));
+        base.put_chat(chat("general"));
+
+        let mut a = base.clone();
+        a.put_member(member("carol"));
+        a.remove_chat("general");
+        a.set_invite(InviteState { uri: Some("cordia://X@beacon".to_string()), expires_at: None });
+
+        let mut b = base.clone();
+        b.put_chat(chat("voice"));
+        b.remove_member("alice");
+        b.set_name("renamed".to_string());
+
+        let mut ab = a.clone();
+        ab.merge(&b);
+        let mut ba = b.clone();
+        ba.merge(&a);
+        let mut aab = a.clone();
+        aab.merge(&a);
+        aab.merge(&b);
+        aab.merge(&b);
+
+        for merged in [&ab, &ba, &aab] {
+            assert_eq!(ids(merged), (vec!["carol".to_string()], vec!["voice".to_string()]));
+            assert_eq!(merged.name(), Some("renamed"));
+            assert_eq!(merged.invite().uri.as_deref(), Some("cordia://X@beacon"));
+        }
+
+        // Re-adding after a removal is a newer write, so it wins
+        ab.put_member(member("alice"));
+        ab.merge(&b);
+        assert!(ab.members().iter().any(|m| m.user_id == "alice"));
+    }
+
+    #[test]
+    fn differing_snapshots_converge() {
+        let mut alice = member("alice");
+        alice.display_name = "Alice".to_string();
+        let a = HouseState::from_snapshot("house", &[alice], &[chat("general")], InviteState::default());
+        let invite = InviteState { uri: Some("cordia://X@beacon".to_string()), expires_at: None };
+        let b = HouseState::from_snapshot("renamed", &[member("alice")], &[chat("general")], invite);
+
+        let mut ab = a.clone();
+        ab.merge(&b);
+        let mut ba = b.clone();
+        ba.merge(&a);
+
+        let encode = |state: &HouseState| serde_json::to_string(state).unwrap();
+        assert_eq!(encode(&ab), encode(&ba));
+        assert_eq!(ab.members()[0].display_name, ba.members()[0].display_name);
+        assert_eq!(ab.name(), ba.name());
+        assert_eq!(ab.invite(), ba.invite());
+    }
+}
@@ -1,16 +1,17 @@
-use ed25519_dalek::{SigningKey, VerifyingKey};
+use ed25519_dalek::{Signer, SigningKey, VerifyingKey};
 use sha2::{Sha256, Digest};
 use aes_gcm::{
     aead::{Aead, AeadCore, KeyInit, OsRng},
     Aes256Gcm, Nonce,
 };
-use chacha20poly1305::XChaCha20Poly1305;
+use chacha20poly1305::{aead::Payload, XChaCha20Poly1305};
 use serde::{Deserialize, Serialize};
 use std::path::PathBuf;
 use std::fs;
 use thiserror::Error;
 
 use crate::account_manager::AccountManager;
+use crate::vault;
 
 #[derive(Error, Debug)]
 pub enum IdentityError {
@@ -28,6 +29,58 @@ pub enum IdentityError {
     HexDecode(String),
     #[error("Account error: {0}")]
     Account(String),
+    #[error("Identity was saved without its private key and must be upgraded to a new key before it can sign")]
+    MissingPrivateKey,
+    #[error("This .key file is protected with a passphrase")]
+    PassphraseRequired,
+    #[error("Passphrase must be at least {0} characters")]
+    WeakPassphrase(usize),
+    #[error("Account is locked - unlock it with its passphrase")]
+    Locked,
+}
+
+// .key container ("CORD"): 16-byte header, then a version-specific payload.
+// Header: magic (4) | version u16 | flags u16 | payload size u32 | checksum u32
+const KEY_FILE_MAGIC: &[u8; 4] = b"CORD";
+const KEY_FILE_HEADER_LEN: usize = 16;
+/// v1: encrypted with the device key (not portable), byte-sum header checksum
+const KEY_FILE_V1: u16 = 1;
+/// v2: encrypted with a passphrase via Argon2id, CRC32 header checksum, header bound as AAD
+const KEY_FILE_V2: u16 = 2;
+
+/// Argon2id cost for new passphrase-protected files.
+/// Stored alongside the salt so it can be raised without breaking old files.
+pub(crate) const PASSPHRASE_KDF_M_COST_KIB: u32 = 64 * 1024;
+pub(crate) const PASSPHRASE_KDF_T_COST: u32 = 3;
+pub(crate) const PASSPHRASE_KDF_P_COST: u32 = 1;
+/// Highest costs accepted from a file. The stored costs come from whoever wrote the file, so
+/// without a cap a crafted .key or vault config could make us allocate gigabytes or spin for hours.
+const PASSPHRASE_KDF_MAX_M_COST_KIB: u32 = 1024 * 1024;
+const PASSPHRASE_KDF_MAX_T_COST: u32 = 16;
+const PASSPHRASE_KDF_MAX_P_COST: u32 = 8;
+pub(crate) const MIN_PASSPHRASE_LEN: usize = 8;
+
+/// Derive a 32-byte key from a user passphrase with Argon2id.
+pub(crate) fn derive_key_from_passphrase(
+    passphrase: &str,
+    salt: &[u8],
+    m_cost_kib: u32,
+    t_cost: u32,
+    p_cost: u32,
+) -> Result<[u8; 32], IdentityError> {
+    if m_cost_kib > PASSPHRASE_KDF_MAX_M_COST_KIB || t_cost > PASSPHRASE_KDF_MAX_T_COST || p_cost > PASSPHRASE_KDF_MAX_P_COST {
+        return Err(IdentityError::Decryption(format!(
+            "KDF parameters exceed the supported maximum (m={} KiB, t={}, p={})",
+            m_cost_kib, t_cost, p_cost
+        )));
+    }
+    let params = argon2::Params::new(m_cost_kib, t_cost, p_cost, Some(32))
+        .map_err(|e| IdentityError::Decryption(format!("Invalid KDF parameters: {}", e)))?;
+    let kdf = argon2::Argon2::new(argon2::Algorithm::Argon2id, argon2::Version::V0x13, params);
+    let mut key = [0u8; 32];
+    kdf.hash_password_into(passphrase.as_bytes(), salt, &mut key)
+        .map_err(|e| IdentityError::Encryption(format!("Key derivation failed: {}", e)))?;
+    Ok(key)
 }
 
 #[derive(Serialize, Deserialize, Clone)]
@@ -37,6 +90,47 @@ pub struct UserIdentity {
     pub public_key: String,     // Hex-encoded public key
     #[serde(skip_serializing)]
     pub private_key: Option<String>, // Hex-encoded private key (only in memory)
+    /// user_id this identity had before `upgrade_legacy_identity` gave it a new key
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub previous_user_id: Option<String>,
+}
+
+/// On-disk and export form of a UserIdentity.
+/// UserIdentity never serializes its private key (it is returned to the frontend),
+/// so anything that must round-trip the key goes through this struct instead.
+#[derive(Serialize, Deserialize)]
+struct StoredIdentity {
+    user_id: String,
+    display_name: String,
+    public_key: String,
+    #[serde(default)]
+    private_key: Option<String>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    previous_user_id: Option<String>,
+}
+
+impl From<&UserIdentity> for StoredIdentity {
+    fn from(identity: &UserIdentity) -> Self {
+        Self {
+            user_id: identity.user_id.clone(),
+            display_name: identity.display_name.clone(),
+            public_key: identity.public_key.clone(),
+            private_key: identity.private_key.clone(),
+            previous_user_id: identity.previous_user_id.clone(),
+        }
+    }
+}
+
+impl From<StoredIdentity> for UserIdentity {
+    fn from(stored: StoredIdentity) -> Self {
+        Self {
+            user_id: stored.user_id,
+            display_name: stored.display_name,
+            public_key: stored.public_key,
+            private_key: stored.private_key,
+            previous_user_id: stored.previous_user_id,
+        }
+    }
 }
 
 #[derive(Serialize, Deserialize)]
@@ -44,11 +138,18 @@ struct EncryptedIdentity {
     nonce: String,
     ciphertext: String,
     salt: String,
+    /// Key also mixes in the account's vault passphrase (see vault.rs)
+    #[serde(default)]
+    passphrase_wrapped: bool,
 }
 
 pub struct IdentityManager {
     data_dir: PathBuf,
     account_id: Option<String>,
+    /// Vault sub-key when the account is unlocked
+    wrap_key: Option<[u8; 32]>,
+    /// Passphrase protection is on: refuse to save without `wrap_key`
+    vault_enabled: bool,
 }
 
 impl IdentityManager {
@@ -68,6 +169,8 @@ impl IdentityManager {
             fs::create_dir_all(&data_dir)?;
             Ok(Self {
                 data_dir,
+                wrap_key: vault::wrapping_key(&account_id, vault::IDENTITY_KEY_PURPOSE),
+                vault_enabled: vault::is_enabled(&account_id),
                 account_id: Some(account_id),
             })
         } else {
@@ -89,9 +192,29 @@ impl IdentityManager {
         Ok(Self {
             data_dir,
             account_id: Some(account_id.to_string()),
+            wrap_key: vault::wrapping_key(account_id, vault::IDENTITY_KEY_PURPOSE),
+            vault_enabled: vault::is_enabled(account_id),
         })
     }
 
+    /// Override the vault key (used by vault migration to read and write with different keys)
+    pub(crate) fn with_wrap_key(mut self, wrap_key: Option<[u8; 32]>) -> Self {
+        self.vault_enabled = wrap_key.is_some();
+        self.wrap_key = wrap_key;
+        self
+    }
+
+    /// Storage key for keys.dat: device-derived, plus the vault key when wrapped
+    fn storage_key(&self, salt: &[u8], wrapped: bool) -> Result<[u8; 32], IdentityError> {
+        let device_key = Self::get_device_key()?;
+        let key = Self::derive_key_from_device(&device_key, salt)?;
+        if !wrapped {
+            return Ok(key);
+        }
+        let wrap_key = self.wrap_key.as_ref().ok_or(IdentityError::Locked)?;
+        Ok(vault::mix_keys(&key, wrap_key))
+    }
+
     /// Check if a specific account has an identity (keys.dat exists)
     /// This does NOT require an active session - used for account listing/selection
     pub fn account_has_identity(account_id: &str) -> Result<bool, IdentityError> {
@@ -161,6 +284,16 @@ impl IdentityManager {
         self.get_keys_path().exists()
     }
 
+    /// Whether keys.dat is wrapped with the vault key (None without an identity)
+    pub(crate) fn identity_is_wrapped(&self) -> Result<Option<bool>, IdentityError> {
+        if !self.has_identity() {
+            return Ok(None);
+        }
+        let encrypted: EncryptedIdentity = serde_json::from_str(&fs::read_to_string(self.get_keys_path())?)
+            .map_err(|_| IdentityError::InvalidIdentity)?;
+        Ok(Some(encrypted.passphrase_wrapped))
+    }
+
     /// Get the account ID if in account mode
     pub fn get_account_id(&self) -> Option<&str> {
         self.account_id.as_deref()
@@ -172,6 +305,14 @@ impl IdentityManager {
         (signing_key, verifying_key)
     }
 
+    /// User ID from the public key hash (first 16 bytes as hex = 32 chars)
+    fn user_id_for_key(verifying_key: &VerifyingKey) -> String {
+        let mut hasher = Sha256::new();
+        hasher.update(verifying_key.as_bytes());
+        let hash = hasher.finalize();
+        hex::encode(&hash[..16])
+    }
+
     pub fn create_identity(display_name: String) -> Result<UserIdentity, IdentityError> {
         // Validate display name
         if display_name.trim().is_empty() {
@@ -184,17 +325,14 @@ impl IdentityManager {
         let private_key_hex = hex::encode(signing_key.to_bytes());
         let public_key_hex = hex::encode(verifying_key.to_bytes());
 
-        // Generate user ID from public key hash (first 16 bytes as hex = 32 chars)
-        let mut hasher = Sha256::new();
-        hasher.update(verifying_key.as_bytes());
-        let hash = hasher.finalize();
-        let user_id = hex::encode(&hash[..16]); // Use first 16 bytes for shorter ID
+        let user_id = Self::user_id_for_key(&verifying_key);
 
         let identity = UserIdentity {
             user_id: user_id.clone(),
             display_name: display_name.trim().to_string(),
             public_key: public_key_hex,
             private_key: Some(private_key_hex),
+            previous_user_id: None,
         };
 
         // Create account container and set session
@@ -224,11 +362,10 @@ impl IdentityManager {
         let encrypted: EncryptedIdentity = serde_json::from_str(&encrypted_data)
             .map_err(|_| IdentityError::InvalidIdentity)?;
         
-        // Derive encryption key from device (passwordless)
-        let device_key = Self::get_device_key()?;
+        // Derive encryption key from device (plus vault passphrase if wrapped)
         let salt = hex::decode(&encrypted.salt)
             .map_err(|e| IdentityError::Decryption(format!("Invalid salt: {}", e)))?;
-        let key = Self::derive_key_from_device(&device_key, &salt)?;
+        let key = self.storage_key(&salt, encrypted.passphrase_wrapped)?;
         
         // Decrypt
         let cipher = Aes256Gcm::new(&key.into());
@@ -242,18 +379,86 @@ impl IdentityManager {
         let plaintext = cipher.decrypt(nonce, ciphertext.as_ref())
             .map_err(|_| IdentityError::InvalidIdentity)?;
         
-        let identity: UserIdentity = serde_json::from_slice(&plaintext)?;
-        Ok(identity)
+        let identity: StoredIdentity = serde_json::from_slice(&plaintext)?;
+        Ok(identity.into())
+    }
+
+    /// Give an identity saved before private keys were persisted a fresh keypair.
+    ///
+    /// The old key is gone, so it cannot be recovered, and the beacon ties a user_id to the hash
+    /// of its key, so the identity also gets a new user_id; the old one is kept in
+    /// `previous_user_id` for re-registering with houses. The account folder and display name
+    /// stay as they are. Returns None when the identity already has its key.
+    pub fn upgrade_legacy_identity(&self) -> Result<Option<UserIdentity>, IdentityError> {
+        let identity = self.load_identity()?;
+        if identity.private_key.is_some() {
+            return Ok(None);
+        }
+
+        let (signing_key, verifying_key) = Self::generate_keypair();
+        let upgraded = UserIdentity {
+            user_id: Self::user_id_for_key(&verifying_key),
+            display_name: identity.display_name,
+            public_key: hex::encode(verifying_key.to_bytes()),
+            private_key: Some(hex::encode(signing_key.to_bytes())),
+            previous_user_id: Some(identity.user_id),
+        };
+        self.save_identity(&upgraded)?;
+        Ok(Some(upgraded))
+    }
+
+    /// Forget `previous_user_id` once every house has been re-registered
+    pub fn clear_previous_user_id(&self) -> Result<(), IdentityError> {
+        let mut identity = self.load_identity()?;
+        if identity.previous_user_id.take().is_some() {
+            self.save_identity(&identity)?;
+        }
+        Ok(())
+    }
+
+    /// Load the identity's Ed25519 signing key.
+    /// Identities saved before the private key was persisted cannot sign until upgraded
+    /// (see `upgrade_legacy_identity`).
+    pub fn signing_key(&self) -> Result<SigningKey, IdentityError> {
+        let identity = self.load_identity()?;
+        let private_hex = identity.private_key.ok_or(IdentityError::MissingPrivateKey)?;
+        let bytes = hex::decode(&private_hex)
+            .map_err(|e| IdentityError::HexDecode(e.to_string()))?;
+        let bytes: [u8; 32] = bytes.try_into()
+            .map_err(|_| IdentityError::InvalidIdentity)?;
+        Ok(SigningKey::from_bytes(&bytes))
+    }
+
+    /// X25519 secret for receiving sealed house keys, derived from the identity key
+    pub fn x25519_secret(&self) -> Result<[u8; 32], IdentityError> {
+        Ok(self.signing_key()?.to_scalar_bytes())
+    }
+
+    /// Base64 X25519 public key matching `x25519_secret` (published as `ServerMember.x25519_pubkey`)
+    pub fn x25519_public_key(&self) -> Result<String, IdentityError> {
+        let verifying_key = self.signing_key()?.verifying_key();
+        Ok(base64::encode(verifying_key.to_montgomery().to_bytes()))
+    }
+
+    /// Sign data with the identity key (returns base64 signature)
+    pub fn sign(&self, data: &[u8]) -> Result<String, IdentityError> {
+        let signing_key = self.signing_key()?;
+        let signature = signing_key.sign(data);
+        Ok(base64::encode(signature.to_bytes()))
     }
 
     pub fn save_identity(&self, identity: &UserIdentity) -> Result<(), IdentityError> {
         // Generate salt
         let salt: [u8; 16] = rand::random();
-        let device_key = Self::get_device_key()?;
-        let key = Self::derive_key_from_device(&device_key, &salt)?;
+        if self.vault_enabled && self.wrap_key.is_none() {
+            // Never silently downgrade a protected account to device-only encryption
+            return Err(IdentityError::Locked);
+        }
+        let wrapped = self.wrap_key.is_some();
+        let key = self.storage_key(&salt, wrapped)?;
         
-        // Serialize identity
-        let plaintext = serde_json::to_vec(identity)?;
+        // Serialize identity (including the private key)
+        let plaintext = serde_json::to_vec(&StoredIdentity::from(identity))?;
         
         // Encrypt
         let cipher = Aes256Gcm::new(&key.into());
@@ -265,12 +470,13 @@ impl IdentityManager {
             nonce: hex::encode(nonce),
             ciphertext: hex::encode(ciphertext),
             salt: hex::encode(salt),
+            passphrase_wrapped: wrapped,
         };
         
         // Save to file
         let keys_path = self.get_keys_path();
         let json = serde_json::to_string_pretty(&encrypted)?;
-        fs::write(keys_path, json)?;
+        vault::write_file_atomic(&keys_path, json.as_bytes())?;
         
         Ok(())
     }
@@ -393,12 +599,12 @@ impl IdentityManager {
         #[derive(Serialize)]
         struct ExportFormat {
             version: u8,
-            identity: UserIdentity,
+            identity: StoredIdentity,
         }
         
         let export = ExportFormat {
             version: 1,
-            identity,
+            identity: StoredIdentity::from(&identity),
         };
         
         let json = serde_json::to_string_pretty(&export)?;
@@ -410,7 +616,7 @@ impl IdentityManager {
         #[derive(Deserialize)]
         struct ExportFormat {
             version: u8,
-            identity: UserIdentity,
+            identity: StoredIdentity,
         }
         
         let export: ExportFormat = serde_json::from_slice(data)
@@ -420,7 +626,7 @@ impl IdentityManager {
             return Err(IdentityError::InvalidIdentity);
         }
         
-        let identity = export.identity;
+        let identity: UserIdentity = export.identity.into();
         
         // Re-encrypt and save with this device's key
         self.save_identity(&identity)?;
@@ -428,12 +634,13 @@ impl IdentityManager {
         Ok(identity)
     }
 
-    /// Export full identity with profile and server keys in binary .key format
+    /// Export full identity with profile and server keys in binary .key format (v2, passphrase-protected)
     pub fn export_full_identity(
         &self,
         profile_data: Option<serde_json::Value>,
         server_keys: Vec<serde_json::Value>,
         signaling_server_url: Option<String>,
+        passphrase: &str,
     ) -> Result<Vec<u8>, IdentityError> {
         let identity = self.load_identity()?;
 
@@ -441,7 +648,7 @@ impl IdentityManager {
         #[derive(Serialize)]
         struct FullExportFormat {
             version: u8,
-            identity: UserIdentity,
+            identity: StoredIdentity,
             profile: Option<serde_json::Value>,
             servers: Vec<serde_json::Value>,
             signaling_server_url: Option<String>,
@@ -449,7 +656,7 @@ impl IdentityManager {
 
         let export = FullExportFormat {
             version: 1,
-            identity,
+            identity: StoredIdentity::from(&identity),
             profile: profile_data,
             servers: server_keys,
             signaling_server_url,
@@ -458,76 +665,78 @@ impl IdentityManager {
         // Serialize to JSON
         let json_bytes = serde_json::to_vec(&export)?;
 
-        // Encrypt with device key (same as stored identity encryption)
-        let device_key = Self::get_device_key()?;
+        Self::seal_key_file_v2(&json_bytes, passphrase)
+    }
+
+    /// Build a v2 .key container around `plaintext`, encrypted under `passphrase`.
+    ///
+    /// Payload: m_cost u32 | t_cost u32 | p_cost u32 | salt (16) | nonce (24) | ciphertext
+    fn seal_key_file_v2(plaintext: &[u8], passphrase: &str) -> Result<Vec<u8>, IdentityError> {
+        if passphrase.chars().count() < MIN_PASSPHRASE_LEN {
+            return Err(IdentityError::WeakPassphrase(MIN_PASSPHRASE_LEN));
+        }
+
         let salt: [u8; 16] = rand::random();
-        let key = Self::derive_key_from_device(&device_key, &salt)?;
+        let key = derive_key_from_passphrase(
+            passphrase,
+            &salt,
+            PASSPHRASE_KDF_M_COST_KIB,
+            PASSPHRASE_KDF_T_COST,
+            PASSPHRASE_KDF_P_COST,
+        )?;
+        let nonce = XChaCha20Poly1305::generate_nonce(&mut OsRng);
+
+        // Ciphertext length is plaintext + 16-byte tag; the header is known before encrypting
+        let payload_len = 12 + salt.len() + nonce.len() + plaintext.len() + 16;
+        let header = Self::key_file_header_v2(payload_len);
 
         let cipher = XChaCha20Poly1305::new((&key).into());
-        let nonce = XChaCha20Poly1305::generate_nonce(&mut OsRng);
-        let ciphertext = cipher.encrypt(&nonce, json_bytes.as_ref())
+        let ciphertext = cipher
+            .encrypt(&nonce, Payload { msg: plaintext, aad: &header })
             .map_err(|_| IdentityError::Encryption("Failed to encrypt export payload".to_string()))?;
 
-        // Build binary format
-        // Magic: "CORD" (4 bytes) - Cordia key file
-        // Version: u16 (2 bytes)
-        // Flags: u16 (2 bytes, reserved, set to 0)
-        // Payload size: u32 (4 bytes)
-        // Header checksum: u32 (4 bytes, CRC32 of first 12 bytes)
-        let mut header = Vec::new();
-        header.extend_from_slice(b"CORD");
-        header.extend_from_slice(&1u16.to_le_bytes()); // version 1
-        header.extend_from_slice(&0u16.to_le_bytes()); // flags (reserved)
-        
-        // Calculate payload size (salt + nonce + ciphertext)
-        let payload = {
-            let mut p = Vec::new();
-            p.extend_from_slice(&salt); // 16 bytes
-            p.extend_from_slice(nonce.as_slice()); // 24 bytes
-            p.extend_from_slice(&ciphertext);
-            p
-        };
-        
-        header.extend_from_slice(&(payload.len() as u32).to_le_bytes());
-        
-        // Simple checksum: sum of first 12 bytes as u32
-        let mut checksum: u32 = 0;
-        for &b in &header[..12] {
-            checksum = checksum.wrapping_add(b as u32);
-        }
-        header.extend_from_slice(&checksum.to_le_bytes());
-
-        // Combine header + payload
-        let mut result = header;
-        result.extend(payload);
-        
+        let mut result = header.to_vec();
+        result.extend_from_slice(&PASSPHRASE_KDF_M_COST_KIB.to_le_bytes());
+        result.extend_from_slice(&PASSPHRASE_KDF_T_COST.to_le_bytes());
+        result.extend_from_slice(&PASSPHRASE_KDF_P_COST.to_le_bytes());
+        result.extend_from_slice(&salt);
+        result.extend_from_slice(nonce.as_slice());
+        result.extend(ciphertext);
         Ok(result)
     }
 
-    /// Decrypt and parse .key format (static, doesn't save - caller must save)
-    pub fn import_key_format_static(data: &[u8]) -> Result<(UserIdentity, Option<serde_json::Value>, Vec<serde_json::Value>, Option<String>), IdentityError> {
-        if data.len() < 16 {
-            return Err(IdentityError::InvalidIdentity);
-        }
-        // Cordia .key format magic
-        if &data[0..4] != b"CORD" {
-            return Err(IdentityError::InvalidIdentity);
-        }
+    fn key_file_header_v2(payload_len: usize) -> [u8; KEY_FILE_HEADER_LEN] {
+        let mut header = [0u8; KEY_FILE_HEADER_LEN];
+        header[0..4].copy_from_slice(KEY_FILE_MAGIC);
+        header[4..6].copy_from_slice(&KEY_FILE_V2.to_le_bytes());
+        header[6..8].copy_from_slice(&0u16.to_le_bytes()); // flags (reserved)
+        header[8..12].copy_from_slice(&(payload_len as u32).to_le_bytes());
+        let checksum = crc32fast::hash(&header[..12]);
+        header[12..16].copy_from_slice(&checksum.to_le_bytes());
+        header
+    }
 
-        // Parse header
-        let version = u16::from_le_bytes([data[4], data[5]]);
-        if version != 1 {
+    /// Decrypt a v2 payload. The header (already CRC-checked) is the AEAD associated data.
+    fn open_key_file_v2(header: &[u8], payload: &[u8], passphrase: &str) -> Result<Vec<u8>, IdentityError> {
+        if payload.len() < 12 + 16 + 24 {
             return Err(IdentityError::InvalidIdentity);
         }
+        let read_u32 = |at: usize| u32::from_le_bytes([payload[at], payload[at + 1], payload[at + 2], payload[at + 3]]);
+        let (m_cost, t_cost, p_cost) = (read_u32(0), read_u32(4), read_u32(8));
+        let salt = &payload[12..28];
+        let nonce_bytes: [u8; 24] = payload[28..52].try_into()
+            .map_err(|_| IdentityError::Decryption("Invalid nonce in .key file".to_string()))?;
+        let ciphertext = &payload[52..];
 
-        let payload_size = u32::from_le_bytes([data[8], data[9], data[10], data[11]]) as usize;
-        
-        if data.len() < 16 + payload_size {
-            return Err(IdentityError::InvalidIdentity);
-        }
+        let key = derive_key_from_passphrase(passphrase, salt, m_cost, t_cost, p_cost)?;
+        let cipher = XChaCha20Poly1305::new((&key).into());
+        cipher
+            .decrypt(&nonce_bytes.into(), Payload { msg: ciphertext, aad: header })
+            .map_err(|_| IdentityError::Decryption("Wrong passphrase or corrupted .key file".to_string()))
+    }
 
-        let payload = &data[16..16 + payload_size];
-        
+    /// Decrypt a v1 payload (salt + nonce + ciphertext under this device's key).
+    fn open_key_file_v1(payload: &[u8]) -> Result<Vec<u8>, IdentityError> {
         if payload.len() < 40 { // salt (16) + nonce (24) minimum
             return Err(IdentityError::InvalidIdentity);
         }
@@ -546,14 +755,50 @@ impl IdentityManager {
         let cipher = XChaCha20Poly1305::new((&key).into());
         let nonce: [u8; 24] = nonce_bytes;
         let nonce = nonce.into();
-        let plaintext = cipher.decrypt(&nonce, ciphertext)
-            .map_err(|_| IdentityError::Decryption("Failed to decrypt .key file".to_string()))?;
+        cipher.decrypt(&nonce, ciphertext)
+            .map_err(|_| IdentityError::Decryption("Failed to decrypt .key file".to_string()))
+    }
+
+    /// Decrypt and parse .key format (static, doesn't save - caller must save).
+    /// v2 files need the export passphrase; v1 files only open on the device that wrote them.
+    pub fn import_key_format_static(data: &[u8], passphrase: Option<&str>) -> Result<(UserIdentity, Option<serde_json::Value>, Vec<serde_json::Value>, Option<String>), IdentityError> {
+        if data.len() < KEY_FILE_HEADER_LEN {
+            return Err(IdentityError::InvalidIdentity);
+        }
+        // Cordia .key format magic
+        if &data[0..4] != KEY_FILE_MAGIC {
+            return Err(IdentityError::InvalidIdentity);
+        }
+
+        // Parse header
+        let version = u16::from_le_bytes([data[4], data[5]]);
+        let payload_size = u32::from_le_bytes([data[8], data[9], data[10], data[11]]) as usize;
+        
+        if data.len() < KEY_FILE_HEADER_LEN + payload_size {
+            return Err(IdentityError::InvalidIdentity);
+        }
+
+        let header = &data[..KEY_FILE_HEADER_LEN];
+        let payload = &data[KEY_FILE_HEADER_LEN..KEY_FILE_HEADER_LEN + payload_size];
+
+        let plaintext = match version {
+            KEY_FILE_V1 => Self::open_key_file_v1(payload)?,
+            KEY_FILE_V2 => {
+                let stored = u32::from_le_bytes([data[12], data[13], data[14], data[15]]);
+                if crc32fast::hash(&header[..12]) != stored {
+                    return Err(IdentityError::InvalidIdentity);
+                }
+                let passphrase = passphrase.ok_or(IdentityError::PassphraseRequired)?;
+                Self::open_key_file_v2(header, payload, passphrase)?
+            }
+            _ => return Err(IdentityError::InvalidIdentity),
+        };
 
         // Deserialize JSON payload
         #[derive(Deserialize)]
         struct FullExportFormat {
             version: u8,
-            identity: UserIdentity,
+            identity: StoredIdentity,
             profile: Option<serde_json::Value>,
             #[serde(alias = "houses")]
             servers: Vec<serde_json::Value>,
@@ -569,8 +814,61 @@ impl IdentityManager {
         }
 
         // Don't save here - caller (import_identity_auto) will save after account setup
-        Ok((export.identity, export.profile, export.servers, export.signaling_server_url))
+        Ok((export.identity.into(), export.profile, export.servers, export.signaling_server_url))
     }
 
 }
 
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use tempfile::tempdir;
+
+    #[test]
+    fn legacy_identity_upgrades_to_a_new_key() {
+        let temp = tempdir().unwrap();
+        let manager = IdentityManager {
+            data_dir: temp.path().to_path_buf(),
+            account_id: None,
+            wrap_key: None,
+            vault_enabled: false,
+        };
+        let (_, verifying_key) = IdentityManager::generate_keypair();
+        let legacy = UserIdentity {
+            user_id: IdentityManager::user_id_for_key(&verifying_key),
+            display_name: "Old Timer".to_string(),
+            public_key: hex::encode(verifying_key.to_bytes()),
+            private_key: None,
+            previous_user_id: None,
+        };
+        manager.save_identity(&legacy).unwrap();
+        assert!(matches!(manager.sign(b"hello"), Err(IdentityError::MissingPrivateKey)));
+
+        let upgraded = manager.upgrade_legacy_identity().unwrap().expect("legacy identity upgraded");
+        assert_ne!(upgraded.user_id, legacy.user_id);
+        assert_eq!(upgraded.previous_user_id.as_deref(), Some(legacy.user_id.as_str()));
+        assert_eq!(upgraded.display_name, legacy.display_name);
+
+        // The new user_id is bound to the new key, and the key now signs
+        let key = manager.signing_key().unwrap();
+        assert_eq!(IdentityManager::user_id_for_key(&key.verifying_key()), upgraded.user_id);
+        assert!(manager.sign(b"hello").is_ok());
+
+        // Upgrading again is a no-op; clearing drops only the old user_id
+        assert!(manager.upgrade_legacy_identity().unwrap().is_none());
+        manager.clear_previous_user_id().unwrap();
+        let reloaded = manager.load_identity().unwrap();
+        assert_eq!(reloaded.user_id, upgraded.user_id);
+        assert!(reloaded.previous_user_id.is_none());
+    }
+
+    #[test]
+    fn key_files_with_oversized_kdf_costs_are_refused() {
+        let mut file = IdentityManager::seal_key_file_v2(b"{}", "correct horse").unwrap();
+        // m_cost is the first payload field; it isn't covered by the header checksum
+        let m_cost = KEY_FILE_HEADER_LEN;
+        file[m_cost..m_cost + 4].copy_from_slice(&(PASSPHRASE_KDF_MAX_M_COST_KIB + 1).to_le_bytes());
+        let err = IdentityManager::import_key_format_static(&file, Some("correct horse")).err().unwrap();
+        assert!(matches!(err, IdentityError::Decryption(msg) if msg.contains("exceed")));
+    }
+}
@@ -6,15 +6,18 @@ mod audio_settings;
 mod server;
 mod signaling;
 mod account_manager;
+mod vault;
+mod house_state;
 
 #[cfg(windows)]
 mod file_association;
 
 use identity::{IdentityManager, UserIdentity};
 use audio_settings::{AudioSettingsManager, AudioSettings};
-use server::{ServerManager, ServerInfo};
+use server::{Server, ServerManager, ServerInfo};
 use signaling::{check_signaling_health, get_default_signaling_url};
 use account_manager::{AccountManager, SessionState, AccountInfo};
+use vault::VaultStatus;
 use serde::{Deserialize, Serialize};
 use sha2::{Digest, Sha256};
 use chacha20poly1305::{XChaCha20Poly1305, aead::{Aead, KeyInit, AeadCore}};
@@ -25,9 +28,44 @@ struct EncryptedServerHint {
     signing_pubkey: String,
     encrypted_state: String,
     signature: String,
+    /// Hex identity key of the member that signed; None means the house signing key signed it.
+    #[serde(default)]
+    signer_pubkey: Option<String>,
+    /// House symmetric key generation the state is encrypted under
+    #[serde(default)]
+    key_epoch: u32,
+    /// Beacon write counter: must be the stored hint's revision + 1 or the write is refused
+    #[serde(default)]
+    revision: u64,
     last_updated: String,
 }
 
+/// Body of a 409 from `POST /api/servers/{signing_pubkey}/register`
+#[derive(Debug, Clone, Deserialize)]
+struct HintConflictResponse {
+    current: EncryptedServerHint,
+}
+
+/// How many times to merge and resend a hint that keeps losing to concurrent writes
+const HINT_PUBLISH_ATTEMPTS: usize = 5;
+
+/// Answer to the beacon's WebSocket AuthChallenge (sent back as AuthResponse).
+#[derive(Debug, Clone, Serialize, Deserialize)]
+struct AuthChallengeResponse {
+    user_id: String,
+    public_key: String,
+    signature: String,
+}
+
+/// Body of the beacon's `POST /api/servers/{signing_pubkey}/events/ack`
+#[derive(Debug, Clone, Serialize, Deserialize)]
+struct EventAck {
+    user_id: String,
+    last_seq: u64,
+    member_pubkey: String,
+    signature: String,
+}
+
 #[derive(Debug, Clone, Serialize, Deserialize)]
 struct InviteTokenCreateRequest {
     code: String,
@@ -41,6 +79,8 @@ struct InviteTokenRecord {
     code: String,
     signing_pubkey: String,
     encrypted_payload: String,
+    /// The beacon no longer returns the create signature (it could be replayed)
+    #[serde(default)]
     signature: String,
     created_at: String,
     expires_at: String,
@@ -52,8 +92,88 @@ struct InviteTokenPayload {
     server: ServerInfo,
     #[serde(rename = "house_symmetric_key_b64")]
     server_symmetric_key_b64: String,
+    #[serde(default)]
+    key_epoch: u32,
+}
+
+/// Body of `POST /api/servers/{signing_pubkey}/keys` (see `rotate_house_key`).
+#[derive(Debug, Clone, Serialize, Deserialize)]
+struct KeyRotationRequest {
+    key_epoch: u32,
+    sealed_keys: std::collections::BTreeMap<String, String>,
+    removed_user_ids: Vec<String>,
+    signature: String,
+}
+
+/// Our sealed copy of the latest house key, from `GET /api/servers/{signing_pubkey}/keys/{user_id}`.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+struct SealedKeyRecord {
+    key_epoch: u32,
+    sealed_key: String,
+}
+
+/// Format tag for current invites. It prefixes both the URI fragment (`#v2.SECRET`)
+/// and the encrypted payload stored on the beacon (`v2:BASE64`).
+const INVITE_V2_TAG: &str = "v2";
+
+/// Split a shared invite code into the lookup code (the only part sent to the beacon)
+/// and the v2 secret fragment. Codes without a fragment are legacy v1 codes.
+fn split_invite_code(input: &str) -> Result<(String, Option<String>), String> {
+    let input = input.trim();
+    let Some((code, fragment)) = input.split_once('#') else {
+        return Ok((input.to_string(), None));
+    };
+    let secret = fragment
+        .strip_prefix(INVITE_V2_TAG)
+        .and_then(|rest| rest.strip_prefix('.'))
+        .filter(|secret| !secret.is_empty())
+        .ok_or_else(|| "Unsupported invite format".to_string())?;
+    Ok((code.trim().to_string(), Some(secret.to_string())))
+}
+
+/// Format tag for sealed invites: `cordia://{signing_pubkey}@{beacon}#sealed.{key_epoch}.{sealed_key}`.
+/// The fragment is the house key sealed to one identity's X25519 key, so there is no code to redeem.
+const SEALED_INVITE_TAG: &str = "sealed";
+
+/// A parsed sealed invite
+struct SealedInvite {
+    signing_pubkey: String,
+    key_epoch: u32,
+    sealed_key: String,
 }
 
+fn parse_sealed_invite(uri: &str) -> Result<SealedInvite, String> {
+    let uri = uri.trim();
+    let rest = ["cordia://", "rmmt://"]
+        .iter()
+        .find_map(|scheme| {
+            uri.get(..scheme.len())
+                .filter(|prefix| prefix.eq_ignore_ascii_case(scheme))
+                .map(|_| &uri[scheme.len()..])
+        })
+        .ok_or_else(|| "Invalid invite link".to_string())?;
+    let (authority, fragment) = rest.split_once('#')
+        .ok_or_else(|| "Not a sealed invite".to_string())?;
+    let signing_pubkey = authority.split('@').next().unwrap_or_default().trim();
+    let (key_epoch, sealed_key) = fragment
+        .strip_prefix(SEALED_INVITE_TAG)
+        .and_then(|rest| rest.strip_prefix('.'))
+        .and_then(|rest| rest.split_once('.'))
+        .ok_or_else(|| "Not a sealed invite".to_string())?;
+    let key_epoch = key_epoch.parse::<u32>()
+        .map_err(|_| "Invalid sealed invite".to_string())?;
+    if signing_pubkey.is_empty() || sealed_key.is_empty() {
+        return Err("Invalid sealed invite".to_string());
+    }
+    Ok(SealedInvite {
+        signing_pubkey: signing_pubkey.to_string(),
+        key_epoch,
+        sealed_key: sealed_key.to_string(),
+    })
+}
+
+/// Legacy v1 invite key: bare SHA-256 of the short code (brute-forceable by the beacon).
+/// TRANSITION: only used to redeem invites created before v2; drop once those have expired.
 fn derive_invite_key(code: &str) -> [u8; 32] {
     let mut hasher = Sha256::new();
     hasher.update(code.as_bytes());
@@ -63,23 +183,53 @@ fn derive_invite_key(code: &str) -> [u8; 32] {
     key
 }
 
-fn encrypt_invite_payload(code: &str, payload: &InviteTokenPayload) -> Result<String, String> {
-    let key = derive_invite_key(code);
+/// v2 invite key: Argon2id over the lookup code and the secret fragment, which never reaches the beacon.
+fn derive_invite_key_v2(code: &str, secret: &str, salt: &[u8]) -> Result<[u8; 32], String> {
+    let params = argon2::Params::new(64 * 1024, 3, 1, Some(32))
+        .map_err(|e| format!("Invalid invite KDF params: {}", e))?;
+    let kdf = argon2::Argon2::new(argon2::Algorithm::Argon2id, argon2::Version::V0x13, params);
+    let mut key = [0u8; 32];
+    kdf.hash_password_into(format!("{}\n{}", code, secret).as_bytes(), salt, &mut key)
+        .map_err(|e| format!("Invite key derivation failed: {}", e))?;
+    Ok(key)
+}
+
+/// Encrypt an invite payload in the v2 format: `v2:` + base64(salt(16) || nonce(24) || ciphertext).
+fn encrypt_invite_payload(code: &str, secret: &str, payload: &InviteTokenPayload) -> Result<String, String> {
+    let mut salt = [0u8; 16];
+    rand::thread_rng().fill_bytes(&mut salt);
+    let key = derive_invite_key_v2(code, secret, &salt)?;
     let cipher = XChaCha20Poly1305::new((&key).into());
     let mut nonce_bytes = [0u8; 24];
     rand::thread_rng().fill_bytes(&mut nonce_bytes);
     let nonce = nonce_bytes.into();
     let plaintext = serde_json::to_vec(payload).map_err(|e| format!("Failed to serialize invite payload: {}", e))?;
     let ciphertext = cipher.encrypt(&nonce, plaintext.as_ref()).map_err(|_| "Invite encryption failed".to_string())?;
-    let mut out = nonce_bytes.to_vec();
+    let mut out = salt.to_vec();
+    out.extend_from_slice(&nonce_bytes);
     out.extend(ciphertext);
-    Ok(base64::encode(&out))
+    Ok(format!("{}:{}", INVITE_V2_TAG, base64::encode(&out)))
 }
 
-fn decrypt_invite_payload(code: &str, encrypted_payload_b64: &str) -> Result<InviteTokenPayload, String> {
-    let key = derive_invite_key(code);
+fn decrypt_invite_payload(code: &str, secret: Option<&str>, encrypted_payload: &str) -> Result<InviteTokenPayload, String> {
+    let (key, data) = match encrypted_payload.strip_prefix(INVITE_V2_TAG).and_then(|rest| rest.strip_prefix(':')) {
+        Some(b64) => {
+            let secret = secret
+                .ok_or_else(|| "This invite needs the full link, including the part after '#'".to_string())?;
+            let data = base64::decode(b64).map_err(|e| format!("Invite payload base64 decode failed: {}", e))?;
+            if data.len() < 16 + 24 {
+                return Err("Invite payload too short".to_string());
+            }
+            let key = derive_invite_key_v2(code, secret, &data[..16])?;
+            (key, data[16..].to_vec())
+        }
+        None => {
+            // Legacy v1 payload: nonce(24) || ciphertext under SHA-256(code)
+            let data = base64::decode(encrypted_payload).map_err(|e| format!("Invite payload base64 decode failed: {}", e))?;
+            (derive_invite_key(code), data)
+        }
+    };
     let cipher = XChaCha20Poly1305::new((&key).into());
-    let data = base64::decode(encrypted_payload_b64).map_err(|e| format!("Invite payload base64 decode failed: {}", e))?;
     if data.len() < 24 {
         return Err("Invite payload too short".to_string());
     }
@@ -123,46 +273,108 @@ fn decrypt_server_hint(symmetric_key: &[u8], encrypted_state_b64: &str) -> Resul
     serde_json::from_slice::<ServerInfo>(&plaintext).map_err(|e| format!("Server hint JSON parse failed: {}", e))
 }
 
-fn merge_server_infos(mut base: ServerInfo, other: ServerInfo) -> ServerInfo {
-    // Members: union by user_id (keep first seen)
-    let mut seen = std::collections::HashSet::<String>::new();
-    let mut merged_members = Vec::new();
-    for m in base.members.into_iter() {
-        if seen.insert(m.user_id.clone()) {
-            merged_members.push(m);
-        }
-    }
-    for m in other.members.into_iter() {
-        if seen.insert(m.user_id.clone()) {
-            merged_members.push(m);
-        }
-    }
-    base.members = merged_members;
-
-    // Chats: union by id
-    let mut seen_chats = std::collections::HashSet::<String>::new();
-    let mut merged_chats = Vec::new();
-    for r in base.chats.into_iter() {
-        if seen_chats.insert(r.id.clone()) {
-            merged_chats.push(r);
-        }
-    }
-    for r in other.chats.into_iter() {
-        if seen_chats.insert(r.id.clone()) {
-            merged_chats.push(r);
-        }
-    }
-    base.chats = merged_chats;
+/// Bytes covered by a hint signature. Must match `auth::hint_signing_payload` on the beacon.
+fn hint_signing_payload(
+    signing_pubkey: &str,
+    encrypted_state: &str,
+    key_epoch: u32,
+    revision: u64,
+    last_updated: &chrono::DateTime<chrono::Utc>,
+) -> Vec<u8> {
+    format!(
+        "cordia-hint-v2\n{}\n{}\n{}\n{}\n{}",
+        signing_pubkey,
+        key_epoch,
+        revision,
+        encrypted_state,
+        last_updated.timestamp_millis()
+    )
+    .into_bytes()
+}
 
-    // Invite state: prefer any active value
-    if base.active_invite_uri.is_none() {
-        base.active_invite_uri = other.active_invite_uri;
-    }
-    if base.active_invite_expires_at.is_none() {
-        base.active_invite_expires_at = other.active_invite_expires_at;
-    }
+/// Bytes covered by the house signature on an invite create. Must match `auth::invite_create_payload`.
+fn invite_create_signing_payload(signing_pubkey: &str, code: &str, max_uses: u32, encrypted_payload: &str) -> Vec<u8> {
+    format!(
+        "cordia-invite-create-v1\n{}\n{}\n{}\n{}",
+        signing_pubkey, code, max_uses, encrypted_payload
+    )
+    .into_bytes()
+}
+
+/// Bytes covered by a member's event ack. Must match `auth::event_ack_payload` (we always ack by seq).
+fn event_ack_signing_payload(signing_pubkey: &str, user_id: &str, last_seq: u64) -> Vec<u8> {
+    format!("cordia-ack-v1\n{}\n{}\n{}\n", signing_pubkey, user_id, last_seq).into_bytes()
+}
+
+/// Bytes covered by the house signature on an invite revoke. Must match `auth::invite_revoke_payload`.
+fn invite_revoke_signing_payload(signing_pubkey: &str, code: &str) -> Vec<u8> {
+    format!("cordia-invite-revoke-v1\n{}\n{}", signing_pubkey, code).into_bytes()
+}
+
+/// Bytes covered by the house signature when authorizing a member key directly. Must match `auth::member_authorize_payload`.
+fn member_authorize_signing_payload(signing_pubkey: &str, member_pubkey: &str) -> Vec<u8> {
+    format!("cordia-member-add-v1\n{}\n{}", signing_pubkey, member_pubkey).into_bytes()
+}
+
+/// Bytes covered by the house signature on a key rotation. Must match `auth::key_rotation_payload`.
+fn key_rotation_signing_payload(
+    signing_pubkey: &str,
+    key_epoch: u32,
+    sealed_keys: &std::collections::BTreeMap<String, String>,
+    removed_user_ids: &[String],
+) -> Vec<u8> {
+    let sealed = sealed_keys
+        .iter()
+        .map(|(user_id, blob)| format!("{}:{}", user_id, blob))
+        .collect::<Vec<_>>()
+        .join("\n");
+    format!(
+        "cordia-rekey-v1\n{}\n{}\n{}\n{}",
+        signing_pubkey,
+        key_epoch,
+        removed_user_ids.join(","),
+        sealed
+    )
+    .into_bytes()
+}
+
+/// Build a signed hint. The house owner signs with the house key; other members sign with their
+/// identity key, which the beacon authorized when they redeemed an invite.
+fn sign_server_hint(server: &Server, encrypted_state: String, revision: u64) -> Result<EncryptedServerHint, String> {
+    let now = chrono::Utc::now();
+    let payload = hint_signing_payload(&server.signing_pubkey, &encrypted_state, server.key_epoch, revision, &now);
+
+    let (signature, signer_pubkey) = if server.has_signing_key() {
+        let signature = server.sign(&payload)
+            .map_err(|e| format!("Failed to sign house hint: {}", e))?;
+        (signature, None)
+    } else {
+        let identity_manager = IdentityManager::new()
+            .map_err(|e| format!("Failed to initialize identity manager: {}", e))?;
+        let identity = identity_manager.load_identity()
+            .map_err(|e| format!("Failed to load identity: {}", e))?;
+        let signature = identity_manager.sign(&payload)
+            .map_err(|e| format!("Failed to sign house hint: {}", e))?;
+        (signature, Some(identity.public_key))
+    };
+
+    Ok(EncryptedServerHint {
+        signing_pubkey: server.signing_pubkey.clone(),
+        encrypted_state,
+        signature,
+        signer_pubkey,
+        key_epoch: server.key_epoch,
+        revision,
+        last_updated: now.to_rfc3339(),
+    })
+}
 
-    base
+/// Merge two copies of a house. The result doesn't depend on which copy is `base`,
+/// except for non-replicated fields (id, URLs), which come from `base`.
+fn merge_server_infos(base: ServerInfo, other: ServerInfo) -> ServerInfo {
+    let mut state = base.house_state();
+    state.merge(&other.house_state());
+    base.with_house_state(state)
 }
 
 fn normalize_signaling_to_http(url: &str) -> Result<String, String> {
@@ -223,8 +435,104 @@ fn load_identity() -> Result<UserIdentity, String> {
     
     let manager = IdentityManager::new()
         .map_err(|e| format!("Failed to initialize identity manager: {}", e))?;
-    manager.load_identity()
-        .map_err(|e| format!("Failed to load identity: {}", e))
+    // Identities saved before private keys were persisted get a new key here instead of
+    // failing every signature later
+    let identity = match manager.upgrade_legacy_identity()
+        .map_err(|e| format!("Failed to upgrade identity: {}", e))?
+    {
+        Some(upgraded) => upgraded,
+        None => manager.load_identity()
+            .map_err(|e| format!("Failed to load identity: {}", e))?,
+    };
+
+    // Until every house is re-registered, keep our local member entries on the new user_id
+    // (idempotent, so an interrupted upgrade finishes on the next load)
+    if let Some(previous_user_id) = &identity.previous_user_id {
+        let server_manager = ServerManager::new()
+            .map_err(|e| format!("Failed to initialize house manager: {}", e))?;
+        server_manager.rename_own_member(previous_user_id, &identity.user_id, manager.x25519_public_key().ok())
+            .map_err(|e| format!("Failed to update house members: {}", e))?;
+    }
+    Ok(identity)
+}
+
+/// Finish an identity upgrade (see `load_identity`) on the beacon: republish the houses we own,
+/// signed with the house key, so members see our new user_id. Other houses only accept hints
+/// signed by keys the owner authorized, so our new key needs a fresh invite there; their names
+/// are returned for the user.
+#[tauri::command]
+async fn reregister_upgraded_identity(signaling_server: String) -> Result<Vec<String>, String> {
+    require_session()?;
+
+    let identity_manager = IdentityManager::new()
+        .map_err(|e| format!("Failed to initialize identity manager: {}", e))?;
+    let identity = identity_manager.load_identity()
+        .map_err(|e| format!("Failed to load identity: {}", e))?;
+    if identity.previous_user_id.is_none() {
+        return Ok(Vec::new());
+    }
+
+    let manager = ServerManager::new()
+        .map_err(|e| format!("Failed to initialize house manager: {}", e))?;
+    let mut needs_invite = Vec::new();
+    for mut server in manager.load_all_servers()
+        .map_err(|e| format!("Failed to load houses: {}", e))?
+    {
+        if !server.has_signing_key() {
+            needs_invite.push(server.name.clone());
+            continue;
+        }
+        let current = get_server_hint(signaling_server.clone(), server.signing_pubkey.clone()).await?;
+        publish_house_state(&signaling_server, &manager, &mut server, current).await?;
+    }
+
+    identity_manager.clear_previous_user_id()
+        .map_err(|e| format!("Failed to save identity: {}", e))?;
+    Ok(needs_invite)
+}
+
+#[tauri::command]
+fn sign_auth_challenge(nonce: String) -> Result<AuthChallengeResponse, String> {
+    // GUARDED: Requires active session
+    require_session()?;
+
+    let manager = IdentityManager::new()
+        .map_err(|e| format!("Failed to initialize identity manager: {}", e))?;
+    let identity = manager.load_identity()
+        .map_err(|e| format!("Failed to load identity: {}", e))?;
+
+    // Must match auth::auth_challenge_payload on the beacon
+    let payload = format!("cordia-auth-v1\n{}", nonce);
+    let signature = manager.sign(payload.as_bytes())
+        .map_err(|e| format!("Failed to sign auth challenge: {}", e))?;
+
+    Ok(AuthChallengeResponse {
+        user_id: identity.user_id,
+        public_key: identity.public_key,
+        signature,
+    })
+}
+
+/// Sign an ack of a house's events up to `last_seq`. The beacon only counts acks from
+/// members whose identity key it authorized for the house.
+#[tauri::command]
+fn sign_event_ack(signing_pubkey: String, last_seq: u64) -> Result<EventAck, String> {
+    // GUARDED: Requires active session
+    require_session()?;
+
+    let manager = IdentityManager::new()
+        .map_err(|e| format!("Failed to initialize identity manager: {}", e))?;
+    let identity = manager.load_identity()
+        .map_err(|e| format!("Failed to load identity: {}", e))?;
+    let signature = manager.sign(&event_ack_signing_payload(&signing_pubkey, &identity.user_id, last_seq))
+        .map_err(|e| format!("Failed to sign event ack: {}", e))?;
+
+    Ok(EventAck {
+        user_id: identity.user_id,
+        last_seq,
+        member_pubkey: identity.public_key,
+        signature,
+    })
 }
 
 #[tauri::command]
@@ -251,6 +559,7 @@ fn export_identity_for_account(account_id: String) -> Result<Vec<u8>, String> {
 fn export_full_identity_for_account(
     account_id: String,
     profile_json: Option<serde_json::Value>,
+    passphrase: String,
 ) -> Result<Vec<u8>, String> {
     // NO GUARD: Can export any account's full identity (for backup before deletion)
     let identity_manager = IdentityManager::for_account(&account_id)
@@ -274,6 +583,7 @@ fn export_full_identity_for_account(
             let mut server_export = serde_json::json!({
                 "signing_pubkey": signing_pubkey,
                 "symmetric_key_b64": base64::encode(&symmetric_key),
+                "key_epoch": server.key_epoch,
                 "invite_uri": invite_uri,
             });
             
@@ -302,12 +612,12 @@ fn export_full_identity_for_account(
         });
     let signaling_server_url = account_info.signaling_server_url;
 
-    identity_manager.export_full_identity(profile_json, server_data, signaling_server_url)
+    identity_manager.export_full_identity(profile_json, server_data, signaling_server_url, &passphrase)
         .map_err(|e| format!("Failed to export full identity: {}", e))
 }
 
 #[tauri::command]
-fn export_full_identity(profile_json: Option<serde_json::Value>) -> Result<Vec<u8>, String> {
+fn export_full_identity(profile_json: Option<serde_json::Value>, passphrase: String) -> Result<Vec<u8>, String> {
     // GUARDED: Requires active session
     require_session()?;
     
@@ -332,6 +642,7 @@ fn export_full_identity(profile_json: Option<serde_json::Value>) -> Result<Vec<u
             let mut server_export = serde_json::json!({
                 "signing_pubkey": signing_pubkey,
                 "symmetric_key_b64": base64::encode(&symmetric_key),
+                "key_epoch": server.key_epoch,
                 "invite_uri": invite_uri,
             });
             
@@ -363,7 +674,7 @@ fn export_full_identity(profile_json: Option<serde_json::Value>) -> Result<Vec<u
         });
     let signaling_server_url = account_info.signaling_server_url;
 
-    identity_manager.export_full_identity(profile_json, server_data, signaling_server_url)
+    identity_manager.export_full_identity(profile_json, server_data, signaling_server_url, &passphrase)
         .map_err(|e| format!("Failed to export full identity: {}", e))
 }
 
@@ -393,6 +704,7 @@ fn export_full_identity_debug(profile_json: Option<serde_json::Value>) -> Result
             let mut server_export = serde_json::json!({
                 "signing_pubkey": signing_pubkey,
                 "symmetric_key_b64": base64::encode(&symmetric_key),
+                "key_epoch": server.key_epoch,
                 "invite_uri": invite_uri,
             });
             
@@ -455,11 +767,11 @@ struct ImportResult {
 }
 
 #[tauri::command]
-fn import_identity(data: Vec<u8>) -> Result<ImportResult, String> {
+fn import_identity(data: Vec<u8>, passphrase: Option<String>) -> Result<ImportResult, String> {
     // NO GUARD: Bootstrap command - works without session for initial setup
     
     // Import .roo format
-    let (identity, profile_json, server_data, signaling_server_url) = IdentityManager::import_key_format_static(&data)
+    let (identity, profile_json, server_data, signaling_server_url) = IdentityManager::import_key_format_static(&data, passphrase.as_deref())
         .map_err(|e| format!("Failed to import .key file: {}", e))?;
     
     // Create account container if it doesn't exist
@@ -564,6 +876,7 @@ fn import_identity(data: Vec<u8>) -> Result<ImportResult, String> {
                         "signing_pubkey": signing_pubkey,
                         "invite_uri": invite_uri,
                         "invite_code": invite_code,
+                        "key_epoch": server_json.get("key_epoch").cloned().unwrap_or(serde_json::json!(0)),
                     });
                     
                     // Restore house using ServerManager method (will encrypt keys with device key)
@@ -672,7 +985,7 @@ fn join_server(server_id: String, user_id: String, display_name: String) -> Resu
     
     let manager = ServerManager::new()
         .map_err(|e| format!("Failed to initialize house manager: {}", e))?;
-    let server = manager.add_member_to_server(&server_id, user_id, display_name)
+    let server = manager.add_member_to_server(&server_id, user_id, display_name, own_x25519_pubkey())
         .map_err(|e| format!("Failed to join house: {}", e))?;
     Ok(server.to_info())
 }
@@ -714,12 +1027,9 @@ fn import_server_hint(server: ServerInfo) -> Result<(), String> {
         .map_err(|e| format!("Failed to import server hint: {}", e))
 }
 
-#[tauri::command]
-async fn register_server_hint(signaling_server: String, hint: EncryptedServerHint) -> Result<(), String> {
-    // Usage command (publishing state) - require session
-    require_session()?;
-
-    let base = normalize_signaling_to_http(&signaling_server)?;
+/// POST a hint. Returns the beacon's current hint if ours was based on an older revision.
+async fn post_server_hint(signaling_server: &str, hint: &EncryptedServerHint) -> Result<Option<EncryptedServerHint>, String> {
+    let base = normalize_signaling_to_http(signaling_server)?;
     let url = format!(
         "{}/api/servers/{}/register",
         base,
@@ -729,16 +1039,47 @@ async fn register_server_hint(signaling_server: String, hint: EncryptedServerHin
     let client = reqwest::Client::new();
     let resp = client
         .post(url)
-        .json(&hint)
+        .json(hint)
         .send()
         .await
         .map_err(|e| format!("Failed to POST house hint: {}", e))?;
 
+    if resp.status().as_u16() == 409 {
+        let conflict = resp.json::<HintConflictResponse>().await
+            .map_err(|e| format!("Failed to parse house hint conflict: {}", e))?;
+        return Ok(Some(conflict.current));
+    }
+
     if !resp.status().is_success() {
-        return Err(format!("Failed to register house hint: HTTP {}", resp.status()));
+        let status = resp.status();
+        let body = resp.text().await.unwrap_or_default();
+        return Err(format!("Failed to register house hint: HTTP {} {}", status, body));
     }
 
-    Ok(())
+    Ok(None)
+}
+
+#[tauri::command]
+async fn register_server_hint(signaling_server: String, hint: EncryptedServerHint) -> Result<(), String> {
+    // Usage command (publishing state) - require session
+    require_session()?;
+
+    let Some(current) = post_server_hint(&signaling_server, &hint).await? else {
+        return Ok(());
+    };
+
+    // Someone published first: fold our hint into the local house, then merge theirs and resend
+    let manager = ServerManager::new()
+        .map_err(|e| format!("Failed to initialize house manager: {}", e))?;
+    let Some(server_id) = manager.find_server_id_by_signing_pubkey(&hint.signing_pubkey)
+        .map_err(|e| format!("Failed to find local house: {}", e))? else {
+        return Err("Failed to register house hint: revision conflict and house not present locally".to_string());
+    };
+    let mut server = manager.load_server(&server_id)
+        .map_err(|e| format!("Failed to load house: {}", e))?;
+
+    merge_hint_state(&mut server, &hint);
+    publish_house_state(&signaling_server, &manager, &mut server, Some(current)).await
 }
 
 #[tauri::command]
@@ -783,50 +1124,140 @@ async fn publish_server_hint_opaque(signaling_server: String, server_id: String)
     let manager = ServerManager::new()
         .map_err(|e| format!("Failed to initialize house manager: {}", e))?;
 
-    let server = manager.load_server(&server_id)
+    let mut server = manager.load_server(&server_id)
         .map_err(|e| format!("Failed to load house: {}", e))?;
 
-    let symmetric_key = server.get_symmetric_key()
-        .ok_or_else(|| "Server missing symmetric key".to_string())?;
+    fill_own_x25519_pubkey(&manager, &mut server)?;
+
+    let current = get_server_hint(signaling_server.clone(), server.signing_pubkey.clone()).await?;
+    publish_house_state(&signaling_server, &manager, &mut server, current).await
+}
 
-    let server_info = server.to_info();
-    let encrypted_state = encrypt_server_hint(&symmetric_key, &server_info)?;
+/// Publish `server` as the house hint, based on `current` (the beacon's hint as last seen).
+/// The beacon's copy is merged in first so publishing never drops another member's writes;
+/// if someone else writes in between, their hint is merged too and we try again.
+async fn publish_house_state(
+    signaling_server: &str,
+    manager: &ServerManager,
+    server: &mut Server,
+    mut current: Option<EncryptedServerHint>,
+) -> Result<(), String> {
+    for _ in 0..HINT_PUBLISH_ATTEMPTS {
+        let mut revision = 1;
+        if let Some(current) = &current {
+            // Never publish under a key the owner has already retired
+            if current.key_epoch > server.key_epoch {
+                fetch_rotated_house_key(signaling_server, manager, server).await?;
+            }
+            if merge_hint_state(server, current) {
+                manager.save_server(server)
+                    .map_err(|e| format!("Failed to save house: {}", e))?;
+            }
+            revision = current.revision + 1;
+        }
 
-    let hint = EncryptedServerHint {
-        signing_pubkey: server_info.signing_pubkey.clone(),
-        encrypted_state,
-        signature: "".to_string(),
-        last_updated: chrono::Utc::now().to_rfc3339(),
+        let symmetric_key = server.get_symmetric_key()
+            .ok_or_else(|| "Server missing symmetric key".to_string())?;
+        let encrypted_state = encrypt_server_hint(&symmetric_key, &server.to_info())?;
+        let hint = sign_server_hint(server, encrypted_state, revision)?;
+
+        match post_server_hint(signaling_server, &hint).await? {
+            None => {
+                // The owner's identity key is authorized like any member's, so the beacon
+                // takes its event acks (idempotent; best-effort)
+                if server.has_signing_key() {
+                    if let Err(e) = authorize_own_identity(signaling_server, server).await {
+                        eprintln!("Warning: failed to authorize our identity key for the house: {}", e);
+                    }
+                }
+                return Ok(());
+            }
+            Some(newer) => current = Some(newer),
+        }
+    }
+    Err("Failed to register house hint: too many concurrent updates, try again".to_string())
+}
+
+async fn authorize_own_identity(signaling_server: &str, server: &Server) -> Result<(), String> {
+    let identity = IdentityManager::new()
+        .and_then(|m| m.load_identity())
+        .map_err(|e| format!("Failed to load identity: {}", e))?;
+    authorize_member_key(signaling_server, server, &identity.public_key).await
+}
+
+/// Merge the house state carried by `hint` into `server`.
+/// Returns false if we can't read it (e.g. a key epoch we don't have).
+fn merge_hint_state(server: &mut Server, hint: &EncryptedServerHint) -> bool {
+    let Some(symmetric_key) = server.symmetric_key_for_epoch(hint.key_epoch) else {
+        return false;
     };
+    let Ok(remote) = decrypt_server_hint(&symmetric_key, &hint.encrypted_state) else {
+        return false;
+    };
+    server.merge_state(&remote.house_state());
+    true
+}
+
+/// Our identity's X25519 public key (None for identities that cannot derive one)
+fn own_x25519_pubkey() -> Option<String> {
+    IdentityManager::new().ok()?.x25519_public_key().ok()
+}
+
+/// Put our X25519 key on our own member entry (saved locally, carried by the next hint)
+/// so the owner can seal rotated house keys to us.
+fn fill_own_x25519_pubkey(manager: &ServerManager, server: &mut Server) -> Result<(), String> {
+    let identity_manager = IdentityManager::new()
+        .map_err(|e| format!("Failed to initialize identity manager: {}", e))?;
+    let identity = identity_manager.load_identity()
+        .map_err(|e| format!("Failed to load identity: {}", e))?;
+    // Identities without a stored private key cannot receive sealed keys
+    let Ok(x25519_pubkey) = identity_manager.x25519_public_key() else {
+        return Ok(());
+    };
+    if !server.set_member_x25519_pubkey(&identity.user_id, x25519_pubkey) {
+        return Ok(());
+    }
+    manager.save_server(server)
+        .map_err(|e| format!("Failed to save house: {}", e))
+}
 
-    register_server_hint(signaling_server, hint).await
+/// user_id for a hex identity public key (same derivation as `IdentityManager::create_identity`)
+fn user_id_for_public_key(public_key_hex: &str) -> Option<String> {
+    let bytes = hex::decode(public_key_hex).ok()?;
+    let hash = Sha256::digest(&bytes);
+    Some(hex::encode(&hash[..16]))
 }
 
+/// Returns the members the owner's rekey couldn't reach (see `rotate_house_key`); empty otherwise.
 #[tauri::command]
-async fn publish_server_hint_member_left(signaling_server: String, server_id: String, user_id: String) -> Result<(), String> {
+async fn publish_server_hint_member_left(signaling_server: String, server_id: String, user_id: String) -> Result<Vec<String>, String> {
     require_session()?;
 
     let manager = ServerManager::new()
         .map_err(|e| format!("Failed to initialize house manager: {}", e))?;
 
-    let server = manager.load_server(&server_id)
+    let mut server = manager.load_server(&server_id)
         .map_err(|e| format!("Failed to load house: {}", e))?;
 
-    let symmetric_key = server.get_symmetric_key()
-        .ok_or_else(|| "Server missing symmetric key".to_string())?;
+    // The owner removing someone else rekeys, so the removed member can't read later hints
+    if server.has_signing_key() {
+        let own_user_id = IdentityManager::new()
+            .and_then(|m| m.load_identity())
+            .map_err(|e| format!("Failed to load identity: {}", e))?
+            .user_id;
+        if own_user_id != user_id {
+            return rotate_house_key(signaling_server, server_id, vec![user_id]).await;
+        }
+    }
 
-    let mut server_info = server.to_info();
-    server_info.members.retain(|m| m.user_id != user_id);
+    // Tombstone the member so stale copies elsewhere can't add them back
+    server.remove_member(&user_id);
+    manager.save_server(&server)
+        .map_err(|e| format!("Failed to save house: {}", e))?;
 
-    let encrypted_state = encrypt_server_hint(&symmetric_key, &server_info)?;
-    let hint = EncryptedServerHint {
-        signing_pubkey: server_info.signing_pubkey.clone(),
-        encrypted_state,
-        signature: "".to_string(),
-        last_updated: chrono::Utc::now().to_rfc3339(),
-    };
-
-    register_server_hint(signaling_server, hint).await
+    let current = get_server_hint(signaling_server.clone(), server.signing_pubkey.clone()).await?;
+    publish_house_state(&signaling_server, &manager, &mut server, current).await?;
+    Ok(Vec::new())
 }
 
 #[tauri::command]
@@ -848,10 +1279,16 @@ async fn fetch_and_import_server_hint_opaque(signaling_server: String, signing_p
         return Err("Cannot decrypt hint: house not present locally (join via invite first)".to_string());
     };
 
-    let local_server = manager.load_server(&server_id)
+    let mut local_server = manager.load_server(&server_id)
         .map_err(|e| format!("Failed to load local house: {}", e))?;
 
-    let symmetric_key = local_server.get_symmetric_key()
+    // The owner rotated the house key since we last synced: pick up our sealed copy
+    if hint.key_epoch > local_server.key_epoch {
+        fetch_rotated_house_key(&signaling_server, &manager, &mut local_server).await?;
+    }
+
+    // Hints from an older epoch are still readable with the retired key
+    let symmetric_key = local_server.symmetric_key_for_epoch(hint.key_epoch)
         .ok_or_else(|| "Cannot decrypt hint: missing symmetric key (join via invite first)".to_string())?;
 
     let decrypted = decrypt_server_hint(&symmetric_key, &hint.encrypted_state)?;
@@ -859,9 +1296,143 @@ async fn fetch_and_import_server_hint_opaque(signaling_server: String, signing_p
     manager.import_server_hint(decrypted)
         .map_err(|e| format!("Failed to import decrypted hint: {}", e))?;
 
+    // A member who left publishes their own tombstone. As owner, rekey so they can't read
+    // anything published after this point. Compare our stored member list before and after the
+    // merge: a stale copy that merely lacks a member removes no one. Members can only remove
+    // themselves, so only the signer counts.
+    if local_server.has_signing_key() && hint.key_epoch == local_server.key_epoch {
+        if let Some(leaver) = hint.signer_pubkey.as_deref().and_then(user_id_for_public_key) {
+            let merged = manager.load_server(&server_id)
+                .map_err(|e| format!("Failed to load local house: {}", e))?;
+            let was_member = local_server.members.iter().any(|m| m.user_id == leaver);
+            let still_member = merged.members.iter().any(|m| m.user_id == leaver);
+            if was_member && !still_member {
+                let unreachable = rotate_house_key(signaling_server, server_id, vec![leaver]).await?;
+                if !unreachable.is_empty() {
+                    eprintln!("Warning: rekeyed without members that have no X25519 key: {:?}", unreachable);
+                }
+            }
+        }
+    }
+
     Ok(true)
 }
 
+/// Fetch our sealed copy of the latest house key from the beacon and install it
+async fn fetch_rotated_house_key(signaling_server: &str, manager: &ServerManager, server: &mut Server) -> Result<(), String> {
+    let identity_manager = IdentityManager::new()
+        .map_err(|e| format!("Failed to initialize identity manager: {}", e))?;
+    let identity = identity_manager.load_identity()
+        .map_err(|e| format!("Failed to load identity: {}", e))?;
+
+    let base = normalize_signaling_to_http(signaling_server)?;
+    let url = format!(
+        "{}/api/servers/{}/keys/{}",
+        base,
+        urlencoding::encode(&server.signing_pubkey),
+        urlencoding::encode(&identity.user_id)
+    );
+
+    let client = reqwest::Client::new();
+    let resp = client
+        .get(url)
+        .send()
+        .await
+        .map_err(|e| format!("Failed to fetch rotated house key: {}", e))?;
+
+    if resp.status().as_u16() == 404 {
+        return Err("The house key was rotated and no copy was sealed to you (ask the owner for a new invite)".to_string());
+    }
+    if !resp.status().is_success() {
+        return Err(format!("Failed to fetch rotated house key: HTTP {}", resp.status()));
+    }
+
+    let record = resp.json::<SealedKeyRecord>().await
+        .map_err(|e| format!("Failed to parse rotated house key: {}", e))?;
+
+    let x25519_secret = identity_manager.x25519_secret()
+        .map_err(|e| format!("Failed to derive X25519 key: {}", e))?;
+    let key = Server::decrypt_invite(&record.sealed_key, &x25519_secret)
+        .map_err(|e| format!("Failed to open rotated house key: {}", e))?;
+
+    server.install_rotated_key(record.key_epoch, key);
+    manager.save_server(server)
+        .map_err(|e| format!("Failed to save house: {}", e))
+}
+
+/// Rekey a house (owner only): generate a new symmetric key, seal it to every remaining member's
+/// X25519 key, store the sealed copies on the beacon and republish the hint under the new key.
+/// Members in `removed_user_ids` are dropped and lose their hint-signing rights on the beacon.
+/// Returns the remaining members without an X25519 key: they got no copy of the new key and
+/// need a new invite to keep reading the house.
+#[tauri::command]
+async fn rotate_house_key(signaling_server: String, server_id: String, removed_user_ids: Vec<String>) -> Result<Vec<String>, String> {
+    require_session()?;
+
+    let manager = ServerManager::new()
+        .map_err(|e| format!("Failed to initialize house manager: {}", e))?;
+
+    let mut server = manager.load_server(&server_id)
+        .map_err(|e| format!("Failed to load house: {}", e))?;
+
+    // The beacon only accepts rotations signed with the house signing key
+    if !server.has_signing_key() {
+        return Err("Only the house owner can rotate the house key".to_string());
+    }
+
+    for user_id in &removed_user_ids {
+        server.remove_member(user_id);
+    }
+    let sealed_keys = server.rotate_symmetric_key()
+        .map_err(|e| format!("Failed to rotate house key: {}", e))?;
+
+    let unreachable: Vec<String> = server.members
+        .iter()
+        .filter(|m| !sealed_keys.contains_key(&m.user_id))
+        .map(|m| m.user_id.clone())
+        .collect();
+
+    let signature = server
+        .sign(&key_rotation_signing_payload(&server.signing_pubkey, server.key_epoch, &sealed_keys, &removed_user_ids))
+        .map_err(|e| format!("Failed to sign key rotation: {}", e))?;
+
+    // Save before uploading: if the upload fails, retrying rotates again to a newer epoch
+    // instead of reusing one the beacon may already have seen.
+    manager.save_server(&server)
+        .map_err(|e| format!("Failed to save house: {}", e))?;
+
+    let base = normalize_signaling_to_http(&signaling_server)?;
+    let url = format!("{}/api/servers/{}/keys", base, urlencoding::encode(&server.signing_pubkey));
+    let req = KeyRotationRequest {
+        key_epoch: server.key_epoch,
+        sealed_keys,
+        removed_user_ids,
+        signature,
+    };
+
+    let client = reqwest::Client::new();
+    let resp = client
+        .post(url)
+        .json(&req)
+        .send()
+        .await
+        .map_err(|e| format!("Failed to upload rotated house key: {}", e))?;
+
+    if !resp.status().is_success() {
+        let status = resp.status();
+        let body = resp.text().await.unwrap_or_default();
+        return Err(format!("Failed to upload rotated house key: HTTP {} {}", status, body));
+    }
+
+    // The active invite link carries the old key
+    if server.active_invite_uri.is_some() {
+        revoke_active_invite(signaling_server, server_id).await?;
+    } else {
+        publish_server_hint_opaque(signaling_server, server_id).await?;
+    }
+    Ok(unreachable)
+}
+
 #[derive(serde::Deserialize)]
 struct InviteResolveResponse {
     signing_pubkey: String,
@@ -873,7 +1444,8 @@ async fn resolve_invite_code(signaling_server: String, invite_code: String) -> R
     require_session()?;
 
     let base = normalize_signaling_to_http(&signaling_server)?;
-    let code = invite_code.trim().to_ascii_uppercase();
+    let (code, _secret) = split_invite_code(&invite_code)?;
+    let code = code.to_ascii_uppercase();
     let url = format!("{}/api/invites/{}", base, urlencoding::encode(&code));
 
     let client = reqwest::Client::new();
@@ -910,6 +1482,11 @@ async fn create_temporary_invite(signaling_server: String, server_id: String, ma
     let server = manager.load_server(&server_id)
         .map_err(|e| format!("Failed to load house: {}", e))?;
 
+    // The beacon only accepts invites signed with the house signing key
+    if !server.has_signing_key() {
+        return Err("Only the house owner can create invites".to_string());
+    }
+
     // Generate a short human-shareable code (8 chars - easy to read over phone)
     const CHARSET: &[u8] = b"ABCDEFGHJKLMNPQRSTUVWXYZ23456789";
     let mut code = String::with_capacity(8);
@@ -919,22 +1496,28 @@ async fn create_temporary_invite(signaling_server: String, server_id: String, ma
         code.push(CHARSET[(b as usize) % CHARSET.len()] as char);
     }
 
-    let mut server_info = server.to_info();
     let symmetric_key = server.get_symmetric_key()
         .ok_or_else(|| "Server missing symmetric key".to_string())?;
 
+    // Secret fragment: never sent to the beacon, so the stored payload can't be brute-forced from the short code
+    let mut secret_bytes = [0u8; 16];
+    rand::thread_rng().fill_bytes(&mut secret_bytes);
+    let secret = hex::encode(secret_bytes);
+
     // Include active invite fields in the payload so new joiners see the current invite state immediately.
     // We treat the invite as "active until revoked"; this timestamp is just to allow UI hiding if it's very stale.
-    let invite_uri = format!("cordia://{}@{}", code, signaling_server.trim());
+    let invite_uri = format!("cordia://{}@{}#{}.{}", code, signaling_server.trim(), INVITE_V2_TAG, secret);
     let expires_at = chrono::Utc::now() + chrono::Duration::days(30);
-    server_info.active_invite_uri = Some(invite_uri.clone());
-    server_info.active_invite_expires_at = Some(expires_at);
+    let mut invited = server.clone();
+    invited.set_active_invite(Some(invite_uri.clone()), Some(expires_at));
+    let server_info = invited.to_info();
 
     let payload = InviteTokenPayload {
         server: server_info.clone(),
         server_symmetric_key_b64: base64::encode(&symmetric_key),
+        key_epoch: server.key_epoch,
     };
-    let encrypted_payload = encrypt_invite_payload(&code, &payload)?;
+    let encrypted_payload = encrypt_invite_payload(&code, &secret, &payload)?;
 
     // POST to signaling server
     let base = normalize_signaling_to_http(&signaling_server)?;
@@ -944,12 +1527,16 @@ async fn create_temporary_invite(signaling_server: String, server_id: String, ma
         urlencoding::encode(&server_info.signing_pubkey)
     );
 
+    let signature = server
+        .sign(&invite_create_signing_payload(&server_info.signing_pubkey, &code, max_uses, &encrypted_payload))
+        .map_err(|e| format!("Failed to sign invite: {}", e))?;
+
     let client = reqwest::Client::new();
     let req = InviteTokenCreateRequest {
         code: code.clone(),
         max_uses,
         encrypted_payload,
-        signature: "".to_string(),
+        signature,
     };
     let resp = client
         .post(url)
@@ -993,10 +1580,27 @@ async fn revoke_active_invite(signaling_server: String, server_id: String) -> Re
         .map(|s| s.to_string());
 
     if let Some(code) = code {
+        // The beacon only accepts revocations signed with the house signing key
+        let signature = server
+            .sign(&invite_revoke_signing_payload(&server.signing_pubkey, code.trim()))
+            .map_err(|_| "Only the house owner can revoke invites".to_string())?;
+
         let base = normalize_signaling_to_http(&signaling_server)?;
         let url = format!("{}/api/invites/{}/revoke", base, urlencoding::encode(code.trim()));
         let client = reqwest::Client::new();
-        let _ = client.post(url).send().await;
+        let resp = client
+            .post(url)
+            .json(&serde_json::json!({ "signature": signature }))
+            .send()
+            .await
+            .map_err(|e| format!("Failed to revoke invite on signaling server: {}", e))?;
+
+        // 404: already expired or used up, so there is nothing left to revoke
+        if !resp.status().is_success() && resp.status().as_u16() != 404 {
+            let status = resp.status();
+            let body = resp.text().await.unwrap_or_default();
+            return Err(format!("Failed to revoke invite: HTTP {} {}", status, body));
+        }
     }
 
     manager
@@ -1009,31 +1613,53 @@ async fn revoke_active_invite(signaling_server: String, server_id: String) -> Re
     Ok(())
 }
 
+/// Invite record from `GET /api/invites/{code}` or its `/redeem`
+async fn read_invite_record(resp: reqwest::Response) -> Result<InviteTokenRecord, String> {
+    if resp.status().as_u16() == 404 {
+        return Err("Invite expired or not found".to_string());
+    }
+    if !resp.status().is_success() {
+        return Err(format!("Failed to fetch invite: HTTP {}", resp.status()));
+    }
+    resp.json::<InviteTokenRecord>().await
+        .map_err(|e| format!("Failed to parse invite token: {}", e))
+}
+
 #[tauri::command]
 async fn redeem_temporary_invite(signaling_server: String, code: String, user_id: String, display_name: String) -> Result<ServerInfo, String> {
     require_session()?;
 
+    // Only the lookup code goes to the beacon; the v2 secret stays local
+    let (code, secret) = split_invite_code(&code)?;
+
     let base = normalize_signaling_to_http(&signaling_server)?;
-    let url = format!("{}/api/invites/{}/redeem", base, urlencoding::encode(code.trim()));
+    let url = format!("{}/api/invites/{}", base, urlencoding::encode(&code));
 
+    // Our identity key becomes an authorized hint signer for the house on redemption
+    let identity = IdentityManager::new()
+        .and_then(|m| m.load_identity())
+        .map_err(|e| format!("Failed to load identity: {}", e))?;
+
+    // Redeeming uses up an invite use and authorizes our key, so first make sure we can open
+    // the payload at all (e.g. a v2 link pasted without its '#' part can't be)
     let client = reqwest::Client::new();
     let resp = client
-        .post(url)
+        .get(&url)
         .send()
         .await
         .map_err(|e| format!("Failed to fetch invite: {}", e))?;
+    let record = read_invite_record(resp).await?;
+    decrypt_invite_payload(&code, secret.as_deref(), &record.encrypted_payload)?;
 
-    if resp.status().as_u16() == 404 {
-        return Err("Invite expired or not found".to_string());
-    }
-    if !resp.status().is_success() {
-        return Err(format!("Failed to fetch invite: HTTP {}", resp.status()));
-    }
-
-    let record = resp.json::<InviteTokenRecord>().await
-        .map_err(|e| format!("Failed to parse invite token: {}", e))?;
+    let resp = client
+        .post(format!("{}/redeem", url))
+        .json(&serde_json::json!({ "member_pubkey": identity.public_key }))
+        .send()
+        .await
+        .map_err(|e| format!("Failed to redeem invite: {}", e))?;
+    let record = read_invite_record(resp).await?;
 
-    let payload = decrypt_invite_payload(code.trim(), &record.encrypted_payload)?;
+    let payload = decrypt_invite_payload(&code, secret.as_deref(), &record.encrypted_payload)?;
     let symmetric_key = base64::decode(&payload.server_symmetric_key_b64)
         .map_err(|e| format!("Invalid symmetric key b64: {}", e))?;
 
@@ -1052,11 +1678,11 @@ async fn redeem_temporary_invite(signaling_server: String, code: String, user_id
 
     // Import merged house + symmetric key locally
     // Returns the actual house ID used (may differ from merged_server.id if house already existed)
-    let actual_server_id = manager.import_server_invite(merged_server.clone(), symmetric_key)
+    let actual_server_id = manager.import_server_invite(merged_server.clone(), symmetric_key, payload.key_epoch)
         .map_err(|e| format!("Failed to import house from invite: {}", e))?;
 
     // Add member locally using the actual house ID
-    let updated = manager.add_member_to_server(&actual_server_id, user_id, display_name)
+    let updated = manager.add_member_to_server(&actual_server_id, user_id, display_name, own_x25519_pubkey())
         .map_err(|e| format!("Failed to join house: {}", e))?;
 
     // CRITICAL: Publish updated encrypted hint as part of redeem flow.
@@ -1067,6 +1693,113 @@ async fn redeem_temporary_invite(signaling_server: String, code: String, user_id
     Ok(updated.to_info())
 }
 
+/// Authorize a member identity key (hex) on the beacon with the house signing key (owner only).
+/// Authorized keys may sign hints and acks.
+async fn authorize_member_key(signaling_server: &str, server: &Server, member_pubkey: &str) -> Result<(), String> {
+    let signature = server
+        .sign(&member_authorize_signing_payload(&server.signing_pubkey, member_pubkey))
+        .map_err(|e| format!("Failed to sign member authorization: {}", e))?;
+
+    let base = normalize_signaling_to_http(signaling_server)?;
+    let url = format!("{}/api/servers/{}/members", base, urlencoding::encode(&server.signing_pubkey));
+    let client = reqwest::Client::new();
+    let resp = client
+        .post(url)
+        .json(&serde_json::json!({ "member_pubkey": member_pubkey, "signature": signature }))
+        .send()
+        .await
+        .map_err(|e| format!("Failed to authorize member on signaling server: {}", e))?;
+
+    if !resp.status().is_success() {
+        return Err(format!("Failed to authorize member: HTTP {}", resp.status()));
+    }
+    Ok(())
+}
+
+/// Invite one specific user (owner only): seal the house key to the X25519 key derived from their
+/// identity public key and authorize that key on the beacon. Only that identity can open the link.
+#[tauri::command]
+async fn create_sealed_invite(signaling_server: String, server_id: String, recipient_public_key: String) -> Result<String, String> {
+    require_session()?;
+
+    let manager = ServerManager::new()
+        .map_err(|e| format!("Failed to initialize house manager: {}", e))?;
+
+    let server = manager.load_server(&server_id)
+        .map_err(|e| format!("Failed to load house: {}", e))?;
+
+    // The beacon only accepts member authorizations signed with the house signing key
+    if !server.has_signing_key() {
+        return Err("Only the house owner can create invites".to_string());
+    }
+
+    let recipient_public_key = recipient_public_key.trim().to_ascii_lowercase();
+    let recipient_bytes: [u8; 32] = hex::decode(&recipient_public_key)
+        .ok()
+        .and_then(|bytes| bytes.try_into().ok())
+        .ok_or_else(|| "Invalid public key (expected 64 hex characters)".to_string())?;
+    let recipient_key = ed25519_dalek::VerifyingKey::from_bytes(&recipient_bytes)
+        .map_err(|_| "Invalid public key".to_string())?;
+
+    let sealed_key = server.generate_invite(&recipient_key.to_montgomery().to_bytes())
+        .map_err(|e| format!("Failed to seal house key: {}", e))?;
+
+    // Sealed invites are never redeemed, so authorize the recipient's hint signatures up front
+    authorize_member_key(&signaling_server, &server, &recipient_public_key).await?;
+
+    // The recipient reads the house state from the hint, so make sure it is current
+    publish_server_hint_opaque(signaling_server.clone(), server_id).await?;
+
+    Ok(format!(
+        "cordia://{}@{}#{}.{}.{}",
+        server.signing_pubkey,
+        signaling_server.trim(),
+        SEALED_INVITE_TAG,
+        server.key_epoch,
+        sealed_key
+    ))
+}
+
+/// Join a house from a sealed invite addressed to our identity.
+#[tauri::command]
+async fn accept_sealed_invite(signaling_server: String, invite: String, user_id: String, display_name: String) -> Result<ServerInfo, String> {
+    require_session()?;
+
+    let invite = parse_sealed_invite(&invite)?;
+
+    let identity_manager = IdentityManager::new()
+        .map_err(|e| format!("Failed to initialize identity manager: {}", e))?;
+    let x25519_secret = identity_manager.x25519_secret()
+        .map_err(|e| format!("Failed to derive X25519 key: {}", e))?;
+    let symmetric_key = Server::decrypt_invite(&invite.sealed_key, &x25519_secret)
+        .map_err(|_| "This invite was sealed to a different identity".to_string())?;
+
+    let hint = get_server_hint(signaling_server.clone(), invite.signing_pubkey.clone()).await?
+        .ok_or_else(|| "House not found on this beacon".to_string())?;
+    if hint.key_epoch != invite.key_epoch {
+        return Err("This invite is out of date (the house key was rotated); ask the owner for a new one".to_string());
+    }
+
+    let server_info = decrypt_server_hint(&symmetric_key, &hint.encrypted_state)?;
+    if server_info.signing_pubkey != invite.signing_pubkey {
+        return Err("Invite does not match the house on this beacon".to_string());
+    }
+
+    let manager = ServerManager::new()
+        .map_err(|e| format!("Failed to initialize house manager: {}", e))?;
+
+    let actual_server_id = manager.import_server_invite(server_info, symmetric_key, invite.key_epoch)
+        .map_err(|e| format!("Failed to import house from invite: {}", e))?;
+
+    let updated = manager.add_member_to_server(&actual_server_id, user_id, display_name, own_x25519_pubkey())
+        .map_err(|e| format!("Failed to join house: {}", e))?;
+
+    // Announce the new membership (our identity key was authorized when the invite was sealed)
+    publish_server_hint_opaque(signaling_server.clone(), actual_server_id).await?;
+
+    Ok(updated.to_info())
+}
+
 #[tauri::command]
 async fn check_signaling_server(url: Option<String>) -> Result<bool, String> {
     let server_url = url.unwrap_or_else(get_default_signaling_url);
@@ -1197,6 +1930,10 @@ fn switch_account(account_id: String) -> Result<(), String> {
 fn logout_account() -> Result<(), String> {
     let manager = AccountManager::new()
         .map_err(|e| format!("Failed to create account manager: {}", e))?;
+    // Drop the vault key so a passphrase-protected account needs it again
+    if let Ok(Some(account_id)) = manager.get_current_account_id() {
+        vault::lock(&account_id);
+    }
     manager.clear_session()
         .map_err(|e| format!("Failed to logout: {}", e))
 }
@@ -1218,6 +1955,43 @@ fn delete_account(account_id: String) -> Result<(), String> {
         .map_err(|e| format!("Failed to delete account: {}", e))
 }
 
+#[tauri::command]
+fn get_vault_status(account_id: String) -> Result<VaultStatus, String> {
+    // NO GUARD: Account select screen needs to know whether to ask for a passphrase
+    Ok(vault::status(&account_id))
+}
+
+#[tauri::command]
+fn unlock_account(account_id: String, passphrase: String) -> Result<(), String> {
+    // NO GUARD: Runs before switch_account for passphrase-protected accounts
+    vault::unlock(&account_id, &passphrase)
+        .map_err(|e| format!("Failed to unlock account: {}", e))
+}
+
+#[tauri::command]
+fn lock_account() -> Result<(), String> {
+    // GUARDED: Requires active session
+    let account_id = require_session()?;
+    vault::lock(&account_id);
+    Ok(())
+}
+
+#[tauri::command]
+fn enable_passphrase_protection(passphrase: String) -> Result<(), String> {
+    // GUARDED: Requires active session
+    let account_id = require_session()?;
+    vault::enable(&account_id, &passphrase)
+        .map_err(|e| format!("Failed to enable passphrase protection: {}", e))
+}
+
+#[tauri::command]
+fn disable_passphrase_protection(passphrase: String) -> Result<(), String> {
+    // GUARDED: Requires active session
+    let account_id = require_session()?;
+    vault::disable(&account_id, &passphrase)
+        .map_err(|e| format!("Failed to disable passphrase protection: {}", e))
+}
+
 fn main() {
     tauri::Builder::default()
         .invoke_handler(tauri::generate_handler![
@@ -1226,6 +2000,9 @@ fn main() {
             check_account_has_identity,
             create_identity,
             load_identity,
+            reregister_upgraded_identity,
+            sign_auth_challenge,
+            sign_event_ack,
             export_identity,
             export_identity_for_account,
             export_full_identity,
@@ -1241,6 +2018,12 @@ fn main() {
             get_current_account_id,
             delete_account,
             register_key_file_association_command,
+            // Passphrase protection (vault) commands
+            get_vault_status,
+            unlock_account,
+            lock_account,
+            enable_passphrase_protection,
+            disable_passphrase_protection,
             // Audio settings commands
             load_audio_settings,
             save_audio_settings,
@@ -1260,6 +2043,9 @@ fn main() {
             publish_server_hint_opaque,
             publish_server_hint_member_left,
             fetch_and_import_server_hint_opaque,
+            rotate_house_key,
+            create_sealed_invite,
+            accept_sealed_invite,
             create_temporary_invite,
             revoke_active_invite,
             redeem_temporary_invite,
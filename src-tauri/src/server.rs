@@ -1,4 +1,5 @@
 use serde::{Deserialize, Serialize};
+use std::collections::BTreeMap;
 use std::path::PathBuf;
 use std::fs;
 use thiserror::Error;
@@ -11,6 +12,8 @@ use rand::rngs::OsRng;
 use zeroize::Zeroize;
 
 use crate::account_manager::AccountManager;
+use crate::house_state::{HouseState, InviteState};
+use crate::vault;
 
 #[derive(Error, Debug)]
 pub enum ServerError {
@@ -44,6 +47,8 @@ pub enum ServerError {
     KeyConversion,
     #[error("Invalid invite URI")]
     InvalidInviteUri,
+    #[error("Account is locked - unlock it with its passphrase")]
+    Locked,
 }
 
 #[derive(Serialize, Deserialize, Clone, Debug, PartialEq)]
@@ -93,6 +98,15 @@ pub struct ServerStorage {
     pub encrypted_signing_secret: Option<String>,  // Base64-encoded encrypted Ed25519 signing key
     #[serde(default)]
     pub encrypted_symmetric_key: Option<String>,   // Base64-encoded encrypted XChaCha20 key
+    /// Secrets encrypted with the device key mixed with the account's vault key
+    #[serde(default)]
+    pub secrets_wrapped: bool,
+    /// Epoch of the current symmetric key (0 until the first rekey)
+    #[serde(default)]
+    pub key_epoch: u32,
+    /// Symmetric keys from earlier epochs, encrypted like the current one
+    #[serde(default)]
+    pub encrypted_previous_keys: Vec<PreviousKeyStorage>,
 
     pub invite_uri: String,
     #[serde(default)]
@@ -110,12 +124,34 @@ pub struct ServerStorage {
     #[serde(default)]
     pub active_invite_expires_at: Option<DateTime<Utc>>,
 
+    /// Replicated state behind name/members/chats/invite (empty in files from older versions)
+    #[serde(default)]
+    pub house_state: HouseState,
+
     // Legacy field for backwards compatibility
     #[serde(default)]
     pub public_key: String,
 }
 
+/// A retired symmetric key, kept only to read data from its epoch
+#[derive(Serialize, Deserialize, Clone, Debug)]
+pub struct PreviousKeyStorage {
+    pub key_epoch: u32,
+    pub encrypted_key: String,  // Base64-encoded encrypted XChaCha20 key
+}
+
 impl ServerStorage {
+    /// Replicated state, rebuilt from the plain fields for files written before it existed
+    fn house_state(&self) -> HouseState {
+        if !self.house_state.is_empty() {
+            return self.house_state.clone();
+        }
+        HouseState::from_snapshot(&self.name, &self.members, &self.chats, InviteState {
+            uri: self.active_invite_uri.clone(),
+            expires_at: self.active_invite_expires_at,
+        })
+    }
+
     fn generate_legacy_invite_code() -> String {
         use rand::Rng;
         const CHARSET: &[u8] = b"ABCDEFGHJKLMNPQRSTUVWXYZ23456789";
@@ -150,10 +186,41 @@ pub struct ServerInfo {
     pub public_key: String,
     pub has_symmetric_key: bool,
     pub has_signing_key: bool,
+    /// Replicated state behind name/members/chats/invite; hints from older clients omit it
+    #[serde(default)]
+    pub house_state: HouseState,
+}
+
+impl ServerInfo {
+    /// Replicated state, rebuilt from the snapshot fields when the sender didn't include it
+    pub fn house_state(&self) -> HouseState {
+        if !self.house_state.is_empty() {
+            return self.house_state.clone();
+        }
+        HouseState::from_snapshot(&self.name, &self.members, &self.chats, InviteState {
+            uri: self.active_invite_uri.clone(),
+            expires_at: self.active_invite_expires_at,
+        })
+    }
+
+    /// Replace the replicated state and the fields derived from it
+    pub fn with_house_state(mut self, state: HouseState) -> Self {
+        if let Some(name) = state.name() {
+            self.name = name.to_string();
+        }
+        self.members = state.members();
+        self.chats = state.chats();
+        let invite = state.invite();
+        self.active_invite_uri = invite.uri;
+        self.active_invite_expires_at = invite.expires_at;
+        self.house_state = state;
+        self
+    }
 }
 
 /// Runtime server struct with decrypted secrets in memory
 /// Secrets are zeroized on drop
+/// name/chats/members/active_invite_* are views of `state`; change them through the methods below
 #[derive(Clone)]
 pub struct Server {
     pub id: String,
@@ -161,6 +228,7 @@ pub struct Server {
     pub created_at: DateTime<Utc>,
     pub chats: Vec<Chat>,
     pub members: Vec<ServerMember>,
+    state: HouseState,
 
     // === SIGNING (Ed25519) - for authentication ===
     pub signing_pubkey: String,  // Base64-encoded Ed25519 verifying key
@@ -168,6 +236,8 @@ pub struct Server {
 
     // === ENCRYPTION (XChaCha20-Poly1305) - THE shared secret ===
     server_symmetric_key: Option<Vec<u8>>,  // 256-bit symmetric key (local only, zeroized on drop)
+    pub key_epoch: u32,  // Bumped on every rekey
+    previous_symmetric_keys: Vec<(u32, Vec<u8>)>,  // (epoch, key) for reading old data only (zeroized on drop)
 
     pub invite_uri: String,
     pub connection_mode: ConnectionMode,
@@ -189,6 +259,9 @@ impl Drop for Server {
         if let Some(ref mut key) = self.server_symmetric_key {
             key.zeroize();
         }
+        for (_, key) in self.previous_symmetric_keys.iter_mut() {
+            key.zeroize();
+        }
     }
 }
 
@@ -236,15 +309,23 @@ impl Server {
         // Legacy invite code for backwards compatibility
         let invite_code = ServerStorage::generate_legacy_invite_code();
 
+        let mut state = HouseState::default();
+        state.set_name(name.clone());
+        state.put_member(creator.clone());
+        state.put_chat(general_chat.clone());
+
         Ok(Server {
             id,
             name,
             created_at: now,
             chats: vec![general_chat],
             members: vec![creator],
+            state,
             signing_pubkey: signing_pubkey.clone(),
             signing_secret: Some(signing_secret),
             server_symmetric_key: Some(symmetric_key),
+            key_epoch: 0,
+            previous_symmetric_keys: Vec::new(),
             invite_uri,
             connection_mode: ConnectionMode::Signaling,
             signaling_url,
@@ -270,7 +351,11 @@ impl Server {
     pub fn generate_invite(&self, recipient_x25519_pubkey: &[u8; 32]) -> Result<String, ServerError> {
         let symmetric_key = self.server_symmetric_key.as_ref()
             .ok_or(ServerError::MissingSymmetricKey)?;
+        Self::seal_key_to(recipient_x25519_pubkey, symmetric_key)
+    }
 
+    /// Seal a symmetric key to an X25519 public key (format read by `decrypt_invite`)
+    fn seal_key_to(recipient_x25519_pubkey: &[u8; 32], symmetric_key: &[u8]) -> Result<String, ServerError> {
         // Generate ephemeral X25519 keypair for this invite
         let ephemeral_secret = EphemeralSecret::random_from_rng(OsRng);
         let ephemeral_public = X25519PublicKey::from(&ephemeral_secret);
@@ -288,7 +373,7 @@ impl Server {
         // Encrypt symmetric key
         let cipher = XChaCha20Poly1305::new((&derived_key).into());
         let nonce = XChaCha20Poly1305::generate_nonce(&mut OsRng);
-        let ciphertext = cipher.encrypt(&nonce, symmetric_key.as_ref())
+        let ciphertext = cipher.encrypt(&nonce, symmetric_key)
             .map_err(|_| ServerError::EncryptionFailed)?;
 
         // Pack: ephemeral_pubkey (32) || nonce (24) || ciphertext
@@ -338,6 +423,55 @@ impl Server {
         Ok(plaintext)
     }
 
+    /// Replace the symmetric key with a fresh one and seal it to every member that has
+    /// published an X25519 key. The old key moves to `previous_symmetric_keys`.
+    /// Returns user_id -> sealed key; members without a usable X25519 key are left out.
+    pub fn rotate_symmetric_key(&mut self) -> Result<BTreeMap<String, String>, ServerError> {
+        if self.server_symmetric_key.is_none() {
+            return Err(ServerError::MissingSymmetricKey);
+        }
+
+        let mut new_key = vec![0u8; 32];
+        use rand::RngCore;
+        OsRng.fill_bytes(&mut new_key);
+
+        let mut sealed_keys = BTreeMap::new();
+        for member in &self.members {
+            let Some(pubkey) = member.x25519_pubkey.as_ref()
+                .and_then(|k| base64::decode(k).ok())
+                .and_then(|k| <[u8; 32]>::try_from(k.as_slice()).ok()) else {
+                continue;
+            };
+            sealed_keys.insert(member.user_id.clone(), Self::seal_key_to(&pubkey, &new_key)?);
+        }
+
+        self.install_rotated_key(self.key_epoch + 1, new_key);
+        Ok(sealed_keys)
+    }
+
+    /// Adopt a key from a newer epoch; the current key is kept for reading old data.
+    /// Keys from the current or an older epoch are ignored.
+    pub fn install_rotated_key(&mut self, key_epoch: u32, key: Vec<u8>) {
+        if key_epoch <= self.key_epoch && self.server_symmetric_key.is_some() {
+            return;
+        }
+        if let Some(old) = self.server_symmetric_key.replace(key) {
+            self.previous_symmetric_keys.push((self.key_epoch, old));
+        }
+        self.key_epoch = key_epoch;
+    }
+
+    /// Symmetric key for a given epoch (the current key or a retired one)
+    pub fn symmetric_key_for_epoch(&self, key_epoch: u32) -> Option<Vec<u8>> {
+        if key_epoch == self.key_epoch {
+            return self.server_symmetric_key.clone();
+        }
+        self.previous_symmetric_keys
+            .iter()
+            .find(|(epoch, _)| *epoch == key_epoch)
+            .map(|(_, key)| key.clone())
+    }
+
     /// Sign data with house signing key (Ed25519)
     pub fn sign(&self, data: &[u8]) -> Result<String, ServerError> {
         let secret = self.signing_secret.as_ref()
@@ -408,14 +542,52 @@ impl Server {
             .map_err(|_| ServerError::DecryptionFailed)
     }
 
-    pub fn add_member(&mut self, user_id: String, display_name: String) {
+    pub fn add_member(&mut self, user_id: String, display_name: String, x25519_pubkey: Option<String>) {
         let member = ServerMember {
             user_id,
             display_name,
             joined_at: Utc::now(),
-            x25519_pubkey: None,
+            x25519_pubkey,
         };
-        self.members.push(member);
+        self.state.put_member(member);
+        self.refresh_views();
+    }
+
+    /// Remove a member (leaves a tombstone so stale copies can't re-add them)
+    pub fn remove_member(&mut self, user_id: &str) -> bool {
+        let removed = self.state.remove_member(user_id);
+        self.refresh_views();
+        removed
+    }
+
+    /// Record a member's X25519 key; returns false if they aren't a member or already have it
+    pub fn set_member_x25519_pubkey(&mut self, user_id: &str, x25519_pubkey: String) -> bool {
+        let Some(member) = self.members.iter().find(|m| m.user_id == user_id) else {
+            return false;
+        };
+        if member.x25519_pubkey.as_deref() == Some(x25519_pubkey.as_str()) {
+            return false;
+        }
+        let mut member = member.clone();
+        member.x25519_pubkey = Some(x25519_pubkey);
+        self.state.put_member(member);
+        self.refresh_views();
+        true
+    }
+
+    /// Move a member entry to a new user_id (after an identity upgrade), keeping its name and
+    /// join time. Returns false if `old_user_id` isn't a member.
+    pub fn rename_member(&mut self, old_user_id: &str, new_user_id: &str, x25519_pubkey: Option<String>) -> bool {
+        let Some(member) = self.members.iter().find(|m| m.user_id == old_user_id) else {
+            return false;
+        };
+        let mut member = member.clone();
+        member.user_id = new_user_id.to_string();
+        member.x25519_pubkey = x25519_pubkey;
+        self.state.remove_member(old_user_id);
+        self.state.put_member(member);
+        self.refresh_views();
+        true
     }
 
     pub fn add_chat(&mut self, name: String, description: Option<String>) -> Chat {
@@ -424,17 +596,38 @@ impl Server {
             name,
             description,
         };
-        self.chats.push(chat.clone());
+        self.state.put_chat(chat.clone());
+        self.refresh_views();
         chat
     }
 
     pub fn remove_chat(&mut self, chat_id: &str) -> bool {
-        if let Some(pos) = self.chats.iter().position(|r| r.id == chat_id) {
-            self.chats.remove(pos);
-            true
-        } else {
-            false
+        let removed = self.state.remove_chat(chat_id);
+        self.refresh_views();
+        removed
+    }
+
+    pub fn set_active_invite(&mut self, uri: Option<String>, expires_at: Option<DateTime<Utc>>) {
+        self.state.set_invite(InviteState { uri, expires_at });
+        self.refresh_views();
+    }
+
+    /// Merge another copy of the house state (e.g. from a hint)
+    pub fn merge_state(&mut self, other: &HouseState) {
+        self.state.merge(other);
+        self.refresh_views();
+    }
+
+    /// Recompute the plain fields from the replicated state
+    fn refresh_views(&mut self) {
+        if let Some(name) = self.state.name() {
+            self.name = name.to_string();
         }
+        self.members = self.state.members();
+        self.chats = self.state.chats();
+        let invite = self.state.invite();
+        self.active_invite_uri = invite.uri;
+        self.active_invite_expires_at = invite.expires_at;
     }
 
     /// Convert to storage format for serialization
@@ -466,6 +659,21 @@ impl Server {
             None
         };
 
+        // Encrypt retired keys the same way
+        let mut encrypted_previous_keys = Vec::new();
+        for (key_epoch, key) in &self.previous_symmetric_keys {
+            let cipher = XChaCha20Poly1305::new(device_key.into());
+            let nonce = XChaCha20Poly1305::generate_nonce(&mut OsRng);
+            let ciphertext = cipher.encrypt(&nonce, key.as_ref())
+                .map_err(|_| ServerError::EncryptionFailed)?;
+            let mut result = nonce.to_vec();
+            result.extend(ciphertext);
+            encrypted_previous_keys.push(PreviousKeyStorage {
+                key_epoch: *key_epoch,
+                encrypted_key: base64::encode(&result),
+            });
+        }
+
         Ok(ServerStorage {
             id: self.id.clone(),
             name: self.name.clone(),
@@ -475,12 +683,16 @@ impl Server {
             signing_pubkey: self.signing_pubkey.clone(),
             encrypted_signing_secret,
             encrypted_symmetric_key,
+            secrets_wrapped: false,
+            key_epoch: self.key_epoch,
+            encrypted_previous_keys,
             invite_uri: self.invite_uri.clone(),
             connection_mode: self.connection_mode.clone(),
             signaling_url: self.signaling_url.clone(),
             invite_code: self.invite_code.clone(),
             active_invite_uri: self.active_invite_uri.clone(),
             active_invite_expires_at: self.active_invite_expires_at,
+            house_state: self.state.clone(),
             public_key: self.public_key.clone(),
         })
     }
@@ -523,15 +735,35 @@ impl Server {
             None
         };
 
+        // Decrypt retired keys
+        let mut previous_symmetric_keys = Vec::new();
+        for previous in &storage.encrypted_previous_keys {
+            let data = base64::decode(&previous.encrypted_key)
+                .map_err(|e| ServerError::Base64Decode(e.to_string()))?;
+            if data.len() < 24 {
+                return Err(ServerError::InvalidCiphertext);
+            }
+            let nonce: [u8; 24] = data[..24].try_into()
+                .map_err(|_| ServerError::KeyConversion)?;
+            let cipher = XChaCha20Poly1305::new(device_key.into());
+            let plaintext = cipher.decrypt((&nonce).into(), &data[24..])
+                .map_err(|_| ServerError::DecryptionFailed)?;
+            previous_symmetric_keys.push((previous.key_epoch, plaintext));
+        }
+
+        let state = storage.house_state();
         Ok(Server {
             id: storage.id,
             name: storage.name,
             created_at: storage.created_at,
             chats: storage.chats,
             members: storage.members,
+            state,
             signing_pubkey: storage.signing_pubkey.clone(),
             signing_secret,
             server_symmetric_key,
+            key_epoch: storage.key_epoch,
+            previous_symmetric_keys,
             invite_uri: storage.invite_uri,
             connection_mode: storage.connection_mode,
             signaling_url: storage.signaling_url,
@@ -544,15 +776,19 @@ impl Server {
 
     /// Create from storage format without decrypting secrets (read-only view)
     pub fn from_storage_readonly(storage: ServerStorage) -> Self {
+        let state = storage.house_state();
         Server {
             id: storage.id,
             name: storage.name,
             created_at: storage.created_at,
             chats: storage.chats,
             members: storage.members,
+            state,
             signing_pubkey: storage.signing_pubkey.clone(),
             signing_secret: None,
             server_symmetric_key: None,
+            key_epoch: storage.key_epoch,
+            previous_symmetric_keys: Vec::new(),
             invite_uri: storage.invite_uri,
             connection_mode: storage.connection_mode,
             signaling_url: storage.signaling_url,
@@ -605,6 +841,7 @@ impl Server {
             public_key: self.public_key.clone(),
             has_symmetric_key: self.server_symmetric_key.is_some(),
             has_signing_key: self.signing_secret.is_some(),
+            house_state: self.state.clone(),
         }
     }
 }
@@ -613,6 +850,10 @@ pub struct ServerManager {
     data_dir: PathBuf,
     account_id: Option<String>,
     device_key: [u8; 32],
+    /// Vault sub-key when the account is unlocked
+    wrap_key: Option<[u8; 32]>,
+    /// Passphrase protection is on: refuse to save without `wrap_key`
+    vault_enabled: bool,
 }
 
 impl ServerManager {
@@ -636,6 +877,8 @@ impl ServerManager {
             fs::create_dir_all(&houses_dir)?;
             Ok(Self {
                 data_dir,
+                wrap_key: vault::wrapping_key(&account_id, vault::HOUSES_KEY_PURPOSE),
+                vault_enabled: vault::is_enabled(&account_id),
                 account_id: Some(account_id),
                 device_key,
             })
@@ -648,6 +891,8 @@ impl ServerManager {
                 data_dir,
                 account_id: None,
                 device_key,
+                wrap_key: None,
+                vault_enabled: false,
             })
         }
     }
@@ -667,9 +912,36 @@ impl ServerManager {
             data_dir,
             account_id: Some(account_id.to_string()),
             device_key,
+            wrap_key: vault::wrapping_key(account_id, vault::HOUSES_KEY_PURPOSE),
+            vault_enabled: vault::is_enabled(account_id),
         })
     }
 
+    /// Override the vault key (used by vault migration to read and write with different keys)
+    pub(crate) fn with_wrap_key(mut self, wrap_key: Option<[u8; 32]>) -> Self {
+        self.vault_enabled = wrap_key.is_some();
+        self.wrap_key = wrap_key;
+        self
+    }
+
+    /// Key for house secrets: the device key, mixed with the vault key when wrapped
+    fn storage_key(&self, wrapped: bool) -> Result<[u8; 32], ServerError> {
+        if !wrapped {
+            return Ok(self.device_key);
+        }
+        let wrap_key = self.wrap_key.as_ref().ok_or(ServerError::Locked)?;
+        Ok(vault::mix_keys(&self.device_key, wrap_key))
+    }
+
+    /// Key for writing: never silently downgrade a protected account to device-only encryption
+    fn write_key(&self) -> Result<([u8; 32], bool), ServerError> {
+        if self.vault_enabled && self.wrap_key.is_none() {
+            return Err(ServerError::Locked);
+        }
+        let wrapped = self.wrap_key.is_some();
+        Ok((self.storage_key(wrapped)?, wrapped))
+    }
+
     fn get_device_key() -> Result<[u8; 32], ServerError> {
         // Derive a device-specific key from machine identifier
         // This mirrors the approach used in identity.rs
@@ -851,9 +1123,11 @@ impl ServerManager {
 
     pub fn save_server(&self, server: &Server) -> Result<(), ServerError> {
         let server_path = self.get_server_path(&server.id);
-        let storage = server.to_storage(&self.device_key)?;
+        let (key, wrapped) = self.write_key()?;
+        let mut storage = server.to_storage(&key)?;
+        storage.secrets_wrapped = wrapped;
         let json = serde_json::to_string_pretty(&storage)?;
-        fs::write(server_path, json)?;
+        vault::write_file_atomic(&server_path, json.as_bytes())?;
         Ok(())
     }
 
@@ -869,10 +1143,12 @@ impl ServerManager {
         fs::create_dir_all(&houses_dir)?;
         use chacha20poly1305::{XChaCha20Poly1305, aead::{Aead, KeyInit}};
         use rand::rngs::OsRng;
+
+        let (storage_key, secrets_wrapped) = self.write_key()?;
         
         // Encrypt symmetric key with device key
         let encrypted_symmetric_key = {
-            let cipher = XChaCha20Poly1305::new((&self.device_key).into());
+            let cipher = XChaCha20Poly1305::new((&storage_key).into());
             let nonce = XChaCha20Poly1305::generate_nonce(&mut OsRng);
             let ciphertext = cipher.encrypt(&nonce, plaintext_symmetric_key.as_ref())
                 .map_err(|_| ServerError::EncryptionFailed)?;
@@ -883,7 +1159,7 @@ impl ServerManager {
         
         // Encrypt signing secret if present
         let encrypted_signing_secret = if let Some(ref secret) = plaintext_signing_secret {
-            let cipher = XChaCha20Poly1305::new((&self.device_key).into());
+            let cipher = XChaCha20Poly1305::new((&storage_key).into());
             let nonce = XChaCha20Poly1305::generate_nonce(&mut OsRng);
             let ciphertext = cipher.encrypt(&nonce, secret.as_ref())
                 .map_err(|_| ServerError::EncryptionFailed)?;
@@ -957,6 +1233,12 @@ impl ServerManager {
             .and_then(|v| v.as_str())
             .map(|s| s.to_string())
             .unwrap_or_default();
+
+        // Retired keys are not exported; older data stays unreadable after a restore
+        let key_epoch: u32 = server_data.get("key_epoch")
+            .and_then(|v| v.as_u64())
+            .map(|v| v as u32)
+            .unwrap_or(0);
         
         // Create ServerStorage with encrypted keys
         let storage = ServerStorage {
@@ -968,19 +1250,23 @@ impl ServerManager {
             signing_pubkey,
             encrypted_signing_secret,
             encrypted_symmetric_key,
+            secrets_wrapped,
+            key_epoch,
+            encrypted_previous_keys: Vec::new(),
             invite_uri,
             connection_mode,
             signaling_url,
             invite_code,
             active_invite_uri,
             active_invite_expires_at,
+            house_state: HouseState::default(),
             public_key,
         };
         
         // Write to disk
         let server_path = self.get_server_path(&server_id);
         let json = serde_json::to_string_pretty(&storage)?;
-        fs::write(server_path, json)?;
+        vault::write_file_atomic(&server_path, json.as_bytes())?;
         
         Ok(())
     }
@@ -994,7 +1280,18 @@ impl ServerManager {
 
         let content = fs::read_to_string(&server_path)?;
         let storage: ServerStorage = serde_json::from_str(&content)?;
-        Server::from_storage(storage, &self.device_key)
+        let key = self.storage_key(storage.secrets_wrapped)?;
+        Server::from_storage(storage, &key)
+    }
+
+    /// Whether a house's secrets are wrapped with the vault key
+    pub(crate) fn server_is_wrapped(&self, server_id: &str) -> Result<bool, ServerError> {
+        let server_path = self.get_server_path(server_id);
+        if !server_path.exists() {
+            return Err(ServerError::NotFound(server_id.to_string()));
+        }
+        let storage: ServerStorage = serde_json::from_str(&fs::read_to_string(&server_path)?)?;
+        Ok(storage.secrets_wrapped)
     }
 
     /// Load server without decrypting secrets (for listing)
@@ -1077,20 +1374,52 @@ impl ServerManager {
         Ok(None)
     }
 
-    pub fn add_member_to_server(&self, server_id: &str, user_id: String, display_name: String) -> Result<Server, ServerError> {
+    pub fn add_member_to_server(
+        &self,
+        server_id: &str,
+        user_id: String,
+        display_name: String,
+        x25519_pubkey: Option<String>,
+    ) -> Result<Server, ServerError> {
         let mut server = self.load_server(server_id)?;
 
         // Check if user is already a member
-        if server.members.iter().any(|m| m.user_id == user_id) {
-            return Ok(server); // Already a member, just return the server
+        if let Some(member) = server.members.iter().find(|m| m.user_id == user_id) {
+            // Already a member: only record an X25519 key we didn't have
+            if member.x25519_pubkey.is_none() {
+                if let Some(x25519_pubkey) = x25519_pubkey {
+                    server.set_member_x25519_pubkey(&user_id, x25519_pubkey);
+                    self.save_server(&server)?;
+                }
+            }
+            return Ok(server);
         }
 
-        server.add_member(user_id, display_name);
+        server.add_member(user_id, display_name, x25519_pubkey);
         self.save_server(&server)?;
 
         Ok(server)
     }
 
+    /// Move our own member entry from `old_user_id` to `new_user_id` in every house.
+    /// Returns the ids of the houses that changed.
+    pub fn rename_own_member(
+        &self,
+        old_user_id: &str,
+        new_user_id: &str,
+        x25519_pubkey: Option<String>,
+    ) -> Result<Vec<String>, ServerError> {
+        let mut renamed = Vec::new();
+        for server_id in self.list_servers()? {
+            let mut server = self.load_server(&server_id)?;
+            if server.rename_member(old_user_id, new_user_id, x25519_pubkey.clone()) {
+                self.save_server(&server)?;
+                renamed.push(server_id);
+            }
+        }
+        Ok(renamed)
+    }
+
     pub fn add_chat_to_server(&self, server_id: &str, name: String, description: Option<String>) -> Result<Server, ServerError> {
         let mut server = self.load_server(server_id)?;
         server.add_chat(name, description);
@@ -1105,8 +1434,7 @@ impl ServerManager {
         active_invite_expires_at: Option<DateTime<Utc>>,
     ) -> Result<(), ServerError> {
         let mut server = self.load_server(server_id)?;
-        server.active_invite_uri = active_invite_uri;
-        server.active_invite_expires_at = active_invite_expires_at;
+        server.set_active_invite(active_invite_uri, active_invite_expires_at);
         self.save_server(&server)?;
         Ok(())
     }
@@ -1142,28 +1470,32 @@ impl ServerManager {
     pub fn import_server_hint(&self, info: ServerInfo) -> Result<(), ServerError> {
         // Find existing server by signing_pubkey (not by id, since id can differ)
         // This handles the case where we imported a server with a new UUID, but beacon provides different UUID
-        let (existing_server_id, preserve_encrypted_signing_secret, preserve_encrypted_symmetric_key) = 
-            match self.find_server_id_by_signing_pubkey(&info.signing_pubkey)? {
-                Some(existing_id) => {
-                    // Server exists - preserve its encrypted secrets
-                    let server_path = self.get_server_path(&existing_id);
-                    let (signing_secret, symmetric_key) = if server_path.exists() {
-                        match fs::read_to_string(&server_path)
-                            .ok()
-                            .and_then(|s| serde_json::from_str::<ServerStorage>(&s).ok())
-                        {
-                            Some(existing) => (existing.encrypted_signing_secret, existing.encrypted_symmetric_key),
-                            None => (None, None),
-                        }
-                    } else {
-                        (None, None)
-                    };
-                    (existing_id, signing_secret, symmetric_key)
-                }
-                None => {
-                    // New server - use the id from the beacon
-                    (info.id.clone(), None, None)
-                }
+        let (existing_server_id, existing) = match self.find_server_id_by_signing_pubkey(&info.signing_pubkey)? {
+            Some(existing_id) => {
+                // Server exists - preserve its encrypted secrets (and which key they are under)
+                let server_path = self.get_server_path(&existing_id);
+                let existing = fs::read_to_string(&server_path)
+                    .ok()
+                    .and_then(|s| serde_json::from_str::<ServerStorage>(&s).ok());
+                (existing_id, existing)
+            }
+            None => {
+                // New server - use the id from the beacon
+                (info.id.clone(), None)
+            }
+        };
+        // Merge with the local copy so writes (and tombstones) we already know about are kept
+        let info = Self::merge_house_state(info, existing.as_ref());
+        let (preserve_encrypted_signing_secret, preserve_encrypted_symmetric_key, preserve_secrets_wrapped, preserve_key_epoch, preserve_previous_keys) =
+            match existing {
+                Some(existing) => (
+                    existing.encrypted_signing_secret,
+                    existing.encrypted_symmetric_key,
+                    existing.secrets_wrapped,
+                    existing.key_epoch,
+                    existing.encrypted_previous_keys,
+                ),
+                None => (None, None, false, 0, Vec::new()),
             };
         
         let server_path = self.get_server_path(&existing_server_id);
@@ -1177,30 +1509,44 @@ impl ServerManager {
             signing_pubkey: info.signing_pubkey,
             encrypted_signing_secret: preserve_encrypted_signing_secret,
             encrypted_symmetric_key: preserve_encrypted_symmetric_key,
+            secrets_wrapped: preserve_secrets_wrapped,
+            key_epoch: preserve_key_epoch,
+            encrypted_previous_keys: preserve_previous_keys,
             invite_uri: info.invite_uri,
             connection_mode: info.connection_mode,
             signaling_url: info.signaling_url,
             invite_code: info.invite_code,
             active_invite_uri: info.active_invite_uri,
             active_invite_expires_at: info.active_invite_expires_at,
+            house_state: info.house_state,
             public_key: info.public_key,
         };
 
         let json = serde_json::to_string_pretty(&storage)?;
-        fs::write(server_path, json)?;
+        vault::write_file_atomic(&server_path, json.as_bytes())?;
         Ok(())
     }
 
+    /// Merge an incoming copy of the house with the local one, if any
+    fn merge_house_state(info: ServerInfo, local: Option<&ServerStorage>) -> ServerInfo {
+        let mut state = info.house_state();
+        if let Some(local) = local {
+            state.merge(&local.house_state());
+        }
+        info.with_house_state(state)
+    }
+
     /// Import a server from an invite token that contains the server symmetric key.
     /// This lets a new member decrypt future Option-B hints.
     /// Returns the actual server ID used (may differ from info.id if server already existed).
-    pub fn import_server_invite(&self, info: ServerInfo, server_symmetric_key: Vec<u8>) -> Result<String, ServerError> {
+    pub fn import_server_invite(&self, info: ServerInfo, server_symmetric_key: Vec<u8>, key_epoch: u32) -> Result<String, ServerError> {
         // Check if server already exists by signing_pubkey
         let existing_server_id_opt = self.find_server_id_by_signing_pubkey(&info.signing_pubkey)?;
+        let (storage_key, secrets_wrapped) = self.write_key()?;
         
         // Encrypt symmetric key with device key for local storage
         let encrypted_symmetric_key = {
-            let cipher = XChaCha20Poly1305::new((&self.device_key).into());
+            let cipher = XChaCha20Poly1305::new((&storage_key).into());
             let nonce = XChaCha20Poly1305::generate_nonce(&mut OsRng);
             let ciphertext = cipher.encrypt(&nonce, server_symmetric_key.as_ref())
                 .map_err(|_| ServerError::EncryptionFailed)?;
@@ -1211,23 +1557,25 @@ impl ServerManager {
 
         // Use existing server ID if found, otherwise use the ID from info
         let server_id = existing_server_id_opt.clone().unwrap_or(info.id.clone());
+
+        let existing = existing_server_id_opt.as_ref()
+            .and_then(|id| fs::read_to_string(self.get_server_path(id)).ok())
+            .and_then(|s| serde_json::from_str::<ServerStorage>(&s).ok());
+        let info = Self::merge_house_state(info, existing.as_ref());
         
-        // If server exists, preserve its encrypted_signing_secret
-        let preserve_encrypted_signing_secret = if let Some(ref existing_id) = existing_server_id_opt {
-            let server_path = self.get_server_path(existing_id);
-            if server_path.exists() {
-                match fs::read_to_string(&server_path)
-                    .ok()
-                    .and_then(|s| serde_json::from_str::<ServerStorage>(&s).ok())
-                {
-                    Some(existing) => existing.encrypted_signing_secret,
-                    None => None,
-                }
-            } else {
-                None
+        // If server exists, preserve its encrypted_signing_secret and retired keys
+        let (preserve_encrypted_signing_secret, preserve_previous_keys) = match existing {
+            Some(existing) if existing.secrets_wrapped == secrets_wrapped => {
+                (existing.encrypted_signing_secret, existing.encrypted_previous_keys)
             }
-        } else {
-            None
+            Some(existing) => {
+                // Stored under the other key (vault migration in progress): re-encrypt it
+                let existing_key = self.storage_key(existing.secrets_wrapped)?;
+                let reencrypted = Server::from_storage(existing, &existing_key)?
+                    .to_storage(&storage_key)?;
+                (reencrypted.encrypted_signing_secret, reencrypted.encrypted_previous_keys)
+            }
+            None => (None, Vec::new()),
         };
 
         let storage = ServerStorage {
@@ -1239,18 +1587,22 @@ impl ServerManager {
             signing_pubkey: info.signing_pubkey,
             encrypted_signing_secret: preserve_encrypted_signing_secret,
             encrypted_symmetric_key,
+            secrets_wrapped,
+            key_epoch,
+            encrypted_previous_keys: preserve_previous_keys,
             invite_uri: info.invite_uri,
             connection_mode: info.connection_mode,
             signaling_url: info.signaling_url,
             invite_code: info.invite_code,
             active_invite_uri: info.active_invite_uri,
             active_invite_expires_at: info.active_invite_expires_at,
+            house_state: info.house_state,
             public_key: info.public_key,
         };
 
         let server_path = self.get_server_path(&storage.id);
         let json = serde_json::to_string_pretty(&storage)?;
-        fs::write(server_path, json)?;
+        vault::write_file_atomic(&server_path, json.as_bytes())?;
         Ok(server_id)  // Return the actual server ID used
     }
 }
@@ -0,0 +1,296 @@
+//! Optional passphrase protection for an account's local secrets.
+//!
+//! By default keys.dat and the house secrets are encrypted with keys derived from the
+//! machine identifier alone. When the vault is enabled for an account, a key derived
+//! from the user's passphrase (Argon2id) is mixed into both, so the files cannot be
+//! decrypted from a disk image or by another local process without the passphrase.
+//!
+//! The derived key lives in process memory from `unlock` until `lock`. Each encrypted
+//! file records whether it was wrapped, so a migration interrupted halfway can be
+//! finished on the next unlock.
+
+use std::collections::HashMap;
+use std::fs;
+use std::io::Write;
+use std::path::{Path, PathBuf};
+use std::sync::{Mutex, OnceLock};
+
+use chacha20poly1305::{
+    aead::{Aead, AeadCore, KeyInit, OsRng},
+    XChaCha20Poly1305,
+};
+use serde::{Deserialize, Serialize};
+use sha2::{Digest, Sha256};
+use thiserror::Error;
+use zeroize::Zeroize;
+
+use crate::account_manager::AccountManager;
+use crate::identity::{
+    derive_key_from_passphrase, IdentityManager, MIN_PASSPHRASE_LEN, PASSPHRASE_KDF_M_COST_KIB,
+    PASSPHRASE_KDF_P_COST, PASSPHRASE_KDF_T_COST,
+};
+use crate::server::ServerManager;
+
+/// Replace `path` with `contents` so that a crash leaves either the old file or the new one:
+/// write a sibling temp file, fsync it, then rename it over the original.
+pub(crate) fn write_file_atomic(path: &Path, contents: &[u8]) -> std::io::Result<()> {
+    let mut tmp_name = path.file_name().unwrap_or_default().to_os_string();
+    tmp_name.push(".tmp");
+    let tmp_path = path.with_file_name(tmp_name);
+    let mut file = fs::File::create(&tmp_path)?;
+    file.write_all(contents)?;
+    file.sync_all()?;
+    drop(file);
+    fs::rename(&tmp_path, path)?;
+    // Persist the rename itself (directories can't be opened for sync on Windows)
+    #[cfg(unix)]
+    if let Some(dir) = path.parent() {
+        fs::File::open(dir)?.sync_all()?;
+    }
+    Ok(())
+}
+
+/// Purpose labels for the sub-keys handed to each manager
+pub(crate) const IDENTITY_KEY_PURPOSE: &[u8] = b"cordia-vault-identity-v1";
+pub(crate) const HOUSES_KEY_PURPOSE: &[u8] = b"cordia-vault-houses-v1";
+
+const VAULT_FILE: &str = "vault.json";
+const VERIFIER_PLAINTEXT: &[u8] = b"cordia-vault-v1";
+
+#[derive(Error, Debug)]
+pub enum VaultError {
+    #[error("IO error: {0}")]
+    Io(#[from] std::io::Error),
+    #[error("Serialization error: {0}")]
+    Serialization(#[from] serde_json::Error),
+    #[error("Account error: {0}")]
+    Account(String),
+    #[error("Passphrase protection is not enabled for this account")]
+    NotEnabled,
+    #[error("Passphrase protection is already enabled for this account")]
+    AlreadyEnabled,
+    #[error("Wrong passphrase")]
+    WrongPassphrase,
+    #[error("Passphrase must be at least {0} characters")]
+    WeakPassphrase(usize),
+    #[error("Crypto error: {0}")]
+    Crypto(String),
+    #[error("Migration failed: {0}")]
+    Migration(String),
+}
+
+/// Stored in the account directory while passphrase protection is enabled
+#[derive(Serialize, Deserialize)]
+struct VaultConfig {
+    version: u8,
+    m_cost: u32,
+    t_cost: u32,
+    p_cost: u32,
+    salt: String,     // hex
+    verifier: String, // hex(nonce || ciphertext of VERIFIER_PLAINTEXT)
+}
+
+/// Whether the vault is enabled / unlocked for an account (returned to the frontend)
+#[derive(Serialize, Clone, Debug)]
+pub struct VaultStatus {
+    pub enabled: bool,
+    pub unlocked: bool,
+}
+
+/// account_id -> passphrase-derived key, for accounts unlocked in this process
+fn unlocked_keys() -> &'static Mutex<HashMap<String, [u8; 32]>> {
+    static KEYS: OnceLock<Mutex<HashMap<String, [u8; 32]>>> = OnceLock::new();
+    KEYS.get_or_init(|| Mutex::new(HashMap::new()))
+}
+
+fn vault_path(account_id: &str) -> Result<PathBuf, VaultError> {
+    let account_manager = AccountManager::new()
+        .map_err(|e| VaultError::Account(e.to_string()))?;
+    Ok(account_manager.get_account_dir(account_id).join(VAULT_FILE))
+}
+
+fn load_config(account_id: &str) -> Result<Option<VaultConfig>, VaultError> {
+    let path = vault_path(account_id)?;
+    if !path.exists() {
+        return Ok(None);
+    }
+    let json = fs::read_to_string(path)?;
+    Ok(Some(serde_json::from_str(&json)?))
+}
+
+fn derive_root_key(config: &VaultConfig, passphrase: &str) -> Result<[u8; 32], VaultError> {
+    let salt = hex::decode(&config.salt)
+        .map_err(|e| VaultError::Crypto(format!("Invalid salt: {}", e)))?;
+    derive_key_from_passphrase(passphrase, &salt, config.m_cost, config.t_cost, config.p_cost)
+        .map_err(|e| VaultError::Crypto(e.to_string()))
+}
+
+fn check_verifier(config: &VaultConfig, root_key: &[u8; 32]) -> Result<(), VaultError> {
+    let data = hex::decode(&config.verifier)
+        .map_err(|e| VaultError::Crypto(format!("Invalid verifier: {}", e)))?;
+    if data.len() < 24 {
+        return Err(VaultError::Crypto("Invalid verifier".to_string()));
+    }
+    let nonce: [u8; 24] = data[..24].try_into()
+        .map_err(|_| VaultError::Crypto("Invalid verifier nonce".to_string()))?;
+    let cipher = XChaCha20Poly1305::new(root_key.into());
+    let plaintext = cipher.decrypt((&nonce).into(), &data[24..])
+        .map_err(|_| VaultError::WrongPassphrase)?;
+    if plaintext != VERIFIER_PLAINTEXT {
+        return Err(VaultError::WrongPassphrase);
+    }
+    Ok(())
+}
+
+/// Sub-key for one kind of storage, so identity and house files never share a key
+fn sub_key(root_key: &[u8; 32], purpose: &[u8]) -> [u8; 32] {
+    let mut hasher = Sha256::new();
+    hasher.update(purpose);
+    hasher.update(root_key);
+    hasher.finalize().into()
+}
+
+/// Combine a device-derived key with a vault sub-key.
+/// Wrapped files need both this machine and the passphrase.
+pub(crate) fn mix_keys(device_key: &[u8; 32], wrap_key: &[u8; 32]) -> [u8; 32] {
+    let mut hasher = Sha256::new();
+    hasher.update(device_key);
+    hasher.update(wrap_key);
+    hasher.finalize().into()
+}
+
+pub fn is_enabled(account_id: &str) -> bool {
+    vault_path(account_id).map(|p| p.exists()).unwrap_or(false)
+}
+
+pub fn status(account_id: &str) -> VaultStatus {
+    let unlocked = unlocked_keys().lock().unwrap().contains_key(account_id);
+    VaultStatus {
+        enabled: is_enabled(account_id),
+        unlocked,
+    }
+}
+
+/// Sub-key for `purpose` if the account's vault is unlocked in this process
+pub(crate) fn wrapping_key(account_id: &str, purpose: &[u8]) -> Option<[u8; 32]> {
+    let keys = unlocked_keys().lock().unwrap();
+    keys.get(account_id).map(|root| sub_key(root, purpose))
+}
+
+/// Check the passphrase and keep the derived key in memory.
+/// Also finishes any migration that was interrupted.
+pub fn unlock(account_id: &str, passphrase: &str) -> Result<(), VaultError> {
+    let config = load_config(account_id)?.ok_or(VaultError::NotEnabled)?;
+    let root_key = derive_root_key(&config, passphrase)?;
+    check_verifier(&config, &root_key)?;
+
+    unlocked_keys().lock().unwrap().insert(account_id.to_string(), root_key);
+
+    // Re-save files still under the device key only, which finishes an interrupted migration
+    rewrap_account(account_id, Some(&root_key))
+}
+
+/// Forget the derived key. Wrapped files are unreadable until the next unlock.
+pub fn lock(account_id: &str) {
+    if let Some(mut key) = unlocked_keys().lock().unwrap().remove(account_id) {
+        key.zeroize();
+    }
+}
+
+/// Turn on passphrase protection and migrate the account's files in place
+pub fn enable(account_id: &str, passphrase: &str) -> Result<(), VaultError> {
+    if is_enabled(account_id) {
+        return Err(VaultError::AlreadyEnabled);
+    }
+    if passphrase.chars().count() < MIN_PASSPHRASE_LEN {
+        return Err(VaultError::WeakPassphrase(MIN_PASSPHRASE_LEN));
+    }
+
+    let salt: [u8; 16] = rand::random();
+    let mut config = VaultConfig {
+        version: 1,
+        m_cost: PASSPHRASE_KDF_M_COST_KIB,
+        t_cost: PASSPHRASE_KDF_T_COST,
+        p_cost: PASSPHRASE_KDF_P_COST,
+        salt: hex::encode(salt),
+        verifier: String::new(),
+    };
+    let root_key = derive_root_key(&config, passphrase)?;
+
+    let cipher = XChaCha20Poly1305::new((&root_key).into());
+    let nonce = XChaCha20Poly1305::generate_nonce(&mut OsRng);
+    let ciphertext = cipher.encrypt(&nonce, VERIFIER_PLAINTEXT)
+        .map_err(|e| VaultError::Crypto(e.to_string()))?;
+    let mut verifier = nonce.to_vec();
+    verifier.extend(ciphertext);
+    config.verifier = hex::encode(verifier);
+
+    // Write the config before touching any file: if migration stops halfway, the
+    // next unlock sees a mix of wrapped and unwrapped files and finishes the job.
+    write_file_atomic(&vault_path(account_id)?, serde_json::to_string_pretty(&config)?.as_bytes())?;
+    unlocked_keys().lock().unwrap().insert(account_id.to_string(), root_key);
+
+    rewrap_account(account_id, Some(&root_key))
+}
+
+/// Turn off passphrase protection, re-saving every file with the device key only
+pub fn disable(account_id: &str, passphrase: &str) -> Result<(), VaultError> {
+    let config = load_config(account_id)?.ok_or(VaultError::NotEnabled)?;
+    let root_key = derive_root_key(&config, passphrase)?;
+    check_verifier(&config, &root_key)?;
+    unlocked_keys().lock().unwrap().insert(account_id.to_string(), root_key);
+
+    // Config stays in place until every file is unwrapped, so a failure leaves
+    // the account readable with the passphrase.
+    rewrap_account(account_id, None)?;
+
+    fs::remove_file(vault_path(account_id)?)?;
+    lock(account_id);
+    Ok(())
+}
+
+/// Save every secret-bearing file of the account under `root_key` (or device-only if None).
+/// Files already in that mode are left alone, so an unlock after a finished migration writes nothing.
+fn rewrap_account(account_id: &str, root_key: Option<&[u8; 32]>) -> Result<(), VaultError> {
+    let unlocked = unlocked_keys().lock().unwrap().get(account_id).copied();
+
+    let identity_reader = IdentityManager::for_account(account_id)
+        .map_err(|e| VaultError::Migration(e.to_string()))?
+        .with_wrap_key(unlocked.map(|k| sub_key(&k, IDENTITY_KEY_PURPOSE)));
+    let identity_writer = IdentityManager::for_account(account_id)
+        .map_err(|e| VaultError::Migration(e.to_string()))?
+        .with_wrap_key(root_key.map(|k| sub_key(k, IDENTITY_KEY_PURPOSE)));
+
+    let wrap = root_key.is_some();
+    let identity_wrapped = identity_reader.identity_is_wrapped()
+        .map_err(|e| VaultError::Migration(format!("identity: {}", e)))?;
+    if identity_wrapped.is_some_and(|wrapped| wrapped != wrap) {
+        let identity = identity_reader.load_identity()
+            .map_err(|e| VaultError::Migration(format!("identity: {}", e)))?;
+        identity_writer.save_identity(&identity)
+            .map_err(|e| VaultError::Migration(format!("identity: {}", e)))?;
+    }
+
+    let houses_reader = ServerManager::for_account(account_id)
+        .map_err(|e| VaultError::Migration(e.to_string()))?
+        .with_wrap_key(unlocked.map(|k| sub_key(&k, HOUSES_KEY_PURPOSE)));
+    let houses_writer = ServerManager::for_account(account_id)
+        .map_err(|e| VaultError::Migration(e.to_string()))?
+        .with_wrap_key(root_key.map(|k| sub_key(k, HOUSES_KEY_PURPOSE)));
+
+    let server_ids = houses_reader.list_servers()
+        .map_err(|e| VaultError::Migration(e.to_string()))?;
+    for server_id in server_ids {
+        let wrapped = houses_reader.server_is_wrapped(&server_id)
+            .map_err(|e| VaultError::Migration(format!("house {}: {}", server_id, e)))?;
+        if wrapped == wrap {
+            continue;
+        }
+        let server = houses_reader.load_server(&server_id)
+            .map_err(|e| VaultError::Migration(format!("house {}: {}", server_id, e)))?;
+        houses_writer.save_server(&server)
+            .map_err(|e| VaultError::Migration(format!("house {}: {}", server_id, e)))?;
+    }
+
+    Ok(())
+}
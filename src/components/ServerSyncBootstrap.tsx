@@ -6,8 +6,9 @@ import { useVoicePresence } from '../contexts/VoicePresenceContext'
 import { useSignaling } from '../contexts/SignalingContext'
 import { useProfile } from '../contexts/ProfileContext'
 import { useRemoteProfiles } from '../contexts/RemoteProfilesContext'
-import { fetchAndImportServerHintOpaque, listServers } from '../lib/tauri'
+import { fetchAndImportServerHintOpaque, listServers, reregisterUpgradedIdentity, signAuthChallenge } from '../lib/tauri'
 import { requestMicrophonePermission } from '../lib/audio'
+import { eventSyncManager, type ServerEvent } from '../lib/event-sync'
 
 const DEBUG_LOG = (_payload: Record<string, unknown>) => { /* no-op: debug ingest removed */ }
 
@@ -31,6 +32,10 @@ export function ServerSyncBootstrap() {
   const wsRef = useRef<WebSocket | null>(null)
   const subscribedSigningPubkeysRef = useRef<Set<string>>(new Set())
   const activeSigningPubkeyRef = useRef<string | null>(null)
+  // Seq of the last server event seen per signing_pubkey; replay resumes from here after a reconnect
+  const lastEventSeqsRef = useRef<Map<string, number>>(new Map())
+  // Beacon session to resume after a drop, so houses don't see us go offline and back
+  const resumeTokenRef = useRef<string | null>(null)
 
   // Request microphone permission once when user is logged in so the prompt appears in one place
   useEffect(() => {
@@ -54,6 +59,17 @@ export function ServerSyncBootstrap() {
       if (isSyncingRef.current) return
       isSyncingRef.current = true
       try {
+        if (identity?.previous_user_id) {
+          try {
+            const needsInvite = await reregisterUpgradedIdentity(signalingUrl)
+            if (needsInvite.length > 0) {
+              console.warn('[ServerSyncBootstrap] Identity was upgraded to a new key; ask the owners of these houses for a new invite:', needsInvite)
+            }
+          } catch (e) {
+            console.warn('[ServerSyncBootstrap] Failed to re-register upgraded identity:', e)
+          }
+        }
+
         const servers = await listServers()
         for (const s of servers) {
           if (cancelled) return
@@ -85,6 +101,8 @@ export function ServerSyncBootstrap() {
 
       const ws = new WebSocket(signalingUrl)
       wsRef.current = ws
+      // The beacon rejects everything but the auth handshake until AuthOk arrives
+      let authenticated = false
 
       const sendProfileAnnounce = async (override?: {
         display_name: string | null
@@ -93,7 +111,7 @@ export function ServerSyncBootstrap() {
         updated_at: string | null
       }) => {
         if (!identity?.user_id) return
-        if (ws.readyState !== WebSocket.OPEN) return
+        if (!authenticated || ws.readyState !== WebSocket.OPEN) return
         try {
           const servers = await listServers()
           const signingPubkeys = servers.map(s => s.signing_pubkey)
@@ -119,7 +137,7 @@ export function ServerSyncBootstrap() {
       }
 
       const sendProfileHello = async () => {
-        if (!ws || ws.readyState !== WebSocket.OPEN) return
+        if (!authenticated || ws.readyState !== WebSocket.OPEN) return
         try {
           const servers = await listServers()
           for (const s of servers) {
@@ -140,7 +158,7 @@ export function ServerSyncBootstrap() {
 
       const sendPresenceHello = async (fromLabel: string) => {
         if (!identity?.user_id) return
-        if (ws.readyState !== WebSocket.OPEN) return
+        if (!authenticated || ws.readyState !== WebSocket.OPEN) return
         // #region agent log
         DEBUG_LOG({ location: 'ServerSyncBootstrap.tsx:sendPresenceHello', message: 'sendPresenceHello invoked', data: { from: fromLabel, readyState: ws.readyState }, hypothesisId: 'H2a' })
         // #endregion
@@ -160,8 +178,48 @@ export function ServerSyncBootstrap() {
         }
       }
 
+      // Replay pages pick up right after our cursor, so all their new events apply. A pushed event
+      // only applies if it is the next seq; after a gap the cursor stays put and we ask for a replay.
+      const applyServerEvents = (signingPubkey: string, events: ServerEvent[], fromReplay: boolean) => {
+        let lastSeq = lastEventSeqsRef.current.get(signingPubkey) ?? 0
+        const fresh: ServerEvent[] = []
+        for (const e of [...events].sort((a, b) => (a.seq ?? 0) - (b.seq ?? 0))) {
+          const seq = e.seq ?? 0
+          if (seq <= lastSeq) continue
+          if (!fromReplay && seq !== lastSeq + 1) {
+            requestEventReplay(signingPubkey)
+            break
+          }
+          fresh.push(e)
+          lastSeq = seq
+        }
+        if (fresh.length === 0) return
+        lastEventSeqsRef.current.set(signingPubkey, lastSeq)
+        // Events only announce that the house changed; its state lives in the hint
+        void (async () => {
+          try {
+            const imported = await fetchAndImportServerHintOpaque(signalingUrl, signingPubkey)
+            if (imported) window.dispatchEvent(new Event('cordia:servers-updated'))
+            await eventSyncManager.acknowledgeEvents(signalingUrl, signingPubkey, lastSeq)
+          } catch (e) {
+            console.warn('[ServerSyncBootstrap] Failed to apply server events:', e)
+          }
+        })()
+      }
+
+      const requestEventReplay = (signingPubkey: string) => {
+        if (!authenticated || ws.readyState !== WebSocket.OPEN) return
+        ws.send(
+          JSON.stringify({
+            type: 'EventReplayRequest',
+            signing_pubkey: signingPubkey,
+            after_seq: lastEventSeqsRef.current.get(signingPubkey) ?? 0,
+          })
+        )
+      }
+
       const subscribeMissingServers = async () => {
-        if (ws.readyState !== WebSocket.OPEN) return
+        if (!authenticated || ws.readyState !== WebSocket.OPEN) return
         try {
           const servers = await listServers()
           const nextSet = new Set(subscribedSigningPubkeysRef.current)
@@ -176,6 +234,7 @@ export function ServerSyncBootstrap() {
                 signing_pubkey: s.signing_pubkey,
               })
             )
+            requestEventReplay(s.signing_pubkey)
           }
           subscribedSigningPubkeysRef.current = nextSet
           // Presence set may have changed (new server joined/imported)
@@ -187,13 +246,13 @@ export function ServerSyncBootstrap() {
         }
       }
 
-      ws.onopen = async () => {
+      const onAuthenticated = async () => {
         try {
           const servers = await listServers()
           const nextSet = new Set<string>()
           for (const s of servers) {
             nextSet.add(s.signing_pubkey)
-            // Register subscription for this signing_pubkey (beacon uses it for ServerHintUpdated broadcasts)
+            // Register subscription for this signing_pubkey (beacon uses it for ServerHintUpdated and EventPosted broadcasts)
             ws.send(
               JSON.stringify({
                 type: 'Register',
@@ -202,10 +261,12 @@ export function ServerSyncBootstrap() {
                 signing_pubkey: s.signing_pubkey,
               })
             )
+            // Catch up on anything posted while we were disconnected
+            requestEventReplay(s.signing_pubkey)
           }
           subscribedSigningPubkeysRef.current = nextSet
           // Announce presence after subscriptions are set up
-          await sendPresenceHello('ws.onopen')
+          await sendPresenceHello('AuthOk')
           await sendProfileAnnounce()
           await sendProfileHello()
         } catch (e) {
@@ -216,6 +277,42 @@ export function ServerSyncBootstrap() {
       ws.onmessage = async (event) => {
         try {
           const msg = JSON.parse(event.data)
+          if (msg.type === 'AuthChallenge') {
+            try {
+              const proof = await signAuthChallenge(msg.nonce)
+              ws.send(JSON.stringify({ type: 'AuthResponse', ...proof }))
+            } catch (e) {
+              console.warn('[ServerSyncBootstrap] Failed to answer auth challenge:', e)
+            }
+            return
+          }
+
+          if (msg.type === 'AuthOk') {
+            authenticated = true
+            const resumeToken = resumeTokenRef.current
+            resumeTokenRef.current = msg.resume_token ?? null
+            if (resumeToken) {
+              ws.send(JSON.stringify({ type: 'Resume', resume_token: resumeToken }))
+              return
+            }
+            await onAuthenticated()
+            return
+          }
+
+          if (msg.type === 'Resumed') {
+            // Subscriptions and presence are still live; only catch up on missed events
+            resumeTokenRef.current = msg.resume_token
+            for (const spk of subscribedSigningPubkeysRef.current) {
+              requestEventReplay(spk)
+            }
+            return
+          }
+
+          if (msg.type === 'ResumeRejected') {
+            await onAuthenticated()
+            return
+          }
+
           if (msg.type === 'ServerHintUpdated') {
             const signingPubkey: string = msg.signing_pubkey
 
@@ -256,6 +353,36 @@ export function ServerSyncBootstrap() {
             return
           }
 
+          if (msg.type === 'EventPosted') {
+            const spk: string = msg.signing_pubkey
+            if (!subscribedSigningPubkeysRef.current.has(spk)) return
+            applyServerEvents(spk, [msg.event as ServerEvent], false)
+            return
+          }
+
+          if (msg.type === 'EventReplay') {
+            const spk: string = msg.signing_pubkey
+            if (!subscribedSigningPubkeysRef.current.has(spk)) return
+            applyServerEvents(spk, (msg.events as ServerEvent[]) || [], true)
+            if (msg.has_more) requestEventReplay(spk)
+            return
+          }
+
+          if (msg.type === 'EventCursorExpired') {
+            // Events we missed were garbage-collected: take the hint as the new baseline
+            const spk: string = msg.signing_pubkey
+            if (!subscribedSigningPubkeysRef.current.has(spk)) return
+            try {
+              const imported = await fetchAndImportServerHintOpaque(signalingUrl, spk)
+              if (imported) window.dispatchEvent(new Event('cordia:servers-updated'))
+            } catch (e) {
+              console.warn('[ServerSyncBootstrap] Failed to resync server hint after cursor expiry:', e)
+            }
+            lastEventSeqsRef.current.set(spk, Number(msg.latest_seq || 0))
+            requestEventReplay(spk)
+            return
+          }
+
           if (msg.type === 'PresenceSnapshot') {
             const spk: string = msg.signing_pubkey
             const users = msg.users as Array<{ user_id: string; active_signing_pubkey?: string | null }>
@@ -348,7 +475,7 @@ export function ServerSyncBootstrap() {
         const next = detail?.signing_pubkey ?? null
         activeSigningPubkeyRef.current = next
         if (!identity?.user_id) return
-        if (ws.readyState !== WebSocket.OPEN) return
+        if (!authenticated || ws.readyState !== WebSocket.OPEN) return
         ws.send(
           JSON.stringify({
             type: 'PresenceActive',
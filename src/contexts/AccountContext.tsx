@@ -8,6 +8,7 @@ import {
   logoutAccount,
   loadIdentity,
   listServers,
+  getVaultStatus,
 } from '../lib/tauri';
 import { useIdentity } from './IdentityContext';
 
@@ -66,6 +67,18 @@ export function AccountProvider({ children }: { children: ReactNode }) {
       }
       setAccountInfoMap(infoMap);
 
+      // Passphrase-protected accounts start locked after a restart: send the user back
+      // to account select, where the passphrase is asked for before switching in.
+      if (session.current_account_id) {
+        const vault = await getVaultStatus(session.current_account_id);
+        if (vault.enabled && !vault.unlocked) {
+          await logoutAccount();
+          clearIdentity();
+          setCurrentAccountId(null);
+          return;
+        }
+      }
+
       // AUTHORITY: If session exists, load identity and local servers so server list is ready when user lands on /home
       if (session.current_account_id) {
         try {
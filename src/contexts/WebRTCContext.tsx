@@ -14,7 +14,7 @@ import { useSignaling } from './SignalingContext'
 import { useVoicePresence } from './VoicePresenceContext'
 import { useSpeaking } from './SpeakingContext'
 import { RemoteAudioAnalyzer } from '../lib/remoteAudioAnalyzer'
-import { loadAudioSettings } from '../lib/tauri'
+import { loadAudioSettings, signAuthChallenge } from '../lib/tauri'
 
 /**
  * WebRTC Context for peer-to-peer voice communication.
@@ -96,6 +96,7 @@ export function WebRTCProvider({ children }: { children: ReactNode }) {
   const isRebuildingAudioRef = useRef<boolean>(false)    // Guard against concurrent rebuilds
   const keepaliveIntervalRef = useRef<ReturnType<typeof setInterval> | null>(null)  // Signaling keepalive
   const signalingConnectedRef = useRef<boolean>(false)   // Track signaling state separately from media
+  const resumeTokenRef = useRef<string | null>(null)     // Beacon session to resume after a signaling drop
   const localAudioAnalyzerRef = useRef<RemoteAudioAnalyzer | null>(null)  // For self-speaking detection
   const cleanedPeersRef = useRef<Set<string>>(new Set())  // Track cleaned peers to prevent double cleanup
 
@@ -898,6 +899,10 @@ export function WebRTCProvider({ children }: { children: ReactNode }) {
       // Start keepalive to prevent idle disconnect
       startKeepalive()
 
+      // VoiceRegister is sent once the beacon accepts our identity (AuthOk)
+    }
+
+    const sendVoiceRegister = () => {
       // Register for voice in the chat (beacon expects server_id and chat_id)
       const registerMessage = {
         type: 'VoiceRegister',
@@ -911,7 +916,40 @@ export function WebRTCProvider({ children }: { children: ReactNode }) {
       console.log(`[Signal] Sent VoiceRegister: peer=${currentPeerIdRef.current}`)
     }
 
-    ws.onmessage = (event) => {
+    ws.onmessage = async (event) => {
+      const msg = JSON.parse(event.data)
+      if (msg.type === 'AuthChallenge') {
+        try {
+          const proof = await signAuthChallenge(msg.nonce)
+          ws.send(JSON.stringify({ type: 'AuthResponse', ...proof }))
+        } catch (e) {
+          console.error('[Signal] Failed to answer auth challenge:', e)
+        }
+        return
+      }
+      if (msg.type === 'AuthOk') {
+        console.log(`[Signal] Authenticated as ${msg.user_id}`)
+        // After a drop, take over the old registration so the room doesn't see us leave and rejoin
+        if (resumeTokenRef.current) {
+          ws.send(JSON.stringify({ type: 'Resume', resume_token: resumeTokenRef.current }))
+          resumeTokenRef.current = msg.resume_token ?? null
+          return
+        }
+        resumeTokenRef.current = msg.resume_token ?? null
+        sendVoiceRegister()
+        return
+      }
+      if (msg.type === 'Resumed') {
+        console.log(`[Signal] Resumed signaling session (${msg.peer_ids.length} peers)`)
+        resumeTokenRef.current = msg.resume_token
+        return
+      }
+      if (msg.type === 'ResumeRejected') {
+        // Keep the token from this connection's AuthOk and register from scratch
+        console.warn(`[Signal] Resume rejected: ${msg.message}`)
+        sendVoiceRegister()
+        return
+      }
       handleSignalingMessage(event.data)
     }
 
@@ -1078,6 +1116,7 @@ export function WebRTCProvider({ children }: { children: ReactNode }) {
     localStreamRef.current = null
 
     // 6. Clear refs
+    resumeTokenRef.current = null
     currentPeerIdRef.current = null
     currentUserIdRef.current = null
     currentSigningPubkeyRef.current = null
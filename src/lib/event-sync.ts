@@ -1,5 +1,7 @@
 // Event synchronization manager for polling server events from beacon
 
+import { fetchAndImportServerHintOpaque, signEventAck } from './tauri'
+
 export interface ServerEvent {
   event_id: string
   signing_pubkey: string
@@ -7,19 +9,36 @@ export interface ServerEvent {
   encrypted_payload: string  // Beacon cannot decrypt
   signature: string  // Signed by member's Ed25519 key
   timestamp: string
+  seq?: number  // Per-house position assigned by the beacon
+}
+
+export interface EventPage {
+  events: ServerEvent[]
+  next_seq: number  // Pass back as after_seq for the next page
+  has_more: boolean
+}
+
+/** Thrown when the beacon no longer has the events after our cursor; resync from the hint. */
+export class EventCursorExpiredError extends Error {
+  constructor(public latestSeq: number) {
+    super('Event cursor expired')
+  }
 }
 
 export interface EncryptedServerHint {
   signing_pubkey: string
   encrypted_state: string  // Beacon cannot decrypt
-  signature: string  // Signed by member's Ed25519 key
+  signature: string  // Signed by the house key, or by signer_pubkey when set
+  signer_pubkey?: string | null  // Member identity key (hex); absent means house key
+  key_epoch?: number  // House key generation the state is encrypted under
+  revision?: number  // Beacon write counter (stored revision + 1 to overwrite)
   last_updated: string
 }
 
 export class EventSyncManager {
   private pollingInterval: number = 5000 // 5 seconds
   private activePolls = new Map<string, ReturnType<typeof setInterval>>()
-  private lastEventIds = new Map<string, string>()
+  private lastEventSeqs = new Map<string, number>()
   private eventHandlers = new Map<string, (events: ServerEvent[]) => void>()
 
   /**
@@ -41,8 +60,17 @@ export class EventSyncManager {
 
     const poll = async () => {
       try {
-        const lastEventId = this.lastEventIds.get(signingPubkey)
-        const events = await this.fetchEvents(signalingServer, signingPubkey, lastEventId)
+        let page: EventPage
+        try {
+          page = await this.fetchEvents(signalingServer, signingPubkey, this.lastEventSeqs.get(signingPubkey) ?? 0)
+        } catch (error) {
+          if (!(error instanceof EventCursorExpiredError)) throw error
+          // Missed events were garbage-collected: take the hint as the new baseline and continue from here
+          await this.resyncFromHint(signalingServer, signingPubkey)
+          this.lastEventSeqs.set(signingPubkey, error.latestSeq)
+          return
+        }
+        const events = page.events
 
         if (events.length > 0) {
           console.log(`Received ${events.length} events for server ${signingPubkey.slice(0, 8)}...`)
@@ -53,17 +81,21 @@ export class EventSyncManager {
             handler(events)
           }
 
-          // Update last event ID
-          const lastEvent = events[events.length - 1]
-          this.lastEventIds.set(signingPubkey, lastEvent.event_id)
+          // Advance the cursor
+          this.lastEventSeqs.set(signingPubkey, page.next_seq)
 
           // Acknowledge events (best-effort)
           await this.acknowledgeEvents(
             signalingServer,
             signingPubkey,
-            lastEvent.event_id
+            page.next_seq
           ).catch(e => console.warn('Failed to ack events:', e))
         }
+
+        // Drain the backlog without waiting for the next interval
+        if (page.has_more) {
+          await poll()
+        }
       } catch (error) {
         console.error(`Failed to poll events for server ${signingPubkey.slice(0, 8)}...:`, error)
       }
@@ -79,6 +111,18 @@ export class EventSyncManager {
     console.log(`Started polling for server ${signingPubkey.slice(0, 8)}...`)
   }
 
+  /**
+   * Import the current hint after missed events were garbage-collected
+   */
+  private async resyncFromHint(signalingServer: string, signingPubkey: string) {
+    try {
+      const imported = await fetchAndImportServerHintOpaque(signalingServer, signingPubkey)
+      if (imported) window.dispatchEvent(new Event('cordia:servers-updated'))
+    } catch (e) {
+      console.warn(`Failed to resync server ${signingPubkey.slice(0, 8)}... from its hint:`, e)
+    }
+  }
+
   /**
    * Stop polling for a server
    */
@@ -109,16 +153,18 @@ export class EventSyncManager {
   private async fetchEvents(
     signalingServer: string,
     signingPubkey: string,
-    sinceEventId?: string
-  ): Promise<ServerEvent[]> {
+    afterSeq: number
+  ): Promise<EventPage> {
     // Normalize beacon URL
     const baseUrl = this.normalizeServerUrl(signalingServer)
     const url = new URL(`${baseUrl}/api/servers/${encodeURIComponent(signingPubkey)}/events`)
-    if (sinceEventId) {
-      url.searchParams.set('since', sinceEventId)
-    }
+    url.searchParams.set('after_seq', String(afterSeq))
 
     const response = await fetch(url.toString())
+    if (response.status === 410) {
+      const body = await response.json().catch(() => ({}))
+      throw new EventCursorExpiredError(Number(body.latest_seq || 0))
+    }
     if (!response.ok) {
       throw new Error(`Failed to fetch events: ${response.status} ${response.statusText}`)
     }
@@ -129,25 +175,23 @@ export class EventSyncManager {
   /**
    * Acknowledge events (best-effort)
    */
-  private async acknowledgeEvents(
+  async acknowledgeEvents(
     signalingServer: string,
     signingPubkey: string,
-    lastEventId: string
+    lastSeq: number
   ): Promise<void> {
     const baseUrl = this.normalizeServerUrl(signalingServer)
     const url = `${baseUrl}/api/servers/${encodeURIComponent(signingPubkey)}/events/ack`
 
-    // Get current user ID from somewhere (this would need to be passed in)
-    const userId = 'unknown' // TODO: Get from context
-
-    await fetch(url, {
+    const ack = await signEventAck(signingPubkey, lastSeq)
+    const response = await fetch(url, {
       method: 'POST',
       headers: { 'Content-Type': 'application/json' },
-      body: JSON.stringify({
-        user_id: userId,
-        last_event_id: lastEventId,
-      }),
+      body: JSON.stringify(ack),
     })
+    if (!response.ok) {
+      throw new Error(`Failed to ack events: ${response.status} ${response.statusText}`)
+    }
   }
 
   /**
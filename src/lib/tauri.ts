@@ -5,6 +5,8 @@ export interface UserIdentity {
   display_name: string
   public_key: string
   private_key?: string
+  /** Set after an upgraded legacy identity got a new key, until its houses are re-registered */
+  previous_user_id?: string
 }
 
 export async function hasIdentity(): Promise<boolean> {
@@ -23,13 +25,44 @@ export async function loadIdentity(): Promise<UserIdentity> {
   return await invoke('load_identity')
 }
 
+/**
+ * Republish owned houses under an upgraded identity's new user_id.
+ * Returns the names of other houses, which need a fresh invite from their owner.
+ */
+export async function reregisterUpgradedIdentity(signalingServer: string): Promise<string[]> {
+  return await invoke('reregister_upgraded_identity', { signalingServer })
+}
+
+export interface AuthChallengeResponse {
+  user_id: string
+  public_key: string
+  signature: string
+}
+
+/** Sign the beacon's WebSocket AuthChallenge nonce with the identity key. */
+export async function signAuthChallenge(nonce: string): Promise<AuthChallengeResponse> {
+  return await invoke('sign_auth_challenge', { nonce })
+}
+
+export interface EventAck {
+  user_id: string
+  last_seq: number
+  member_pubkey: string
+  signature: string
+}
+
+/** Sign an ack of a house's events up to lastSeq (the beacon only counts acks from members). */
+export async function signEventAck(signingPubkey: string, lastSeq: number): Promise<EventAck> {
+  return await invoke('sign_event_ack', { signingPubkey, lastSeq })
+}
+
 export async function exportIdentity(): Promise<Uint8Array> {
   const data = await invoke<number[]>('export_identity')
   return new Uint8Array(data)
 }
 
-export async function importIdentity(data: Uint8Array): Promise<{ identity: UserIdentity, profile_json: any }> {
-  return await invoke('import_identity', { data: Array.from(data) })
+export async function importIdentity(data: Uint8Array, passphrase?: string): Promise<{ identity: UserIdentity, profile_json: any }> {
+  return await invoke('import_identity', { data: Array.from(data), passphrase: passphrase || null })
 }
 
 export interface AudioSettings {
@@ -93,6 +126,9 @@ export interface EncryptedServerHint {
   signing_pubkey: string
   encrypted_state: string
   signature: string
+  signer_pubkey?: string | null
+  key_epoch?: number
+  revision?: number
   last_updated: string
 }
 
@@ -145,10 +181,16 @@ export async function publishServerHintOpaque(signalingServer: string, serverId:
   return await invoke('publish_server_hint_opaque', { signalingServer, serverId })
 }
 
-export async function publishServerHintMemberLeft(signalingServer: string, serverId: string, userId: string): Promise<void> {
+/** Returns the user_ids the owner's rekey could not reach (empty unless the owner removes someone). */
+export async function publishServerHintMemberLeft(signalingServer: string, serverId: string, userId: string): Promise<string[]> {
   return await invoke('publish_server_hint_member_left', { signalingServer, serverId, userId })
 }
 
+/** Returns the user_ids left without the new key (no X25519 key); they need a new invite. */
+export async function rotateHouseKey(signalingServer: string, serverId: string, removedUserIds: string[] = []): Promise<string[]> {
+  return await invoke('rotate_house_key', { signalingServer, serverId, removedUserIds })
+}
+
 export async function fetchAndImportServerHintOpaque(signalingServer: string, signingPubkey: string): Promise<boolean> {
   return await invoke('fetch_and_import_server_hint_opaque', { signalingServer, signingPubkey })
 }
@@ -170,6 +212,20 @@ export async function redeemTemporaryInvite(
   return await invoke('redeem_temporary_invite', { signalingServer, code, userId, displayName })
 }
 
+/** Invite one user by their identity public key (hex). Only that identity can open the returned link. */
+export async function createSealedInvite(signalingServer: string, serverId: string, recipientPublicKey: string): Promise<string> {
+  return await invoke('create_sealed_invite', { signalingServer, serverId, recipientPublicKey })
+}
+
+export async function acceptSealedInvite(
+  signalingServer: string,
+  invite: string,
+  userId: string,
+  displayName: string
+): Promise<Server> {
+  return await invoke('accept_sealed_invite', { signalingServer, invite, userId, displayName })
+}
+
 export async function revokeActiveInvite(signalingServer: string, serverId: string): Promise<void> {
   return await invoke('revoke_active_invite', { signalingServer, serverId })
 }
@@ -232,8 +288,8 @@ export async function exportIdentityForAccount(accountId: string): Promise<Uint8
   return new Uint8Array(data)
 }
 
-export async function exportFullIdentity(profileJson?: any): Promise<Uint8Array> {
-  const data = await invoke<number[]>('export_full_identity', { profileJson })
+export async function exportFullIdentity(passphrase: string, profileJson?: any): Promise<Uint8Array> {
+  const data = await invoke<number[]>('export_full_identity', { profileJson, passphrase })
   return new Uint8Array(data)
 }
 
@@ -241,8 +297,8 @@ export async function exportFullIdentityDebug(profileJson?: any): Promise<string
   return await invoke('export_full_identity_debug', { profileJson })
 }
 
-export async function exportFullIdentityForAccount(accountId: string, profileJson?: any): Promise<Uint8Array> {
-  const data = await invoke<number[]>('export_full_identity_for_account', { accountId, profileJson })
+export async function exportFullIdentityForAccount(accountId: string, passphrase: string, profileJson?: any): Promise<Uint8Array> {
+  const data = await invoke<number[]>('export_full_identity_for_account', { accountId, profileJson, passphrase })
   return new Uint8Array(data)
 }
 
@@ -250,6 +306,31 @@ export async function deleteAccount(accountId: string): Promise<void> {
   return await invoke('delete_account', { accountId })
 }
 
+export interface VaultStatus {
+  enabled: boolean
+  unlocked: boolean
+}
+
+export async function getVaultStatus(accountId: string): Promise<VaultStatus> {
+  return await invoke('get_vault_status', { accountId })
+}
+
+export async function unlockAccount(accountId: string, passphrase: string): Promise<void> {
+  return await invoke('unlock_account', { accountId, passphrase })
+}
+
+export async function lockAccount(): Promise<void> {
+  return await invoke('lock_account')
+}
+
+export async function enablePassphraseProtection(passphrase: string): Promise<void> {
+  return await invoke('enable_passphrase_protection', { passphrase })
+}
+
+export async function disablePassphraseProtection(passphrase: string): Promise<void> {
+  return await invoke('disable_passphrase_protection', { passphrase })
+}
+
 export async function registerKeyFileAssociation(): Promise<void> {
   return await invoke('register_key_file_association_command')
 }
@@ -262,10 +343,12 @@ export async function registerKeyFileAssociation(): Promise<void> {
  */
 export function parseInviteUri(uri: string): { signingPubkey: string; server: string } | null {
   // Be tolerant of users pasting uppercased scheme (e.g. CORDIA://...), and trim whitespace.
-  const match = uri.trim().match(/^cordia:\/\/([^@]+)@(.+)$/i)
+  // v2 invites carry a secret fragment (#v2.SECRET); it stays attached to the code and is
+  // split off by the backend, which never sends it to the beacon.
+  const match = uri.trim().match(/^(?:cordia|rmmt):\/\/([^@]+)@([^#]+)(#.*)?$/i)
   if (!match) return null
   return {
-    signingPubkey: match[1],
+    signingPubkey: match[1] + (match[3] ?? ''),
     server: match[2],
   }
 }
@@ -4,7 +4,8 @@ import { useAccount } from '../contexts/AccountContext'
 import type { CSSProperties } from 'react'
 import { Plus, X, Download, Loader2 } from 'lucide-react'
 import { Button } from '../components/ui/button'
-import { deleteAccount, exportFullIdentityForAccount } from '../lib/tauri'
+import { Input } from '../components/ui/input'
+import { deleteAccount, exportFullIdentityForAccount, getVaultStatus, unlockAccount } from '../lib/tauri'
 
 function AccountSelectPage() {
   const { accounts, accountInfoMap, isLoading: accountsLoading, switchToAccount, authError, refreshAccounts } = useAccount()
@@ -13,6 +14,11 @@ function AccountSelectPage() {
   const [isExporting, setIsExporting] = useState(false)
   const [isDeleting, setIsDeleting] = useState(false)
   const [deleteError, setDeleteError] = useState<string | null>(null)
+  const [exportPassphrase, setExportPassphrase] = useState('')
+  const [unlockTarget, setUnlockTarget] = useState<string | null>(null)
+  const [unlockPassphrase, setUnlockPassphrase] = useState('')
+  const [unlockError, setUnlockError] = useState<string | null>(null)
+  const [isUnlocking, setIsUnlocking] = useState(false)
 
   useEffect(() => {
     // If no accounts exist, redirect to setup
@@ -23,6 +29,15 @@ function AccountSelectPage() {
 
   const handleSelectAccount = async (accountId: string) => {
     try {
+      // Passphrase-protected accounts must be unlocked before their keys can be read
+      const vault = await getVaultStatus(accountId)
+      if (vault.enabled && !vault.unlocked) {
+        setUnlockTarget(accountId)
+        setUnlockPassphrase('')
+        setUnlockError(null)
+        return
+      }
+
       // Switch to the account (sets session AND loads identity)
       // AccountContext handles everything
       await switchToAccount(accountId)
@@ -33,6 +48,28 @@ function AccountSelectPage() {
     }
   }
 
+  const handleUnlock = async () => {
+    if (!unlockTarget) return
+
+    setIsUnlocking(true)
+    setUnlockError(null)
+
+    try {
+      await unlockAccount(unlockTarget, unlockPassphrase)
+    } catch (err) {
+      const message = err instanceof Error ? err.message : typeof err === 'string' ? err : ''
+      setUnlockError(message.includes('Wrong passphrase') ? 'Wrong passphrase' : message || 'Failed to unlock account')
+      setIsUnlocking(false)
+      return
+    }
+
+    const accountId = unlockTarget
+    setUnlockTarget(null)
+    setUnlockPassphrase('')
+    setIsUnlocking(false)
+    await handleSelectAccount(accountId)
+  }
+
   const handleCreateNew = () => {
     navigate('/account/setup')
   }
@@ -41,6 +78,7 @@ function AccountSelectPage() {
     e.stopPropagation()
     setDeleteTarget(accountId)
     setDeleteError(null)
+    setExportPassphrase('')
   }
 
   const sanitizeFilename = (name: string): string => {
@@ -50,6 +88,10 @@ function AccountSelectPage() {
 
   const handleExportKeys = async () => {
     if (!deleteTarget) return
+    if (exportPassphrase.length < 8) {
+      setDeleteError('Enter a passphrase of at least 8 characters to protect the backup')
+      return
+    }
 
     setIsExporting(true)
     setDeleteError(null)
@@ -63,7 +105,7 @@ function AccountSelectPage() {
         show_real_name: profile.show_real_name,
       } : null
 
-      const data = await exportFullIdentityForAccount(deleteTarget, profileJson)
+      const data = await exportFullIdentityForAccount(deleteTarget, exportPassphrase, profileJson)
       
       // Generate filename: sanitized display_name from profile or account_info, fallback to user_id
       let filename: string
@@ -86,7 +128,7 @@ function AccountSelectPage() {
       document.body.removeChild(a)
       URL.revokeObjectURL(url)
     } catch (err) {
-      setDeleteError(err instanceof Error ? err.message : 'Failed to export account')
+      setDeleteError(err instanceof Error ? err.message : typeof err === 'string' ? err : 'Failed to export account')
     } finally {
       setIsExporting(false)
     }
@@ -253,6 +295,75 @@ function AccountSelectPage() {
           </div>
         </div>
 
+        {unlockTarget && (
+          <div className="fixed inset-0 bg-background/80 backdrop-blur-sm flex items-center justify-center p-4 z-50">
+            <div className="bg-card border-2 border-border rounded-lg p-6 max-w-md w-full space-y-6">
+              <div className="space-y-2">
+                <h2 className="text-xl font-light tracking-tight">Unlock Account</h2>
+                <div className="h-px bg-foreground/20 w-full"></div>
+              </div>
+
+              <form
+                className="space-y-4"
+                onSubmit={(e) => {
+                  e.preventDefault()
+                  handleUnlock()
+                }}
+              >
+                <p className="text-sm text-muted-foreground font-light">
+                  This account is protected with a passphrase.
+                </p>
+
+                <Input
+                  type="password"
+                  autoFocus
+                  value={unlockPassphrase}
+                  onChange={(e) => {
+                    setUnlockPassphrase(e.target.value)
+                    setUnlockError(null)
+                  }}
+                  placeholder="Passphrase"
+                  className="h-11 font-light"
+                  disabled={isUnlocking}
+                />
+
+                {unlockError && (
+                  <div className="bg-destructive/10 border-l-2 border-destructive p-3 text-sm text-destructive">
+                    {unlockError}
+                  </div>
+                )}
+
+                <div className="flex gap-3">
+                  <Button
+                    type="button"
+                    variant="ghost"
+                    onClick={() => setUnlockTarget(null)}
+                    disabled={isUnlocking}
+                    className="flex-1 font-light"
+                  >
+                    Cancel
+                  </Button>
+                  <Button
+                    type="submit"
+                    variant="outline"
+                    disabled={isUnlocking || !unlockPassphrase}
+                    className="flex-1 font-light"
+                  >
+                    {isUnlocking ? (
+                      <>
+                        <Loader2 className="mr-2 h-4 w-4 animate-spin" />
+                        Unlocking...
+                      </>
+                    ) : (
+                      'Unlock'
+                    )}
+                  </Button>
+                </div>
+              </form>
+            </div>
+          </div>
+        )}
+
         {deleteTarget && (
           <div className="fixed inset-0 bg-background/80 backdrop-blur-sm flex items-center justify-center p-4 z-50">
             <div className="bg-card border-2 border-border rounded-lg p-6 max-w-md w-full space-y-6">
@@ -273,6 +384,15 @@ function AccountSelectPage() {
                   </p>
                 </div>
 
+                <Input
+                  type="password"
+                  value={exportPassphrase}
+                  onChange={(e) => setExportPassphrase(e.target.value)}
+                  placeholder="Backup passphrase (at least 8 characters)"
+                  className="h-11 font-light"
+                  disabled={isExporting || isDeleting}
+                />
+
                 {deleteError && (
                   <div className="bg-destructive/10 border-l-2 border-destructive p-3 text-sm text-destructive">
                     {deleteError}
@@ -2,6 +2,8 @@ import { useState, useEffect } from 'react'
 import { useNavigate } from 'react-router-dom'
 import { ArrowLeft, Upload, Loader2, Key, Shield } from 'lucide-react'
 import { Button } from '../components/ui/button'
+import { Input } from '../components/ui/input'
+import { Label } from '../components/ui/label'
 import { importIdentity } from '../lib/tauri'
 
 function IdentityRestorePage() {
@@ -9,6 +11,9 @@ function IdentityRestorePage() {
   const [error, setError] = useState<string | null>(null)
   const [displayedText, setDisplayedText] = useState('')
   const [isDragging, setIsDragging] = useState(false)
+  const [passphrase, setPassphrase] = useState('')
+  // Last selected file, kept so the import can be retried after entering the passphrase
+  const [pendingFile, setPendingFile] = useState<File | null>(null)
   const navigate = useNavigate()
   
   const fullText = 'Restore Your Account'
@@ -51,6 +56,7 @@ function IdentityRestorePage() {
   }, [])
 
   const processFile = async (file: File) => {
+    setPendingFile(file)
     setIsImporting(true)
     setError(null)
 
@@ -59,7 +65,8 @@ function IdentityRestorePage() {
       const data = new Uint8Array(arrayBuffer)
       
       // Import identity (bootstrap command: creates account + sets session)
-      const result = await importIdentity(data)
+      // v2 backups need the export passphrase; older device-bound backups ignore it
+      const result = await importIdentity(data, passphrase || undefined)
       
       // Restore profile data to localStorage if present
       if (result.profile_json) {
@@ -80,7 +87,14 @@ function IdentityRestorePage() {
       // Reload to initialize AccountContext with new session
       window.location.href = '/home'
     } catch (err) {
-      setError(err instanceof Error ? err.message : 'Failed to import account. The file may be corrupted or invalid.')
+      const message = err instanceof Error ? err.message : typeof err === 'string' ? err : ''
+      if (message.includes('protected with a passphrase')) {
+        setError('This backup is protected with a passphrase. Enter it below and try again.')
+      } else if (message.includes('Wrong passphrase')) {
+        setError('Wrong passphrase, or the file is corrupted.')
+      } else {
+        setError(message || 'Failed to import account. The file may be corrupted or invalid.')
+      }
       setIsImporting(false)
     }
   }
@@ -154,7 +168,7 @@ function IdentityRestorePage() {
               <div className="flex items-start gap-3 fade-in" style={{ animationDelay: '0.35s' }}>
                 <div className="w-1 h-1 bg-foreground/40 rounded-full mt-2"></div>
                 <span className="flex items-center gap-2">
-                  Storage encrypted with this device's key
+                  Backup unlocked with your passphrase
                   <Key className="h-3 w-3 opacity-60" />
                 </span>
               </div>
@@ -198,6 +212,31 @@ function IdentityRestorePage() {
               </div>
             </div>
 
+            <div className="space-y-2">
+              <Label htmlFor="restore-passphrase" className="text-xs font-medium uppercase tracking-wider text-muted-foreground">
+                Backup Passphrase
+              </Label>
+              <Input
+                id="restore-passphrase"
+                type="password"
+                value={passphrase}
+                onChange={(e) => setPassphrase(e.target.value)}
+                placeholder="Passphrase used when exporting"
+                className="h-11 font-light"
+                disabled={isImporting}
+              />
+            </div>
+
+            {error && pendingFile && !isImporting && (
+              <Button
+                variant="outline"
+                onClick={() => processFile(pendingFile)}
+                className="w-full h-11 font-light border-border/50 hover:bg-accent"
+              >
+                Try Again
+              </Button>
+            )}
+
             {error && (
               <div className="bg-destructive/10 border-l-2 border-destructive p-4 text-sm text-destructive">
                 {error}
@@ -213,7 +252,7 @@ function IdentityRestorePage() {
 
             <div className="bg-muted/30 border-l-2 border-foreground/20 p-4 text-xs text-muted-foreground font-light space-y-1">
               <p className="font-medium text-foreground/80">Note</p>
-              <p>Your original keys are preserved. Only the storage encryption changes to match this device.</p>
+              <p>Your original keys are preserved. Once imported, they are stored encrypted with this device's key.</p>
             </div>
             </div>
           </div>
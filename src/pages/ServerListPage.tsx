@@ -5,7 +5,7 @@ import { useEffect, useMemo, useRef, useState, type CSSProperties } from 'react'
 import { useSignaling } from '../contexts/SignalingContext'
 import { SignalingStatus } from '../components/SignalingStatus'
 import { UserProfileCard } from '../components/UserProfileCard'
-import { createServer, deleteServer, type Server, parseInviteUri, publishServerHintOpaque, publishServerHintMemberLeft, redeemTemporaryInvite } from '../lib/tauri'
+import { createServer, deleteServer, type Server, parseInviteUri, publishServerHintOpaque, publishServerHintMemberLeft, redeemTemporaryInvite, acceptSealedInvite } from '../lib/tauri'
 import { useIdentity } from '../contexts/IdentityContext'
 import { usePresence, type PresenceLevel } from '../contexts/PresenceContext'
 import { useVoicePresence } from '../contexts/VoicePresenceContext'
@@ -436,7 +436,7 @@ function ServerListPage() {
       let signalingServer = signalingUrl || ''
       let inviteCode: string | null = null
 
-      if (/^rmmt:\/\//i.test(input)) {
+      if (/^(?:cordia|rmmt):\/\//i.test(input)) {
         const parsed = parseInviteUri(input)
         if (!parsed) {
           setJoinError('Invalid invite. Paste the full invite link (cordia://...).')
@@ -447,7 +447,20 @@ function ServerListPage() {
           parsed.server.startsWith('ws://') || parsed.server.startsWith('wss://')
             ? parsed.server
             : `wss://${parsed.server}`
-        // Temporary invites use cordia://{code}@{server}
+        // Sealed invites (cordia://{house}@{server}#sealed.…) are addressed to our identity; no code to redeem
+        if (/#sealed\./i.test(input)) {
+          const updatedServer = await acceptSealedInvite(signalingServer, input, identity.user_id, identity.display_name)
+
+          await refreshServers()
+          window.dispatchEvent(new Event('cordia:servers-updated'))
+          setShowJoinInline(false)
+          setInviteCode('')
+          setJoinError('')
+          navigate(`/home/${updatedServer.id}`, { state: { server: updatedServer } })
+          return
+        }
+
+        // Temporary invites use cordia://{code}@{server}#v2.{secret} (legacy: no fragment)
         inviteCode = parsed.signingPubkey
       } else {
         if (!signalingServer) {
@@ -498,7 +511,10 @@ function ServerListPage() {
       // Best-effort: advertise leave to other members.
       if (identity && signalingStatus === 'connected' && signalingUrl) {
         // IMPORTANT: publish BEFORE deleting locally (encryption needs the local symmetric key)
-        await publishServerHintMemberLeft(signalingUrl, serverId, identity.user_id)
+        const unreachable = await publishServerHintMemberLeft(signalingUrl, serverId, identity.user_id)
+        if (unreachable.length > 0) {
+          console.warn('[Leave] Rekeyed without members that need a new invite:', unreachable)
+        }
       }
 
       // Delete locally last (so the leave broadcast can be encrypted)
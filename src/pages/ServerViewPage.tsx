@@ -2,7 +2,7 @@ import { useEffect, useState, useRef, type CSSProperties } from 'react'
 import { useParams, useNavigate, useLocation } from 'react-router-dom'
 import { ArrowLeft, Copy, Check, PhoneOff, Plus, Trash2, Phone } from 'lucide-react'
 import { Button } from '../components/ui/button'
-import { loadServer, addRoom, removeChat, type Server, type Chat, fetchAndImportServerHintOpaque, publishServerHintOpaque, createTemporaryInvite, revokeActiveInvite } from '../lib/tauri'
+import { loadServer, addRoom, removeChat, type Server, type Chat, fetchAndImportServerHintOpaque, publishServerHintOpaque, createTemporaryInvite, revokeActiveInvite, createSealedInvite } from '../lib/tauri'
 import { useIdentity } from '../contexts/IdentityContext'
 import { useWebRTC } from '../contexts/WebRTCContext'
 import { SignalingStatus } from '../components/SignalingStatus'
@@ -50,6 +50,11 @@ function ServerViewPage() {
   const [isCreatingChat, setIsCreatingChat] = useState(false)
   const [isCreatingInvite, setIsCreatingInvite] = useState(false)
   const [isRevokingInvite, setIsRevokingInvite] = useState(false)
+  const [sealedInviteKey, setSealedInviteKey] = useState('')
+  const [sealedInviteUri, setSealedInviteUri] = useState<string | null>(null)
+  const [sealedInviteError, setSealedInviteError] = useState('')
+  const [isSealingInvite, setIsSealingInvite] = useState(false)
+  const [copiedSealedInvite, setCopiedSealedInvite] = useState(false)
   const [deleteChatTarget, setDeleteChatTarget] = useState<Chat | null>(null)
   const [isDeletingChat, setIsDeletingChat] = useState(false)
   const [deleteChatError, setDeleteChatError] = useState('')
@@ -225,9 +230,10 @@ function ServerViewPage() {
   const getActiveInviteCode = (): string | null => {
     const uri = getActiveInviteUri()
     if (!uri) return null
-    // Backend uses cordia://CODE@host; accept cordia:// or legacy rmmt://
-    const parsed = uri.trim().match(/^(?:cordia|rmmt):\/\/([^@]+)@/i)
-    return parsed ? parsed[1] : null
+    // Backend uses cordia://CODE@host#v2.SECRET; accept cordia:// or legacy rmmt://.
+    // The shareable code keeps the secret fragment (CODE#v2.SECRET) so it can be redeemed on its own.
+    const parsed = uri.trim().match(/^(?:cordia|rmmt):\/\/([^@]+)@[^#]*(#.*)?$/i)
+    return parsed ? parsed[1] + (parsed[2] ?? '') : null
   }
 
   const handleCreateInvite = async () => {
@@ -262,6 +268,30 @@ function ServerViewPage() {
     }
   }
 
+  const handleCreateSealedInvite = async () => {
+    if (!serverId || !sealedInviteKey.trim()) return
+    if (signalingStatus !== 'connected' || !signalingUrl) return
+    setIsSealingInvite(true)
+    setSealedInviteError('')
+    try {
+      const uri = await createSealedInvite(signalingUrl, serverId, sealedInviteKey.trim())
+      setSealedInviteUri(uri)
+      setSealedInviteKey('')
+    } catch (e) {
+      console.warn('Failed to create sealed invite:', e)
+      setSealedInviteError(String(e))
+    } finally {
+      setIsSealingInvite(false)
+    }
+  }
+
+  const copySealedInvite = () => {
+    if (!sealedInviteUri) return
+    navigator.clipboard.writeText(sealedInviteUri)
+    setCopiedSealedInvite(true)
+    setTimeout(() => setCopiedSealedInvite(false), 2000)
+  }
+
   // When server hints are imported via WS sync, refresh our local view.
   useEffect(() => {
     if (!serverId) return
@@ -761,6 +791,52 @@ function ServerViewPage() {
                 </Button>
               </div>
             )}
+            {server.has_signing_key && (
+              <div className="mt-3 space-y-2">
+                <p className="text-xs text-muted-foreground font-light px-2">
+                  Invite a specific user with their public key (Settings → Account). Only they can open the link.
+                </p>
+                <div className="flex items-center gap-2">
+                  <input
+                    type="text"
+                    value={sealedInviteKey}
+                    onChange={(e) => setSealedInviteKey(e.target.value)}
+                    className="flex-1 min-w-0 px-3 py-2 bg-background border border-border rounded-md text-xs font-mono"
+                    placeholder="Public key"
+                  />
+                  <Button
+                    onClick={handleCreateSealedInvite}
+                    size="sm"
+                    className="h-9 font-light shrink-0"
+                    disabled={isSealingInvite || !sealedInviteKey.trim() || signalingStatus !== 'connected' || !signalingUrl}
+                  >
+                    {isSealingInvite ? 'Sealing…' : 'Invite'}
+                  </Button>
+                </div>
+                {sealedInviteError && (
+                  <p className="text-xs text-red-500 font-light px-2">{sealedInviteError}</p>
+                )}
+                {sealedInviteUri && (
+                  <div className="flex items-center gap-2">
+                    <div className="flex-1 px-3 py-2 bg-background border border-border rounded-md">
+                      <code className="text-xs font-mono break-all">{sealedInviteUri}</code>
+                    </div>
+                    <Button
+                      onClick={copySealedInvite}
+                      variant="outline"
+                      size="icon"
+                      className="h-9 w-9 shrink-0"
+                    >
+                      {copiedSealedInvite ? (
+                        <Check className="h-4 w-4 text-green-500" />
+                      ) : (
+                        <Copy className="h-4 w-4" />
+                      )}
+                    </Button>
+                  </div>
+                )}
+              </div>
+            )}
           </div>
         </div>
       </div>
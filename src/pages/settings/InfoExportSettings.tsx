@@ -1,17 +1,40 @@
-import { useState } from 'react'
-import { Download, Loader2, LogOut } from 'lucide-react'
+import { useEffect, useState } from 'react'
+import { Download, Loader2, Lock, LogOut } from 'lucide-react'
 import { Button } from '../../components/ui/button'
+import { Input } from '../../components/ui/input'
+import { Label } from '../../components/ui/label'
 import { useAccount } from '../../contexts/AccountContext'
 import { useProfile } from '../../contexts/ProfileContext'
 import { useIdentity } from '../../contexts/IdentityContext'
-import { exportFullIdentity, exportFullIdentityDebug } from '../../lib/tauri'
+import {
+  disablePassphraseProtection,
+  enablePassphraseProtection,
+  exportFullIdentity,
+  getVaultStatus,
+} from '../../lib/tauri'
+
+const MIN_PASSPHRASE_LENGTH = 8
 
 export function InfoExportSettings() {
-  const { logout } = useAccount()
+  const { logout, currentAccountId } = useAccount()
   const { profile } = useProfile()
   const { identity } = useIdentity()
   const [isExporting, setIsExporting] = useState(false)
   const [exportError, setExportError] = useState<string | null>(null)
+  const [passphrase, setPassphrase] = useState('')
+  const [confirmPassphrase, setConfirmPassphrase] = useState('')
+  const [vaultEnabled, setVaultEnabled] = useState<boolean | null>(null)
+  const [vaultPassphrase, setVaultPassphrase] = useState('')
+  const [vaultConfirm, setVaultConfirm] = useState('')
+  const [isUpdatingVault, setIsUpdatingVault] = useState(false)
+  const [vaultError, setVaultError] = useState<string | null>(null)
+
+  useEffect(() => {
+    if (!currentAccountId) return
+    getVaultStatus(currentAccountId)
+      .then((status) => setVaultEnabled(status.enabled))
+      .catch(() => setVaultEnabled(null))
+  }, [currentAccountId])
 
   function handleLogout() {
     logout()
@@ -22,7 +45,48 @@ export function InfoExportSettings() {
     return name.replace(/[/\\:*?"<>|]/g, '_')
   }
 
+  async function handleToggleVault() {
+    if (vaultEnabled === null) return
+    if (!vaultEnabled) {
+      if (vaultPassphrase.length < MIN_PASSPHRASE_LENGTH) {
+        setVaultError(`Passphrase must be at least ${MIN_PASSPHRASE_LENGTH} characters`)
+        return
+      }
+      if (vaultPassphrase !== vaultConfirm) {
+        setVaultError('Passphrases do not match')
+        return
+      }
+    }
+
+    setIsUpdatingVault(true)
+    setVaultError(null)
+
+    try {
+      if (vaultEnabled) {
+        await disablePassphraseProtection(vaultPassphrase)
+      } else {
+        await enablePassphraseProtection(vaultPassphrase)
+      }
+      setVaultEnabled(!vaultEnabled)
+      setVaultPassphrase('')
+      setVaultConfirm('')
+    } catch (err) {
+      setVaultError(err instanceof Error ? err.message : typeof err === 'string' ? err : 'Failed to update passphrase protection')
+    } finally {
+      setIsUpdatingVault(false)
+    }
+  }
+
   async function handleExport() {
+    if (passphrase.length < MIN_PASSPHRASE_LENGTH) {
+      setExportError(`Passphrase must be at least ${MIN_PASSPHRASE_LENGTH} characters`)
+      return
+    }
+    if (passphrase !== confirmPassphrase) {
+      setExportError('Passphrases do not match')
+      return
+    }
+
     setIsExporting(true)
     setExportError(null)
 
@@ -34,7 +98,7 @@ export function InfoExportSettings() {
         show_real_name: profile.show_real_name,
       }
 
-      const data = await exportFullIdentity(profileJson)
+      const data = await exportFullIdentity(passphrase, profileJson)
       
       // Generate filename: sanitized display_name or fallback to user_id
       let filename: string
@@ -47,19 +111,7 @@ export function InfoExportSettings() {
         filename = 'account'
       }
       
-      // DEBUG: Export as text file to inspect contents (includes houses)
-      const debugJson = await exportFullIdentityDebug(profileJson)
-      const debugBlob = new Blob([debugJson], { type: 'text/plain' })
-      const debugUrl = URL.createObjectURL(debugBlob)
-      const debugA = document.createElement('a')
-      debugA.href = debugUrl
-      debugA.download = `${filename}.txt`
-      document.body.appendChild(debugA)
-      debugA.click()
-      document.body.removeChild(debugA)
-      URL.revokeObjectURL(debugUrl)
-      
-      // Also export the binary .key file
+      // Binary .key file (v2: encrypted with the passphrase, importable on any device)
       const blob = new Blob([data as BlobPart], { type: 'application/octet-stream' })
       const url = URL.createObjectURL(blob)
       const a = document.createElement('a')
@@ -69,8 +121,10 @@ export function InfoExportSettings() {
       a.click()
       document.body.removeChild(a)
       URL.revokeObjectURL(url)
+      setPassphrase('')
+      setConfirmPassphrase('')
     } catch (err) {
-      setExportError(err instanceof Error ? err.message : 'Failed to export account')
+      setExportError(err instanceof Error ? err.message : typeof err === 'string' ? err : 'Failed to export account')
     } finally {
       setIsExporting(false)
     }
@@ -92,6 +146,34 @@ export function InfoExportSettings() {
               {exportError}
             </div>
           )}
+          <div className="space-y-2">
+            <Label htmlFor="export-passphrase" className="text-xs font-medium uppercase tracking-wider text-muted-foreground">
+              Backup Passphrase
+            </Label>
+            <Input
+              id="export-passphrase"
+              type="password"
+              value={passphrase}
+              onChange={(e) => {
+                setPassphrase(e.target.value)
+                setExportError(null)
+              }}
+              placeholder={`At least ${MIN_PASSPHRASE_LENGTH} characters`}
+              className="h-11 font-light"
+              disabled={isExporting}
+            />
+            <Input
+              type="password"
+              value={confirmPassphrase}
+              onChange={(e) => {
+                setConfirmPassphrase(e.target.value)
+                setExportError(null)
+              }}
+              placeholder="Confirm passphrase"
+              className="h-11 font-light"
+              disabled={isExporting}
+            />
+          </div>
           <Button
             variant="outline"
             onClick={handleExport}
@@ -111,11 +193,76 @@ export function InfoExportSettings() {
             )}
           </Button>
           <p className="text-xs text-muted-foreground font-light">
-            Save this file in a secure location. You can import it on another device to restore your account.
+            Save this file in a secure location. You can import it on any device with the passphrase to restore your account. The passphrase cannot be recovered if you forget it.
           </p>
         </div>
       </div>
 
+      <div className="bg-card/50 backdrop-blur-sm border border-border/50 space-y-6">
+        <div className="space-y-1">
+          <div className="inline-block">
+          <h2 className="text-lg font-light tracking-tight">Passphrase Protection</h2>
+            <div className="h-px bg-foreground/20 mt-1 w-full"></div>
+          </div>
+          <p className="text-xs text-muted-foreground font-light">
+            {vaultEnabled
+              ? 'Your keys on this device are locked with a passphrase. Enter it to turn protection off.'
+              : 'Lock your keys on this device with a passphrase. You will need it each time you log in.'}
+          </p>
+        </div>
+        <div className="space-y-4">
+          {vaultError && (
+            <div className="bg-destructive/10 border-l-2 border-destructive p-4 text-sm text-destructive">
+              {vaultError}
+            </div>
+          )}
+          <div className="space-y-2">
+            <Input
+              type="password"
+              value={vaultPassphrase}
+              onChange={(e) => {
+                setVaultPassphrase(e.target.value)
+                setVaultError(null)
+              }}
+              placeholder={vaultEnabled ? 'Current passphrase' : `At least ${MIN_PASSPHRASE_LENGTH} characters`}
+              className="h-11 font-light"
+              disabled={isUpdatingVault || vaultEnabled === null}
+            />
+            {!vaultEnabled && (
+              <Input
+                type="password"
+                value={vaultConfirm}
+                onChange={(e) => {
+                  setVaultConfirm(e.target.value)
+                  setVaultError(null)
+                }}
+                placeholder="Confirm passphrase"
+                className="h-11 font-light"
+                disabled={isUpdatingVault || vaultEnabled === null}
+              />
+            )}
+          </div>
+          <Button
+            variant="outline"
+            onClick={handleToggleVault}
+            disabled={isUpdatingVault || vaultEnabled === null || !vaultPassphrase}
+            className="w-full h-11 font-light border-border/50 hover:bg-accent"
+          >
+            {isUpdatingVault ? (
+              <>
+                <Loader2 className="mr-2 h-4 w-4 animate-spin" />
+                Updating...
+              </>
+            ) : (
+              <>
+                <Lock className="mr-2 h-4 w-4" />
+                {vaultEnabled ? 'Turn Off Passphrase Protection' : 'Turn On Passphrase Protection'}
+              </>
+            )}
+          </Button>
+        </div>
+      </div>
+
       <div className="bg-card/50 backdrop-blur-sm border border-border/50 space-y-6">
         <div className="space-y-1">
           <div className="inline-block">